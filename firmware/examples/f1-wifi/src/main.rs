@@ -3,31 +3,46 @@
 #![feature(type_alias_impl_trait)]
 
 extern crate alloc;
-use esp_hal as hal;
-use hal::{clock::ClockControl, embassy, peripherals::Peripherals, prelude::*, timer::TimerGroup, Rng};
+use core::fmt::Write as _;
 use core::mem::MaybeUninit;
 use esp_backtrace as _;
+use esp_hal as hal;
 use esp_println::println;
+use hal::{
+    clock::ClockControl, embassy, macros::ram, peripherals::Peripherals, prelude::*,
+    timer::TimerGroup, Rng,
+};
 
-
-use esp_wifi::{initialize, EspWifiInitFor};
 use esp_wifi::wifi::{
-    ClientConfiguration, Configuration, WifiController, WifiDevice, WifiEvent,
-    WifiStaDevice, WifiState,
+    AccessPointConfiguration, ClientConfiguration, Configuration, ScanConfig, WifiApDevice,
+    WifiController, WifiDevice, WifiError, WifiEvent, WifiStaDevice, WifiState,
 };
-
+use esp_wifi::{initialize, EspWifiInitFor};
 
 use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{
+    Config, IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4,
+};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::PubSubChannel;
 use embassy_time::{Duration, Timer};
-use embassy_net::{Config, Stack, StackResources};
+use embedded_io_async::Write;
 
-use static_cell::make_static;
+use esp_storage::FlashStorage;
+use f1_logic::captive_portal::{self, Leases, PORTAL_URL, SERVER_IP};
+use f1_logic::connection::{Action, ConnectionManager, ConnectionState};
+use f1_logic::provisioning::{self, HttpError, AP_SSID};
+use f1_logic::settings::{Network, Settings, SettingsStore, MAX_NETWORKS, SETTINGS_PARTITION};
+use heapless::{String, Vec};
 
+use static_cell::make_static;
 
 #[global_allocator]
 static ALLOCATOR: esp_alloc::EspHeap = esp_alloc::EspHeap::empty();
 
-
 fn init_heap() {
     const HEAP_SIZE: usize = 32 * 1024;
     static mut HEAP: MaybeUninit<[u8; HEAP_SIZE]> = MaybeUninit::uninit();
@@ -37,11 +52,43 @@ fn init_heap() {
     }
 }
 
-const SSID: &str = env!("SSID");
-const PASSWORD: &str = env!("PASSWORD");
+/// Set before a restart to start provisioning although credentials are stored.
+/// RTC fast memory keeps its contents over a software reset.
+#[ram(rtc_fast, uninitialized)]
+static mut PROVISIONING_REQUEST: u32 = 0;
+
+const PROVISIONING_MAGIC: u32 = 0x5052_4F56;
+
+fn take_provisioning_request() -> bool {
+    unsafe {
+        let requested = PROVISIONING_REQUEST == PROVISIONING_MAGIC;
+        PROVISIONING_REQUEST = 0;
+        requested
+    }
+}
+
+/// Connection state changes, for the status indicator and the race player
+pub static CONNECTION_STATE: PubSubChannel<CriticalSectionRawMutex, ConnectionState, 4, 4, 1> =
+    PubSubChannel::new();
+
+/// How often the signal strength is measured while connected
+const RSSI_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The portal gives up after this time when networks are stored, they may
+/// have been unreachable only for a while
+const PORTAL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+fn restart_into_provisioning() -> ! {
+    unsafe {
+        PROVISIONING_REQUEST = PROVISIONING_MAGIC;
+    }
+    hal::reset::software_reset();
+    loop {}
+}
 
 #[main]
 async fn main(spawner: Spawner) -> ! {
+    init_heap();
     esp_println::logger::init_logger(log::LevelFilter::Info);
 
     let peripherals = Peripherals::take();
@@ -61,17 +108,71 @@ async fn main(spawner: Spawner) -> ! {
     )
     .unwrap();
 
+    let mut store = match SettingsStore::new(FlashStorage::new(), SETTINGS_PARTITION) {
+        Ok(store) => Some(store),
+        Err(err) => {
+            println!("Failed to open settings: {:?}", err);
+            None
+        }
+    };
+    let settings = match store.as_mut().map(|store| store.load()) {
+        Some(Ok(settings)) => settings,
+        Some(Err(err)) => {
+            println!("Failed to load settings: {:?}", err);
+            Settings::default()
+        }
+        None => Settings::default(),
+    };
+
+    let provisioning_requested = take_provisioning_request();
     let wifi = peripherals.WIFI;
+    let timer_group0 = TimerGroup::new(peripherals.TIMG0, &clocks);
+    let seed = 1234; // very random, very secure seed
+
+    if settings.networks.is_empty() || provisioning_requested {
+        let (wifi_interface, controller) =
+            esp_wifi::wifi::new_with_mode(&init, wifi, WifiApDevice).unwrap();
+        embassy::init(&clocks, timer_group0);
+
+        let server_ip = Ipv4Address::from_bytes(&SERVER_IP);
+        let config = Config::ipv4_static(StaticConfigV4 {
+            address: Ipv4Cidr::new(server_ip, 24),
+            gateway: Some(server_ip),
+            dns_servers: Default::default(),
+        });
+
+        // DHCP, DNS and HTTP sockets
+        let stack = &*make_static!(Stack::new(
+            wifi_interface,
+            config,
+            make_static!(StackResources::<4>::new()),
+            seed
+        ));
+
+        spawner.spawn(ap_connection(controller)).ok();
+        spawner.spawn(ap_net_task(stack)).ok();
+        spawner.spawn(dhcp_task(stack)).ok();
+        spawner.spawn(dns_task(stack)).ok();
+
+        if settings.networks.is_empty() {
+            run_portal(stack, store, settings).await
+        }
+        select(
+            run_portal(stack, store, settings),
+            Timer::after(PORTAL_TIMEOUT),
+        )
+        .await;
+        println!("No credentials received, trying the stored networks again");
+        hal::reset::software_reset();
+        loop {}
+    }
+
     let (wifi_interface, controller) =
         esp_wifi::wifi::new_with_mode(&init, wifi, WifiStaDevice).unwrap();
-
-    let timer_group0 = TimerGroup::new(peripherals.TIMG0, &clocks);
     embassy::init(&clocks, timer_group0);
 
     let config = Config::dhcpv4(Default::default());
 
-    let seed = 1234; // very random, very secure seed
-
     // Init network stack
     let stack = &*make_static!(Stack::new(
         wifi_interface,
@@ -80,8 +181,11 @@ async fn main(spawner: Spawner) -> ! {
         seed
     ));
 
-    spawner.spawn(connection(controller)).ok();
-    spawner.spawn(net_task(&stack)).ok();
+    spawner.spawn(status_task()).ok();
+    spawner
+        .spawn(connection(controller, settings.networks))
+        .ok();
+    spawner.spawn(net_task(stack)).ok();
 
     loop {
         if stack.is_link_up() {
@@ -100,51 +204,278 @@ async fn main(spawner: Spawner) -> ! {
     }
 
     loop {
-
+        Timer::after(Duration::from_secs(60)).await;
     }
 }
 
 #[embassy_executor::task]
-async fn connection(mut controller: WifiController<'static>) {
+async fn connection(mut controller: WifiController<'static>, networks: Vec<Network, MAX_NETWORKS>) {
     println!("start connection task");
     println!("Device capabilities: {:?}", controller.get_capabilities());
+    let publisher = CONNECTION_STATE.immediate_publisher();
+    let mut manager = ConnectionManager::new(networks.len());
+    let mut action = manager.start();
 
     loop {
-        match esp_wifi::wifi::get_wifi_state() {
-            WifiState::StaConnected => {
-                // wait until we're no longer connected
-                controller.wait_for_event(WifiEvent::StaDisconnected).await;
-                Timer::after(Duration::from_millis(5000)).await
+        let (index, delay_ms) = match action {
+            Action::Connect { network, delay_ms } => (network, delay_ms),
+            Action::Provision => {
+                println!("Connecting failed repeatedly, starting provisioning");
+                restart_into_provisioning();
             }
-            _ => {}
+        };
+        if delay_ms > 0 {
+            println!("Retrying in {} ms", delay_ms);
+            Timer::after(Duration::from_millis(delay_ms.into())).await;
         }
-        if !matches!(controller.is_started(), Ok(true)) {
-            let client_config = Configuration::Client(ClientConfiguration {
-                ssid: SSID.try_into().unwrap(),
-                password: PASSWORD.try_into().unwrap(),
-                auth_method: if PASSWORD.is_empty() { esp_wifi::wifi::AuthMethod::None } else { Default::default() },
-                ..Default::default()
-            });
 
-            controller.set_configuration(&client_config).unwrap();
-            println!("Starting wifi");
-            controller.start().await.unwrap();
-            println!("Wifi started!");
+        let network = &networks[usize::from(index)];
+        publisher.publish_immediate(ConnectionState::Connecting { network: index });
+        println!("About to connect to {}...", network.ssid);
+        if let Err(e) = connect(&mut controller, network).await {
+            println!("Failed to connect to {}: {:?}", network.ssid, e);
+            publisher.publish_immediate(ConnectionState::Disconnected);
+            action = manager.failed();
+            continue;
         }
-        println!("About to connect...");
+        println!("Wifi connected to {}", network.ssid);
+        manager.connected();
+
+        // Report the signal strength until the connection drops
+        // Lowest value until a scan finds the access point
+        let mut rssi = i8::MIN;
+        loop {
+            if let Some(signal) = signal_strength(&mut controller, &network.ssid).await {
+                rssi = signal;
+            }
+            publisher.publish_immediate(ConnectionState::Connected {
+                network: index,
+                rssi,
+            });
 
-        match controller.connect().await {
-            Ok(_) => println!("Wifi connected!"),
-            Err(e) => {
-                println!("Failed to connect to wifi: {e:?}");
-                Timer::after(Duration::from_millis(5000)).await
+            // Keep a disconnect that happened during the scan pending
+            let disconnected = controller.wait_for_events(WifiEvent::StaDisconnected.into(), false);
+            if let Either::First(_) = select(disconnected, Timer::after(RSSI_INTERVAL)).await {
+                break;
             }
         }
+
+        println!("Wifi disconnected from {}", network.ssid);
+        publisher.publish_immediate(ConnectionState::Disconnected);
+        action = manager.disconnected();
+    }
+}
+
+/// Configure the station for `network` and connect to it
+async fn connect(
+    controller: &mut WifiController<'static>,
+    network: &Network,
+) -> Result<(), WifiError> {
+    if matches!(controller.is_started(), Ok(true)) {
+        controller.stop().await?;
+    }
+    let client_config = Configuration::Client(ClientConfiguration {
+        ssid: network.ssid.clone(),
+        password: network.password.clone(),
+        auth_method: if network.password.is_empty() {
+            esp_wifi::wifi::AuthMethod::None
+        } else {
+            Default::default()
+        },
+        ..Default::default()
+    });
+    controller.set_configuration(&client_config)?;
+    controller.start().await?;
+    controller.connect().await
+}
+
+/// Signal strength of the access point in dBm, measured with a scan for
+/// its network name
+async fn signal_strength(controller: &mut WifiController<'static>, ssid: &str) -> Option<i8> {
+    let config = ScanConfig {
+        ssid: Some(ssid),
+        ..Default::default()
+    };
+    match controller.scan_with_config::<4>(config).await {
+        Ok((access_points, _)) => access_points.iter().map(|ap| ap.signal_strength).max(),
+        Err(e) => {
+            println!("Failed to measure signal strength: {:?}", e);
+            None
+        }
     }
 }
 
+/// Show connection state changes
+#[embassy_executor::task]
+async fn status_task() {
+    let Ok(mut subscriber) = CONNECTION_STATE.subscriber() else {
+        return;
+    };
+    loop {
+        let state = subscriber.next_message_pure().await;
+        println!(
+            "Connection: {:?}, status: {:?}",
+            state,
+            state.firmware_state()
+        );
+    }
+}
 
 #[embassy_executor::task]
 async fn net_task(stack: &'static Stack<WifiDevice<'static, WifiStaDevice>>) {
     stack.run().await
 }
+
+#[embassy_executor::task]
+async fn ap_connection(mut controller: WifiController<'static>) {
+    loop {
+        if let WifiState::ApStarted = esp_wifi::wifi::get_wifi_state() {
+            // wait until the access point stops
+            controller.wait_for_event(WifiEvent::ApStop).await;
+            Timer::after(Duration::from_millis(5000)).await
+        }
+        if !matches!(controller.is_started(), Ok(true)) {
+            let ap_config = Configuration::AccessPoint(AccessPointConfiguration {
+                ssid: AP_SSID.try_into().unwrap(),
+                ..Default::default()
+            });
+            controller.set_configuration(&ap_config).unwrap();
+            controller.start().await.unwrap();
+            println!("Provisioning access point {} started", AP_SSID);
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn ap_net_task(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn dhcp_task(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1536];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1536];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(67).unwrap();
+
+    let mut leases = Leases::default();
+    let mut request = [0; 576];
+    let mut reply = [0; 576];
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+        if let Some(reply_len) =
+            captive_portal::dhcp_reply(&request[..len], &mut leases, &mut reply)
+        {
+            // The client has no address yet
+            let broadcast = IpEndpoint::new(IpAddress::v4(255, 255, 255, 255), 68);
+            if let Err(e) = socket.send_to(&reply[..reply_len], broadcast).await {
+                println!("Failed to send DHCP reply: {:?}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn dns_task(stack: &'static Stack<WifiDevice<'static, WifiApDevice>>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(53).unwrap();
+
+    let mut query = [0; 512];
+    let mut reply = [0; 512];
+    loop {
+        let Ok((len, client)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        if let Some(reply_len) = captive_portal::dns_reply(&query[..len], &mut reply) {
+            if let Err(e) = socket.send_to(&reply[..reply_len], client).await {
+                println!("Failed to send DNS reply: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Serve the provisioning page until credentials are submitted, then save
+/// them and restart
+async fn run_portal(
+    stack: &'static Stack<WifiDevice<'static, WifiApDevice>>,
+    mut store: Option<SettingsStore<FlashStorage>>,
+    mut settings: Settings,
+) -> ! {
+    let mut rx_buffer = [0; 1536];
+    let mut tx_buffer = [0; 1536];
+    let mut request = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if let Err(e) = socket.accept(80).await {
+            println!("Accept error: {:?}", e);
+            continue;
+        }
+
+        // Read until the request is complete
+        let mut len = 0;
+        let result = loop {
+            match socket.read(&mut request[len..]).await {
+                Ok(0) | Err(_) => break None,
+                Ok(n) => {
+                    len += n;
+                    match provisioning::parse_request(&request[..len]) {
+                        Ok(request) => {
+                            break Some(provisioning::handle_request(&request, PORTAL_URL))
+                        }
+                        Err(HttpError::Incomplete) if len < request.len() => {}
+                        Err(_) => break None,
+                    }
+                }
+            }
+        };
+
+        if let Some((response, credentials)) = result {
+            let mut out: String<1024> = String::new();
+            if write!(out, "{}", response).is_ok() {
+                socket.write_all(out.as_bytes()).await.ok();
+                socket.flush().await.ok();
+            }
+
+            if let Some(credentials) = credentials {
+                println!("Received credentials for {}", credentials.ssid);
+                settings.add_network(credentials);
+                match store.as_mut().map(|store| store.save(&settings)) {
+                    Some(Ok(())) => {
+                        socket.close();
+                        // Let the response reach the client
+                        Timer::after(Duration::from_secs(1)).await;
+                        hal::reset::software_reset();
+                    }
+                    Some(Err(err)) => println!("Failed to save settings: {:?}", err),
+                    None => println!("Settings storage unavailable"),
+                }
+            }
+        }
+
+        socket.close();
+        Timer::after(Duration::from_millis(50)).await;
+        socket.abort();
+    }
+}
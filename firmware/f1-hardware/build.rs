@@ -0,0 +1,117 @@
+use std::env;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Board the firmware is built for
+const BOARD: &str = "esp32c3";
+
+fn main() {
+    println!("cargo:rustc-env=F1_GIT_COMMIT={}", git_commit());
+    println!("cargo:rustc-env=F1_BUILD_TIMESTAMP={}", build_timestamp());
+    println!("cargo:rustc-env=F1_BOARD={}", BOARD);
+    println!("cargo:rustc-env=F1_FEATURES={}", enabled_features());
+
+    // The Wi-Fi driver calls functions in the ROM, esp-wifi provides the
+    // linker script with their addresses
+    if env::var_os("CARGO_FEATURE_WIFI").is_some() {
+        println!("cargo:rustc-link-arg=-Trom_functions.x");
+    }
+
+    // Rebuild when the checked out commit changes. HEAD usually names a
+    // branch, a commit on it only changes the branch ref and the HEAD log.
+    for path in ["HEAD", "logs/HEAD", "index", "packed-refs"] {
+        if let Some(path) = git(&["rev-parse", "--git-path", path]) {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        if let Some(path) = git(&["rev-parse", "--git-path", &branch]) {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    // and when the sources change, so edits that aren't staged get marked
+    // dirty
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=../f1-logic/src");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+fn git_commit() -> String {
+    match git(&["rev-parse", "--short", "HEAD"]) {
+        Some(commit) => {
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+                .map(|status| !status.is_empty())
+                .unwrap_or(false);
+            if dirty {
+                format!("{}-dirty", commit)
+            } else {
+                commit
+            }
+        }
+        None => "unknown".to_string(),
+    }
+}
+
+fn enabled_features() -> String {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    features.join(",")
+}
+
+/// Build time as ISO 8601 UTC. Honors SOURCE_DATE_EPOCH for reproducible builds.
+///
+/// Only updated when the build script reruns, i.e. when one of the files
+/// watched in `main` changes, not on every build.
+fn build_timestamp() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+// Convert days since 1970-01-01 to a (year, month, day) date
+// (Howard Hinnant's civil_from_days algorithm)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
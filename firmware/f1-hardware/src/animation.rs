@@ -0,0 +1,47 @@
+use crate::driver_info::DRIVERS;
+use crate::hd108::HD108;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal_async::spi::SpiBus;
+use esp_println::println;
+use f1_logic::animation::{scale, Rgb, StartupAnimation, OFF};
+use f1_logic::board::MAX_LED_COUNT;
+use heapless08::Vec;
+
+/// Time between two animation frames
+const ANIMATION_FRAME_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Play `animation` at `brightness` (0-255) to the end and turn the LEDs off
+pub async fn play_animation(
+    hd108: &mut HD108<impl SpiBus<u8>>,
+    animation: StartupAnimation,
+    brightness: u8,
+) {
+    let team_colors = team_colors();
+    let mut frame = [OFF; MAX_LED_COUNT];
+    let frame = &mut frame[..hd108.led_count()];
+
+    let start = Instant::now();
+    while animation.render(start.elapsed().as_millis() as u32, &team_colors, frame) {
+        for led in frame.iter_mut() {
+            *led = scale(*led, brightness);
+        }
+        if let Err(err) = hd108.set_frame(frame).await {
+            println!("Failed to set LEDs: {:?}", err);
+            break;
+        }
+        Timer::after(ANIMATION_FRAME_INTERVAL).await;
+    }
+
+    hd108.set_off().await.unwrap();
+}
+
+// One color per team, in driver order
+fn team_colors() -> Vec<Rgb, 20> {
+    let mut colors = Vec::new();
+    for (i, driver) in DRIVERS.iter().enumerate() {
+        if DRIVERS[..i].iter().all(|d| d.team != driver.team) {
+            colors.push(driver.color).ok();
+        }
+    }
+    colors
+}
@@ -0,0 +1,232 @@
+use crate::state::SharedState;
+use crate::version::version;
+use crate::{Message, FRAME_INTERVAL_MS};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Sender;
+use embedded_io_async::Read;
+use esp_hal::usb_serial_jtag::UsbSerialJtagRx;
+use esp_hal::Async;
+use esp_println::println;
+use f1_logic::animation::StartupAnimation;
+use f1_logic::connection::ConnectionState;
+use f1_logic::console::{parse, Command, LineBuffer, LineError, ParseError, HELP};
+use f1_logic::settings::Settings;
+use heapless08::String;
+
+#[embassy_executor::task]
+pub async fn console_task(
+    mut rx: UsbSerialJtagRx<'static, Async>,
+    sender: Sender<'static, NoopRawMutex, Message, 1>,
+    state: &'static SharedState,
+) {
+    // Room for a race URL
+    let mut lines = LineBuffer::<160>::new();
+    let mut buf = [0u8; 16];
+
+    loop {
+        let len = match rx.read(&mut buf).await {
+            Ok(len) => len,
+            Err(e) => {
+                println!("Console read error: {:?}", e);
+                continue;
+            }
+        };
+
+        for &byte in &buf[..len] {
+            match lines.push(byte) {
+                Some(Ok(line)) => match parse(line) {
+                    Ok(command) => execute(command, &sender, state).await,
+                    Err(ParseError::UnknownCommand) => {
+                        println!("unknown command, type 'help' for a list of commands")
+                    }
+                    Err(e) => println!("error: {:?}", e),
+                },
+                Some(Err(LineError::TooLong)) => println!("error: line too long"),
+                Some(Err(LineError::InvalidUtf8)) => println!("error: invalid characters"),
+                None => {}
+            }
+        }
+    }
+}
+
+async fn execute(
+    command: Command<'_>,
+    sender: &Sender<'static, NoopRawMutex, Message, 1>,
+    state: &SharedState,
+) {
+    match command {
+        Command::Status => {
+            let playback = state.playback();
+            println!("state: {:?}", state.status());
+            println!(
+                "race: {} frame {}/{}",
+                if playback.playing {
+                    "playing"
+                } else {
+                    "stopped"
+                },
+                playback.frame,
+                playback.frame_count
+            );
+            println!("brightness: {}", state.brightness());
+            println!("race: session {}", state.settings().race_session_key);
+            #[cfg(feature = "wifi")]
+            if let Some(progress) = state.download_progress() {
+                println!(
+                    "download: {}/{} bytes ({}%)",
+                    progress.stored,
+                    progress.size,
+                    progress.percent()
+                );
+            }
+            #[cfg(feature = "wifi")]
+            if let Some((session_key, stats)) = state.live_status() {
+                println!(
+                    "live: session {}, {} frames played, {} lost, {} late",
+                    session_key, stats.played, stats.lost, stats.late
+                );
+            }
+            print_temperature(state);
+            print_connection(state);
+            println!("firmware: {}", version());
+        }
+        Command::Play => sender.send(Message::Play).await,
+        Command::Pause => sender.send(Message::Pause).await,
+        Command::Stop => {
+            #[cfg(feature = "wifi")]
+            state.request_live(None);
+            sender.send(Message::Stop).await
+        }
+        Command::Seek { seconds } => {
+            let frame = seconds as usize * 1000 / FRAME_INTERVAL_MS as usize;
+            sender.send(Message::Seek { frame }).await
+        }
+        Command::Brightness(Some(brightness)) => sender.send(Message::Brightness(brightness)).await,
+        Command::Brightness(None) => println!("brightness: {}", state.brightness()),
+        Command::Temperature => print_temperature(state),
+        Command::Version => println!("firmware: {}", version()),
+        Command::TestLeds => sender.send(Message::TestLeds).await,
+        Command::Animation(Some(animation)) => sender.send(Message::Animation(animation)).await,
+        Command::Animation(None) => {
+            println!("animation: {}", state.startup_animation());
+            for animation in StartupAnimation::ALL {
+                println!("  {}", animation);
+            }
+        }
+        Command::Teammates(Some(style)) => state.update_settings(|s| s.teammate_colors = style),
+        Command::Teammates(None) => println!("teammates: {}", state.teammate_colors().name()),
+        Command::Race(Some(session_key)) => {
+            state.update_settings(|s| s.race_session_key = session_key)
+        }
+        Command::Race(None) => println!("race: session {}", state.settings().race_session_key),
+        Command::RaceUrl(Some(url)) => {
+            let Ok(url) = String::try_from(url) else {
+                println!("error: {:?}", ParseError::InvalidArgument);
+                return;
+            };
+            state.update_settings(|s| s.race_url = url);
+            #[cfg(feature = "wifi")]
+            state.request_download();
+        }
+        Command::RaceUrl(None) => print_race_url(&state.settings()),
+        #[cfg(feature = "wifi")]
+        Command::Download => state.request_download(),
+        #[cfg(not(feature = "wifi"))]
+        Command::Download => println!("error: downloads need the wifi feature"),
+        #[cfg(feature = "wifi")]
+        Command::Live(session_key) => state.request_live(Some(
+            session_key.unwrap_or_else(|| state.settings().race_session_key),
+        )),
+        #[cfg(not(feature = "wifi"))]
+        Command::Live(_) => println!("error: live streams need the wifi feature"),
+        #[cfg(feature = "ota")]
+        Command::Ota(url) => match String::try_from(url) {
+            Ok(url) => state.request_ota(url),
+            Err(_) => println!("error: {:?}", ParseError::InvalidArgument),
+        },
+        #[cfg(not(feature = "ota"))]
+        Command::Ota(_) => println!("error: firmware updates need the ota feature"),
+        Command::Mqtt(Some(url)) => {
+            let Ok(url) = String::try_from(url) else {
+                println!("error: {:?}", ParseError::InvalidArgument);
+                return;
+            };
+            state.update_settings(|s| s.mqtt_url = url);
+            #[cfg(feature = "mqtt")]
+            state.mqtt_changed();
+            #[cfg(not(feature = "mqtt"))]
+            println!("note: the board publishes with the mqtt feature only");
+        }
+        Command::Mqtt(None) => print_mqtt_url(&state.settings()),
+        Command::Settings => print_settings(&state.settings()),
+        Command::ResetSettings => state.update_settings(|s| *s = Settings::default()),
+        Command::Help => println!("{}", HELP),
+    }
+}
+
+fn print_temperature(state: &SharedState) {
+    match state.temperature_c() {
+        Some(temperature_c) => println!("temperature: {:.2} °C", temperature_c),
+        None => println!("temperature: unknown"),
+    }
+}
+
+fn print_connection(state: &SharedState) {
+    match state.connection() {
+        None => println!("wifi: offline"),
+        Some(ConnectionState::Disconnected) => println!("wifi: disconnected"),
+        Some(ConnectionState::Connecting { network }) => {
+            println!("wifi: connecting to {}", network_name(state, network))
+        }
+        Some(ConnectionState::Connected { network, rssi }) => println!(
+            "wifi: connected to {}, {} dBm",
+            network_name(state, network),
+            rssi
+        ),
+    }
+}
+
+fn network_name(state: &SharedState, network: u8) -> String<32> {
+    state
+        .settings()
+        .networks
+        .get(usize::from(network))
+        .map(|n| n.ssid.clone())
+        .unwrap_or_default()
+}
+
+fn print_race_url(settings: &Settings) {
+    if settings.race_url.is_empty() {
+        println!("race url: not set");
+    } else {
+        println!("race url: {}", settings.race_url);
+    }
+}
+
+fn print_mqtt_url(settings: &Settings) {
+    if settings.mqtt_url.is_empty() {
+        println!("mqtt: off");
+    } else {
+        println!("mqtt: {}", settings.mqtt_url);
+    }
+}
+
+fn print_settings(settings: &Settings) {
+    println!("brightness: {}", settings.brightness);
+    println!("animation: {}", settings.startup_animation);
+    println!("teammates: {}", settings.teammate_colors.name());
+    println!("race: session {}", settings.race_session_key);
+    print_race_url(settings);
+    print_mqtt_url(settings);
+    if settings.networks.is_empty() {
+        println!("wifi: no networks");
+    }
+    for network in &settings.networks {
+        let security = if network.password.is_empty() {
+            "open"
+        } else {
+            "password set"
+        };
+        println!("wifi: {} ({})", network.ssid, security);
+    }
+}
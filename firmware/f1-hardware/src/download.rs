@@ -0,0 +1,183 @@
+//! Race download into the race partition.
+//!
+//! The race is downloaded at boot when a race URL is set and no race is
+//! stored, and whenever the console asks for it. Failed attempts are retried
+//! with backoff and continue where the previous attempt stopped, the HTTP
+//! side lives in `f1_logic::download`. Firmware updates connect the same
+//! way, see `ota.rs`.
+
+use crate::state::SharedState;
+use crate::wifi::WifiStack;
+use core::fmt;
+use embassy_net::dns::{self, DnsQueryType};
+use embassy_net::tcp::{self, ConnectError, TcpSocket};
+use embassy_time::{Duration, Timer};
+use esp_println::println;
+use esp_storage::FlashStorageError;
+use f1_logic::connection::Backoff;
+use f1_logic::download::{self, DownloadError, Url};
+use f1_logic::race_store::{RaceError, RaceInfo};
+use f1_logic::status::FirmwareState;
+
+/// Longest wait for data from the server
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub enum ConnectionError {
+    Dns(dns::Error),
+    NoAddress,
+    Connect(ConnectError),
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionError::Dns(err) => write!(f, "DNS lookup failed: {:?}", err),
+            ConnectionError::NoAddress => write!(f, "no address for the host"),
+            ConnectionError::Connect(err) => write!(f, "connecting failed: {:?}", err),
+        }
+    }
+}
+
+enum Error {
+    Connection(ConnectionError),
+    NoRacePartition,
+    Download(DownloadError<tcp::Error, RaceError<FlashStorageError>>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connection(err) => write!(f, "{}", err),
+            Error::NoRacePartition => write!(f, "no race partition"),
+            Error::Download(err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
+impl Error {
+    /// Whether trying again may help
+    fn is_temporary(&self) -> bool {
+        match self {
+            Error::NoRacePartition => false,
+            Error::Download(
+                DownloadError::InvalidUrl
+                | DownloadError::Store(RaceError::InvalidSize | RaceError::InvalidFrame),
+            ) => false,
+            Error::Download(DownloadError::Status(status)) => *status >= 500,
+            _ => true,
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn download_task(stack: &'static WifiStack, state: &'static SharedState) {
+    let stored = state.race().lock().await.frame_count() > 0;
+    if stored || state.settings().race_url.is_empty() {
+        state.wait_download_request().await;
+    }
+
+    loop {
+        download_with_retries(stack, state).await;
+        state.wait_download_request().await;
+    }
+}
+
+async fn download_with_retries(stack: &'static WifiStack, state: &'static SharedState) {
+    let mut backoff = Backoff::default();
+    loop {
+        let race_url = state.settings().race_url;
+        let Some(url) = Url::parse(&race_url) else {
+            println!("No race URL set, use the race-url command");
+            return;
+        };
+
+        show_status(state, FirmwareState::Downloading);
+        let result = download(stack, &url, state).await;
+        state.set_download_progress(None);
+        show_status(state, state.idle_status());
+
+        match result {
+            Ok(race) => {
+                println!("Race downloaded, {} frames", race.frame_count());
+                return;
+            }
+            Err(err) if err.is_temporary() => {
+                let delay_ms = backoff.next_delay();
+                println!("Race download failed: {}, retrying in {} ms", err, delay_ms);
+                Timer::after(Duration::from_millis(delay_ms.into())).await;
+            }
+            Err(err) => {
+                println!("Race download failed: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+/// Show `status` on the status indicator unless a race or an error is
+/// showing
+fn show_status(state: &SharedState, status: FirmwareState) {
+    if matches!(
+        state.status(),
+        FirmwareState::Idle | FirmwareState::Connecting | FirmwareState::Downloading
+    ) {
+        state.set_status(status);
+    }
+}
+
+/// Connect to the server of `url`
+pub async fn connect<'a>(
+    stack: &'static WifiStack,
+    url: &Url<'_>,
+    rx_buffer: &'a mut [u8],
+    tx_buffer: &'a mut [u8],
+) -> Result<TcpSocket<'a>, ConnectionError> {
+    stack.wait_config_up().await;
+    let address = *stack
+        .dns_query(url.host, DnsQueryType::A)
+        .await
+        .map_err(ConnectionError::Dns)?
+        .first()
+        .ok_or(ConnectionError::NoAddress)?;
+
+    let mut socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+    socket.set_timeout(Some(SOCKET_TIMEOUT));
+    socket
+        .connect((address, url.port))
+        .await
+        .map_err(ConnectionError::Connect)?;
+    Ok(socket)
+}
+
+async fn download(
+    stack: &'static WifiStack,
+    url: &Url<'_>,
+    state: &SharedState,
+) -> Result<RaceInfo, Error> {
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 512];
+    println!(
+        "Downloading the race from {}:{}{}",
+        url.host, url.port, url.path
+    );
+    let mut socket = connect(stack, url, &mut rx_buffer, &mut tx_buffer)
+        .await
+        .map_err(Error::Connection)?;
+
+    // Playback stops while the race is replaced
+    let mut race = state.race().lock().await;
+    let store = race.store().ok_or(Error::NoRacePartition)?;
+    let mut percent = None;
+    let result = download::download(&mut socket, url, store, |progress| {
+        state.set_download_progress(Some(progress));
+        if percent != Some(progress.percent() / 10) {
+            percent = Some(progress.percent() / 10);
+            println!("Race download {}%", progress.percent());
+        }
+    })
+    .await
+    .map_err(Error::Download);
+
+    socket.close();
+    result
+}
@@ -1,24 +1,38 @@
 use embedded_hal_async::spi::SpiBus;
+use f1_logic::animation::Rgb;
+use f1_logic::board::MAX_LED_COUNT;
+use f1_logic::selftest::DEFAULT_GAIN;
 use heapless07::Vec;
 
+// Start frame, one frame per LED and one extra clock per LED
+const MAX_BUFFER_SIZE: usize = 16 + MAX_LED_COUNT * 8 + (MAX_LED_COUNT + 7) / 8;
+
 pub struct HD108<SPI> {
     pub spi: SPI,
+    led_count: usize,
 }
 
 impl<SPI> HD108<SPI>
 where
     SPI: SpiBus<u8>,
 {
-    pub fn new(spi: SPI) -> Self {
-        Self { spi }
+    pub fn new(spi: SPI, led_count: usize) -> Self {
+        Self {
+            spi,
+            led_count: led_count.min(MAX_LED_COUNT),
+        }
+    }
+
+    pub fn led_count(&self) -> usize {
+        self.led_count
     }
 
     // Function to create an LED frame
-    fn create_led_frame(red: u16, green: u16, blue: u16) -> [u8; 8] {
+    fn create_led_frame(red: u16, green: u16, blue: u16, gain: u8) -> [u8; 8] {
         let start_code: u8 = 0b1;
-        let red_gain: u8 = 0b00010; // Regulation level 2 - 2.24 mA
-        let green_gain: u8 = 0b00010; // Regulation level 2 - 2.24 mA
-        let blue_gain: u8 = 0b00010; // Regulation level 2 - 2.24 mA
+        let red_gain: u8 = gain & 0x1F;
+        let green_gain: u8 = gain & 0x1F;
+        let blue_gain: u8 = gain & 0x1F;
 
         // Combine the gain values into a 15-bit number
         let current_gain =
@@ -42,23 +56,26 @@ where
         ]
     }
 
-    pub async fn set_off(&mut self) -> Result<(), SPI::Error> {
+    // Write a full update of the chain, `led_frame` creates the frame for each LED number
+    async fn write_frames(
+        &mut self,
+        led_frame: impl Fn(usize) -> [u8; 8],
+    ) -> Result<(), SPI::Error> {
         // At least 128 bits of zeros for the start frame
         let start_frame = [0x00; 16];
 
-        // Create data frames for all 96 LEDs
-        let mut data: Vec<u8, 796> = Vec::new();
+        // Create data frames for all LEDs
+        let mut data: Vec<u8, MAX_BUFFER_SIZE> = Vec::new();
         data.extend_from_slice(&start_frame).unwrap();
 
-        // Set all LEDs to off
-        let off_led_frame = Self::create_led_frame(0x0000, 0x0000, 0x0000);
-        for _ in 0..96 {
-            data.extend_from_slice(&off_led_frame).unwrap();
+        for i in 1..=self.led_count {
+            data.extend_from_slice(&led_frame(i)).unwrap();
         }
 
         // Additional clock pulses equal to the number of LEDs in the strip
-        let additional_clocks = [0x00; 12];
-        data.extend_from_slice(&additional_clocks).unwrap();
+        let additional_clocks = [0x00; (MAX_LED_COUNT + 7) / 8];
+        data.extend_from_slice(&additional_clocks[..(self.led_count + 7) / 8])
+            .unwrap();
 
         // Write the data to the SPI bus
         self.spi.write(&data).await?;
@@ -66,16 +83,15 @@ where
         Ok(())
     }
 
-    pub async fn set_leds(&mut self, leds: &[(usize, u8, u8, u8)]) -> Result<(), SPI::Error> {
-        // At least 128 bits of zeros for the start frame
-        let start_frame = [0x00; 16];
-
-        // Create data frames for all 96 LEDs
-        let mut data: Vec<u8, 796> = Vec::new();
-        data.extend_from_slice(&start_frame).unwrap();
+    pub async fn set_off(&mut self) -> Result<(), SPI::Error> {
+        // Set all LEDs to off
+        let off_led_frame = Self::create_led_frame(0x0000, 0x0000, 0x0000, DEFAULT_GAIN);
+        self.write_frames(|_| off_led_frame).await
+    }
 
+    pub async fn set_leds(&mut self, leds: &[(usize, u8, u8, u8)]) -> Result<(), SPI::Error> {
         // Set the specified LEDs to the given colors and all others to off
-        for i in 1..=96 {
+        self.write_frames(|i| {
             if let Some(&(_led_num, red, green, blue)) =
                 leds.iter().find(|&&(led_num, _, _, _)| led_num == i)
             {
@@ -84,21 +100,55 @@ where
                 let green = ((green as u16) << 8) | (green as u16);
                 let blue = ((blue as u16) << 8) | (blue as u16);
 
-                let led_frame = Self::create_led_frame(red, green, blue);
-                data.extend_from_slice(&led_frame).unwrap();
+                Self::create_led_frame(red, green, blue, DEFAULT_GAIN)
             } else {
-                let off_led_frame = Self::create_led_frame(0x0000, 0x0000, 0x0000); // LED off
-                data.extend_from_slice(&off_led_frame).unwrap();
+                Self::create_led_frame(0x0000, 0x0000, 0x0000, DEFAULT_GAIN) // LED off
             }
-        }
+        })
+        .await
+    }
 
-        // Additional clock pulses equal to the number of LEDs in the strip
-        let additional_clocks = [0x00; 12];
-        data.extend_from_slice(&additional_clocks).unwrap();
+    /// Set every LED from a framebuffer, `frame[0]` is LED 1. LEDs beyond the end of `frame` are off.
+    pub async fn set_frame(&mut self, frame: &[Rgb]) -> Result<(), SPI::Error> {
+        self.write_frames(|i| {
+            let (red, green, blue) = frame.get(i - 1).copied().unwrap_or((0, 0, 0));
+            Self::create_led_frame(
+                ((red as u16) << 8) | (red as u16),
+                ((green as u16) << 8) | (green as u16),
+                ((blue as u16) << 8) | (blue as u16),
+                DEFAULT_GAIN,
+            )
+        })
+        .await
+    }
 
-        // Write the data to the SPI bus
-        self.spi.write(&data).await?;
+    /// Set the LEDs to 16 bit colors at current gain level `gain` (0-31), all others off
+    pub async fn set_leds_with_gain(
+        &mut self,
+        leds: &[(usize, u16, u16, u16)],
+        gain: u8,
+    ) -> Result<(), SPI::Error> {
+        self.write_frames(|i| {
+            if let Some(&(_led_num, red, green, blue)) =
+                leds.iter().find(|&&(led_num, _, _, _)| led_num == i)
+            {
+                Self::create_led_frame(red, green, blue, gain)
+            } else {
+                Self::create_led_frame(0x0000, 0x0000, 0x0000, gain)
+            }
+        })
+        .await
+    }
 
-        Ok(())
+    /// Set all LEDs to the same 16 bit color at current gain level `gain` (0-31)
+    pub async fn set_all_with_gain(
+        &mut self,
+        red: u16,
+        green: u16,
+        blue: u16,
+        gain: u8,
+    ) -> Result<(), SPI::Error> {
+        let led_frame = Self::create_led_frame(red, green, blue, gain);
+        self.write_frames(|_| led_frame).await
     }
 }
@@ -0,0 +1,145 @@
+//! Live sessions streamed by the relay.
+//!
+//! The relay runs on the host of the race URL and streams over UDP, see
+//! `f1_logic::stream`. The frames go through a jitter buffer and on to the
+//! LED task, which plays them while no race is playing.
+
+use crate::state::SharedState;
+use crate::wifi::WifiStack;
+use embassy_futures::select::{select3, Either3};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::IpEndpoint;
+use embassy_time::{Duration, Instant, Timer};
+use esp_println::println;
+use f1_logic::download::Url;
+use f1_logic::stream::{
+    JitterBuffer, Message, Request, DEFAULT_DELAY_MS, KEEPALIVE_INTERVAL_MS, MAX_MESSAGE_SIZE,
+    STREAM_PORT,
+};
+
+/// Frames buffered, a bit more than the playback delay at 50 ms per frame
+const BUFFERED_FRAMES: usize = 64;
+
+/// Time between two checks for a frame to play
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[embassy_executor::task]
+pub async fn live_task(stack: &'static WifiStack, state: &'static SharedState) {
+    let mut next_session = None;
+    loop {
+        let session_key = match next_session.take() {
+            Some(session_key) => session_key,
+            None => match state.wait_live_request().await {
+                Some(session_key) => session_key,
+                None => continue,
+            },
+        };
+        println!("Following session {} live", session_key);
+        next_session = follow(stack, state, session_key).await;
+        state.set_live_status(None);
+    }
+}
+
+/// Play the session until the stream ends or the console stops it. Returns
+/// the session to follow next when the console asked for another one.
+async fn follow(stack: &'static WifiStack, state: &SharedState, session_key: u32) -> Option<u32> {
+    let race_url = state.settings().race_url;
+    let Some(url) = Url::parse(&race_url) else {
+        println!("No race URL set, the relay runs on its host");
+        return None;
+    };
+    stack.wait_config_up().await;
+    let relay = match stack.dns_query(url.host, DnsQueryType::A).await {
+        Ok(addresses) if !addresses.is_empty() => IpEndpoint::new(addresses[0], STREAM_PORT),
+        result => {
+            println!("Failed to look up {}: {:?}", url.host, result.err());
+            return None;
+        }
+    };
+
+    let mut rx_meta = [PacketMetadata::EMPTY; 16];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 128];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(0).unwrap();
+
+    let mut buffer = JitterBuffer::<BUFFERED_FRAMES>::new(DEFAULT_DELAY_MS);
+    let mut next_keepalive = Instant::now();
+    let mut stalled = true;
+    let mut datagram = [0; MAX_MESSAGE_SIZE];
+    loop {
+        let now = Instant::now();
+        if now >= next_keepalive {
+            // Also subscribes again after the relay dropped the board
+            let sequence = buffer.next_sequence();
+            send(
+                &socket,
+                relay,
+                Request::Subscribe {
+                    session_key,
+                    sequence,
+                },
+            )
+            .await;
+            next_keepalive = now + Duration::from_millis(KEEPALIVE_INTERVAL_MS.into());
+        }
+
+        match select3(
+            socket.recv_from(&mut datagram),
+            Timer::after(POLL_INTERVAL),
+            state.wait_live_request(),
+        )
+        .await
+        {
+            Either3::First(Ok((len, from))) => {
+                if from == relay {
+                    if let Some(message) = Message::decode(&datagram[..len]) {
+                        buffer.receive(message, Instant::now().as_millis());
+                    }
+                }
+            }
+            Either3::First(Err(err)) => println!("Failed to receive: {:?}", err),
+            Either3::Second(()) => {}
+            Either3::Third(request) => {
+                send(&socket, relay, Request::Unsubscribe).await;
+                return request;
+            }
+        }
+
+        let now_ms = Instant::now().as_millis();
+        if let Some(frame) = buffer.poll(now_ms) {
+            state.show_live_frame(frame);
+        }
+        if buffer.take_resync() {
+            let sequence = buffer.next_sequence();
+            send(&socket, relay, Request::Resync { sequence }).await;
+        }
+        if buffer.is_finished() {
+            println!("Live session {} ended", session_key);
+            return None;
+        }
+        if buffer.is_stalled(now_ms) != stalled {
+            stalled = !stalled;
+            if stalled {
+                println!("No data from the relay at {}", relay);
+            }
+        }
+        state.set_live_status(Some((session_key, buffer.stats())));
+    }
+}
+
+async fn send(socket: &UdpSocket<'_>, relay: IpEndpoint, request: Request) {
+    let mut buf = [0; MAX_MESSAGE_SIZE];
+    let len = request.encode(&mut buf).unwrap();
+    if let Err(err) = socket.send_to(&buf[..len], relay).await {
+        println!("Failed to send to the relay: {:?}", err);
+    }
+}
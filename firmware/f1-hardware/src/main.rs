@@ -2,15 +2,37 @@
 #![no_main]
 #![feature(type_alias_impl_trait)]
 
+mod animation;
+mod console;
+#[cfg(feature = "wifi")]
+mod download;
 mod driver_info;
 mod hd108;
-use crate::driver_info::DRIVERS;
+#[cfg(feature = "wifi")]
+mod live;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "ota")]
+mod ota;
+#[cfg(feature = "wifi")]
+mod portal;
+mod race;
+mod selftest;
+mod settings;
+mod state;
+mod version;
+#[cfg(feature = "wifi")]
+mod web;
+#[cfg(feature = "wifi")]
+mod wifi;
+use crate::driver_info::{DriverInfo, DRIVERS};
 use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::channel::Receiver;
 use embassy_sync::channel::Sender;
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_hal_async::spi::SpiBus;
 use esp_backtrace as _;
 use esp_hal::analog::adc::AdcPin;
@@ -20,28 +42,50 @@ use esp_hal::{
     analog::adc::{Adc, AdcConfig, Attenuation},
     clock::ClockControl,
     dma::{Dma, DmaPriority},
-    gpio::{Event, GpioPin, Input, Io, Pull},
+    gpio::{Event, GpioPin, Input, Io, Level, Output, Pull},
     peripherals::Peripherals,
     prelude::*,
     spi::{master::Spi, SpiMode},
     system::SystemControl,
     timer::timg::TimerGroup,
+    usb_serial_jtag::UsbSerialJtag,
 };
 use esp_println::println;
-use f1_logic::data_frame::{DriverData, UpdateFrame, NUM_DRIVERS};
+use f1_logic::animation::StartupAnimation;
+use f1_logic::board::BoardRevision;
+use f1_logic::data_frame::{UpdateFrame, FRAME_INTERVAL_MS};
+use f1_logic::selftest::MAX_TEMPERATURE_C;
+use f1_logic::settings::TeammateColors;
+use f1_logic::status::{FirmwareState, PatternPlayer};
 use hd108::HD108;
-use heapless08::Vec;
 use panic_halt as _;
+use state::{Playback, SharedState};
 use static_cell::StaticCell;
 
 enum Message {
     ButtonPressed,
+    Play,
+    /// Stop the race and keep its position, `Play` continues from there
+    Pause,
+    Stop,
+    Seek {
+        frame: usize,
+    },
+    Brightness(u8),
+    TestLeds,
+    Animation(StartupAnimation),
 }
 
 static SIGNAL_CHANNEL: StaticCell<Channel<NoopRawMutex, Message, 1>> = StaticCell::new();
 
+static SHARED_STATE: StaticCell<SharedState> = StaticCell::new();
+
 type AdcCal = esp_hal::analog::adc::AdcCalLine<esp_hal::peripherals::ADC1>;
 
+/// The board has cooled down again below this temperature, see
+/// `MAX_TEMPERATURE_C`
+const COOLED_DOWN_TEMPERATURE_C: f32 = 55.0;
+
 #[embassy_executor::task]
 async fn button_task(
     mut button_pin: Input<'static, GpioPin<10>>,
@@ -55,10 +99,37 @@ async fn button_task(
     }
 }
 
+#[embassy_executor::task]
+async fn status_task(status_pin: Output<'static, GpioPin<3>>, state: &'static SharedState) {
+    let mut player =
+        PatternPlayer::new(status_pin, FirmwareState::Idle, Instant::now().as_millis()).unwrap();
+
+    loop {
+        let next_step = player.update(Instant::now().as_millis()).unwrap();
+
+        // Wait for the next step of the pattern, or switch pattern when the state changes
+        match select(
+            state.wait_status(),
+            Timer::at(Instant::from_millis(next_step)),
+        )
+        .await
+        {
+            Either::First(status) => {
+                println!("Status: {:?}", status);
+                player
+                    .set_state(status, Instant::now().as_millis())
+                    .unwrap();
+            }
+            Either::Second(_) => {}
+        }
+    }
+}
+
 #[embassy_executor::task]
 async fn temperature_task(
     mut adc1: Adc<'static, esp_hal::peripherals::ADC1>,
     mut adc1_pin: AdcPin<GpioPin<1>, esp_hal::peripherals::ADC1, AdcCal>,
+    state: &'static SharedState,
 ) {
     loop {
         // Non-blocking read of ADC value
@@ -86,6 +157,13 @@ async fn temperature_task(
             let temperature_c = convert_voltage_to_temperature(pin_mv);
             // Print temperature
             println!("Temperature: {:.2} °C", temperature_c);
+            state.set_temperature_c(temperature_c);
+
+            if temperature_c > MAX_TEMPERATURE_C {
+                state.set_overheated(true);
+            } else if temperature_c < COOLED_DOWN_TEMPERATURE_C {
+                state.set_overheated(false);
+            }
         }
 
         // Wait for 1 second before the next reading
@@ -97,123 +175,245 @@ async fn temperature_task(
 async fn led_task(
     mut hd108: HD108<impl SpiBus<u8> + 'static>,
     receiver: Receiver<'static, NoopRawMutex, Message, 1>,
+    state: &'static SharedState,
+    self_test: bool,
 ) {
-    // Define the brightness levels
-    let low_brightness = 10; // Low brightness for background LEDs
-
-    // Start the train animation immediately
-    let high_brightness = 255;
-    let led_count = 97;
-    let train_length = 15;
-    let colors = [
-        (high_brightness, 0, 0),
-        (high_brightness, 0, 0),
-        (high_brightness, 0, 0),
-        (high_brightness, 0, 0),
-        (high_brightness, 0, 0),
-        (0, 0, high_brightness),
-        (0, 0, high_brightness),
-        (0, 0, high_brightness),
-        (0, 0, high_brightness),
-        (0, 0, high_brightness),
-        (0, high_brightness, 0),
-        (0, high_brightness, 0),
-        (0, high_brightness, 0),
-        (0, high_brightness, 0),
-        (0, high_brightness, 0),
-    ];
-
-    let mut iteration_count = 0;
-
-    while iteration_count < 10 {
-        for i in 0..led_count {
-            let mut led_updates: heapless08::Vec<(usize, u8, u8, u8), 97> = heapless08::Vec::new();
-
-            // Set all LEDs to low brightness
-            for j in 0..led_count {
-                led_updates
-                    .push((j, low_brightness, low_brightness, low_brightness))
-                    .unwrap();
-            }
-
-            // Update the train LEDs with high brightness colors
-            for j in 0..train_length {
-                let pos = (i + j) % led_count;
-                let color = colors[j];
-                led_updates[pos] = (pos, color.0, color.1, color.2);
-            }
-
-            hd108.set_leds(&led_updates).await.unwrap();
-            Timer::after(Duration::from_millis(10)).await;
-        }
-        iteration_count += 1;
+    if self_test {
+        // Diagnostic mode, the button was held down during boot
+        selftest::run_self_test(&mut hd108, &receiver, state).await;
     }
 
+    let startup_animation = state.startup_animation();
+    animation::play_animation(&mut hd108, startup_animation, state.brightness()).await;
     println!("Startup animation complete...");
 
-    // Set all leds off
-    hd108.set_off().await.unwrap();
-
+    // Position of the paused race
+    let mut paused_at: Option<usize> = None;
     loop {
+        // A download in progress holds the race
+        let frame_count = state.race().try_lock().map_or(0, |race| race.frame_count());
+        state.set_status(match paused_at {
+            Some(_) => FirmwareState::Paused,
+            None => state.idle_status(),
+        });
+        state.set_playback(Playback {
+            playing: false,
+            frame: paused_at.unwrap_or(0),
+            frame_count,
+        });
+
         // Wait for the start message
-        receiver.receive().await;
+        #[cfg(not(feature = "wifi"))]
+        let Idle::Message(message) = wait_idle(&receiver, state).await;
+        #[cfg(feature = "wifi")]
+        let message = match wait_idle(&receiver, state).await {
+            Idle::Message(message) => message,
+            Idle::LiveFrame(frame) => {
+                play_live(&mut hd108, &receiver, state, frame).await;
+                continue;
+            }
+        };
+        let mut frame_index = match message {
+            Message::ButtonPressed | Message::Play => paused_at.unwrap_or(0),
+            Message::Seek { frame } => frame,
+            Message::Pause => continue,
+            Message::Brightness(brightness) => {
+                state.set_brightness(brightness);
+                continue;
+            }
+            Message::TestLeds => {
+                selftest::run_self_test(&mut hd108, &receiver, state).await;
+                continue;
+            }
+            Message::Animation(startup_animation) => {
+                state.set_startup_animation(startup_animation);
+                animation::play_animation(&mut hd108, startup_animation, state.brightness()).await;
+                continue;
+            }
+            Message::Stop => {
+                if paused_at.take().is_some() {
+                    hd108.set_off().await.unwrap();
+                }
+                continue;
+            }
+        };
+        paused_at = None;
 
+        if frame_count == 0 {
+            println!("No race to play");
+            continue;
+        }
         println!("Starting race...");
+        state.set_status(FirmwareState::Racing);
 
-        // Start deserialization in chunks
-        let data_bin = include_bytes!("output.bin");
-        let mut remaining_data = &data_bin[..];
-
-        while !remaining_data.is_empty() {
-            // Attempt to deserialize a single frame from the data using `try_from_bytes`
-            match UpdateFrame::try_from_bytes(remaining_data) {
-                Ok(frame) => {
-                    // Move the remaining_data pointer forward by the size of the serialized frame
-                    let frame_size = UpdateFrame::SERIALIZED_SIZE;
-                    remaining_data = &remaining_data[frame_size..];
-
-                    // Prepare LED updates
-                    let mut led_updates: heapless08::Vec<(usize, u8, u8, u8), 20> =
-                        heapless08::Vec::new();
-                    for driver_data in &frame.frame {
-                        if let Some(driver) = DRIVERS
-                            .iter()
-                            .find(|d| d.number == driver_data.driver_number as u32)
-                        {
-                            led_updates
-                                .push((
-                                    driver_data.led_num as usize,
-                                    driver.color.0,
-                                    driver.color.1,
-                                    driver.color.2,
-                                ))
-                                .unwrap();
-                        }
-                    }
-
-                    // Set the LEDs for this frame
-                    if let Err(err) = hd108.set_leds(&led_updates).await {
-                        println!("Failed to set LEDs: {:?}", err);
-                    }
+        // Deserialize one frame at a time
+        while frame_index < frame_count {
+            let Ok(mut race) = state.race().try_lock() else {
+                println!("Race download started, stopping the race");
+                break;
+            };
+            let frame = race.frame(frame_index);
+            drop(race);
+
+            match frame {
+                Some(frame) => {
+                    show_frame(&mut hd108, &frame, state).await;
+
+                    frame_index += 1;
+                    state.set_playback(Playback {
+                        playing: true,
+                        frame: frame_index,
+                        frame_count,
+                    });
 
                     // Wait for the next frame update
-                    Timer::after(Duration::from_millis(50)).await;
+                    Timer::after(Duration::from_millis(FRAME_INTERVAL_MS)).await;
                 }
-                Err(_) => {
+                None => {
                     println!("Failed to deserialize frame");
+                    state.set_status(FirmwareState::Error);
+                    // Keep the error pattern visible for a while
+                    Timer::after(Duration::from_secs(5)).await;
                     break;
                 }
             }
 
-            // Check if a stop message was received
-            if receiver.try_receive().is_ok() {
-                hd108.set_off().await.unwrap();
-                break;
+            // Handle messages received during the race
+            match receiver.try_receive() {
+                Ok(Message::ButtonPressed | Message::Stop) => break,
+                Ok(Message::Pause) => {
+                    // Keep the current frame on the LEDs
+                    paused_at = Some(frame_index);
+                    break;
+                }
+                Ok(Message::Seek { frame }) => frame_index = frame,
+                Ok(Message::Brightness(brightness)) => state.set_brightness(brightness),
+                Ok(Message::Animation(startup_animation)) => {
+                    state.set_startup_animation(startup_animation)
+                }
+                Ok(Message::Play | Message::TestLeds) | Err(_) => {}
             }
         }
 
         // Ensure LEDs are turned off at the end
-        hd108.set_off().await.unwrap();
+        if paused_at.is_none() {
+            hd108.set_off().await.unwrap();
+        }
+    }
+}
+
+/// What the LED task waits for while no race is playing
+enum Idle {
+    Message(Message),
+    /// A live session started, see `live.rs`
+    #[cfg(feature = "wifi")]
+    LiveFrame(UpdateFrame),
+}
+
+async fn wait_idle(
+    receiver: &Receiver<'static, NoopRawMutex, Message, 1>,
+    state: &SharedState,
+) -> Idle {
+    #[cfg(feature = "wifi")]
+    return match select(receiver.receive(), state.wait_live_frame()).await {
+        Either::First(message) => Idle::Message(message),
+        Either::Second(frame) => Idle::LiveFrame(frame),
+    };
+    #[cfg(not(feature = "wifi"))]
+    {
+        let _ = state;
+        Idle::Message(receiver.receive().await)
+    }
+}
+
+/// Play the frames of a live session until it stops sending them or the
+/// race is stopped
+#[cfg(feature = "wifi")]
+async fn play_live(
+    hd108: &mut HD108<impl SpiBus<u8>>,
+    receiver: &Receiver<'static, NoopRawMutex, Message, 1>,
+    state: &SharedState,
+    first_frame: UpdateFrame,
+) {
+    use embassy_time::with_timeout;
+    use f1_logic::stream::STREAM_TIMEOUT_MS;
+
+    println!("Playing the live session...");
+    state.set_status(FirmwareState::Racing);
+    let mut frame = first_frame;
+    loop {
+        show_frame(hd108, &frame, state).await;
+        let next_frame = with_timeout(
+            Duration::from_millis(STREAM_TIMEOUT_MS.into()),
+            state.wait_live_frame(),
+        );
+        match select(receiver.receive(), next_frame).await {
+            Either::First(Message::ButtonPressed | Message::Stop) => {
+                state.request_live(None);
+                break;
+            }
+            Either::First(Message::Brightness(brightness)) => state.set_brightness(brightness),
+            Either::First(Message::Animation(startup_animation)) => {
+                state.set_startup_animation(startup_animation)
+            }
+            Either::First(
+                Message::Play | Message::Pause | Message::Seek { .. } | Message::TestLeds,
+            ) => {}
+            Either::Second(Ok(next)) => frame = next,
+            Either::Second(Err(_)) => break,
+        }
+    }
+    hd108.set_off().await.unwrap();
+}
+
+/// Light the LED of every driver in `frame`
+async fn show_frame(hd108: &mut HD108<impl SpiBus<u8>>, frame: &UpdateFrame, state: &SharedState) {
+    let brightness = state.brightness();
+    let teammate_colors = state.teammate_colors();
+
+    // Prepare LED updates
+    let mut led_updates: heapless08::Vec<(usize, u8, u8, u8), 20> = heapless08::Vec::new();
+    for driver_data in &frame.frame {
+        if let Some(driver) = DRIVERS
+            .iter()
+            .find(|d| d.number == driver_data.driver_number as u32)
+        {
+            let color = driver_color(driver, teammate_colors);
+            led_updates
+                .push((
+                    driver_data.led_num as usize,
+                    scale(color.0, brightness),
+                    scale(color.1, brightness),
+                    scale(color.2, brightness),
+                ))
+                .unwrap();
+        }
+    }
+
+    // Set the LEDs for this frame
+    if let Err(err) = hd108.set_leds(&led_updates).await {
+        println!("Failed to set LEDs: {:?}", err);
+    }
+}
+
+/// Scale a color channel by the brightness (0-255)
+fn scale(channel: u8, brightness: u8) -> u8 {
+    (channel as u16 * brightness as u16 / 255) as u8
+}
+
+/// Team color of `driver`, dimmed for the second driver of a team when requested
+fn driver_color(driver: &DriverInfo, teammate_colors: TeammateColors) -> (u8, u8, u8) {
+    let first_of_team = DRIVERS
+        .iter()
+        .find(|d| d.team == driver.team)
+        .map_or(true, |d| d.number == driver.number);
+    match teammate_colors {
+        TeammateColors::Dimmed if !first_of_team => (
+            scale(driver.color.0, 96),
+            scale(driver.color.1, 96),
+            scale(driver.color.2, 96),
+        ),
+        _ => driver.color,
     }
 }
 
@@ -227,12 +427,40 @@ fn convert_voltage_to_temperature(pin_mv: u16) -> f32 {
     temperature_c
 }
 
+fn read_board_revision(
+    adc1: &mut Adc<'static, esp_hal::peripherals::ADC1>,
+    board_id_pin: &mut AdcPin<GpioPin<4>, esp_hal::peripherals::ADC1, AdcCal>,
+) -> BoardRevision {
+    const SAMPLES: usize = 8;
+
+    // Boards without a strap leave the pin floating, hold it low with the
+    // internal pull-down. Switching the pin to analog turned it off.
+    let io_mux = unsafe { &*esp_hal::peripherals::IO_MUX::PTR };
+    io_mux.gpio(4).modify(|_, w| w.fun_wpd().set_bit());
+
+    // Several readings to filter out noise and catch a wandering pin
+    let mut samples_mv = [0; SAMPLES];
+    for sample_mv in samples_mv.iter_mut() {
+        *sample_mv = nb::block!(adc1.read_oneshot(board_id_pin)).unwrap();
+    }
+
+    io_mux.gpio(4).modify(|_, w| w.fun_wpd().clear_bit());
+    BoardRevision::from_strap_samples(&samples_mv)
+}
+
 #[main]
 async fn main(spawner: Spawner) {
     println!("Starting program!...");
+    println!("Firmware {}", version::version());
+    #[cfg(feature = "ota")]
+    let boot = ota::check_boot();
 
     let peripherals = Peripherals::take();
     let system = SystemControl::new(peripherals.SYSTEM);
+    // Wi-Fi needs the maximum CPU clock
+    #[cfg(feature = "wifi")]
+    let clocks = ClockControl::max(system.clock_control).freeze();
+    #[cfg(not(feature = "wifi"))]
     let clocks = ClockControl::boot_defaults(system.clock_control).freeze();
 
     let timg0 = TimerGroup::new_async(peripherals.TIMG0, &clocks);
@@ -241,6 +469,7 @@ async fn main(spawner: Spawner) {
     let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
 
     let analog_pin = io.pins.gpio1;
+    let board_id_pin = io.pins.gpio4;
     let sclk = io.pins.gpio6;
     let miso = io.pins.gpio8;
     let mosi = io.pins.gpio7;
@@ -249,7 +478,20 @@ async fn main(spawner: Spawner) {
     let mut adc1_config = AdcConfig::new();
     let adc1_pin =
         adc1_config.enable_pin_with_cal::<_, AdcCal>(analog_pin, Attenuation::Attenuation11dB);
-    let adc1 = Adc::new(peripherals.ADC1, adc1_config);
+    let mut board_id_adc_pin =
+        adc1_config.enable_pin_with_cal::<_, AdcCal>(board_id_pin, Attenuation::Attenuation11dB);
+    let mut adc1 = Adc::new(peripherals.ADC1, adc1_config);
+
+    // Detect the PCB revision from the board ID strap
+    let board_revision = read_board_revision(&mut adc1, &mut board_id_adc_pin);
+    let board = board_revision.config();
+    println!(
+        "Board: {} ({:?}), {} LEDs, pins {:?}",
+        board.name, board_revision, board.led_count, board.pins
+    );
+    if let BoardRevision::Unknown(_) = board_revision {
+        println!("Unknown board revision, falling back to {}", board.name);
+    }
 
     let dma = Dma::new(peripherals.DMA);
 
@@ -270,15 +512,26 @@ async fn main(spawner: Spawner) {
             DmaPriority::Priority0,
         ));
 
-    let hd108 = HD108::new(spi);
+    let hd108 = HD108::new(spi, board.led_count);
 
     // Initialize the button pin as input with interrupt and pull-up resistor
     let mut button_pin = Input::new(io.pins.gpio10, Pull::Up);
 
+    // Holding the button during boot starts the LED self test
+    let self_test = button_pin.is_low();
+
     // Enable interrupts for the button pin
     button_pin.listen(Event::FallingEdge);
 
     let signal_channel = SIGNAL_CHANNEL.init(Channel::new());
+    let (settings_store, settings) = settings::load();
+    let shared_state = SHARED_STATE.init(SharedState::new(settings, race::Race::load()));
+
+    // Command console on the USB serial port
+    let (_console_tx, console_rx) = UsbSerialJtag::new_async(peripherals.USB_DEVICE).split();
+
+    // Dedicated status indicator LED
+    let status_pin = Output::new(io.pins.gpio3, Level::Low);
 
     // Spawn the button task with ownership of the button pin and the sender
     spawner
@@ -287,9 +540,80 @@ async fn main(spawner: Spawner) {
 
     // Spawn the led task with the receiver
     spawner
-        .spawn(led_task(hd108, signal_channel.receiver()))
+        .spawn(led_task(
+            hd108,
+            signal_channel.receiver(),
+            shared_state,
+            self_test,
+        ))
+        .unwrap();
+
+    // Spawn the status indicator task
+    spawner
+        .spawn(status_task(status_pin, shared_state))
         .unwrap();
 
     // Spawn the temperature task
-    spawner.spawn(temperature_task(adc1, adc1_pin)).unwrap();
+    spawner
+        .spawn(temperature_task(adc1, adc1_pin, shared_state))
+        .unwrap();
+
+    // Spawn the console task
+    spawner
+        .spawn(console::console_task(
+            console_rx,
+            signal_channel.sender(),
+            shared_state,
+        ))
+        .unwrap();
+
+    if let Some(store) = settings_store {
+        spawner
+            .spawn(settings::settings_task(store, shared_state))
+            .unwrap();
+    }
+
+    #[cfg(feature = "wifi")]
+    {
+        let mut rng = esp_hal::rng::Rng::new(peripherals.RNG);
+        let seed = (u64::from(rng.random()) << 32) | u64::from(rng.random());
+        let timer = esp_hal::timer::systimer::SystemTimer::new(peripherals.SYSTIMER).alarm0;
+        let started = esp_wifi::initialize(
+            esp_wifi::EspWifiInitFor::Wifi,
+            timer,
+            rng,
+            peripherals.RADIO_CLK,
+            &clocks,
+        )
+        .map_err(|e| println!("Failed to initialize Wi-Fi: {:?}", e))
+        .and_then(|init| {
+            wifi::start(&spawner, &init, peripherals.WIFI, seed, shared_state)
+                .map_err(|e| println!("Failed to start Wi-Fi: {:?}", e))
+        });
+        match started {
+            Ok(Some(stack)) => {
+                spawner
+                    .spawn(download::download_task(stack, shared_state))
+                    .unwrap();
+                spawner.spawn(live::live_task(stack, shared_state)).unwrap();
+                spawner
+                    .spawn(web::web_task(stack, signal_channel.sender(), shared_state))
+                    .unwrap();
+                #[cfg(feature = "mqtt")]
+                spawner
+                    .spawn(mqtt::mqtt_task(
+                        stack,
+                        signal_channel.sender(),
+                        shared_state,
+                    ))
+                    .unwrap();
+                #[cfg(feature = "ota")]
+                spawner
+                    .spawn(ota::ota_task(stack, shared_state, boot))
+                    .unwrap();
+            }
+            Ok(None) => {}
+            Err(()) => println!("Continuing offline"),
+        }
+    }
 }
@@ -0,0 +1,122 @@
+//! MQTT client for home automation.
+//!
+//! Publishes the board state to the broker in the settings and carries out
+//! the commands published to the board, like the web page does. The topics
+//! and the Home Assistant discovery live in `f1_logic::mqtt`.
+
+use crate::download::{self, ConnectionError};
+use crate::state::SharedState;
+use crate::version::version;
+use crate::web;
+use crate::wifi::WifiStack;
+use crate::Message;
+use core::fmt;
+use embassy_futures::select::{select, Either};
+use embassy_net::tcp;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Sender;
+use embassy_time::{Duration, Instant, Timer};
+use esp_hal::efuse::Efuse;
+use esp_println::println;
+use f1_logic::download::Url;
+use f1_logic::mqtt::{self, MqttError, Node, Packet, Receiver, STATE_INTERVAL_MS};
+use f1_logic::web::JSON_SIZE;
+use heapless08::String;
+
+/// Wait before connecting again after the connection failed
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+enum Error {
+    Connection(ConnectionError),
+    Mqtt(MqttError<tcp::Error>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Connection(err) => write!(f, "{}", err),
+            Error::Mqtt(err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn mqtt_task(
+    stack: &'static WifiStack,
+    sender: Sender<'static, NoopRawMutex, Message, 1>,
+    state: &'static SharedState,
+) {
+    let node = Node::new(Efuse::get_mac_address());
+    loop {
+        let mqtt_url = state.settings().mqtt_url;
+        let Some(broker) = mqtt::parse_broker(&mqtt_url) else {
+            state.wait_mqtt_changed().await;
+            continue;
+        };
+        println!(
+            "Connecting to the MQTT broker {} as {}",
+            broker.host,
+            node.id()
+        );
+        let session = run(stack, &sender, state, &node, &broker);
+        // A new broker ends the session
+        if let Either::First(Err(err)) = select(session, state.wait_mqtt_changed()).await {
+            println!("MQTT connection failed: {}", err);
+            select(Timer::after(RETRY_DELAY), state.wait_mqtt_changed()).await;
+        }
+    }
+}
+
+/// Publish the state and carry out commands until the connection fails
+async fn run(
+    stack: &'static WifiStack,
+    sender: &Sender<'static, NoopRawMutex, Message, 1>,
+    state: &SharedState,
+    node: &Node,
+    broker: &Url<'_>,
+) -> Result<(), Error> {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = download::connect(stack, broker, &mut rx_buffer, &mut tx_buffer)
+        .await
+        .map_err(Error::Connection)?;
+    let (mut reader, mut writer) = socket.split();
+    let mut receiver = Receiver::new();
+    mqtt::connect(&mut reader, &mut writer, &mut receiver, node)
+        .await
+        .map_err(Error::Mqtt)?;
+    mqtt::announce(&mut writer, node, version())
+        .await
+        .map_err(Error::Mqtt)?;
+    println!("MQTT connected");
+
+    let state_topic = node.state_topic();
+    let mut next_state = Instant::now();
+    loop {
+        match select(receiver.receive(&mut reader), Timer::at(next_state)).await {
+            Either::First(Ok(Packet::Publish { topic, payload, .. })) => {
+                if let Some(action) = node.command(topic, payload) {
+                    web::perform(action, sender, state).await;
+                    // Show the result right away
+                    next_state = Instant::now();
+                }
+            }
+            Either::First(Ok(_)) => {}
+            Either::First(Err(err)) => return Err(Error::Mqtt(err)),
+            Either::Second(()) => {
+                let mut json: String<JSON_SIZE> = String::new();
+                // The status fits, see JSON_SIZE
+                let _ = f1_logic::web::write_status(&mut json, &web::status(state));
+                let publish = Packet::Publish {
+                    topic: &state_topic,
+                    payload: json.as_bytes(),
+                    retain: false,
+                };
+                mqtt::send(&mut writer, &publish)
+                    .await
+                    .map_err(Error::Mqtt)?;
+                next_state = Instant::now() + Duration::from_millis(STATE_INTERVAL_MS);
+            }
+        }
+    }
+}
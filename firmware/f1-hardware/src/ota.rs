@@ -0,0 +1,184 @@
+//! Firmware updates over the air.
+//!
+//! The console asks for an update with the URL of an update file, see
+//! `f1_logic::ota`. It is downloaded into the app partition that is not
+//! running, and the board restarts into it. The new firmware starts in
+//! trial and marks itself valid once the network is up. When it restarts
+//! before, or doesn't get there in time, the previous firmware starts again.
+//! Update files are made with `espflash save-image` and the `ota_image` tool
+//! of f1-logic. The `otadata` and `ota_<n>` partitions are the ones of the
+//! flashed partition table, see `partitions-ota.csv`.
+
+use crate::download::{self, ConnectionError};
+use crate::state::SharedState;
+use crate::version::version;
+use crate::wifi::WifiStack;
+use core::fmt;
+use core::ops::Range;
+use embassy_net::tcp;
+use embassy_time::{with_timeout, Duration, Timer};
+use esp_println::println;
+use esp_storage::{FlashStorage, FlashStorageError};
+use f1_logic::download::{DownloadError, Url};
+use f1_logic::ota::{Boot, ImageHeader, OtaData, OtaError, OtaWriter};
+use f1_logic::partition_table::{PartitionError, PartitionTable, MAX_OTA_APPS};
+use heapless08::Vec;
+
+/// Time an update has to get the network up before it is rolled back
+const TRIAL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+enum Error {
+    InvalidUrl,
+    Partition(PartitionError<FlashStorageError>),
+    Connection(ConnectionError),
+    Flash(OtaError<FlashStorageError>),
+    Download(DownloadError<tcp::Error, OtaError<FlashStorageError>>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidUrl => write!(f, "invalid URL"),
+            Error::Partition(err) => write!(f, "no OTA partitions: {:?}", err),
+            Error::Connection(err) => write!(f, "{}", err),
+            Error::Flash(err) => write!(f, "{:?}", err),
+            Error::Download(err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
+/// The `otadata` partition and the `ota_<n>` app partitions it selects
+struct Partitions {
+    ota_data: Range<u32>,
+    apps: Vec<Range<u32>, MAX_OTA_APPS>,
+}
+
+impl Partitions {
+    fn read() -> Result<Self, Error> {
+        let table = PartitionTable::read(&mut FlashStorage::new()).map_err(Error::Partition)?;
+        Ok(Self {
+            ota_data: table.ota_data().map_err(Error::Partition)?,
+            apps: table.ota_apps().map_err(Error::Partition)?,
+        })
+    }
+
+    fn otadata(&self) -> Result<OtaData<FlashStorage>, Error> {
+        OtaData::new(FlashStorage::new(), self.ota_data.clone(), self.apps.len())
+            .map_err(Error::Flash)
+    }
+}
+
+fn otadata() -> Result<OtaData<FlashStorage>, Error> {
+    Partitions::read()?.otadata()
+}
+
+fn flash_error(err: FlashStorageError) -> Error {
+    Error::Flash(err.into())
+}
+
+/// Start or end the trial of an update, called first thing at startup.
+/// Restarts into the previous firmware when the update restarted in trial.
+pub fn check_boot() -> Boot {
+    match otadata().and_then(|mut otadata| otadata.boot().map_err(flash_error)) {
+        Ok(Boot::RolledBack) => {
+            println!("The firmware update restarted in trial, rolling back");
+            esp_hal::reset::software_reset();
+            loop {
+                core::hint::spin_loop();
+            }
+        }
+        Ok(boot) => {
+            if boot == Boot::Trial {
+                println!("Firmware update in trial");
+            }
+            boot
+        }
+        Err(err) => {
+            println!("Failed to read the OTA data: {}", err);
+            Boot::Normal
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn ota_task(stack: &'static WifiStack, state: &'static SharedState, boot: Boot) {
+    if boot == Boot::Trial {
+        confirm(stack).await;
+    }
+
+    loop {
+        let url = state.wait_ota_request().await;
+        match update(stack, &url).await {
+            Ok(header) => {
+                println!(
+                    "Firmware {} ({}) installed, restarting",
+                    header.version, header.git_commit
+                );
+                // Let the message out
+                Timer::after(Duration::from_millis(100)).await;
+                esp_hal::reset::software_reset();
+            }
+            Err(err) => println!("Firmware update failed: {}", err),
+        }
+    }
+}
+
+/// Mark the update valid once the network is up, roll it back otherwise
+async fn confirm(stack: &'static WifiStack) {
+    let healthy = with_timeout(TRIAL_TIMEOUT, stack.wait_config_up())
+        .await
+        .is_ok();
+    let result = otadata().and_then(|mut otadata| {
+        if healthy {
+            otadata.mark_valid().map_err(flash_error)?;
+        } else {
+            otadata.abort().map_err(flash_error)?;
+        }
+        Ok(())
+    });
+    match (healthy, result) {
+        (true, Ok(())) => println!("Firmware update confirmed"),
+        (false, Ok(())) => {
+            println!("No network with the firmware update, rolling back");
+            esp_hal::reset::software_reset();
+        }
+        (_, Err(err)) => println!("Failed to write the OTA data: {}", err),
+    }
+}
+
+async fn update(stack: &'static WifiStack, url: &str) -> Result<ImageHeader, Error> {
+    let url = Url::parse(url).ok_or(Error::InvalidUrl)?;
+    let partitions = Partitions::read()?;
+    let mut otadata = partitions.otadata()?;
+    let slot = otadata.inactive_slot().map_err(flash_error)?;
+    let mut writer = OtaWriter::new(
+        FlashStorage::new(),
+        partitions.apps[slot].clone(),
+        *version(),
+    )
+    .map_err(Error::Flash)?;
+
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 512];
+    println!(
+        "Downloading the firmware from {}:{}{}",
+        url.host, url.port, url.path
+    );
+    let mut socket = download::connect(stack, &url, &mut rx_buffer, &mut tx_buffer)
+        .await
+        .map_err(Error::Connection)?;
+    let mut percent = None;
+    let result = f1_logic::download::download(&mut socket, &url, &mut writer, |progress| {
+        if percent != Some(progress.percent() / 10) {
+            percent = Some(progress.percent() / 10);
+            println!("Firmware download {}%", progress.percent());
+        }
+    })
+    .await
+    .map_err(Error::Download);
+    socket.close();
+
+    let header = result?;
+    otadata.activate(slot).map_err(flash_error)?;
+    Ok(header)
+}
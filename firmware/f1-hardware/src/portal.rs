@@ -0,0 +1,235 @@
+//! Provisioning access point with a captive portal.
+//!
+//! The board serves DHCP, DNS and the network settings page itself, the
+//! request handling lives in `f1_logic::provisioning` and
+//! `f1_logic::captive_portal`.
+
+use crate::state::SharedState;
+use core::fmt::Write as _;
+use embassy_executor::Spawner;
+use embassy_futures::select::select;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{
+    Config, IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4,
+};
+use embassy_time::{Duration, Timer};
+use embedded_io_async::Write;
+use esp_hal::peripherals::WIFI;
+use esp_println::println;
+use esp_wifi::wifi::{
+    AccessPointConfiguration, Configuration, WifiApDevice, WifiController, WifiDevice, WifiError,
+    WifiEvent, WifiState,
+};
+use esp_wifi::EspWifiInitialization;
+use f1_logic::captive_portal::{self, Leases, PORTAL_URL, SERVER_IP};
+use f1_logic::provisioning::{self, HttpError, AP_SSID};
+use heapless08::String;
+use static_cell::StaticCell;
+
+type ApStack = Stack<WifiDevice<'static, WifiApDevice>>;
+
+/// DHCP, DNS and HTTP sockets
+const SOCKET_COUNT: usize = 4;
+
+/// The portal gives up after this time when networks are stored, they may
+/// have been unreachable only for a while
+const PORTAL_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Longest wait for the settings task to save new credentials
+const SAVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub fn start(
+    spawner: &Spawner,
+    init: &EspWifiInitialization,
+    wifi: WIFI,
+    seed: u64,
+    state: &'static SharedState,
+) -> Result<(), WifiError> {
+    let (wifi_interface, controller) = esp_wifi::wifi::new_with_mode(init, wifi, WifiApDevice)?;
+
+    let server_ip = Ipv4Address::from_bytes(&SERVER_IP);
+    let config = Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(server_ip, 24),
+        gateway: Some(server_ip),
+        dns_servers: Default::default(),
+    });
+
+    static RESOURCES: StaticCell<StackResources<SOCKET_COUNT>> = StaticCell::new();
+    static STACK: StaticCell<ApStack> = StaticCell::new();
+    let stack = &*STACK.init(Stack::new(
+        wifi_interface,
+        config,
+        RESOURCES.init(StackResources::new()),
+        seed,
+    ));
+
+    spawner.spawn(ap_task(controller)).unwrap();
+    spawner.spawn(ap_net_task(stack)).unwrap();
+    spawner.spawn(dhcp_task(stack)).unwrap();
+    spawner.spawn(dns_task(stack)).unwrap();
+    spawner.spawn(portal_task(stack, state)).unwrap();
+    Ok(())
+}
+
+#[embassy_executor::task]
+async fn ap_task(mut controller: WifiController<'static>) {
+    loop {
+        if let WifiState::ApStarted = esp_wifi::wifi::get_wifi_state() {
+            // wait until the access point stops
+            controller.wait_for_event(WifiEvent::ApStop).await;
+            Timer::after(Duration::from_millis(5000)).await
+        }
+        let ap_config = Configuration::AccessPoint(AccessPointConfiguration {
+            ssid: AP_SSID.try_into().unwrap(),
+            ..Default::default()
+        });
+        let started = match controller.set_configuration(&ap_config) {
+            Ok(()) => controller.start().await,
+            Err(e) => Err(e),
+        };
+        match started {
+            Ok(()) => println!("Provisioning access point {} started", AP_SSID),
+            Err(e) => {
+                println!("Failed to start access point: {:?}", e);
+                Timer::after(Duration::from_millis(5000)).await
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn ap_net_task(stack: &'static ApStack) {
+    stack.run().await
+}
+
+#[embassy_executor::task]
+async fn dhcp_task(stack: &'static ApStack) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1536];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1536];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(67).unwrap();
+
+    let mut leases = Leases::default();
+    let mut request = [0; 576];
+    let mut reply = [0; 576];
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut request).await else {
+            continue;
+        };
+        if let Some(reply_len) =
+            captive_portal::dhcp_reply(&request[..len], &mut leases, &mut reply)
+        {
+            // The client has no address yet
+            let broadcast = IpEndpoint::new(IpAddress::v4(255, 255, 255, 255), 68);
+            if let Err(e) = socket.send_to(&reply[..reply_len], broadcast).await {
+                println!("Failed to send DHCP reply: {:?}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn dns_task(stack: &'static ApStack) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = UdpSocket::new(
+        stack,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    socket.bind(53).unwrap();
+
+    let mut query = [0; 512];
+    let mut reply = [0; 512];
+    loop {
+        let Ok((len, client)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        if let Some(reply_len) = captive_portal::dns_reply(&query[..len], &mut reply) {
+            if let Err(e) = socket.send_to(&reply[..reply_len], client).await {
+                println!("Failed to send DNS reply: {:?}", e);
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn portal_task(stack: &'static ApStack, state: &'static SharedState) {
+    if state.settings().networks.is_empty() {
+        run_portal(stack, state).await
+    }
+    select(run_portal(stack, state), Timer::after(PORTAL_TIMEOUT)).await;
+    println!("No credentials received, trying the stored networks again");
+    esp_hal::reset::software_reset();
+}
+
+/// Serve the provisioning page until credentials are submitted, then save
+/// them and restart
+async fn run_portal(stack: &'static ApStack, state: &'static SharedState) -> ! {
+    let mut rx_buffer = [0; 1536];
+    let mut tx_buffer = [0; 1536];
+    let mut request = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if let Err(e) = socket.accept(80).await {
+            println!("Accept error: {:?}", e);
+            continue;
+        }
+
+        // Read until the request is complete
+        let mut len = 0;
+        let result = loop {
+            match socket.read(&mut request[len..]).await {
+                Ok(0) | Err(_) => break None,
+                Ok(n) => {
+                    len += n;
+                    match provisioning::parse_request(&request[..len]) {
+                        Ok(request) => {
+                            break Some(provisioning::handle_request(&request, PORTAL_URL))
+                        }
+                        Err(HttpError::Incomplete) if len < request.len() => {}
+                        Err(_) => break None,
+                    }
+                }
+            }
+        };
+
+        if let Some((response, credentials)) = result {
+            let mut out: String<1024> = String::new();
+            if write!(out, "{}", response).is_ok() {
+                socket.write_all(out.as_bytes()).await.ok();
+                socket.flush().await.ok();
+            }
+
+            if let Some(credentials) = credentials {
+                println!("Received credentials for {}", credentials.ssid);
+                state.update_settings(|s| s.add_network(credentials));
+                socket.close();
+                // Let the response reach the client and the settings task
+                // write the flash
+                select(state.wait_settings_saved(), Timer::after(SAVE_TIMEOUT)).await;
+                Timer::after(Duration::from_secs(1)).await;
+                esp_hal::reset::software_reset();
+            }
+        }
+
+        socket.close();
+        Timer::after(Duration::from_millis(50)).await;
+        socket.abort();
+    }
+}
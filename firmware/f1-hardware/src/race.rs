@@ -0,0 +1,94 @@
+//! Race data for playback.
+//!
+//! Without Wi-Fi the race embedded in the firmware plays. With Wi-Fi the
+//! firmware is too small for a race, it plays the race downloaded into the
+//! race partition instead, see `download.rs` and `partitions-wifi.csv`, or
+//! `partitions-ota.csv` with firmware updates. The partition is looked up in
+//! the flashed partition table, a board flashed with `partitions.csv` has
+//! none and runs without a race.
+
+use f1_logic::data_frame::UpdateFrame;
+#[cfg(feature = "wifi")]
+use {
+    esp_println::println,
+    esp_storage::FlashStorage,
+    f1_logic::partition_table::PartitionTable,
+    f1_logic::race_store::{RaceStore, RACE_PARTITION_LABEL},
+};
+
+#[cfg(not(feature = "wifi"))]
+pub struct Race {
+    data: &'static [u8],
+}
+
+#[cfg(not(feature = "wifi"))]
+impl Race {
+    pub fn load() -> Self {
+        Self {
+            data: include_bytes!("output.bin"),
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.data.len() / UpdateFrame::SERIALIZED_SIZE
+    }
+
+    pub fn frame(&mut self, index: usize) -> Option<UpdateFrame> {
+        let offset = index * UpdateFrame::SERIALIZED_SIZE;
+        UpdateFrame::try_from_bytes(self.data.get(offset..)?).ok()
+    }
+}
+
+#[cfg(feature = "wifi")]
+pub type Store = RaceStore<FlashStorage>;
+
+#[cfg(feature = "wifi")]
+pub struct Race {
+    store: Option<Store>,
+}
+
+#[cfg(feature = "wifi")]
+impl Race {
+    /// Open the race partition. Without it the board runs without a race,
+    /// also when it shares flash with the firmware.
+    pub fn load() -> Self {
+        let mut flash = FlashStorage::new();
+        let partition = match PartitionTable::read(&mut flash)
+            .and_then(|table| table.data(RACE_PARTITION_LABEL))
+        {
+            Ok(partition) => partition,
+            Err(err) => {
+                println!("No usable race partition: {:?}", err);
+                println!("Flash the firmware with partitions-wifi.csv or partitions-ota.csv");
+                return Self { store: None };
+            }
+        };
+        match RaceStore::new(flash, partition) {
+            Ok(store) => {
+                if store.race().is_none() {
+                    println!("No race stored");
+                }
+                Self { store: Some(store) }
+            }
+            Err(err) => {
+                println!("Failed to open the race partition: {:?}", err);
+                Self { store: None }
+            }
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.store
+            .as_ref()
+            .and_then(|store| store.race())
+            .map_or(0, |race| race.frame_count())
+    }
+
+    pub fn frame(&mut self, index: usize) -> Option<UpdateFrame> {
+        self.store.as_mut()?.read_frame(index).ok()
+    }
+
+    pub fn store(&mut self) -> Option<&mut Store> {
+        self.store.as_mut()
+    }
+}
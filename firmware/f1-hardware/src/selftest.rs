@@ -0,0 +1,94 @@
+use crate::hd108::HD108;
+use crate::state::SharedState;
+use crate::Message;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Receiver;
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::spi::SpiBus;
+use esp_println::println;
+use f1_logic::selftest::{SelfTest, StepReport, TestStep, MAX_TEMPERATURE_C};
+use f1_logic::status::FirmwareState;
+
+/// Duration of a color step. Long enough for the temperature task to take a new reading.
+const COLOR_STEP_DURATION: Duration = Duration::from_millis(1500);
+
+/// Duration of a single LED in the index walk
+const INDEX_STEP_DURATION: Duration = Duration::from_millis(300);
+
+/// Run the LED self test. A stop or button press aborts the test.
+pub async fn run_self_test(
+    hd108: &mut HD108<impl SpiBus<u8>>,
+    receiver: &Receiver<'static, NoopRawMutex, Message, 1>,
+    state: &SharedState,
+) {
+    let led_count = hd108.led_count();
+    let test = SelfTest::new(led_count);
+    println!(
+        "Starting LED self test: {} steps on {} LEDs",
+        test.step_count(),
+        led_count
+    );
+    let temperature_before_c = state.temperature_c();
+    let mut failed = None;
+
+    for step in test {
+        let temperature_start_c = state.temperature_c();
+        let pwm = step.pwm(led_count);
+        let (r, g, b) = step.color().channels();
+        let (red, green, blue) = (r as u16 * pwm, g as u16 * pwm, b as u16 * pwm);
+
+        let (result, duration) = match step {
+            TestStep::Color { gain, .. } => (
+                hd108.set_all_with_gain(red, green, blue, gain).await,
+                COLOR_STEP_DURATION,
+            ),
+            TestStep::Index { led } => (
+                hd108
+                    .set_leds_with_gain(&[(led, red, green, blue)], step.gain())
+                    .await,
+                INDEX_STEP_DURATION,
+            ),
+        };
+        if let Err(err) = result {
+            println!("Failed to set LEDs: {:?}", err);
+            failed = Some(FirmwareState::Error);
+            break;
+        }
+
+        Timer::after(duration).await;
+
+        let report = StepReport {
+            step,
+            current_ma: step.estimated_current_ma(led_count),
+            temperature_start_c,
+            temperature_end_c: state.temperature_c(),
+        };
+        println!("{}", report);
+
+        if matches!(report.temperature_end_c, Some(t) if t > MAX_TEMPERATURE_C) {
+            println!("Self test aborted: board too hot");
+            failed = Some(FirmwareState::Overheated);
+            break;
+        }
+
+        if let Ok(Message::ButtonPressed | Message::Stop) = receiver.try_receive() {
+            println!("Self test aborted");
+            break;
+        }
+    }
+
+    hd108.set_off().await.unwrap();
+
+    if let (Some(before), Some(after)) = (temperature_before_c, state.temperature_c()) {
+        println!("Temperature rise during test: {:+.1} °C", after - before);
+    }
+
+    match failed {
+        Some(status) => {
+            state.set_status(status);
+            // Keep the failure pattern visible for a while
+            Timer::after(Duration::from_secs(5)).await;
+        }
+        None => println!("LED self test finished"),
+    }
+}
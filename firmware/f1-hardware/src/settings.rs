@@ -0,0 +1,51 @@
+use crate::state::SharedState;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Timer};
+use esp_println::println;
+use esp_storage::FlashStorage;
+use f1_logic::settings::{Settings, SettingsStore, SETTINGS_PARTITION};
+
+/// Changes are saved once the settings have not changed for this long,
+/// so a series of console commands causes a single flash write
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+
+pub type Store = SettingsStore<FlashStorage>;
+
+/// Open the settings partition and load the saved settings. Falls back to
+/// the defaults when the flash can't be read.
+pub fn load() -> (Option<Store>, Settings) {
+    let mut store = match SettingsStore::new(FlashStorage::new(), SETTINGS_PARTITION) {
+        Ok(store) => store,
+        Err(err) => {
+            println!("Failed to open settings: {:?}", err);
+            return (None, Settings::default());
+        }
+    };
+    match store.load() {
+        Ok(settings) => (Some(store), settings),
+        Err(err) => {
+            println!("Failed to load settings: {:?}", err);
+            (Some(store), Settings::default())
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn settings_task(mut store: Store, state: &'static SharedState) {
+    loop {
+        state.wait_settings_changed().await;
+
+        // Wait until the changes settle
+        while let Either::First(_) =
+            select(state.wait_settings_changed(), Timer::after(SAVE_DELAY)).await
+        {}
+
+        match store.save(&state.settings()) {
+            Ok(()) => {
+                println!("Settings saved");
+                state.settings_saved();
+            }
+            Err(err) => println!("Failed to save settings: {:?}", err),
+        }
+    }
+}
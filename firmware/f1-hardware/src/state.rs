@@ -0,0 +1,303 @@
+use crate::race::Race;
+use core::cell::{Cell, RefCell};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::mutex::Mutex as AsyncMutex;
+use embassy_sync::signal::Signal;
+use f1_logic::animation::StartupAnimation;
+use f1_logic::connection::ConnectionState;
+use f1_logic::settings::{Settings, TeammateColors};
+use f1_logic::status::FirmwareState;
+#[cfg(feature = "wifi")]
+use {
+    f1_logic::data_frame::UpdateFrame, f1_logic::race_store::Progress,
+    f1_logic::stream::StreamStats,
+};
+#[cfg(feature = "ota")]
+use {f1_logic::settings::MAX_URL_LEN, heapless08::String};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Playback {
+    pub playing: bool,
+    pub frame: usize,
+    pub frame_count: usize,
+}
+
+/// State shared between the tasks, for reporting status
+pub struct SharedState {
+    status: Mutex<NoopRawMutex, Cell<FirmwareState>>,
+    status_signal: Signal<NoopRawMutex, FirmwareState>,
+    overheated: Mutex<NoopRawMutex, Cell<bool>>,
+    temperature_c: Mutex<NoopRawMutex, Cell<Option<f32>>>,
+    playback: Mutex<NoopRawMutex, Cell<Playback>>,
+    settings: Mutex<NoopRawMutex, RefCell<Settings>>,
+    settings_signal: Signal<NoopRawMutex, ()>,
+    settings_saved: Signal<NoopRawMutex, ()>,
+    connection: Mutex<NoopRawMutex, Cell<Option<ConnectionState>>>,
+    race: AsyncMutex<NoopRawMutex, Race>,
+    #[cfg(feature = "wifi")]
+    download: Mutex<NoopRawMutex, Cell<Option<Progress>>>,
+    #[cfg(feature = "wifi")]
+    download_signal: Signal<NoopRawMutex, ()>,
+    #[cfg(feature = "wifi")]
+    live_request: Signal<NoopRawMutex, Option<u32>>,
+    #[cfg(feature = "wifi")]
+    live_frame: Signal<NoopRawMutex, UpdateFrame>,
+    #[cfg(feature = "wifi")]
+    live_status: Mutex<NoopRawMutex, Cell<Option<(u32, StreamStats)>>>,
+    #[cfg(feature = "ota")]
+    ota_request: Signal<NoopRawMutex, String<MAX_URL_LEN>>,
+    #[cfg(feature = "mqtt")]
+    mqtt_signal: Signal<NoopRawMutex, ()>,
+}
+
+impl SharedState {
+    pub fn new(settings: Settings, race: Race) -> Self {
+        Self {
+            status: Mutex::new(Cell::new(FirmwareState::Idle)),
+            status_signal: Signal::new(),
+            overheated: Mutex::new(Cell::new(false)),
+            temperature_c: Mutex::new(Cell::new(None)),
+            playback: Mutex::new(Cell::new(Playback {
+                playing: false,
+                frame: 0,
+                frame_count: 0,
+            })),
+            settings: Mutex::new(RefCell::new(settings)),
+            settings_signal: Signal::new(),
+            settings_saved: Signal::new(),
+            connection: Mutex::new(Cell::new(None)),
+            race: AsyncMutex::new(race),
+            #[cfg(feature = "wifi")]
+            download: Mutex::new(Cell::new(None)),
+            #[cfg(feature = "wifi")]
+            download_signal: Signal::new(),
+            #[cfg(feature = "wifi")]
+            live_request: Signal::new(),
+            #[cfg(feature = "wifi")]
+            live_frame: Signal::new(),
+            #[cfg(feature = "wifi")]
+            live_status: Mutex::new(Cell::new(None)),
+            #[cfg(feature = "ota")]
+            ota_request: Signal::new(),
+            #[cfg(feature = "mqtt")]
+            mqtt_signal: Signal::new(),
+        }
+    }
+
+    /// The firmware state, `Overheated` while the board is too hot
+    pub fn status(&self) -> FirmwareState {
+        if self.overheated() {
+            return FirmwareState::Overheated;
+        }
+        self.status.lock(|s| s.get())
+    }
+
+    /// Update the firmware state and notify the status indicator, which
+    /// keeps showing `Overheated` while the board is too hot
+    pub fn set_status(&self, status: FirmwareState) {
+        self.status.lock(|s| s.set(status));
+        if !self.overheated() {
+            self.status_signal.signal(status);
+        }
+    }
+
+    pub fn overheated(&self) -> bool {
+        self.overheated.lock(|o| o.get())
+    }
+
+    /// Show `Overheated` until the board has cooled down, then the state of
+    /// the playback and the connection at that time
+    pub fn set_overheated(&self, overheated: bool) {
+        if self.overheated.lock(|o| o.replace(overheated)) == overheated {
+            return;
+        }
+        if overheated {
+            self.status_signal.signal(FirmwareState::Overheated);
+        } else {
+            self.set_status(self.playback_status());
+        }
+    }
+
+    /// Wait for the next firmware state change
+    pub async fn wait_status(&self) -> FirmwareState {
+        self.status_signal.wait().await
+    }
+
+    pub fn temperature_c(&self) -> Option<f32> {
+        self.temperature_c.lock(|t| t.get())
+    }
+
+    pub fn set_temperature_c(&self, temperature_c: f32) {
+        self.temperature_c.lock(|t| t.set(Some(temperature_c)));
+    }
+
+    pub fn playback(&self) -> Playback {
+        self.playback.lock(|p| p.get())
+    }
+
+    pub fn set_playback(&self, playback: Playback) {
+        self.playback.lock(|p| p.set(playback));
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings.lock(|s| s.borrow().clone())
+    }
+
+    /// Change the settings and notify the settings task, which saves them
+    pub fn update_settings(&self, update: impl FnOnce(&mut Settings)) {
+        self.settings.lock(|s| update(&mut s.borrow_mut()));
+        self.settings_signal.signal(());
+    }
+
+    /// Wait for the next settings change
+    pub async fn wait_settings_changed(&self) {
+        self.settings_signal.wait().await
+    }
+
+    /// Called by the settings task after the settings were written to flash
+    pub fn settings_saved(&self) {
+        self.settings_saved.signal(());
+    }
+
+    /// Wait until the settings are written to flash
+    #[cfg(feature = "wifi")]
+    pub async fn wait_settings_saved(&self) {
+        self.settings_saved.wait().await
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.settings.lock(|s| s.borrow().brightness)
+    }
+
+    pub fn set_brightness(&self, brightness: u8) {
+        self.update_settings(|s| s.brightness = brightness);
+    }
+
+    pub fn startup_animation(&self) -> StartupAnimation {
+        self.settings.lock(|s| s.borrow().startup_animation)
+    }
+
+    pub fn set_startup_animation(&self, animation: StartupAnimation) {
+        self.update_settings(|s| s.startup_animation = animation);
+    }
+
+    pub fn teammate_colors(&self) -> TeammateColors {
+        self.settings.lock(|s| s.borrow().teammate_colors)
+    }
+
+    /// Wi-Fi connection state, `None` when the board runs offline
+    pub fn connection(&self) -> Option<ConnectionState> {
+        self.connection.lock(|c| c.get())
+    }
+
+    #[cfg(feature = "wifi")]
+    pub fn set_connection(&self, connection: ConnectionState) {
+        self.connection.lock(|c| c.set(Some(connection)));
+    }
+
+    /// The race to play. A download holds the lock until it completes.
+    pub fn race(&self) -> &AsyncMutex<NoopRawMutex, Race> {
+        &self.race
+    }
+
+    /// Progress of the race download, `None` when no download is running
+    #[cfg(feature = "wifi")]
+    pub fn download_progress(&self) -> Option<Progress> {
+        self.download.lock(|d| d.get())
+    }
+
+    #[cfg(feature = "wifi")]
+    pub fn set_download_progress(&self, progress: Option<Progress>) {
+        self.download.lock(|d| d.set(progress));
+    }
+
+    /// Ask the download task to download the race
+    #[cfg(feature = "wifi")]
+    pub fn request_download(&self) {
+        self.download_signal.signal(());
+    }
+
+    #[cfg(feature = "wifi")]
+    pub async fn wait_download_request(&self) {
+        self.download_signal.wait().await
+    }
+
+    /// Ask the live task to follow a session, or to stop with `None`
+    #[cfg(feature = "wifi")]
+    pub fn request_live(&self, session_key: Option<u32>) {
+        self.live_request.signal(session_key);
+    }
+
+    #[cfg(feature = "wifi")]
+    pub async fn wait_live_request(&self) -> Option<u32> {
+        self.live_request.wait().await
+    }
+
+    /// Hand a frame of the live session to the LED task
+    #[cfg(feature = "wifi")]
+    pub fn show_live_frame(&self, frame: UpdateFrame) {
+        self.live_frame.signal(frame);
+    }
+
+    #[cfg(feature = "wifi")]
+    pub async fn wait_live_frame(&self) -> UpdateFrame {
+        self.live_frame.wait().await
+    }
+
+    /// Session followed live and its statistics, `None` when not following
+    #[cfg(feature = "wifi")]
+    pub fn live_status(&self) -> Option<(u32, StreamStats)> {
+        self.live_status.lock(|l| l.get())
+    }
+
+    #[cfg(feature = "wifi")]
+    pub fn set_live_status(&self, status: Option<(u32, StreamStats)>) {
+        self.live_status.lock(|l| l.set(status));
+    }
+
+    /// Ask the OTA task to update the firmware from `url`
+    #[cfg(feature = "ota")]
+    pub fn request_ota(&self, url: String<MAX_URL_LEN>) {
+        self.ota_request.signal(url);
+    }
+
+    #[cfg(feature = "ota")]
+    pub async fn wait_ota_request(&self) -> String<MAX_URL_LEN> {
+        self.ota_request.wait().await
+    }
+
+    /// Tell the MQTT task the broker URL changed
+    #[cfg(feature = "mqtt")]
+    pub fn mqtt_changed(&self) {
+        self.mqtt_signal.signal(());
+    }
+
+    #[cfg(feature = "mqtt")]
+    pub async fn wait_mqtt_changed(&self) {
+        self.mqtt_signal.wait().await
+    }
+
+    /// Firmware state to show when no race is playing
+    pub fn idle_status(&self) -> FirmwareState {
+        self.connection()
+            .map_or(FirmwareState::Idle, |c| c.firmware_state())
+    }
+
+    /// Firmware state of the race playing, paused or followed live,
+    /// otherwise of the connection
+    fn playback_status(&self) -> FirmwareState {
+        let playback = self.playback();
+        #[cfg(feature = "wifi")]
+        let live = self.live_status().is_some();
+        #[cfg(not(feature = "wifi"))]
+        let live = false;
+        if playback.playing || live {
+            FirmwareState::Racing
+        } else if playback.frame > 0 {
+            FirmwareState::Paused
+        } else {
+            self.idle_status()
+        }
+    }
+}
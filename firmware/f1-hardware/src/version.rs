@@ -0,0 +1,14 @@
+use f1_logic::version::VersionInfo;
+
+static VERSION: VersionInfo = VersionInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_commit: env!("F1_GIT_COMMIT"),
+    build_timestamp: env!("F1_BUILD_TIMESTAMP"),
+    board: env!("F1_BOARD"),
+    features: env!("F1_FEATURES"),
+};
+
+/// Version and build metadata of the running firmware, filled in by build.rs
+pub fn version() -> &'static VersionInfo {
+    &VERSION
+}
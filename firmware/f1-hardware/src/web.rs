@@ -0,0 +1,122 @@
+//! Control page and JSON API on the station network.
+//!
+//! Routing and the JSON live in `f1_logic::web`, this task serves them and
+//! hands the commands to the LED task like the console does.
+
+use crate::state::SharedState;
+use crate::version::version;
+use crate::wifi::WifiStack;
+use crate::{Message, FRAME_INTERVAL_MS};
+use embassy_net::tcp::TcpSocket;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Sender;
+use embassy_time::{Duration, Timer};
+use embedded_io_async::Write;
+use esp_println::println;
+use f1_logic::download::Url;
+use f1_logic::provisioning::{self, HttpError};
+use f1_logic::web::{self, Action, Status};
+use heapless08::String;
+
+const HTTP_PORT: u16 = 80;
+
+#[embassy_executor::task]
+pub async fn web_task(
+    stack: &'static WifiStack,
+    sender: Sender<'static, NoopRawMutex, Message, 1>,
+    state: &'static SharedState,
+) {
+    let mut rx_buffer = [0; 1536];
+    let mut tx_buffer = [0; 1536];
+    let mut request = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if let Err(e) = socket.accept(HTTP_PORT).await {
+            println!("Accept error: {:?}", e);
+            continue;
+        }
+
+        // Read until the request is complete
+        let mut len = 0;
+        let result = loop {
+            match socket.read(&mut request[len..]).await {
+                Ok(0) | Err(_) => break None,
+                Ok(n) => {
+                    len += n;
+                    match provisioning::parse_request(&request[..len]) {
+                        Ok(request) => break Some(web::handle_request(&request, &status(state))),
+                        Err(HttpError::Incomplete) if len < request.len() => {}
+                        Err(_) => break None,
+                    }
+                }
+            }
+        };
+
+        if let Some((response, action)) = result {
+            if let Some(action) = action {
+                perform(action, &sender, state).await;
+            }
+            let mut head: String<256> = String::new();
+            if response.write_head(&mut head).is_ok() {
+                socket.write_all(head.as_bytes()).await.ok();
+                socket.write_all(response.body().as_bytes()).await.ok();
+                socket.flush().await.ok();
+            }
+        }
+
+        socket.close();
+        Timer::after(Duration::from_millis(50)).await;
+        socket.abort();
+    }
+}
+
+pub fn status(state: &SharedState) -> Status {
+    let playback = state.playback();
+    Status {
+        state: state.status(),
+        playing: playback.playing,
+        frame: playback.frame,
+        frame_count: playback.frame_count,
+        frame_interval_ms: FRAME_INTERVAL_MS as u32,
+        session_key: state.settings().race_session_key,
+        brightness: state.brightness(),
+        temperature_c: state.temperature_c(),
+        version: *version(),
+    }
+}
+
+/// Carry out `action`, also for MQTT commands
+pub async fn perform(
+    action: Action,
+    sender: &Sender<'static, NoopRawMutex, Message, 1>,
+    state: &SharedState,
+) {
+    match action {
+        Action::Play => sender.send(Message::Play).await,
+        Action::Pause => sender.send(Message::Pause).await,
+        Action::Stop => {
+            state.request_live(None);
+            sender.send(Message::Stop).await
+        }
+        Action::Seek { seconds } => {
+            let frame = seconds as usize * 1000 / FRAME_INTERVAL_MS as usize;
+            sender.send(Message::Seek { frame }).await
+        }
+        Action::SelectRace(session_key) => {
+            let race_url = state.settings().race_url;
+            let Some(url) = Url::parse(&race_url).and_then(|url| url.relay_race(session_key))
+            else {
+                println!("No race URL set, the relay runs on its host");
+                return;
+            };
+            state.update_settings(|s| {
+                s.race_session_key = session_key;
+                s.race_url = url;
+            });
+            state.request_download();
+        }
+        Action::Brightness(brightness) => sender.send(Message::Brightness(brightness)).await,
+    }
+}
@@ -0,0 +1,212 @@
+//! Wi-Fi station and network stack.
+//!
+//! The stored networks are tried in order by the [`ConnectionManager`].
+//! Without stored networks, after connecting failed repeatedly, or when
+//! requested over a restart, the provisioning portal starts instead.
+//! The race keeps playing from flash either way.
+
+use crate::portal;
+use crate::state::SharedState;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_net::{Config, Stack, StackResources};
+use embassy_time::{Duration, Timer};
+use esp_hal::macros::ram;
+use esp_hal::peripherals::WIFI;
+use esp_println::println;
+use esp_wifi::wifi::{
+    ClientConfiguration, Configuration, ScanConfig, WifiController, WifiDevice, WifiError,
+    WifiEvent, WifiStaDevice,
+};
+use esp_wifi::EspWifiInitialization;
+use f1_logic::connection::{Action, ConnectionManager, ConnectionState};
+use f1_logic::settings::{Network, MAX_NETWORKS};
+use f1_logic::status::FirmwareState;
+use heapless08::Vec;
+use static_cell::StaticCell;
+
+pub type WifiStack = Stack<WifiDevice<'static, WifiStaDevice>>;
+
+/// Sockets available to the network tasks
+const SOCKET_COUNT: usize = 7;
+
+/// How often the signal strength is measured while connected
+const RSSI_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Set before a restart to start provisioning although networks are stored.
+/// RTC fast memory keeps its contents over a software reset.
+#[ram(rtc_fast, uninitialized)]
+static mut PROVISIONING_REQUEST: u32 = 0;
+
+const PROVISIONING_MAGIC: u32 = 0x5052_4F56;
+
+fn take_provisioning_request() -> bool {
+    unsafe {
+        let requested = PROVISIONING_REQUEST == PROVISIONING_MAGIC;
+        PROVISIONING_REQUEST = 0;
+        requested
+    }
+}
+
+/// Restart and start the provisioning portal
+pub fn restart_into_provisioning() -> ! {
+    unsafe {
+        PROVISIONING_REQUEST = PROVISIONING_MAGIC;
+    }
+    esp_hal::reset::software_reset();
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// Start the station or the provisioning portal. Returns the network stack
+/// when running as a station.
+pub fn start(
+    spawner: &Spawner,
+    init: &EspWifiInitialization,
+    wifi: WIFI,
+    seed: u64,
+    state: &'static SharedState,
+) -> Result<Option<&'static WifiStack>, WifiError> {
+    let networks = state.settings().networks;
+    if networks.is_empty() || take_provisioning_request() {
+        portal::start(spawner, init, wifi, seed, state)?;
+        return Ok(None);
+    }
+
+    let (wifi_interface, controller) = esp_wifi::wifi::new_with_mode(init, wifi, WifiStaDevice)?;
+
+    static RESOURCES: StaticCell<StackResources<SOCKET_COUNT>> = StaticCell::new();
+    static STACK: StaticCell<WifiStack> = StaticCell::new();
+    let stack = &*STACK.init(Stack::new(
+        wifi_interface,
+        Config::dhcpv4(Default::default()),
+        RESOURCES.init(StackResources::new()),
+        seed,
+    ));
+
+    state.set_connection(ConnectionState::Disconnected);
+    spawner
+        .spawn(connection_task(controller, networks, state))
+        .unwrap();
+    spawner.spawn(net_task(stack)).unwrap();
+    Ok(Some(stack))
+}
+
+/// Publish the connection state and show it on the status indicator
+fn publish(state: &SharedState, connection: ConnectionState) {
+    state.set_connection(connection);
+    // A race or an error stays visible
+    if matches!(
+        state.status(),
+        FirmwareState::Idle | FirmwareState::Connecting
+    ) {
+        state.set_status(connection.firmware_state());
+    }
+}
+
+#[embassy_executor::task]
+async fn connection_task(
+    mut controller: WifiController<'static>,
+    networks: Vec<Network, MAX_NETWORKS>,
+    state: &'static SharedState,
+) {
+    let mut manager = ConnectionManager::new(networks.len());
+    let mut action = manager.start();
+
+    loop {
+        let (index, delay_ms) = match action {
+            Action::Connect { network, delay_ms } => (network, delay_ms),
+            Action::Provision => {
+                println!("Connecting failed repeatedly, starting provisioning");
+                restart_into_provisioning();
+            }
+        };
+        if delay_ms > 0 {
+            println!("Retrying in {} ms", delay_ms);
+            Timer::after(Duration::from_millis(delay_ms.into())).await;
+        }
+
+        let network = &networks[usize::from(index)];
+        publish(state, ConnectionState::Connecting { network: index });
+        println!("Connecting to {}...", network.ssid);
+        if let Err(e) = connect(&mut controller, network).await {
+            println!("Failed to connect to {}: {:?}", network.ssid, e);
+            publish(state, ConnectionState::Disconnected);
+            action = manager.failed();
+            continue;
+        }
+        println!("Wifi connected to {}", network.ssid);
+        manager.connected();
+
+        // Report the signal strength until the connection drops
+        // Lowest value until a scan finds the access point
+        let mut rssi = i8::MIN;
+        loop {
+            if let Some(signal) = signal_strength(&mut controller, &network.ssid).await {
+                rssi = signal;
+            }
+            publish(
+                state,
+                ConnectionState::Connected {
+                    network: index,
+                    rssi,
+                },
+            );
+
+            // Keep a disconnect that happened during the scan pending
+            let disconnected = controller.wait_for_events(WifiEvent::StaDisconnected.into(), false);
+            if let Either::First(_) = select(disconnected, Timer::after(RSSI_INTERVAL)).await {
+                break;
+            }
+        }
+
+        println!("Wifi disconnected from {}", network.ssid);
+        publish(state, ConnectionState::Disconnected);
+        action = manager.disconnected();
+    }
+}
+
+/// Configure the station for `network` and connect to it
+async fn connect(
+    controller: &mut WifiController<'static>,
+    network: &Network,
+) -> Result<(), WifiError> {
+    if matches!(controller.is_started(), Ok(true)) {
+        controller.stop().await?;
+    }
+    let client_config = Configuration::Client(ClientConfiguration {
+        ssid: network.ssid.clone(),
+        password: network.password.clone(),
+        auth_method: if network.password.is_empty() {
+            esp_wifi::wifi::AuthMethod::None
+        } else {
+            Default::default()
+        },
+        ..Default::default()
+    });
+    controller.set_configuration(&client_config)?;
+    controller.start().await?;
+    controller.connect().await
+}
+
+/// Signal strength of the access point in dBm, measured with a scan for
+/// its network name
+async fn signal_strength(controller: &mut WifiController<'static>, ssid: &str) -> Option<i8> {
+    let config = ScanConfig {
+        ssid: Some(ssid),
+        ..Default::default()
+    };
+    match controller.scan_with_config::<4>(config).await {
+        Ok((access_points, _)) => access_points.iter().map(|ap| ap.signal_strength).max(),
+        Err(e) => {
+            println!("Failed to measure signal strength: {:?}", e);
+            None
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(stack: &'static WifiStack) {
+    stack.run().await
+}
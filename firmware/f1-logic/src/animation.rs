@@ -0,0 +1,522 @@
+//! Startup animations.
+//!
+//! Effects render a single frame for a point in time into a framebuffer
+//! with one color per LED (index 0 is LED 1), so they can be previewed and
+//! tested without hardware. The LED count is the length of the framebuffer.
+
+use core::fmt;
+
+pub type Rgb = (u8, u8, u8);
+
+pub const OFF: Rgb = (0, 0, 0);
+
+pub trait Effect {
+    /// Duration of the effect in ms
+    fn duration_ms(&self) -> u32;
+
+    /// Render the effect at `t_ms` (0 up to the duration) into `frame`
+    fn render(&self, t_ms: u32, frame: &mut [Rgb]);
+}
+
+/// Scale a color by `level` (0-255)
+pub fn scale(color: Rgb, level: u8) -> Rgb {
+    let s = |c: u8| (c as u16 * level as u16 / 255) as u8;
+    (s(color.0), s(color.1), s(color.2))
+}
+
+/// Fully saturated color on the color wheel, `hue` 0-255
+pub fn color_wheel(hue: u8) -> Rgb {
+    let region = hue / 43;
+    let rise = (hue - region * 43) * 6;
+    let fall = 255 - rise;
+    match region {
+        0 => (255, rise, 0),
+        1 => (fall, 255, 0),
+        2 => (0, 255, rise),
+        3 => (0, fall, 255),
+        4 => (rise, 0, 255),
+        _ => (255, 0, fall),
+    }
+}
+
+// Position along a cyclic effect: how far `t_ms` is into `duration_ms`
+// when `count` items are passed during the effect
+fn progress(t_ms: u32, duration_ms: u32, count: usize) -> usize {
+    if duration_ms == 0 {
+        return 0;
+    }
+    (t_ms.min(duration_ms) as u64 * count as u64 / duration_ms as u64) as usize
+}
+
+/// A train of colors running along the track over a dim background
+#[derive(Debug, Clone, Copy)]
+pub struct Chase<'a> {
+    pub colors: &'a [Rgb],
+    pub background: Rgb,
+    pub laps: u32,
+    pub duration_ms: u32,
+}
+
+impl Effect for Chase<'_> {
+    fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    fn render(&self, t_ms: u32, frame: &mut [Rgb]) {
+        let led_count = frame.len();
+        if led_count == 0 {
+            return;
+        }
+        frame.fill(self.background);
+        let head = progress(t_ms, self.duration_ms, led_count * self.laps as usize);
+        for (j, color) in self.colors.iter().enumerate() {
+            frame[(head + j) % led_count] = *color;
+        }
+    }
+}
+
+/// A single bright LED with a tail that fades out
+#[derive(Debug, Clone, Copy)]
+pub struct Comet {
+    pub color: Rgb,
+    pub tail_length: usize,
+    pub laps: u32,
+    pub duration_ms: u32,
+}
+
+impl Effect for Comet {
+    fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    fn render(&self, t_ms: u32, frame: &mut [Rgb]) {
+        let led_count = frame.len();
+        if led_count == 0 {
+            return;
+        }
+        frame.fill(OFF);
+        let head = progress(t_ms, self.duration_ms, led_count * self.laps as usize) % led_count;
+        let tail_length = self.tail_length.min(led_count - 1);
+        for i in (0..=tail_length).rev() {
+            let level = 255 - (i * 255 / (tail_length + 1)) as u8;
+            frame[(head + led_count - i) % led_count] = scale(self.color, level);
+        }
+    }
+}
+
+/// The color wheel spread over the track, rotating `cycles` times
+#[derive(Debug, Clone, Copy)]
+pub struct Rainbow {
+    pub cycles: u32,
+    pub duration_ms: u32,
+}
+
+impl Effect for Rainbow {
+    fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    fn render(&self, t_ms: u32, frame: &mut [Rgb]) {
+        let led_count = frame.len();
+        let offset = progress(t_ms, self.duration_ms, 256 * self.cycles as usize);
+        for (i, led) in frame.iter_mut().enumerate() {
+            let hue = (i * 256 / led_count + offset) % 256;
+            *led = color_wheel(hue as u8);
+        }
+    }
+}
+
+/// All LEDs fading in and out
+#[derive(Debug, Clone, Copy)]
+pub struct Breathing {
+    pub color: Rgb,
+    pub period_ms: u32,
+    pub duration_ms: u32,
+}
+
+impl Effect for Breathing {
+    fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    fn render(&self, t_ms: u32, frame: &mut [Rgb]) {
+        let period = self.period_ms.max(2);
+        let phase = t_ms % period;
+        let half = period / 2;
+        // Triangle wave, squared so it looks linear to the eye
+        let triangle = if phase < half {
+            phase * 255 / half
+        } else {
+            (period - phase) * 255 / (period - half)
+        };
+        let level = (triangle * triangle / 255) as u8;
+        frame.fill(scale(self.color, level));
+    }
+}
+
+/// Blocks of team colors driving around the track
+#[derive(Debug, Clone, Copy)]
+pub struct TeamParade<'a> {
+    pub colors: &'a [Rgb],
+    pub block_length: usize,
+    pub laps: u32,
+    pub duration_ms: u32,
+}
+
+impl Effect for TeamParade<'_> {
+    fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    fn render(&self, t_ms: u32, frame: &mut [Rgb]) {
+        let led_count = frame.len();
+        if self.colors.is_empty() || self.block_length == 0 {
+            frame.fill(OFF);
+            return;
+        }
+        let offset = progress(t_ms, self.duration_ms, led_count * self.laps as usize);
+        for (i, led) in frame.iter_mut().enumerate() {
+            // Distance behind the front of the parade
+            let position = (offset + led_count - i % led_count) % led_count;
+            let block = position / self.block_length;
+            // Leave a dark LED between two teams
+            *led = if position % self.block_length == self.block_length - 1 {
+                OFF
+            } else {
+                self.colors[block % self.colors.len()]
+            };
+        }
+    }
+}
+
+/// Black and white blocks swapping places, like a waved checkered flag
+#[derive(Debug, Clone, Copy)]
+pub struct CheckeredFlag {
+    pub square_length: usize,
+    pub flips: u32,
+    pub duration_ms: u32,
+}
+
+impl Effect for CheckeredFlag {
+    fn duration_ms(&self) -> u32 {
+        self.duration_ms
+    }
+
+    fn render(&self, t_ms: u32, frame: &mut [Rgb]) {
+        let square_length = self.square_length.max(1);
+        let flip = progress(t_ms, self.duration_ms, self.flips as usize + 1) % 2;
+        for (i, led) in frame.iter_mut().enumerate() {
+            *led = if (i / square_length + flip) % 2 == 0 {
+                (255, 255, 255)
+            } else {
+                OFF
+            };
+        }
+    }
+}
+
+/// Effects played one after another
+#[derive(Clone, Copy)]
+pub struct Sequence<'a> {
+    pub effects: &'a [&'a dyn Effect],
+}
+
+impl Effect for Sequence<'_> {
+    fn duration_ms(&self) -> u32 {
+        self.effects.iter().map(|e| e.duration_ms()).sum()
+    }
+
+    fn render(&self, mut t_ms: u32, frame: &mut [Rgb]) {
+        for (i, effect) in self.effects.iter().enumerate() {
+            let last = i == self.effects.len() - 1;
+            if t_ms < effect.duration_ms() || last {
+                effect.render(t_ms, frame);
+                return;
+            }
+            t_ms -= effect.duration_ms();
+        }
+        frame.fill(OFF);
+    }
+}
+
+/// The startup animations that can be selected at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupAnimation {
+    None,
+    Train,
+    Comet,
+    Rainbow,
+    Breathing,
+    Parade,
+    Checkered,
+    Showcase,
+}
+
+impl StartupAnimation {
+    pub const ALL: [StartupAnimation; 8] = [
+        StartupAnimation::None,
+        StartupAnimation::Train,
+        StartupAnimation::Comet,
+        StartupAnimation::Rainbow,
+        StartupAnimation::Breathing,
+        StartupAnimation::Parade,
+        StartupAnimation::Checkered,
+        StartupAnimation::Showcase,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StartupAnimation::None => "none",
+            StartupAnimation::Train => "train",
+            StartupAnimation::Comet => "comet",
+            StartupAnimation::Rainbow => "rainbow",
+            StartupAnimation::Breathing => "breathing",
+            StartupAnimation::Parade => "parade",
+            StartupAnimation::Checkered => "checkered",
+            StartupAnimation::Showcase => "showcase",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    /// Render the animation at `t_ms`. `team_colors` is used by the parade.
+    /// Returns false once the animation has finished.
+    pub fn render(&self, t_ms: u32, team_colors: &[Rgb], frame: &mut [Rgb]) -> bool {
+        let parade = TeamParade {
+            colors: team_colors,
+            block_length: 5,
+            laps: 2,
+            duration_ms: 8000,
+        };
+        let effect: &dyn Effect = match self {
+            StartupAnimation::None => return false,
+            StartupAnimation::Train => &TRAIN,
+            StartupAnimation::Comet => &COMET,
+            StartupAnimation::Rainbow => &RAINBOW,
+            StartupAnimation::Breathing => &BREATHING,
+            StartupAnimation::Parade => &parade,
+            StartupAnimation::Checkered => &CHECKERED,
+            StartupAnimation::Showcase => &Sequence {
+                effects: &[&TRAIN, &COMET, &RAINBOW, &parade, &CHECKERED],
+            },
+        };
+        if t_ms >= effect.duration_ms() {
+            return false;
+        }
+        effect.render(t_ms, frame);
+        true
+    }
+}
+
+impl fmt::Display for StartupAnimation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+const TRAIN_COLORS: [Rgb; 15] = [
+    (255, 0, 0),
+    (255, 0, 0),
+    (255, 0, 0),
+    (255, 0, 0),
+    (255, 0, 0),
+    (0, 0, 255),
+    (0, 0, 255),
+    (0, 0, 255),
+    (0, 0, 255),
+    (0, 0, 255),
+    (0, 255, 0),
+    (0, 255, 0),
+    (0, 255, 0),
+    (0, 255, 0),
+    (0, 255, 0),
+];
+
+/// The original startup train: red, blue and green over a dim background
+const TRAIN: Chase = Chase {
+    colors: &TRAIN_COLORS,
+    background: (10, 10, 10),
+    laps: 10,
+    duration_ms: 10_000,
+};
+
+const COMET: Comet = Comet {
+    color: (255, 255, 255),
+    tail_length: 12,
+    laps: 3,
+    duration_ms: 4000,
+};
+
+const RAINBOW: Rainbow = Rainbow {
+    cycles: 2,
+    duration_ms: 5000,
+};
+
+const BREATHING: Breathing = Breathing {
+    color: (220, 0, 0),
+    period_ms: 2000,
+    duration_ms: 6000,
+};
+
+const CHECKERED: CheckeredFlag = CheckeredFlag {
+    square_length: 4,
+    flips: 8,
+    duration_ms: 4000,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: usize = 96;
+
+    #[test]
+    fn test_chase() {
+        let colors = [(255, 0, 0), (0, 255, 0)];
+        let chase = Chase {
+            colors: &colors,
+            background: (1, 1, 1),
+            laps: 1,
+            duration_ms: 960,
+        };
+        let mut frame = [OFF; N];
+
+        chase.render(0, &mut frame);
+        assert_eq!(frame[0], (255, 0, 0));
+        assert_eq!(frame[1], (0, 255, 0));
+        assert_eq!(frame[2], (1, 1, 1));
+
+        // 10 ms per LED, the train wraps around the end of the track
+        chase.render(950, &mut frame);
+        assert_eq!(frame[95], (255, 0, 0));
+        assert_eq!(frame[0], (0, 255, 0));
+        assert_eq!(frame[1], (1, 1, 1));
+    }
+
+    #[test]
+    fn test_comet_tail_fades() {
+        let comet = Comet {
+            color: (200, 200, 200),
+            tail_length: 3,
+            laps: 1,
+            duration_ms: 960,
+        };
+        let mut frame = [OFF; N];
+        comet.render(100, &mut frame);
+
+        assert_eq!(frame[10], (200, 200, 200));
+        assert!(frame[9].0 < frame[10].0);
+        assert!(frame[8].0 < frame[9].0);
+        assert!(frame[7].0 < frame[8].0 && frame[7].0 > 0);
+        assert_eq!(frame[6], OFF);
+        assert_eq!(frame[11], OFF);
+    }
+
+    #[test]
+    fn test_rainbow() {
+        let rainbow = Rainbow {
+            cycles: 1,
+            duration_ms: 1000,
+        };
+        let mut frame = [OFF; N];
+        rainbow.render(0, &mut frame);
+        assert_eq!(frame[0], (255, 0, 0));
+        assert_ne!(frame[N / 3], frame[0]);
+
+        // Half a cycle later the start of the track has the opposite hue
+        let first = frame[N / 2];
+        rainbow.render(500, &mut frame);
+        assert_eq!(frame[0], first);
+    }
+
+    #[test]
+    fn test_breathing() {
+        let breathing = Breathing {
+            color: (255, 0, 0),
+            period_ms: 1000,
+            duration_ms: 3000,
+        };
+        let mut frame = [(9, 9, 9); N];
+        breathing.render(0, &mut frame);
+        assert!(frame.iter().all(|c| *c == OFF));
+        breathing.render(500, &mut frame);
+        assert!(frame.iter().all(|c| *c == (255, 0, 0)));
+        breathing.render(1250, &mut frame);
+        assert!(frame[0].0 > 0 && frame[0].0 < 255);
+    }
+
+    #[test]
+    fn test_team_parade() {
+        let colors = [(255, 0, 0), (0, 0, 255)];
+        let parade = TeamParade {
+            colors: &colors,
+            block_length: 3,
+            laps: 1,
+            duration_ms: 960,
+        };
+        let mut frame = [OFF; 12];
+        parade.render(0, &mut frame);
+        assert_eq!(frame[0], (255, 0, 0));
+        // LEDs further along the chain are further behind the front
+        assert_eq!(&frame[9..12], &[(0, 0, 255), OFF, (255, 0, 0)]);
+        assert_eq!(&frame[7..9], &[OFF, (0, 0, 255)]);
+    }
+
+    #[test]
+    fn test_checkered_flag() {
+        let flag = CheckeredFlag {
+            square_length: 2,
+            flips: 1,
+            duration_ms: 1000,
+        };
+        let mut frame = [OFF; 8];
+        flag.render(0, &mut frame);
+        let white = (255, 255, 255);
+        assert_eq!(frame, [white, white, OFF, OFF, white, white, OFF, OFF]);
+        flag.render(600, &mut frame);
+        assert_eq!(frame, [OFF, OFF, white, white, OFF, OFF, white, white]);
+    }
+
+    #[test]
+    fn test_sequence() {
+        let first = Breathing {
+            color: (255, 0, 0),
+            period_ms: 1000,
+            duration_ms: 1000,
+        };
+        let second = CheckeredFlag {
+            square_length: 1,
+            flips: 0,
+            duration_ms: 500,
+        };
+        let sequence = Sequence {
+            effects: &[&first, &second],
+        };
+        assert_eq!(sequence.duration_ms(), 1500);
+
+        let mut frame = [OFF; 4];
+        sequence.render(500, &mut frame);
+        assert_eq!(frame[1], (255, 0, 0));
+        sequence.render(1000, &mut frame);
+        assert_eq!(frame[1], OFF);
+        assert_eq!(frame[0], (255, 255, 255));
+    }
+
+    #[test]
+    fn test_startup_animation() {
+        let mut frame = [OFF; N];
+        for animation in StartupAnimation::ALL {
+            assert_eq!(
+                StartupAnimation::from_name(animation.name()),
+                Some(animation)
+            );
+        }
+        assert!(!StartupAnimation::None.render(0, &[], &mut frame));
+        assert!(StartupAnimation::Train.render(0, &[], &mut frame));
+        assert!(!StartupAnimation::Train.render(10_000, &[], &mut frame));
+        assert!(StartupAnimation::Showcase.render(20_000, &[(255, 0, 0)], &mut frame));
+        assert_eq!(StartupAnimation::from_name("fireworks"), None);
+    }
+}
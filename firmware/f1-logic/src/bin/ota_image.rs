@@ -0,0 +1,60 @@
+//! Packs an app image into an update file for `ota <url>`, see
+//! `f1_logic::ota`.
+//!
+//! cargo run --features use-std --bin ota_image -- <image.bin> <version> <git commit> <output.ota>
+//!
+//! The app image comes from `espflash save-image --chip esp32c3`.
+
+use std::fs;
+use std::process::ExitCode;
+
+use f1_logic::ota::{ImageHeader, Version};
+use sha2::{Digest, Sha256};
+
+// Board the firmware is built for, as in f1-hardware's build.rs
+const BOARD: &str = "esp32c3";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [image_path, version, git_commit, output_path] = &args[..] else {
+        eprintln!("usage: ota_image <image.bin> <version> <git commit> <output.ota>");
+        return ExitCode::from(2);
+    };
+    let Some(version) = Version::parse(version) else {
+        eprintln!("Invalid version {}, expected major.minor.patch", version);
+        return ExitCode::from(2);
+    };
+    let Ok(git_commit) = git_commit.as_str().try_into() else {
+        eprintln!("Git commit {} is too long", git_commit);
+        return ExitCode::from(2);
+    };
+    let image = match fs::read(image_path) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("Failed to read {}: {}", image_path, err);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let header = ImageHeader {
+        image_size: image.len() as u32,
+        sha256: Sha256::digest(&image).into(),
+        version,
+        git_commit,
+        board: BOARD.try_into().unwrap(),
+    };
+    let mut file = header.to_bytes().to_vec();
+    file.extend_from_slice(&image);
+    if let Err(err) = fs::write(output_path, &file) {
+        eprintln!("Failed to write {}: {}", output_path, err);
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "Firmware {} ({}), {} bytes, saved to {}",
+        header.version,
+        header.git_commit,
+        image.len(),
+        output_path
+    );
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,81 @@
+//! Streaming writes to flash.
+//!
+//! Race downloads and firmware updates both write a file of known size to
+//! a partition as it arrives. [`BlockWriter`] collects the data into blocks
+//! and erases the sectors ahead of the writes, so nothing is erased that
+//! the file doesn't reach.
+
+use core::ops::Range;
+use embedded_storage::nor_flash::NorFlash;
+
+/// Data is written in blocks of this size, it must be a multiple of the
+/// flash write size
+pub(crate) const BLOCK_SIZE: usize = 256;
+
+/// Writes data in blocks from a sector aligned flash offset
+pub(crate) struct BlockWriter {
+    start: u32,
+    // Data bytes in flash and the end of the erased sectors, from `start`
+    written: u32,
+    erased: u32,
+    // Data not written yet
+    block: [u8; BLOCK_SIZE],
+    block_len: usize,
+}
+
+impl BlockWriter {
+    pub(crate) fn new(start: u32) -> Self {
+        Self::resume(start, 0)
+    }
+
+    /// Continue after `written` bytes already in flash, at the end of a
+    /// sector. The sectors after them are erased again.
+    pub(crate) fn resume(start: u32, written: u32) -> Self {
+        Self {
+            start,
+            written,
+            erased: written,
+            block: [0; BLOCK_SIZE],
+            block_len: 0,
+        }
+    }
+
+    /// Buffer the start of `data`, returns the rest when the block is full
+    pub(crate) fn push<'d>(&mut self, data: &'d [u8]) -> &'d [u8] {
+        let n = data.len().min(BLOCK_SIZE - self.block_len);
+        self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+        self.block_len += n;
+        &data[n..]
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.block_len == BLOCK_SIZE
+    }
+
+    /// Write the buffered block, padded to the write size. Returns the data
+    /// bytes the block added, from `start`.
+    pub(crate) fn flush<F: NorFlash>(&mut self, flash: &mut F) -> Result<Range<u32>, F::Error> {
+        let sector_size = F::ERASE_SIZE as u32;
+        let from = self.written;
+        if self.block_len == 0 {
+            return Ok(from..from);
+        }
+        let len = self.block_len.next_multiple_of(F::WRITE_SIZE);
+        self.block[self.block_len..len].fill(0xFF);
+        let to = from + len as u32;
+        self.written += self.block_len as u32;
+        self.block_len = 0;
+
+        // Erase the sectors the block reaches into
+        if to > self.erased {
+            let erase_from = self.erased.next_multiple_of(sector_size);
+            let erase_to = to.next_multiple_of(sector_size);
+            self.erased = erase_to;
+            if erase_to > erase_from {
+                flash.erase(self.start + erase_from, self.start + erase_to)?;
+            }
+        }
+        flash.write(self.start + from, &self.block[..len])?;
+        Ok(from..self.written)
+    }
+}
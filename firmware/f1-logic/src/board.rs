@@ -0,0 +1,211 @@
+//! PCB revision detection.
+//!
+//! Board revisions after the first have a resistor divider (the "strap") on
+//! the board ID ADC pin. The firmware measures the strap voltage at boot with
+//! the pin's internal pull-down enabled and decodes it to a
+//! [`BoardRevision`], which selects the LED count, LED layout and pin map.
+//! Readings that match no strap, or that wander like a floating pin, select
+//! the 10x10 board: the existing boards have no strap.
+
+use crate::circuit;
+use crate::led_layout::{self, LedPosition};
+use crate::transform::Transform;
+
+/// Allowed deviation of a strap reading from its nominal voltage
+const STRAP_TOLERANCE_MV: u16 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BoardRevision {
+    /// 10x10 cm Zandvoort board with 96 LEDs
+    Zandvoort10x10,
+    /// 20x20 cm Zandvoort board with 216 LEDs
+    Zandvoort20x20,
+    /// Strap reading that matches no known board or is not steady, in mV
+    Unknown(u16),
+}
+
+/// Nominal strap voltages in mV.
+///
+/// | Board | Strap | Reading |
+/// |-------|-------|---------|
+/// | 10x10 | none, the internal pull-down (about 45 kΩ) holds the pin low | 0 mV |
+/// | 20x20 | equal divider of at most 10 kΩ per leg between 3.3V and ground | 1650 mV |
+///
+/// The pull-down in parallel with the lower 10 kΩ leg pulls the 20x20 reading
+/// to about 1490 mV, inside the tolerance. Stiffer dividers stay closer to
+/// 1650 mV. The board ID pin is GPIO4 (MTMS), so the strap must not be fitted
+/// on boards that use the JTAG pins for debugging.
+const STRAP_TABLE: &[(u16, BoardRevision)] = &[
+    (0, BoardRevision::Zandvoort10x10),
+    (1650, BoardRevision::Zandvoort20x20),
+];
+
+impl BoardRevision {
+    /// Decode a strap voltage measurement
+    pub fn from_strap_mv(strap_mv: u16) -> Self {
+        STRAP_TABLE
+            .iter()
+            .find(|(nominal_mv, _)| nominal_mv.abs_diff(strap_mv) <= STRAP_TOLERANCE_MV)
+            .map(|(_, revision)| *revision)
+            .unwrap_or(BoardRevision::Unknown(strap_mv))
+    }
+
+    /// Decode a series of strap voltage measurements. A pin without a stiff
+    /// strap wanders between readings, so readings further apart than the
+    /// tolerance give [`BoardRevision::Unknown`] whatever their average.
+    pub fn from_strap_samples(samples_mv: &[u16]) -> Self {
+        let min_mv = samples_mv.iter().copied().min().unwrap_or(0);
+        let max_mv = samples_mv.iter().copied().max().unwrap_or(0);
+        let total_mv: u32 = samples_mv.iter().map(|&mv| mv as u32).sum();
+        let mean_mv = (total_mv / samples_mv.len().max(1) as u32) as u16;
+        if max_mv - min_mv > STRAP_TOLERANCE_MV {
+            return BoardRevision::Unknown(mean_mv);
+        }
+        Self::from_strap_mv(mean_mv)
+    }
+
+    /// Hardware configuration for this revision.
+    ///
+    /// Unknown boards get the 10x10 configuration: it drives the fewest LEDs,
+    /// so it never clocks data past the end of a shorter chain.
+    pub fn config(&self) -> &'static BoardConfig {
+        match self {
+            BoardRevision::Zandvoort10x10 => &ZANDVOORT_10X10,
+            BoardRevision::Zandvoort20x20 => &ZANDVOORT_20X20,
+            BoardRevision::Unknown(_) => &ZANDVOORT_10X10,
+        }
+    }
+}
+
+/// GPIO numbers of the board peripherals
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PinMap {
+    pub led_clock: u8,
+    pub led_data: u8,
+    pub button: u8,
+    pub temperature_adc: u8,
+    pub board_id_adc: u8,
+    pub status_led: u8,
+}
+
+/// Both Zandvoort boards share the ESP32-C3 pinout
+const ZANDVOORT_PINS: PinMap = PinMap {
+    led_clock: 6,
+    led_data: 7,
+    button: 10,
+    temperature_adc: 1,
+    board_id_adc: 4,
+    status_led: 3,
+};
+
+#[derive(Debug, PartialEq)]
+pub struct BoardConfig {
+    pub name: &'static str,
+    pub led_count: usize,
+    pub leds: &'static [LedPosition],
+    pub pins: PinMap,
+    /// Registers `leds` against OpenF1 positions, `None` until fitted
+    pub openf1_transform: Option<Transform>,
+}
+
+static ZANDVOORT_10X10: BoardConfig = BoardConfig {
+    name: "zandvoort_10x10",
+    led_count: 96,
+    leds: led_layout::ZANDVOORT_10X10,
+    pins: ZANDVOORT_PINS,
+    openf1_transform: Some(circuit::ZANDVOORT.transform),
+};
+
+static ZANDVOORT_20X20: BoardConfig = BoardConfig {
+    name: "zandvoort_20x20",
+    led_count: 216,
+    leds: led_layout::ZANDVOORT_20X20,
+    pins: ZANDVOORT_PINS,
+    // Its LEDs follow the track in other places than the simulator's
+    openf1_transform: None,
+};
+
+/// Largest LED count of all supported boards
+pub const MAX_LED_COUNT: usize = 216;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_strap() {
+        assert_eq!(
+            BoardRevision::from_strap_mv(0),
+            BoardRevision::Zandvoort10x10
+        );
+        assert_eq!(
+            BoardRevision::from_strap_mv(150),
+            BoardRevision::Zandvoort10x10
+        );
+        assert_eq!(
+            BoardRevision::from_strap_mv(1650),
+            BoardRevision::Zandvoort20x20
+        );
+        assert_eq!(
+            BoardRevision::from_strap_mv(1500),
+            BoardRevision::Zandvoort20x20
+        );
+        assert_eq!(
+            BoardRevision::from_strap_mv(1000),
+            BoardRevision::Unknown(1000)
+        );
+        assert_eq!(
+            BoardRevision::from_strap_mv(3000),
+            BoardRevision::Unknown(3000)
+        );
+    }
+
+    #[test]
+    fn test_unstrapped_board() {
+        // No strap: the pull-down holds the pin at ground
+        let revision = BoardRevision::from_strap_samples(&[0, 4, 0, 12, 0, 0, 7, 0]);
+        assert_eq!(revision, BoardRevision::Zandvoort10x10);
+
+        // A floating pin averaging inside the 20x20 window
+        let revision =
+            BoardRevision::from_strap_samples(&[1210, 1980, 1450, 1890, 1320, 2010, 1560, 1780]);
+        assert_eq!(revision, BoardRevision::Unknown(1650));
+        assert_eq!(revision.config().name, "zandvoort_10x10");
+
+        assert_eq!(
+            BoardRevision::from_strap_samples(&[]),
+            BoardRevision::Zandvoort10x10
+        );
+    }
+
+    #[test]
+    fn test_strapped_board() {
+        // 10 kΩ divider with the pull-down
+        let revision =
+            BoardRevision::from_strap_samples(&[1480, 1492, 1488, 1475, 1490, 1485, 1483, 1491]);
+        assert_eq!(revision, BoardRevision::Zandvoort20x20);
+    }
+
+    #[test]
+    fn unknown_board_falls_back_to_smallest_board() {
+        let config = BoardRevision::Unknown(1000).config();
+        let smallest = [BoardRevision::Zandvoort10x10, BoardRevision::Zandvoort20x20]
+            .iter()
+            .map(|r| r.config().led_count)
+            .min()
+            .unwrap();
+        assert_eq!(config.led_count, smallest);
+    }
+
+    #[test]
+    fn layouts_match_led_count() {
+        for revision in [BoardRevision::Zandvoort10x10, BoardRevision::Zandvoort20x20] {
+            let config = revision.config();
+            assert_eq!(config.leds.len(), config.led_count);
+            assert!(config.led_count <= MAX_LED_COUNT);
+            for (i, led) in config.leds.iter().enumerate() {
+                assert_eq!(led.led_number as usize, i + 1);
+            }
+        }
+    }
+}
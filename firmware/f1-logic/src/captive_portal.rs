@@ -0,0 +1,375 @@
+//! DHCP and DNS servers for the provisioning access point.
+//!
+//! Clients joining the access point get an address from a small DHCP
+//! server, and every DNS name resolves to the board, so the operating
+//! system's connectivity check lands on the provisioning page.
+
+/// Address of the board on the access point network
+pub const SERVER_IP: [u8; 4] = [192, 168, 2, 1];
+
+pub const PORTAL_URL: &str = "http://192.168.2.1/";
+
+/// Number of clients that can get an address
+pub const MAX_LEASES: usize = 8;
+
+const LEASE_TIME_S: u32 = 3600;
+
+/// Smallest DHCP message, replies are padded to this size
+pub const DHCP_MIN_SIZE: usize = 300;
+
+const DHCP_MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+const DHCP_OPTIONS_OFFSET: usize = 240;
+
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+const DHCP_NAK: u8 = 6;
+
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVER: u8 = 6;
+const OPTION_REQUESTED_IP: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_CAPTIVE_PORTAL: u8 = 114;
+const OPTION_END: u8 = 255;
+
+/// Addresses handed out to clients, by hardware address.
+/// Client `i` gets `192.168.2.(i + 2)`.
+#[derive(Debug, Default)]
+pub struct Leases {
+    clients: [Option<[u8; 6]>; MAX_LEASES],
+    // Next lease to reuse when all are taken
+    next: usize,
+}
+
+impl Leases {
+    fn address_of(index: usize) -> [u8; 4] {
+        [SERVER_IP[0], SERVER_IP[1], SERVER_IP[2], index as u8 + 2]
+    }
+
+    /// Address of `mac`, assigning a new one when needed
+    pub fn lease(&mut self, mac: [u8; 6]) -> [u8; 4] {
+        let index = match self.clients.iter().position(|c| *c == Some(mac)) {
+            Some(index) => index,
+            None => {
+                let index = self
+                    .clients
+                    .iter()
+                    .position(Option::is_none)
+                    .unwrap_or(self.next);
+                self.next = (index + 1) % MAX_LEASES;
+                self.clients[index] = Some(mac);
+                index
+            }
+        };
+        Self::address_of(index)
+    }
+}
+
+/// Build the reply to a DHCP client message in `out`, which must have room
+/// for [`DHCP_MIN_SIZE`] bytes. Returns the reply length, or `None` when the
+/// message needs no reply.
+pub fn dhcp_reply(request: &[u8], leases: &mut Leases, out: &mut [u8]) -> Option<usize> {
+    // BOOTREQUEST from an Ethernet client
+    if request.len() < DHCP_OPTIONS_OFFSET
+        || request[0] != 1
+        || request[1] != 1
+        || request[2] != 6
+        || request[236..240] != DHCP_MAGIC_COOKIE
+    {
+        return None;
+    }
+    let options = &request[DHCP_OPTIONS_OFFSET..];
+    let mac: [u8; 6] = request[28..34].try_into().ok()?;
+
+    let reply_type = match dhcp_option(options, OPTION_MESSAGE_TYPE)? {
+        [DHCP_DISCOVER] => DHCP_OFFER,
+        [DHCP_REQUEST] => {
+            if let Some(server) = dhcp_option(options, OPTION_SERVER_ID) {
+                if server != SERVER_IP {
+                    // The client chose another server
+                    return None;
+                }
+            }
+            let requested = dhcp_option(options, OPTION_REQUESTED_IP).unwrap_or(&request[12..16]);
+            if requested == leases.lease(mac) {
+                DHCP_ACK
+            } else {
+                DHCP_NAK
+            }
+        }
+        _ => return None,
+    };
+    let address = if reply_type == DHCP_NAK {
+        [0; 4]
+    } else {
+        leases.lease(mac)
+    };
+
+    let out = out.get_mut(..DHCP_MIN_SIZE)?;
+    out.fill(0);
+    out[0] = 2; // BOOTREPLY
+    out[1..3].copy_from_slice(&request[1..3]);
+    // Transaction id and flags
+    out[4..8].copy_from_slice(&request[4..8]);
+    out[10..12].copy_from_slice(&request[10..12]);
+    out[16..20].copy_from_slice(&address);
+    out[20..24].copy_from_slice(&SERVER_IP);
+    out[28..44].copy_from_slice(&request[28..44]);
+    out[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+
+    let mut options = OptionWriter {
+        buf: out,
+        pos: DHCP_OPTIONS_OFFSET,
+    };
+    options.push(OPTION_MESSAGE_TYPE, &[reply_type])?;
+    options.push(OPTION_SERVER_ID, &SERVER_IP)?;
+    if reply_type != DHCP_NAK {
+        options.push(OPTION_LEASE_TIME, &LEASE_TIME_S.to_be_bytes())?;
+        options.push(OPTION_SUBNET_MASK, &[255, 255, 255, 0])?;
+        options.push(OPTION_ROUTER, &SERVER_IP)?;
+        options.push(OPTION_DNS_SERVER, &SERVER_IP)?;
+        options.push(OPTION_CAPTIVE_PORTAL, PORTAL_URL.as_bytes())?;
+    }
+    options.buf[options.pos] = OPTION_END;
+
+    Some(DHCP_MIN_SIZE.max(options.pos + 1))
+}
+
+// Value of DHCP option `code`
+fn dhcp_option(mut options: &[u8], code: u8) -> Option<&[u8]> {
+    loop {
+        match *options.first()? {
+            OPTION_END => return None,
+            // Padding
+            0 => options = &options[1..],
+            c => {
+                let len = *options.get(1)? as usize;
+                let value = options.get(2..2 + len)?;
+                if c == code {
+                    return Some(value);
+                }
+                options = &options[2 + len..];
+            }
+        }
+    }
+}
+
+struct OptionWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl OptionWriter<'_> {
+    fn push(&mut self, code: u8, value: &[u8]) -> Option<()> {
+        // Keep room for the end option
+        let end = self.pos + 2 + value.len();
+        if end >= self.buf.len() {
+            return None;
+        }
+        self.buf[self.pos] = code;
+        self.buf[self.pos + 1] = value.len() as u8;
+        self.buf[self.pos + 2..end].copy_from_slice(value);
+        self.pos = end;
+        Some(())
+    }
+}
+
+const DNS_HEADER_SIZE: usize = 12;
+const DNS_TYPE_A: u16 = 1;
+const DNS_CLASS_IN: u16 = 1;
+const DNS_ANSWER_SIZE: usize = 16;
+
+/// Answer a DNS query with [`SERVER_IP`] for every name. Returns the reply
+/// length, or `None` when the query can't be answered.
+pub fn dns_reply(query: &[u8], out: &mut [u8]) -> Option<usize> {
+    if query.len() < DNS_HEADER_SIZE {
+        return None;
+    }
+    let flags = u16::from_be_bytes([query[2], query[3]]);
+    let question_count = u16::from_be_bytes([query[4], query[5]]);
+    // Only standard queries with a single question
+    if flags & 0x8000 != 0 || (flags >> 11) & 0xF != 0 || question_count != 1 {
+        return None;
+    }
+
+    // Skip the name labels of the question
+    let mut pos = DNS_HEADER_SIZE;
+    loop {
+        let len = *query.get(pos)? as usize;
+        if len & 0xC0 != 0 {
+            return None;
+        }
+        pos += 1 + len;
+        if len == 0 {
+            break;
+        }
+    }
+    let question_end = pos + 4;
+    let question = query.get(DNS_HEADER_SIZE..question_end)?;
+    let qtype = u16::from_be_bytes([query[pos], query[pos + 1]]);
+    let qclass = u16::from_be_bytes([query[pos + 2], query[pos + 3]]);
+    let answer = qtype == DNS_TYPE_A && qclass == DNS_CLASS_IN;
+
+    let len = question_end + if answer { DNS_ANSWER_SIZE } else { 0 };
+    let out = out.get_mut(..len)?;
+    out[0..2].copy_from_slice(&query[0..2]);
+    // Response, copy the recursion desired bit, recursion available
+    let reply_flags = 0x8080 | (flags & 0x0100);
+    out[2..4].copy_from_slice(&reply_flags.to_be_bytes());
+    out[4..6].copy_from_slice(&1u16.to_be_bytes());
+    out[6..8].copy_from_slice(&(answer as u16).to_be_bytes());
+    out[8..12].fill(0);
+    out[DNS_HEADER_SIZE..question_end].copy_from_slice(question);
+
+    if answer {
+        let a = &mut out[question_end..];
+        // Pointer to the name in the question
+        a[0..2].copy_from_slice(&0xC00Cu16.to_be_bytes());
+        a[2..4].copy_from_slice(&DNS_TYPE_A.to_be_bytes());
+        a[4..6].copy_from_slice(&DNS_CLASS_IN.to_be_bytes());
+        a[6..10].copy_from_slice(&60u32.to_be_bytes());
+        a[10..12].copy_from_slice(&4u16.to_be_bytes());
+        a[12..16].copy_from_slice(&SERVER_IP);
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: [u8; 6] = [0x02, 0x00, 0x00, 0x12, 0x34, 0x56];
+
+    fn dhcp_message(options: &[u8]) -> [u8; 300] {
+        let mut msg = [0u8; 300];
+        msg[0] = 1;
+        msg[1] = 1;
+        msg[2] = 6;
+        msg[4..8].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        msg[10] = 0x80; // Broadcast flag
+        msg[28..34].copy_from_slice(&MAC);
+        msg[236..240].copy_from_slice(&DHCP_MAGIC_COOKIE);
+        msg[240..240 + options.len()].copy_from_slice(options);
+        msg
+    }
+
+    #[test]
+    fn test_dhcp_discover_and_request() {
+        let mut leases = Leases::default();
+        let mut out = [0u8; 576];
+
+        let discover = dhcp_message(&[53, 1, DHCP_DISCOVER, 255]);
+        let len = dhcp_reply(&discover, &mut leases, &mut out).unwrap();
+        assert_eq!(len, DHCP_MIN_SIZE);
+        let offer = &out[..len];
+        assert_eq!(offer[0], 2);
+        assert_eq!(&offer[4..8], &[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(offer[10], 0x80);
+        assert_eq!(&offer[16..20], &[192, 168, 2, 2]);
+        assert_eq!(&offer[28..34], &MAC);
+        let options = &offer[240..];
+        assert_eq!(
+            dhcp_option(options, OPTION_MESSAGE_TYPE),
+            Some(&[DHCP_OFFER][..])
+        );
+        assert_eq!(
+            dhcp_option(options, OPTION_DNS_SERVER),
+            Some(&SERVER_IP[..])
+        );
+        assert_eq!(
+            dhcp_option(options, OPTION_CAPTIVE_PORTAL),
+            Some(PORTAL_URL.as_bytes())
+        );
+
+        let request = dhcp_message(&[
+            53,
+            1,
+            DHCP_REQUEST,
+            50,
+            4,
+            192,
+            168,
+            2,
+            2,
+            54,
+            4,
+            192,
+            168,
+            2,
+            1,
+            255,
+        ]);
+        let len = dhcp_reply(&request, &mut leases, &mut out).unwrap();
+        assert_eq!(
+            dhcp_option(&out[240..len], OPTION_MESSAGE_TYPE),
+            Some(&[DHCP_ACK][..])
+        );
+        assert_eq!(&out[16..20], &[192, 168, 2, 2]);
+
+        // A request for an address the client doesn't own
+        let request = dhcp_message(&[53, 1, DHCP_REQUEST, 50, 4, 192, 168, 2, 9, 255]);
+        let len = dhcp_reply(&request, &mut leases, &mut out).unwrap();
+        assert_eq!(
+            dhcp_option(&out[240..len], OPTION_MESSAGE_TYPE),
+            Some(&[DHCP_NAK][..])
+        );
+
+        // A request for another server is ignored
+        let request = dhcp_message(&[53, 1, DHCP_REQUEST, 54, 4, 10, 0, 0, 1, 255]);
+        assert_eq!(dhcp_reply(&request, &mut leases, &mut out), None);
+
+        // Not a DHCP message
+        assert_eq!(dhcp_reply(&discover[..100], &mut leases, &mut out), None);
+    }
+
+    #[test]
+    fn test_leases() {
+        let mut leases = Leases::default();
+        assert_eq!(leases.lease(MAC), [192, 168, 2, 2]);
+        assert_eq!(leases.lease([1; 6]), [192, 168, 2, 3]);
+        assert_eq!(leases.lease(MAC), [192, 168, 2, 2]);
+
+        // When all leases are taken the oldest is reused
+        for i in 2..MAX_LEASES as u8 {
+            leases.lease([i; 6]);
+        }
+        assert_eq!(leases.lease([100; 6]), [192, 168, 2, 2]);
+        assert_eq!(leases.lease([101; 6]), [192, 168, 2, 3]);
+    }
+
+    // Query for connectivitycheck.gstatic.com
+    const QUERY: &[u8] = b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x11connectivitycheck\x07gstatic\x03com\x00\x00\x01\x00\x01";
+
+    #[test]
+    fn test_dns_reply() {
+        let mut out = [0u8; 512];
+        let len = dns_reply(QUERY, &mut out).unwrap();
+        assert_eq!(len, QUERY.len() + DNS_ANSWER_SIZE);
+        assert_eq!(&out[0..2], &[0x12, 0x34]);
+        assert_eq!(&out[2..4], &[0x81, 0x80]);
+        assert_eq!(&out[6..8], &[0, 1]);
+        assert_eq!(&out[12..QUERY.len()], &QUERY[12..]);
+        assert_eq!(&out[len - 4..len], &SERVER_IP);
+
+        // AAAA queries get an empty answer
+        let mut query = [0u8; 64];
+        let query = &mut query[..QUERY.len()];
+        query.copy_from_slice(QUERY);
+        query[QUERY.len() - 3] = 28;
+        let len = dns_reply(query, &mut out).unwrap();
+        assert_eq!(len, QUERY.len());
+        assert_eq!(&out[6..8], &[0, 0]);
+
+        // Responses and truncated queries are ignored
+        let mut response = [0u8; 64];
+        let response = &mut response[..QUERY.len()];
+        response.copy_from_slice(QUERY);
+        response[2] |= 0x80;
+        assert_eq!(dns_reply(response, &mut out), None);
+        assert_eq!(dns_reply(&QUERY[..20], &mut out), None);
+    }
+}
@@ -0,0 +1,300 @@
+//! Circuit definitions.
+//!
+//! A circuit is the LED layout of a track on a board together with what the
+//! race logic needs to know about the track: where a lap starts, the
+//! sectors, the pit lane, the driving direction and how the layout lines up
+//! with OpenF1 positions.
+//!
+//! Definitions are written as JSON in `firmware/tracks/` and compiled into
+//! the const tables in `circuit/` with `track_table` from f1-simulation.
+
+use crate::led_layout::LedPosition;
+use crate::transform::Transform;
+
+mod zandvoort;
+
+pub use zandvoort::ZANDVOORT;
+
+/// Timing sectors per lap
+pub const SECTOR_COUNT: usize = 3;
+
+/// All known circuits
+pub const CIRCUITS: &[&Circuit] = &[&ZANDVOORT];
+
+/// Order in which cars pass the LEDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// `U1`, `U2`, …
+    Ascending,
+    /// `U1`, `Un`, `Un-1`, …
+    Descending,
+}
+
+/// Where the pit lane leaves and rejoins the track.
+///
+/// The boards have no LEDs along the pit lane, cars in it show on the track
+/// LEDs next to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PitLane {
+    pub entry_led: u16,
+    pub exit_led: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Circuit {
+    pub name: &'static str,
+    /// The LEDs along the track, numbered from 1 in chain order
+    pub leds: &'static [LedPosition],
+    /// LED at the start/finish line
+    pub start_finish_led: u16,
+    /// First LED of every sector, the first sector starts at
+    /// `start_finish_led`
+    pub sector_leds: [u16; SECTOR_COUNT],
+    pub pit_lane: Option<PitLane>,
+    pub direction: Direction,
+    /// From LED positions to OpenF1 positions
+    pub transform: Transform,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitError {
+    NoLeds,
+    /// LED numbers have to run from 1 in chain order
+    LedNumbering {
+        index: usize,
+        led_number: u16,
+    },
+    UnknownLed(u16),
+    /// The first sector has to start at the start/finish line
+    FirstSector,
+    /// Sectors have to follow each other around the lap
+    SectorOrder,
+    /// The transform collapses the layout
+    Transform,
+}
+
+impl Circuit {
+    pub fn led_count(&self) -> usize {
+        self.leds.len()
+    }
+
+    /// Index into `leds` of an LED
+    fn index_of(&self, led_number: u16) -> Option<usize> {
+        let index = (led_number as usize).checked_sub(1)?;
+        (index < self.leds.len()).then_some(index)
+    }
+
+    /// LEDs a car passes from the start/finish line to `led_number`, `None`
+    /// for LEDs the circuit doesn't have
+    pub fn lap_position(&self, led_number: u16) -> Option<usize> {
+        let index = self.index_of(led_number)?;
+        let start = self.index_of(self.start_finish_led)?;
+        let count = self.leds.len();
+        Some(match self.direction {
+            Direction::Ascending => (index + count - start) % count,
+            Direction::Descending => (start + count - index) % count,
+        })
+    }
+
+    /// Sector of `led_number`, from 0
+    pub fn sector(&self, led_number: u16) -> Option<usize> {
+        let position = self.lap_position(led_number)?;
+        self.sector_leds.iter().rposition(|&led| {
+            self.lap_position(led)
+                .is_some_and(|start| start <= position)
+        })
+    }
+
+    /// Check the definition is consistent
+    pub fn check(&self) -> Result<(), CircuitError> {
+        if self.leds.is_empty() {
+            return Err(CircuitError::NoLeds);
+        }
+        for (index, led) in self.leds.iter().enumerate() {
+            if led.led_number as usize != index + 1 {
+                return Err(CircuitError::LedNumbering {
+                    index,
+                    led_number: led.led_number,
+                });
+            }
+        }
+        let pit_leds = self.pit_lane.iter().flat_map(|p| [p.entry_led, p.exit_led]);
+        let leds = [self.start_finish_led]
+            .into_iter()
+            .chain(self.sector_leds)
+            .chain(pit_leds);
+        for led in leds {
+            self.index_of(led).ok_or(CircuitError::UnknownLed(led))?;
+        }
+        if self.sector_leds[0] != self.start_finish_led {
+            return Err(CircuitError::FirstSector);
+        }
+        let positions = self.sector_leds.map(|led| self.lap_position(led));
+        if positions.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(CircuitError::SectorOrder);
+        }
+        if self.transform.inverse().is_none() {
+            return Err(CircuitError::Transform);
+        }
+        Ok(())
+    }
+}
+
+/// The circuit called `name`
+pub fn find(name: &str) -> Option<&'static Circuit> {
+    CIRCUITS.iter().copied().find(|c| c.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEDS: &[LedPosition] = &[
+        LedPosition {
+            led_number: 1,
+            x_mm: 0.0,
+            y_mm: 0.0,
+            rotation_deg: 0.0,
+        },
+        LedPosition {
+            led_number: 2,
+            x_mm: 10.0,
+            y_mm: 0.0,
+            rotation_deg: 0.0,
+        },
+        LedPosition {
+            led_number: 3,
+            x_mm: 10.0,
+            y_mm: 10.0,
+            rotation_deg: 0.0,
+        },
+        LedPosition {
+            led_number: 4,
+            x_mm: 5.0,
+            y_mm: 15.0,
+            rotation_deg: 0.0,
+        },
+        LedPosition {
+            led_number: 5,
+            x_mm: 0.0,
+            y_mm: 10.0,
+            rotation_deg: 0.0,
+        },
+        LedPosition {
+            led_number: 6,
+            x_mm: 0.0,
+            y_mm: 5.0,
+            rotation_deg: 0.0,
+        },
+    ];
+
+    fn circuit(direction: Direction) -> Circuit {
+        Circuit {
+            name: "test",
+            leds: LEDS,
+            start_finish_led: 2,
+            sector_leds: match direction {
+                Direction::Ascending => [2, 4, 6],
+                Direction::Descending => [2, 6, 4],
+            },
+            pit_lane: Some(PitLane {
+                entry_led: 1,
+                exit_led: 3,
+            }),
+            direction,
+            transform: Transform::IDENTITY,
+        }
+    }
+
+    #[test]
+    fn test_lap_position() {
+        let c = circuit(Direction::Ascending);
+        assert_eq!(c.led_count(), 6);
+        assert_eq!(c.lap_position(2), Some(0));
+        assert_eq!(c.lap_position(6), Some(4));
+        assert_eq!(c.lap_position(1), Some(5));
+        assert_eq!(c.lap_position(0), None);
+        assert_eq!(c.lap_position(7), None);
+
+        let c = circuit(Direction::Descending);
+        assert_eq!(c.lap_position(2), Some(0));
+        assert_eq!(c.lap_position(1), Some(1));
+        assert_eq!(c.lap_position(6), Some(2));
+        assert_eq!(c.lap_position(3), Some(5));
+    }
+
+    #[test]
+    fn test_sector() {
+        let c = circuit(Direction::Ascending);
+        assert_eq!(c.sector(2), Some(0));
+        assert_eq!(c.sector(3), Some(0));
+        assert_eq!(c.sector(4), Some(1));
+        assert_eq!(c.sector(6), Some(2));
+        assert_eq!(c.sector(1), Some(2));
+        assert_eq!(c.sector(9), None);
+
+        let c = circuit(Direction::Descending);
+        assert_eq!(c.sector(1), Some(0));
+        assert_eq!(c.sector(5), Some(1));
+        assert_eq!(c.sector(3), Some(2));
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(circuit(Direction::Ascending).check(), Ok(()));
+        assert_eq!(circuit(Direction::Descending).check(), Ok(()));
+
+        let c = Circuit {
+            leds: &LEDS[1..],
+            ..circuit(Direction::Ascending)
+        };
+        assert_eq!(
+            c.check(),
+            Err(CircuitError::LedNumbering {
+                index: 0,
+                led_number: 2
+            })
+        );
+        let c = Circuit {
+            pit_lane: Some(PitLane {
+                entry_led: 7,
+                exit_led: 1,
+            }),
+            ..circuit(Direction::Ascending)
+        };
+        assert_eq!(c.check(), Err(CircuitError::UnknownLed(7)));
+        let c = Circuit {
+            sector_leds: [3, 4, 6],
+            ..circuit(Direction::Ascending)
+        };
+        assert_eq!(c.check(), Err(CircuitError::FirstSector));
+        let c = Circuit {
+            sector_leds: [2, 6, 4],
+            ..circuit(Direction::Ascending)
+        };
+        assert_eq!(c.check(), Err(CircuitError::SectorOrder));
+        let c = Circuit {
+            transform: Transform {
+                d: 0.0,
+                ..Transform::IDENTITY
+            },
+            ..circuit(Direction::Ascending)
+        };
+        assert_eq!(c.check(), Err(CircuitError::Transform));
+        let c = Circuit {
+            leds: &[],
+            ..circuit(Direction::Ascending)
+        };
+        assert_eq!(c.check(), Err(CircuitError::NoLeds));
+    }
+
+    #[test]
+    fn test_circuits() {
+        for circuit in CIRCUITS {
+            assert_eq!(circuit.check(), Ok(()), "{}", circuit.name);
+        }
+        let zandvoort = find("zandvoort").unwrap();
+        assert_eq!(zandvoort.led_count(), 96);
+        assert!(find("monza").is_none());
+    }
+}
@@ -0,0 +1,600 @@
+//! Generated by `track_table` from `firmware/tracks/zandvoort.json`,
+//! edit the definition instead.
+
+use super::{Circuit, Direction};
+use crate::led_layout::LedPosition;
+use crate::transform::Transform;
+
+pub const ZANDVOORT: Circuit = Circuit {
+    name: "zandvoort",
+    leds: &[
+        LedPosition {
+            led_number: 1,
+            x_mm: 74.625,
+            y_mm: 33.45,
+            rotation_deg: -15.0,
+        },
+        LedPosition {
+            led_number: 2,
+            x_mm: 70.9,
+            y_mm: 34.95,
+            rotation_deg: -28.0,
+        },
+        LedPosition {
+            led_number: 3,
+            x_mm: 67.625,
+            y_mm: 37.225,
+            rotation_deg: -41.0,
+        },
+        LedPosition {
+            led_number: 4,
+            x_mm: 65.6,
+            y_mm: 40.7,
+            rotation_deg: -105.0,
+        },
+        LedPosition {
+            led_number: 5,
+            x_mm: 68.325,
+            y_mm: 43.65,
+            rotation_deg: -150.0,
+        },
+        LedPosition {
+            led_number: 6,
+            x_mm: 72.125,
+            y_mm: 44.8,
+            rotation_deg: -170.0,
+        },
+        LedPosition {
+            led_number: 7,
+            x_mm: 76.05,
+            y_mm: 45.6,
+            rotation_deg: -168.0,
+        },
+        LedPosition {
+            led_number: 8,
+            x_mm: 79.8,
+            y_mm: 46.775,
+            rotation_deg: -160.0,
+        },
+        LedPosition {
+            led_number: 9,
+            x_mm: 83.05,
+            y_mm: 49.125,
+            rotation_deg: -123.0,
+        },
+        LedPosition {
+            led_number: 10,
+            x_mm: 83.425,
+            y_mm: 53.125,
+            rotation_deg: -68.0,
+        },
+        LedPosition {
+            led_number: 11,
+            x_mm: 80.25,
+            y_mm: 55.65,
+            rotation_deg: -11.0,
+        },
+        LedPosition {
+            led_number: 12,
+            x_mm: 76.275,
+            y_mm: 56.175,
+            rotation_deg: -5.0,
+        },
+        LedPosition {
+            led_number: 13,
+            x_mm: 72.3,
+            y_mm: 56.4,
+            rotation_deg: 0.0,
+        },
+        LedPosition {
+            led_number: 14,
+            x_mm: 68.3,
+            y_mm: 56.35,
+            rotation_deg: 3.0,
+        },
+        LedPosition {
+            led_number: 15,
+            x_mm: 64.275,
+            y_mm: 56.025,
+            rotation_deg: 6.0,
+        },
+        LedPosition {
+            led_number: 16,
+            x_mm: 60.325,
+            y_mm: 55.525,
+            rotation_deg: 8.0,
+        },
+        LedPosition {
+            led_number: 17,
+            x_mm: 56.375,
+            y_mm: 54.8,
+            rotation_deg: 12.0,
+        },
+        LedPosition {
+            led_number: 18,
+            x_mm: 52.475,
+            y_mm: 53.825,
+            rotation_deg: 16.0,
+        },
+        LedPosition {
+            led_number: 19,
+            x_mm: 48.675,
+            y_mm: 52.65,
+            rotation_deg: 18.0,
+        },
+        LedPosition {
+            led_number: 20,
+            x_mm: 44.9,
+            y_mm: 51.325,
+            rotation_deg: 19.0,
+        },
+        LedPosition {
+            led_number: 21,
+            x_mm: 41.2,
+            y_mm: 49.8,
+            rotation_deg: 23.0,
+        },
+        LedPosition {
+            led_number: 22,
+            x_mm: 37.6,
+            y_mm: 48.075,
+            rotation_deg: 27.0,
+        },
+        LedPosition {
+            led_number: 23,
+            x_mm: 33.925,
+            y_mm: 46.5,
+            rotation_deg: -10.0,
+        },
+        LedPosition {
+            led_number: 24,
+            x_mm: 31.3,
+            y_mm: 49.5,
+            rotation_deg: -35.0,
+        },
+        LedPosition {
+            led_number: 25,
+            x_mm: 27.325,
+            y_mm: 49.125,
+            rotation_deg: 42.0,
+        },
+        LedPosition {
+            led_number: 26,
+            x_mm: 26.575,
+            y_mm: 45.225,
+            rotation_deg: 98.0,
+        },
+        LedPosition {
+            led_number: 27,
+            x_mm: 27.2,
+            y_mm: 41.275,
+            rotation_deg: 99.0,
+        },
+        LedPosition {
+            led_number: 28,
+            x_mm: 27.9,
+            y_mm: 37.325,
+            rotation_deg: 100.0,
+        },
+        LedPosition {
+            led_number: 29,
+            x_mm: 28.6,
+            y_mm: 33.375,
+            rotation_deg: 99.0,
+        },
+        LedPosition {
+            led_number: 30,
+            x_mm: 29.3,
+            y_mm: 29.425,
+            rotation_deg: 100.0,
+        },
+        LedPosition {
+            led_number: 31,
+            x_mm: 29.975,
+            y_mm: 25.475,
+            rotation_deg: 99.0,
+        },
+        LedPosition {
+            led_number: 32,
+            x_mm: 29.775,
+            y_mm: 21.5,
+            rotation_deg: 68.0,
+        },
+        LedPosition {
+            led_number: 33,
+            x_mm: 26.75,
+            y_mm: 18.85,
+            rotation_deg: 15.0,
+        },
+        LedPosition {
+            led_number: 34,
+            x_mm: 22.8,
+            y_mm: 18.375,
+            rotation_deg: 2.0,
+        },
+        LedPosition {
+            led_number: 35,
+            x_mm: 18.8,
+            y_mm: 18.4,
+            rotation_deg: -4.0,
+        },
+        LedPosition {
+            led_number: 36,
+            x_mm: 14.825,
+            y_mm: 19.0,
+            rotation_deg: -16.0,
+        },
+        LedPosition {
+            led_number: 37,
+            x_mm: 11.2,
+            y_mm: 20.775,
+            rotation_deg: -36.0,
+        },
+        LedPosition {
+            led_number: 38,
+            x_mm: 8.425,
+            y_mm: 23.6,
+            rotation_deg: -55.0,
+        },
+        LedPosition {
+            led_number: 39,
+            x_mm: 6.75,
+            y_mm: 27.225,
+            rotation_deg: -74.0,
+        },
+        LedPosition {
+            led_number: 40,
+            x_mm: 6.3,
+            y_mm: 31.25,
+            rotation_deg: -94.0,
+        },
+        LedPosition {
+            led_number: 41,
+            x_mm: 7.15,
+            y_mm: 35.275,
+            rotation_deg: -110.0,
+        },
+        LedPosition {
+            led_number: 42,
+            x_mm: 8.625,
+            y_mm: 39.125,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 43,
+            x_mm: 10.2,
+            y_mm: 43.05,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 44,
+            x_mm: 11.75,
+            y_mm: 46.9,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 45,
+            x_mm: 13.3,
+            y_mm: 50.75,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 46,
+            x_mm: 14.85,
+            y_mm: 54.55,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 47,
+            x_mm: 16.4,
+            y_mm: 58.375,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 48,
+            x_mm: 17.95,
+            y_mm: 62.175,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 49,
+            x_mm: 19.463,
+            y_mm: 66.001,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 50,
+            x_mm: 21.05,
+            y_mm: 69.825,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 51,
+            x_mm: 22.575,
+            y_mm: 73.55,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 52,
+            x_mm: 24.1,
+            y_mm: 77.3,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 53,
+            x_mm: 25.625,
+            y_mm: 81.05,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 54,
+            x_mm: 27.14,
+            y_mm: 84.76,
+            rotation_deg: -112.0,
+        },
+        LedPosition {
+            led_number: 55,
+            x_mm: 28.675,
+            y_mm: 88.45,
+            rotation_deg: -113.0,
+        },
+        LedPosition {
+            led_number: 56,
+            x_mm: 30.2,
+            y_mm: 92.15,
+            rotation_deg: -113.0,
+        },
+        LedPosition {
+            led_number: 57,
+            x_mm: 33.2,
+            y_mm: 94.75,
+            rotation_deg: -170.0,
+        },
+        LedPosition {
+            led_number: 58,
+            x_mm: 36.85,
+            y_mm: 93.0,
+            rotation_deg: 120.0,
+        },
+        LedPosition {
+            led_number: 59,
+            x_mm: 36.975,
+            y_mm: 88.95,
+            rotation_deg: 68.0,
+        },
+        LedPosition {
+            led_number: 60,
+            x_mm: 35.425,
+            y_mm: 85.175,
+            rotation_deg: 67.0,
+        },
+        LedPosition {
+            led_number: 61,
+            x_mm: 33.925,
+            y_mm: 81.45,
+            rotation_deg: 70.0,
+        },
+        LedPosition {
+            led_number: 62,
+            x_mm: 32.675,
+            y_mm: 77.6,
+            rotation_deg: 75.0,
+        },
+        LedPosition {
+            led_number: 63,
+            x_mm: 32.05,
+            y_mm: 73.65,
+            rotation_deg: 87.0,
+        },
+        LedPosition {
+            led_number: 64,
+            x_mm: 32.0,
+            y_mm: 69.65,
+            rotation_deg: 90.0,
+        },
+        LedPosition {
+            led_number: 65,
+            x_mm: 30.4,
+            y_mm: 65.95,
+            rotation_deg: 35.0,
+        },
+        LedPosition {
+            led_number: 66,
+            x_mm: 26.775,
+            y_mm: 64.175,
+            rotation_deg: 20.0,
+        },
+        LedPosition {
+            led_number: 67,
+            x_mm: 23.1,
+            y_mm: 62.45,
+            rotation_deg: 30.0,
+        },
+        LedPosition {
+            led_number: 68,
+            x_mm: 22.175,
+            y_mm: 58.475,
+            rotation_deg: 120.0,
+        },
+        LedPosition {
+            led_number: 69,
+            x_mm: 25.9,
+            y_mm: 57.0,
+            rotation_deg: -167.0,
+        },
+        LedPosition {
+            led_number: 70,
+            x_mm: 29.7,
+            y_mm: 58.125,
+            rotation_deg: -163.0,
+        },
+        LedPosition {
+            led_number: 71,
+            x_mm: 33.55,
+            y_mm: 59.325,
+            rotation_deg: -165.0,
+        },
+        LedPosition {
+            led_number: 72,
+            x_mm: 37.45,
+            y_mm: 60.225,
+            rotation_deg: -170.0,
+        },
+        LedPosition {
+            led_number: 73,
+            x_mm: 41.45,
+            y_mm: 60.625,
+            rotation_deg: 180.0,
+        },
+        LedPosition {
+            led_number: 74,
+            x_mm: 45.425,
+            y_mm: 60.225,
+            rotation_deg: 170.0,
+        },
+        LedPosition {
+            led_number: 75,
+            x_mm: 49.35,
+            y_mm: 59.475,
+            rotation_deg: 170.0,
+        },
+        LedPosition {
+            led_number: 76,
+            x_mm: 53.275,
+            y_mm: 58.8,
+            rotation_deg: 175.0,
+        },
+        LedPosition {
+            led_number: 77,
+            x_mm: 57.25,
+            y_mm: 58.625,
+            rotation_deg: 180.0,
+        },
+        LedPosition {
+            led_number: 78,
+            x_mm: 61.125,
+            y_mm: 59.675,
+            rotation_deg: -158.0,
+        },
+        LedPosition {
+            led_number: 79,
+            x_mm: 64.675,
+            y_mm: 61.525,
+            rotation_deg: -149.0,
+        },
+        LedPosition {
+            led_number: 80,
+            x_mm: 68.1,
+            y_mm: 63.625,
+            rotation_deg: -150.0,
+        },
+        LedPosition {
+            led_number: 81,
+            x_mm: 71.8,
+            y_mm: 65.175,
+            rotation_deg: -162.0,
+        },
+        LedPosition {
+            led_number: 82,
+            x_mm: 75.775,
+            y_mm: 65.725,
+            rotation_deg: 180.0,
+        },
+        LedPosition {
+            led_number: 83,
+            x_mm: 79.75,
+            y_mm: 65.65,
+            rotation_deg: 178.0,
+        },
+        LedPosition {
+            led_number: 84,
+            x_mm: 83.725,
+            y_mm: 65.425,
+            rotation_deg: 177.0,
+        },
+        LedPosition {
+            led_number: 85,
+            x_mm: 87.675,
+            y_mm: 65.1,
+            rotation_deg: 175.0,
+        },
+        LedPosition {
+            led_number: 86,
+            x_mm: 91.45,
+            y_mm: 63.8,
+            rotation_deg: 149.0,
+        },
+        LedPosition {
+            led_number: 87,
+            x_mm: 94.275,
+            y_mm: 60.95,
+            rotation_deg: 123.0,
+        },
+        LedPosition {
+            led_number: 88,
+            x_mm: 95.7,
+            y_mm: 57.15,
+            rotation_deg: 95.0,
+        },
+        LedPosition {
+            led_number: 89,
+            x_mm: 95.325,
+            y_mm: 53.125,
+            rotation_deg: 70.0,
+        },
+        LedPosition {
+            led_number: 90,
+            x_mm: 93.375,
+            y_mm: 49.55,
+            rotation_deg: 57.0,
+        },
+        LedPosition {
+            led_number: 91,
+            x_mm: 91.1,
+            y_mm: 46.25,
+            rotation_deg: 55.0,
+        },
+        LedPosition {
+            led_number: 92,
+            x_mm: 88.975,
+            y_mm: 42.825,
+            rotation_deg: 63.0,
+        },
+        LedPosition {
+            led_number: 93,
+            x_mm: 87.175,
+            y_mm: 39.25,
+            rotation_deg: 63.0,
+        },
+        LedPosition {
+            led_number: 94,
+            x_mm: 85.375,
+            y_mm: 35.675,
+            rotation_deg: 63.0,
+        },
+        LedPosition {
+            led_number: 95,
+            x_mm: 82.55,
+            y_mm: 32.825,
+            rotation_deg: 10.0,
+        },
+        LedPosition {
+            led_number: 96,
+            x_mm: 78.55,
+            y_mm: 32.725,
+            rotation_deg: -6.0,
+        },
+    ],
+    start_finish_led: 50,
+    sector_leds: [50, 70, 23],
+    pit_lane: None,
+    direction: Direction::Ascending,
+    transform: Transform {
+        a: 108.73279,
+        b: -0.0003812,
+        c: 0.0003812,
+        d: 108.73279,
+        tx: -1701.034,
+        ty: -3602.939,
+    },
+};
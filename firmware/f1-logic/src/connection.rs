@@ -0,0 +1,242 @@
+//! Wi-Fi connection management.
+//!
+//! [`ConnectionManager`] decides which stored network to try next and how
+//! long to wait before trying. The stored networks are tried in order, after
+//! a round where every network failed the manager waits with exponential
+//! backoff. Too many failed rounds fall back to provisioning.
+//! The Wi-Fi driver itself lives in the firmware, this module only keeps
+//! the state.
+
+use crate::provisioning::ConnectionAttempts;
+use crate::status::FirmwareState;
+
+/// Delay before the first retry after a failed round
+pub const INITIAL_BACKOFF_MS: u32 = 1_000;
+
+/// Longest delay between two rounds
+pub const MAX_BACKOFF_MS: u32 = 60_000;
+
+/// Connection state published to the rest of the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    /// Connecting to the stored network with index `network`
+    Connecting {
+        network: u8,
+    },
+    /// Connected to the stored network with index `network`, `rssi` in dBm
+    Connected {
+        network: u8,
+        rssi: i8,
+    },
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self) -> bool {
+        matches!(self, ConnectionState::Connected { .. })
+    }
+
+    /// State to show on the status indicator
+    pub fn firmware_state(&self) -> FirmwareState {
+        match self {
+            ConnectionState::Disconnected | ConnectionState::Connecting { .. } => {
+                FirmwareState::Connecting
+            }
+            ConnectionState::Connected { .. } => FirmwareState::Idle,
+        }
+    }
+}
+
+/// Exponential backoff, doubling the delay up to a maximum
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    initial_ms: u32,
+    max_ms: u32,
+    next_ms: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(INITIAL_BACKOFF_MS, MAX_BACKOFF_MS)
+    }
+}
+
+impl Backoff {
+    pub const fn new(initial_ms: u32, max_ms: u32) -> Self {
+        Self {
+            initial_ms,
+            max_ms,
+            next_ms: initial_ms,
+        }
+    }
+
+    /// Delay to wait now, the following delay is doubled
+    pub fn next_delay(&mut self) -> u32 {
+        let delay = self.next_ms;
+        self.next_ms = self.next_ms.saturating_mul(2).min(self.max_ms);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next_ms = self.initial_ms;
+    }
+}
+
+/// What the Wi-Fi task should do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Wait `delay_ms`, then connect to the stored network with index `network`
+    Connect { network: u8, delay_ms: u32 },
+    /// Give up and start the provisioning portal
+    Provision,
+}
+
+/// Chooses the network to connect to and the delay between attempts
+#[derive(Debug, Clone)]
+pub struct ConnectionManager {
+    network_count: u8,
+    network: u8,
+    backoff: Backoff,
+    rounds: ConnectionAttempts,
+}
+
+impl ConnectionManager {
+    pub fn new(network_count: usize) -> Self {
+        Self {
+            network_count: network_count.min(u8::MAX as usize) as u8,
+            network: 0,
+            backoff: Backoff::default(),
+            rounds: ConnectionAttempts::default(),
+        }
+    }
+
+    /// First action after boot
+    pub fn start(&mut self) -> Action {
+        if self.network_count == 0 {
+            return Action::Provision;
+        }
+        self.network = 0;
+        Action::Connect {
+            network: 0,
+            delay_ms: 0,
+        }
+    }
+
+    /// The current network connected
+    pub fn connected(&mut self) {
+        self.backoff.reset();
+        self.rounds.connected();
+    }
+
+    /// Connecting to the current network failed
+    pub fn failed(&mut self) -> Action {
+        if self.network + 1 < self.network_count {
+            self.network += 1;
+            return Action::Connect {
+                network: self.network,
+                delay_ms: 0,
+            };
+        }
+
+        // Every network failed, wait before the next round
+        if self.rounds.failed() {
+            return Action::Provision;
+        }
+        self.network = 0;
+        Action::Connect {
+            network: 0,
+            delay_ms: self.backoff.next_delay(),
+        }
+    }
+
+    /// An established connection dropped. The same network is tried again
+    /// first, it was working a moment ago.
+    pub fn disconnected(&mut self) -> Action {
+        Action::Connect {
+            network: self.network,
+            delay_ms: self.backoff.next_delay(),
+        }
+    }
+
+    /// Index of the network currently used
+    pub fn network(&self) -> u8 {
+        self.network
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provisioning::MAX_CONNECT_FAILURES;
+
+    fn connect(network: u8, delay_ms: u32) -> Action {
+        Action::Connect { network, delay_ms }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(1_000, 5_000);
+        assert_eq!(backoff.next_delay(), 1_000);
+        assert_eq!(backoff.next_delay(), 2_000);
+        assert_eq!(backoff.next_delay(), 4_000);
+        assert_eq!(backoff.next_delay(), 5_000);
+        assert_eq!(backoff.next_delay(), 5_000);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), 1_000);
+    }
+
+    #[test]
+    fn test_networks_tried_in_order() {
+        let mut manager = ConnectionManager::new(3);
+        assert_eq!(manager.start(), connect(0, 0));
+        assert_eq!(manager.failed(), connect(1, 0));
+        assert_eq!(manager.failed(), connect(2, 0));
+        // A failed round backs off before starting over
+        assert_eq!(manager.failed(), connect(0, 1_000));
+        assert_eq!(manager.failed(), connect(1, 0));
+        assert_eq!(manager.failed(), connect(2, 0));
+        assert_eq!(manager.failed(), connect(0, 2_000));
+    }
+
+    #[test]
+    fn test_reconnect_after_disconnect() {
+        let mut manager = ConnectionManager::new(2);
+        manager.start();
+        assert_eq!(manager.failed(), connect(1, 0));
+        manager.connected();
+        assert_eq!(manager.network(), 1);
+        assert_eq!(manager.disconnected(), connect(1, 1_000));
+        manager.connected();
+        // The backoff starts over after a successful connection
+        assert_eq!(manager.disconnected(), connect(1, 1_000));
+    }
+
+    #[test]
+    fn test_provision_after_failed_rounds() {
+        let mut manager = ConnectionManager::new(1);
+        assert_eq!(manager.start(), connect(0, 0));
+        for _ in 1..MAX_CONNECT_FAILURES {
+            assert!(matches!(
+                manager.failed(),
+                Action::Connect { network: 0, .. }
+            ));
+        }
+        assert_eq!(manager.failed(), Action::Provision);
+
+        assert_eq!(ConnectionManager::new(0).start(), Action::Provision);
+    }
+
+    #[test]
+    fn test_firmware_state() {
+        assert_eq!(
+            ConnectionState::Connecting { network: 0 }.firmware_state(),
+            FirmwareState::Connecting
+        );
+        let connected = ConnectionState::Connected {
+            network: 0,
+            rssi: -60,
+        };
+        assert!(connected.is_connected());
+        assert_eq!(connected.firmware_state(), FirmwareState::Idle);
+    }
+}
@@ -0,0 +1,359 @@
+//! Line based command console.
+//!
+//! Bytes received over the serial port are collected into lines by
+//! [`LineBuffer`] and parsed into a [`Command`] with [`parse`].
+
+use crate::animation::StartupAnimation;
+use crate::download::Url;
+use crate::mqtt::parse_broker;
+use crate::settings::TeammateColors;
+use heapless::Vec;
+
+pub const HELP: &str = "\
+commands:
+  status              show firmware and race state
+  play                start or continue the race
+  pause               pause the race, play continues it
+  stop                stop the race or the live session
+  seek <seconds>      jump to a position in the race
+  brightness [0-255]  show or set LED brightness
+  temp                show board temperature
+  version             show firmware version
+  test-leds           run the LED self test
+  animation [name]    show or set and preview the startup animation
+  teammates [style]   show or set teammate colors: same or dimmed
+  race [session]      show or select the race by OpenF1 session key
+  race-url [url]      show or set the http:// URL to download the race from
+  download            download the race now
+  live [session]      follow a session live, from the relay at the race URL host
+  ota <url>           update the firmware from an http:// URL
+  mqtt [url|off]      show or set the mqtt:// broker to publish to
+  settings            show the saved settings
+  reset-settings      restore the default settings
+  help                show this help";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    Status,
+    Play,
+    Pause,
+    Stop,
+    Seek {
+        seconds: u32,
+    },
+    Brightness(Option<u8>),
+    Temperature,
+    Version,
+    TestLeds,
+    Animation(Option<StartupAnimation>),
+    Teammates(Option<TeammateColors>),
+    Race(Option<u32>),
+    /// A valid race URL, see [`Url::parse`]
+    RaceUrl(Option<&'a str>),
+    Download,
+    /// Follow a session live, the selected race when `None`
+    Live(Option<u32>),
+    /// A valid update file URL, see [`Url::parse`]
+    Ota(&'a str),
+    /// A valid broker URL, see [`parse_broker`], empty for `mqtt off`
+    Mqtt(Option<&'a str>),
+    Settings,
+    ResetSettings,
+    Help,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownCommand,
+    MissingArgument,
+    InvalidArgument,
+    TooManyArguments,
+}
+
+/// Parse a single line into a command
+pub fn parse(line: &str) -> Result<Command<'_>, ParseError> {
+    let mut words = line.split_ascii_whitespace();
+    let name = words.next().ok_or(ParseError::UnknownCommand)?;
+
+    let command = match name {
+        "status" => Command::Status,
+        "play" | "start" => Command::Play,
+        "pause" => Command::Pause,
+        "stop" => Command::Stop,
+        "seek" => {
+            let seconds = words.next().ok_or(ParseError::MissingArgument)?;
+            Command::Seek {
+                seconds: seconds.parse().map_err(|_| ParseError::InvalidArgument)?,
+            }
+        }
+        "brightness" => match words.next() {
+            Some(level) => Command::Brightness(Some(
+                level.parse().map_err(|_| ParseError::InvalidArgument)?,
+            )),
+            None => Command::Brightness(None),
+        },
+        "temp" => Command::Temperature,
+        "version" => Command::Version,
+        "test-leds" => Command::TestLeds,
+        "animation" => match words.next() {
+            Some(name) => Command::Animation(Some(
+                StartupAnimation::from_name(name).ok_or(ParseError::InvalidArgument)?,
+            )),
+            None => Command::Animation(None),
+        },
+        "teammates" => match words.next() {
+            Some(name) => Command::Teammates(Some(
+                TeammateColors::from_name(name).ok_or(ParseError::InvalidArgument)?,
+            )),
+            None => Command::Teammates(None),
+        },
+        "race" => match words.next() {
+            Some(session_key) => Command::Race(Some(
+                session_key
+                    .parse()
+                    .map_err(|_| ParseError::InvalidArgument)?,
+            )),
+            None => Command::Race(None),
+        },
+        "race-url" => match words.next() {
+            Some(url) => {
+                Url::parse(url).ok_or(ParseError::InvalidArgument)?;
+                Command::RaceUrl(Some(url))
+            }
+            None => Command::RaceUrl(None),
+        },
+        "download" => Command::Download,
+        "live" => match words.next() {
+            Some(session_key) => Command::Live(Some(
+                session_key
+                    .parse()
+                    .map_err(|_| ParseError::InvalidArgument)?,
+            )),
+            None => Command::Live(None),
+        },
+        "ota" => {
+            let url = words.next().ok_or(ParseError::MissingArgument)?;
+            Url::parse(url).ok_or(ParseError::InvalidArgument)?;
+            Command::Ota(url)
+        }
+        "mqtt" => match words.next() {
+            Some("off") => Command::Mqtt(Some("")),
+            Some(url) => {
+                parse_broker(url).ok_or(ParseError::InvalidArgument)?;
+                Command::Mqtt(Some(url))
+            }
+            None => Command::Mqtt(None),
+        },
+        "settings" => Command::Settings,
+        "reset-settings" => Command::ResetSettings,
+        "help" | "?" => Command::Help,
+        _ => return Err(ParseError::UnknownCommand),
+    };
+
+    if words.next().is_some() {
+        return Err(ParseError::TooManyArguments);
+    }
+    Ok(command)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineError {
+    /// The line did not fit in the buffer and was discarded
+    TooLong,
+    /// The line is not valid UTF-8
+    InvalidUtf8,
+}
+
+/// Collects received bytes into lines.
+///
+/// Lines end with `\r`, `\n` or `\r\n`. Backspace and delete remove the
+/// last character, so the console can be used from a plain terminal.
+pub struct LineBuffer<const N: usize> {
+    buf: Vec<u8, N>,
+    overflow: bool,
+    complete: bool,
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            overflow: false,
+            complete: false,
+        }
+    }
+
+    /// Add a byte to the buffer. Returns the line, without surrounding
+    /// whitespace, when `byte` completes a non-blank line.
+    /// The line borrows the buffer until the next push.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&str, LineError>> {
+        if self.complete {
+            self.buf.clear();
+            self.complete = false;
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                if core::mem::take(&mut self.overflow) {
+                    self.buf.clear();
+                    return Some(Err(LineError::TooLong));
+                }
+                if self.buf.iter().all(u8::is_ascii_whitespace) {
+                    // Blank line or the second half of "\r\n"
+                    self.buf.clear();
+                    return None;
+                }
+                self.complete = true;
+                Some(
+                    core::str::from_utf8(&self.buf)
+                        .map(str::trim)
+                        .map_err(|_| LineError::InvalidUtf8),
+                )
+            }
+            0x08 | 0x7f => {
+                self.buf.pop();
+                None
+            }
+            _ => {
+                if self.buf.push(byte).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type LineResult<'a> = Result<Result<Command<'a>, ParseError>, LineError>;
+
+    /// Feed a byte stream to a line buffer and check the parsed lines
+    fn assert_lines<const N: usize>(input: &[u8], expected: &[LineResult]) {
+        let mut lines = LineBuffer::<N>::new();
+        let mut count = 0;
+        for &byte in input {
+            if let Some(line) = lines.push(byte) {
+                assert_eq!(line.map(parse), expected[count]);
+                count += 1;
+            }
+        }
+        assert_eq!(count, expected.len());
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(parse("status"), Ok(Command::Status));
+        assert_eq!(parse("play"), Ok(Command::Play));
+        assert_eq!(parse("pause"), Ok(Command::Pause));
+        assert_eq!(parse("stop"), Ok(Command::Stop));
+        assert_eq!(parse("seek 90"), Ok(Command::Seek { seconds: 90 }));
+        assert_eq!(parse("brightness"), Ok(Command::Brightness(None)));
+        assert_eq!(parse("brightness  128"), Ok(Command::Brightness(Some(128))));
+        assert_eq!(parse("temp"), Ok(Command::Temperature));
+        assert_eq!(parse("version"), Ok(Command::Version));
+        assert_eq!(parse("test-leds"), Ok(Command::TestLeds));
+        assert_eq!(parse("animation"), Ok(Command::Animation(None)));
+        assert_eq!(
+            parse("animation rainbow"),
+            Ok(Command::Animation(Some(StartupAnimation::Rainbow)))
+        );
+        assert_eq!(
+            parse("teammates dimmed"),
+            Ok(Command::Teammates(Some(TeammateColors::Dimmed)))
+        );
+        assert_eq!(parse("race 9158"), Ok(Command::Race(Some(9158))));
+        assert_eq!(parse("race"), Ok(Command::Race(None)));
+        assert_eq!(
+            parse("race-url http://192.168.1.10:8000/monza.bin"),
+            Ok(Command::RaceUrl(Some("http://192.168.1.10:8000/monza.bin")))
+        );
+        assert_eq!(parse("race-url"), Ok(Command::RaceUrl(None)));
+        assert_eq!(parse("download"), Ok(Command::Download));
+        assert_eq!(parse("live"), Ok(Command::Live(None)));
+        assert_eq!(parse("live 9158"), Ok(Command::Live(Some(9158))));
+        assert_eq!(
+            parse("ota http://192.168.1.10:8000/f1-hardware.ota"),
+            Ok(Command::Ota("http://192.168.1.10:8000/f1-hardware.ota"))
+        );
+        assert_eq!(
+            parse("mqtt mqtt://192.168.1.5"),
+            Ok(Command::Mqtt(Some("mqtt://192.168.1.5")))
+        );
+        assert_eq!(parse("mqtt off"), Ok(Command::Mqtt(Some(""))));
+        assert_eq!(parse("mqtt"), Ok(Command::Mqtt(None)));
+        assert_eq!(parse("settings"), Ok(Command::Settings));
+        assert_eq!(parse("reset-settings"), Ok(Command::ResetSettings));
+        assert_eq!(parse("help"), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse("launch"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("seek"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("seek -1"), Err(ParseError::InvalidArgument));
+        assert_eq!(parse("brightness 256"), Err(ParseError::InvalidArgument));
+        assert_eq!(parse("animation disco"), Err(ParseError::InvalidArgument));
+        assert_eq!(parse("teammates mixed"), Err(ParseError::InvalidArgument));
+        assert_eq!(parse("race monza"), Err(ParseError::InvalidArgument));
+        assert_eq!(parse("live monza"), Err(ParseError::InvalidArgument));
+        assert_eq!(parse("ota"), Err(ParseError::MissingArgument));
+        assert_eq!(
+            parse("ota f1-hardware.ota"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            parse("race-url https://example.com/monza.bin"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(
+            parse("mqtt 192.168.1.5:1883"),
+            Err(ParseError::InvalidArgument)
+        );
+        assert_eq!(parse("stop now"), Err(ParseError::TooManyArguments));
+    }
+
+    #[test]
+    fn test_byte_stream() {
+        assert_lines::<32>(
+            b"status\r\nplay\n\n  seek 12 \rstop",
+            &[
+                Ok(Ok(Command::Status)),
+                Ok(Ok(Command::Play)),
+                Ok(Ok(Command::Seek { seconds: 12 })),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_backspace() {
+        assert_lines::<32>(
+            b"stpo\x08\x08op\r\nbrightness 99\x7f0\n",
+            &[Ok(Ok(Command::Stop)), Ok(Ok(Command::Brightness(Some(90))))],
+        );
+    }
+
+    #[test]
+    fn test_line_too_long() {
+        assert_lines::<8>(
+            b"brightness 10\nplay\n",
+            &[Err(LineError::TooLong), Ok(Ok(Command::Play))],
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        assert_lines::<8>(
+            b"\xffplay\nplay\n",
+            &[Err(LineError::InvalidUtf8), Ok(Ok(Command::Play))],
+        );
+    }
+}
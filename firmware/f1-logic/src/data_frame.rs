@@ -1,4 +1,4 @@
-#[derive(Debug, bincode::Encode, bincode::Decode, PartialEq, Default)]
+#[derive(Debug, Clone, Copy, bincode::Encode, bincode::Decode, PartialEq, Default)]
 pub struct DriverData {
     pub driver_number: u8,
     pub led_num: u8,
@@ -6,7 +6,10 @@ pub struct DriverData {
 
 pub const NUM_DRIVERS: usize = 20;
 
-#[derive(Debug, bincode::Encode, bincode::Decode, PartialEq, Default)]
+/// Time between two race frames on the board
+pub const FRAME_INTERVAL_MS: u64 = 50;
+
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode, PartialEq, Default)]
 pub struct UpdateFrame {
     pub frame: [DriverData; NUM_DRIVERS],
 }
@@ -0,0 +1,616 @@
+//! Race file download over HTTP.
+//!
+//! [`download`] requests a file on a connected socket and stores it as it
+//! arrives, race files with a [`RaceStore`], firmware updates with an
+//! [`OtaWriter`](crate::ota::OtaWriter). When part of the file is stored
+//! already, only the rest is requested with a `Range` header. A server
+//! without range support sends the whole file and the download starts over.
+//! The caller connects the socket and retries after an error, each attempt
+//! continues where the previous one stopped.
+
+use crate::race_store::{Progress, RaceError, RaceInfo, RaceStore};
+use crate::settings::{crc32_update, MAX_URL_LEN};
+use core::fmt::Write as _;
+use embedded_io_async::{Read, Write};
+use embedded_storage::nor_flash::NorFlash;
+use heapless::String;
+
+pub const HTTP_PORT: u16 = 80;
+
+/// Room for the response head and the data read at once
+const BUFFER_SIZE: usize = 1024;
+
+/// Room for the request, the URL is at most [`MAX_URL_LEN`] long
+const REQUEST_SIZE: usize = MAX_URL_LEN + 128;
+
+/// Parts of a `http://host[:port][/path]` URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Url<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    pub path: &'a str,
+}
+
+impl<'a> Url<'a> {
+    /// Parse a plain HTTP URL, HTTPS is not supported
+    pub fn parse(url: &'a str) -> Option<Self> {
+        Self::parse_scheme(url, "http", HTTP_PORT)
+    }
+
+    /// Parse a `<scheme>://host[:port][/path]` URL, `default_port` is used
+    /// when it has no port
+    pub fn parse_scheme(url: &'a str, scheme: &str, default_port: u16) -> Option<Self> {
+        if url.len() > MAX_URL_LEN
+            || url.contains(|c: char| c.is_ascii_whitespace() || c.is_control())
+        {
+            return None;
+        }
+        let rest = url.strip_prefix(scheme)?.strip_prefix("://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (authority, default_port),
+        };
+        if host.is_empty() || host.contains('@') || port == 0 {
+            return None;
+        }
+        Some(Self { host, port, path })
+    }
+
+    /// The race file of `session_key` on the relay at the host of this URL
+    pub fn relay_race(&self, session_key: u32) -> Option<String<MAX_URL_LEN>> {
+        let mut url = String::new();
+        write!(
+            url,
+            "http://{}:{}/races/{}.bin",
+            self.host, self.port, session_key
+        )
+        .ok()?;
+        Some(url)
+    }
+}
+
+/// Where a download stores the file
+pub trait Storage {
+    type Error;
+    /// What [`finish`](Self::finish) tells about the stored file
+    type Info;
+
+    /// Progress of an unfinished download to continue, if any
+    fn progress(&self) -> Option<Progress>;
+
+    /// Start storing a new file of `size` bytes. `source_id` identifies the
+    /// file, a download only continues with data of the same file.
+    fn begin(&mut self, size: u32, source_id: u32) -> Result<(), Self::Error>;
+
+    /// Store the next part of the file
+    fn write(&mut self, data: &[u8]) -> Result<Progress, Self::Error>;
+
+    /// Complete the download after the last [`write`](Self::write)
+    fn finish(&mut self) -> Result<Self::Info, Self::Error>;
+}
+
+impl<F: NorFlash> Storage for RaceStore<F> {
+    type Error = RaceError<F::Error>;
+    type Info = RaceInfo;
+
+    fn progress(&self) -> Option<Progress> {
+        RaceStore::progress(self)
+    }
+
+    fn begin(&mut self, size: u32, source_id: u32) -> Result<(), Self::Error> {
+        RaceStore::begin(self, size, source_id)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<Progress, Self::Error> {
+        RaceStore::write(self, data)
+    }
+
+    fn finish(&mut self) -> Result<RaceInfo, Self::Error> {
+        RaceStore::finish(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadError<S, E> {
+    Socket(S),
+    Store(E),
+    /// The request does not fit the request buffer
+    InvalidUrl,
+    /// The server answered with an unexpected status code
+    Status(u16),
+    /// The response head is malformed, too long or doesn't match the request
+    InvalidResponse,
+    /// The connection closed before the file was complete
+    ConnectionClosed,
+    /// The file changed on the server since the download started. The next
+    /// attempt starts over.
+    SourceChanged,
+}
+
+impl<S, E> From<E> for DownloadError<S, E> {
+    fn from(err: E) -> Self {
+        DownloadError::Store(err)
+    }
+}
+
+/// Response head fields the download needs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct ResponseHead<'a> {
+    status: u16,
+    content_length: Option<u32>,
+    /// First byte and file size from `Content-Range`
+    content_range: Option<(u32, u32)>,
+    etag: &'a str,
+}
+
+fn parse_head(head: &str) -> Option<ResponseHead<'_>> {
+    let mut lines = head.split("\r\n");
+    let mut status_line = lines.next()?.split(' ');
+    if !status_line.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    let mut response = ResponseHead {
+        status: status_line.next()?.parse().ok()?,
+        ..Default::default()
+    };
+
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("content-length") {
+            response.content_length = Some(value.parse().ok()?);
+        } else if name.eq_ignore_ascii_case("content-range") {
+            // bytes <first>-<last>/<size>
+            let (range, size) = value.strip_prefix("bytes ")?.split_once('/')?;
+            let (first, _) = range.split_once('-')?;
+            response.content_range = Some((first.parse().ok()?, size.parse().ok()?));
+        } else if name.eq_ignore_ascii_case("etag") {
+            response.etag = value;
+        } else if name.eq_ignore_ascii_case("transfer-encoding") {
+            // Chunked bodies are not supported
+            return None;
+        }
+    }
+    Some(response)
+}
+
+/// Identifies a file on the server, a changed file has a different ETag
+fn source_id(url: &Url, etag: &str, size: u32) -> u32 {
+    let mut crc = crc32_update(0xFFFF_FFFF, url.host.as_bytes());
+    crc = crc32_update(crc, &url.port.to_le_bytes());
+    crc = crc32_update(crc, url.path.as_bytes());
+    crc = crc32_update(crc, etag.as_bytes());
+    crc32_update(crc, &size.to_le_bytes()) ^ 0xFFFF_FFFF
+}
+
+/// Download the file at `url` over the connected `socket` into `store`.
+/// `on_progress` is called whenever data was stored.
+pub async fn download<S, T>(
+    socket: &mut S,
+    url: &Url<'_>,
+    store: &mut T,
+    mut on_progress: impl FnMut(Progress),
+) -> Result<T::Info, DownloadError<S::Error, T::Error>>
+where
+    S: Read + Write,
+    T: Storage,
+{
+    let resume = store.progress().filter(|progress| progress.stored > 0);
+
+    let mut request: String<REQUEST_SIZE> = String::new();
+    write!(request, "GET {} HTTP/1.1\r\nHost: {}", url.path, url.host)
+        .map_err(|_| DownloadError::InvalidUrl)?;
+    if url.port != HTTP_PORT {
+        write!(request, ":{}", url.port).map_err(|_| DownloadError::InvalidUrl)?;
+    }
+    if let Some(progress) = resume {
+        write!(request, "\r\nRange: bytes={}-", progress.stored)
+            .map_err(|_| DownloadError::InvalidUrl)?;
+    }
+    write!(request, "\r\nConnection: close\r\n\r\n").map_err(|_| DownloadError::InvalidUrl)?;
+    socket
+        .write_all(request.as_bytes())
+        .await
+        .map_err(DownloadError::Socket)?;
+    socket.flush().await.map_err(DownloadError::Socket)?;
+
+    // Read until the end of the response head
+    let mut buf = [0; BUFFER_SIZE];
+    let mut len = 0;
+    let head_end = loop {
+        if let Some(end) = buf[..len].windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if len == buf.len() {
+            return Err(DownloadError::InvalidResponse);
+        }
+        match socket.read(&mut buf[len..]).await {
+            Ok(0) => return Err(DownloadError::ConnectionClosed),
+            Ok(n) => len += n,
+            Err(e) => return Err(DownloadError::Socket(e)),
+        }
+    };
+    let head = core::str::from_utf8(&buf[..head_end])
+        .ok()
+        .and_then(parse_head)
+        .ok_or(DownloadError::InvalidResponse)?;
+
+    let (offset, size) = match (head.status, head.content_range) {
+        (200, _) => (
+            0,
+            head.content_length.ok_or(DownloadError::InvalidResponse)?,
+        ),
+        (206, Some(range)) => range,
+        (status, _) => return Err(DownloadError::Status(status)),
+    };
+    let source_id = source_id(url, head.etag, size);
+    match resume {
+        Some(progress) if offset > 0 => {
+            if offset != progress.stored {
+                return Err(DownloadError::InvalidResponse);
+            }
+            if size != progress.size || source_id != progress.source_id {
+                // Forget the old file, the next attempt starts over
+                store.begin(size, source_id)?;
+                return Err(DownloadError::SourceChanged);
+            }
+        }
+        _ if offset == 0 => store.begin(size, source_id)?,
+        _ => return Err(DownloadError::InvalidResponse),
+    }
+
+    // The rest of the first read is body data
+    let mut progress = store.write(&buf[head_end + 4..len])?;
+    on_progress(progress);
+    while progress.stored < progress.size {
+        match socket.read(&mut buf).await {
+            Ok(0) => return Err(DownloadError::ConnectionClosed),
+            Ok(n) => progress = store.write(&buf[..n])?,
+            Err(e) => return Err(DownloadError::Socket(e)),
+        }
+        on_progress(progress);
+    }
+    Ok(store.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_flash::MemFlash;
+    use crate::race_store::tests::{race_file, PARTITION};
+    use embassy_futures::block_on;
+    use embedded_storage::nor_flash::NorFlashErrorKind;
+
+    extern crate std;
+    use std::io::{self, Read as _, Write as _};
+    use std::net::{TcpListener, TcpStream};
+    use std::string::String as StdString;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::vec::Vec;
+
+    /// Blocking socket for the async download
+    struct Socket(TcpStream);
+
+    impl embedded_io_async::ErrorType for Socket {
+        type Error = io::Error;
+    }
+
+    impl Read for Socket {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Socket {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+            self.0.write(buf)
+        }
+    }
+
+    /// Stand-in for a web server serving one file
+    struct Server {
+        file: Vec<u8>,
+        etag: &'static str,
+        ranges: bool,
+        /// Close the next connection after this many body bytes
+        drop_after: Option<usize>,
+    }
+
+    struct StandIn {
+        port: u16,
+        server: Arc<Mutex<Server>>,
+        requests: Arc<Mutex<Vec<StdString>>>,
+    }
+
+    impl StandIn {
+        fn start(file: Vec<u8>, ranges: bool) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let server = Arc::new(Mutex::new(Server {
+                file,
+                etag: "\"v1\"",
+                ranges,
+                drop_after: None,
+            }));
+            let requests = Arc::new(Mutex::new(Vec::new()));
+
+            let (shared, log) = (server.clone(), requests.clone());
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let request = read_request(&mut stream);
+                    log.lock().unwrap().push(request.clone());
+                    respond(&mut stream, &request, &mut shared.lock().unwrap());
+                }
+            });
+            Self {
+                port,
+                server,
+                requests,
+            }
+        }
+
+        fn connect(&self) -> Socket {
+            Socket(TcpStream::connect(("127.0.0.1", self.port)).unwrap())
+        }
+
+        fn last_request(&self) -> StdString {
+            self.requests.lock().unwrap().last().unwrap().clone()
+        }
+    }
+
+    fn read_request(stream: &mut TcpStream) -> StdString {
+        let mut request = Vec::new();
+        let mut byte = [0];
+        while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+            request.push(byte[0]);
+        }
+        StdString::from_utf8(request).unwrap()
+    }
+
+    fn respond(stream: &mut TcpStream, request: &str, server: &mut Server) {
+        let range = request
+            .lines()
+            .find_map(|line| line.strip_prefix("Range: bytes="))
+            .map(|range| range.trim_end_matches('-').parse::<usize>().unwrap())
+            .filter(|_| server.ranges);
+        let size = server.file.len();
+        let head = match range {
+            Some(first) => std::format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nETag: {}\r\n\r\n",
+                size - first,
+                first,
+                size - 1,
+                size,
+                server.etag
+            ),
+            None => std::format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: {}\r\n\r\n",
+                size, server.etag
+            ),
+        };
+        let body = &server.file[range.unwrap_or(0)..];
+        let body = &body[..server
+            .drop_after
+            .take()
+            .unwrap_or(body.len())
+            .min(body.len())];
+        // Small writes to exercise reads that split frames. The client may
+        // hang up early.
+        let _ = stream.write_all(head.as_bytes());
+        for chunk in body.chunks(100) {
+            if stream.write_all(chunk).is_err() {
+                break;
+            }
+        }
+    }
+
+    type Error = DownloadError<io::Error, RaceError<NorFlashErrorKind>>;
+
+    fn run(
+        server: &StandIn,
+        store: &mut RaceStore<&mut MemFlash>,
+        progress: &mut Vec<Progress>,
+    ) -> core::result::Result<RaceInfo, Error> {
+        let url = std::format!("http://127.0.0.1:{}/races/monza.bin", server.port);
+        let url = Url::parse(&url).unwrap();
+        block_on(download(&mut server.connect(), &url, store, |p| {
+            progress.push(p)
+        }))
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(
+            Url::parse("http://example.com:8080/races/monza.bin"),
+            Some(Url {
+                host: "example.com",
+                port: 8080,
+                path: "/races/monza.bin"
+            })
+        );
+        assert_eq!(
+            Url::parse("http://192.168.1.10"),
+            Some(Url {
+                host: "192.168.1.10",
+                port: 80,
+                path: "/"
+            })
+        );
+        assert_eq!(Url::parse("https://example.com/race.bin"), None);
+        assert_eq!(Url::parse("http://:80/race.bin"), None);
+        assert_eq!(Url::parse("http://example.com:http/"), None);
+        assert_eq!(Url::parse("http://example.com/a b"), None);
+        assert_eq!(Url::parse("example.com/race.bin"), None);
+
+        assert_eq!(
+            Url::parse_scheme("mqtt://broker.local", "mqtt", 1883),
+            Some(Url {
+                host: "broker.local",
+                port: 1883,
+                path: "/"
+            })
+        );
+        assert_eq!(Url::parse_scheme("http://broker.local", "mqtt", 1883), None);
+    }
+
+    #[test]
+    fn test_relay_race() {
+        let url = Url::parse("http://relay.local:8080/races/9149.bin").unwrap();
+        assert_eq!(
+            url.relay_race(9158).unwrap(),
+            "http://relay.local:8080/races/9158.bin"
+        );
+        let url = Url::parse("http://192.168.1.10/").unwrap();
+        assert_eq!(
+            url.relay_race(9149).unwrap(),
+            "http://192.168.1.10:80/races/9149.bin"
+        );
+        let host = "h".repeat(MAX_URL_LEN - 10);
+        let long = std::format!("http://{}/", host);
+        assert_eq!(Url::parse(&long).unwrap().relay_race(9149), None);
+    }
+
+    #[test]
+    fn test_parse_head() {
+        let head = parse_head(
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: 60\r\ncontent-range: bytes 40-99/100\r\nETag: \"abc\"",
+        )
+        .unwrap();
+        assert_eq!(head.status, 206);
+        assert_eq!(head.content_length, Some(60));
+        assert_eq!(head.content_range, Some((40, 100)));
+        assert_eq!(head.etag, "\"abc\"");
+
+        assert_eq!(parse_head("SSH-2.0-OpenSSH"), None);
+        assert_eq!(
+            parse_head("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked"),
+            None
+        );
+    }
+
+    #[test]
+    fn download_complete_file() {
+        let file = race_file(80);
+        let server = StandIn::start(file.clone(), true);
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        let mut progress = Vec::new();
+
+        let info = run(&server, &mut store, &mut progress).unwrap();
+        assert_eq!(info.size, file.len() as u32);
+        assert_eq!(info.crc32, crate::settings::crc32(&file));
+        assert!(!server.last_request().contains("Range"));
+        assert!(server
+            .last_request()
+            .starts_with("GET /races/monza.bin HTTP/1.1\r\nHost: 127.0.0.1:"));
+        assert_eq!(progress.last().unwrap().percent(), 100);
+        assert!(progress.windows(2).all(|p| p[0].stored <= p[1].stored));
+        assert_eq!(
+            store.read_frame(79).unwrap().to_bytes().unwrap(),
+            file[3160..]
+        );
+    }
+
+    #[test]
+    fn resume_interrupted_download() {
+        let file = race_file(80);
+        let server = StandIn::start(file.clone(), true);
+        server.server.lock().unwrap().drop_after = Some(1500);
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        let mut progress = Vec::new();
+
+        let err = run(&server, &mut store, &mut progress).unwrap_err();
+        assert!(matches!(err, DownloadError::ConnectionClosed));
+        assert_eq!(store.progress().unwrap().stored, 1500);
+
+        // The next attempt continues where the first stopped
+        let info = run(&server, &mut store, &mut progress).unwrap();
+        assert!(server.last_request().contains("Range: bytes=1500-\r\n"));
+        assert_eq!(info.crc32, crate::settings::crc32(&file));
+    }
+
+    #[test]
+    fn resume_after_restart() {
+        let file = race_file(80);
+        let server = StandIn::start(file.clone(), true);
+        server.server.lock().unwrap().drop_after = Some(1500);
+        let mut flash = MemFlash::with_sectors(20);
+        let mut progress = Vec::new();
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        run(&server, &mut store, &mut progress).unwrap_err();
+        store.release();
+
+        // Only whole sectors survive the restart
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        run(&server, &mut store, &mut progress).unwrap();
+        assert!(server.last_request().contains("Range: bytes=1280-\r\n"));
+        assert_eq!(store.race().unwrap().crc32, crate::settings::crc32(&file));
+    }
+
+    #[test]
+    fn server_without_ranges() {
+        let file = race_file(80);
+        let server = StandIn::start(file.clone(), false);
+        server.server.lock().unwrap().drop_after = Some(1500);
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        let mut progress = Vec::new();
+        run(&server, &mut store, &mut progress).unwrap_err();
+
+        // The whole file comes again and replaces the partial one
+        progress.clear();
+        let info = run(&server, &mut store, &mut progress).unwrap();
+        assert!(server.last_request().contains("Range: bytes=1500-\r\n"));
+        assert!(progress[0].stored < BUFFER_SIZE as u32);
+        assert_eq!(info.crc32, crate::settings::crc32(&file));
+    }
+
+    #[test]
+    fn changed_file_starts_over() {
+        let server = StandIn::start(race_file(80), true);
+        server.server.lock().unwrap().drop_after = Some(1500);
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        let mut progress = Vec::new();
+        run(&server, &mut store, &mut progress).unwrap_err();
+
+        let file = race_file(70);
+        {
+            let mut server = server.server.lock().unwrap();
+            server.file.clone_from(&file);
+            server.etag = "\"v2\"";
+        }
+        let err = run(&server, &mut store, &mut progress).unwrap_err();
+        assert!(matches!(err, DownloadError::SourceChanged));
+        let info = run(&server, &mut store, &mut progress).unwrap();
+        assert!(!server.last_request().contains("Range"));
+        assert_eq!(info.crc32, crate::settings::crc32(&file));
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        let mut progress = Vec::new();
+
+        // Not a whole number of frames
+        let server = StandIn::start(race_file(2)[..70].to_vec(), true);
+        let err = run(&server, &mut store, &mut progress).unwrap_err();
+        assert!(matches!(err, DownloadError::Store(RaceError::InvalidSize)));
+
+        // Not race data
+        let server = StandIn::start(std::vec![0; 400], true);
+        let err = run(&server, &mut store, &mut progress).unwrap_err();
+        assert!(matches!(err, DownloadError::Store(RaceError::InvalidFrame)));
+        assert_eq!(store.race(), None);
+    }
+}
@@ -0,0 +1,230 @@
+//! Nearest-LED lookups.
+//!
+//! A k-d tree over the LED positions replaces scanning every LED for every
+//! position. [`LedIndex::nearest`] returns exactly what [`nearest_linear`]
+//! does, including the first LED of several at the same distance.
+
+use crate::track::Point;
+use heapless::Vec;
+
+/// Static k-d tree over up to `N` LED positions
+#[derive(Debug, Clone)]
+pub struct LedIndex<const N: usize> {
+    points: Vec<Point, N>,
+    /// Indices into `points`. The median of every range is its node, the
+    /// lower half its left subtree and the upper half its right one.
+    nodes: Vec<usize, N>,
+}
+
+impl<const N: usize> LedIndex<N> {
+    /// The index over `points`, `None` for more than `N`
+    pub fn new(points: impl IntoIterator<Item = Point>) -> Option<Self> {
+        let mut index = Self {
+            points: Vec::new(),
+            nodes: Vec::new(),
+        };
+        for point in points {
+            index.nodes.push(index.points.len()).ok()?;
+            index.points.push(point).ok()?;
+        }
+        build(&index.points, &mut index.nodes, 0);
+        Some(index)
+    }
+
+    /// Number of LEDs
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Index of the LED closest to `point`, `None` without LEDs
+    pub fn nearest(&self, point: Point) -> Option<usize> {
+        let mut best = None;
+        self.search(&self.nodes, 0, point, &mut best);
+        best.map(|(_, index)| index)
+    }
+
+    /// Call `f` with the index of every LED at most `radius` from `point`,
+    /// in no particular order
+    pub fn within(&self, point: Point, radius: f32, mut f: impl FnMut(usize)) {
+        self.visit(&self.nodes, 0, point, radius * radius, &mut f);
+    }
+
+    fn search(&self, nodes: &[usize], depth: usize, point: Point, best: &mut Option<(f32, usize)>) {
+        if nodes.is_empty() {
+            return;
+        }
+        let mid = nodes.len() / 2;
+        let index = nodes[mid];
+        let distance = distance_squared(self.points[index], point);
+        // The first LED wins a tie, like in the linear search
+        if best.map_or(true, |b| (distance, index) < b) {
+            *best = Some((distance, index));
+        }
+
+        let diff = split_offset(self.points[index], point, depth);
+        let (near, far) = halves(nodes, mid, diff);
+        self.search(near, depth + 1, point, best);
+        // LEDs on the far side are at least `diff` away, at the same
+        // distance they can still win by index
+        if best.map_or(true, |(d, _)| diff * diff <= d) {
+            self.search(far, depth + 1, point, best);
+        }
+    }
+
+    fn visit(
+        &self,
+        nodes: &[usize],
+        depth: usize,
+        point: Point,
+        radius_squared: f32,
+        f: &mut impl FnMut(usize),
+    ) {
+        if nodes.is_empty() {
+            return;
+        }
+        let mid = nodes.len() / 2;
+        let index = nodes[mid];
+        if distance_squared(self.points[index], point) <= radius_squared {
+            f(index);
+        }
+
+        let diff = split_offset(self.points[index], point, depth);
+        let (near, far) = halves(nodes, mid, diff);
+        self.visit(near, depth + 1, point, radius_squared, f);
+        if diff * diff <= radius_squared {
+            self.visit(far, depth + 1, point, radius_squared, f);
+        }
+    }
+}
+
+/// Order `nodes` into a subtree, splitting on x at even depths
+fn build(points: &[Point], nodes: &mut [usize], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let mid = nodes.len() / 2;
+    let key = |&i: &usize| {
+        if depth % 2 == 0 {
+            points[i].x
+        } else {
+            points[i].y
+        }
+    };
+    nodes.select_nth_unstable_by(mid, |a, b| key(a).total_cmp(&key(b)));
+    let (lower, upper) = nodes.split_at_mut(mid);
+    build(points, lower, depth + 1);
+    build(points, &mut upper[1..], depth + 1);
+}
+
+/// Offset of `point` from the splitting line through `node`
+fn split_offset(node: Point, point: Point, depth: usize) -> f32 {
+    if depth % 2 == 0 {
+        point.x - node.x
+    } else {
+        point.y - node.y
+    }
+}
+
+/// The subtree on the side of the splitting line of `point`, then the other
+fn halves(nodes: &[usize], mid: usize, diff: f32) -> (&[usize], &[usize]) {
+    if diff < 0.0 {
+        (&nodes[..mid], &nodes[mid + 1..])
+    } else {
+        (&nodes[mid + 1..], &nodes[..mid])
+    }
+}
+
+fn distance_squared(a: Point, b: Point) -> f32 {
+    (a.x - b.x) * (a.x - b.x) + (a.y - b.y) * (a.y - b.y)
+}
+
+/// Index of the LED closest to `point` by scanning all of them
+pub fn nearest_linear(points: &[Point], point: Point) -> Option<usize> {
+    (0..points.len()).min_by(|&a, &b| {
+        distance_squared(points[a], point).total_cmp(&distance_squared(points[b], point))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::led_layout::{ZANDVOORT_10X10, ZANDVOORT_20X20};
+    use std::vec::Vec;
+
+    fn index<const N: usize>(points: &[Point]) -> LedIndex<N> {
+        LedIndex::new(points.iter().copied()).unwrap()
+    }
+
+    fn assert_same<const N: usize>(index: &LedIndex<N>, points: &[Point], point: Point) {
+        assert_eq!(
+            index.nearest(point),
+            nearest_linear(points, point),
+            "at {:?}",
+            point
+        );
+        for radius in [0.5, 2.0, 10.0] {
+            let mut found = Vec::new();
+            index.within(point, radius, |i| found.push(i));
+            found.sort_unstable();
+            let expected: Vec<usize> = (0..points.len())
+                .filter(|&i| distance_squared(points[i], point) <= radius * radius)
+                .collect();
+            assert_eq!(found, expected, "at {:?} within {}", point, radius);
+        }
+    }
+
+    #[test]
+    fn test_same_as_linear() {
+        for layout in [ZANDVOORT_10X10, ZANDVOORT_20X20] {
+            let points: Vec<Point> = layout.iter().map(|l| Point::new(l.x_mm, l.y_mm)).collect();
+            let index = index::<216>(&points);
+            // A grid over the board and beyond it
+            for i in -10..=110 {
+                for j in -10..=110 {
+                    assert_same(&index, &points, Point::new(i as f32 * 2.0, j as f32 * 2.0));
+                }
+            }
+            // On the LEDs and halfway between neighbours
+            for (i, &point) in points.iter().enumerate() {
+                let next = points[(i + 1) % points.len()];
+                assert_same(&index, &points, point);
+                let between = Point::new((point.x + next.x) / 2.0, (point.y + next.y) / 2.0);
+                assert_same(&index, &points, between);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ties() {
+        // A 20x20 grid with 1 mm spacing, every LED twice
+        let points: Vec<Point> = (0..800)
+            .map(|i| Point::new((i % 400 % 20) as f32, (i % 400 / 20) as f32))
+            .collect();
+        let index = index::<800>(&points);
+        for i in -4..=84 {
+            for j in -4..=84 {
+                assert_same(
+                    &index,
+                    &points,
+                    Point::new(i as f32 * 0.25, j as f32 * 0.25),
+                );
+            }
+        }
+        assert_eq!(index.nearest(Point::new(3.0, 2.0)), Some(43));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let points = [Point::new(0.0, 0.0); 3];
+        assert!(LedIndex::<2>::new(points).is_none());
+        let index = LedIndex::<2>::new([]).unwrap();
+        assert!(index.is_empty());
+        assert!(index.nearest(Point::new(1.0, 2.0)).is_none());
+    }
+}
@@ -0,0 +1,1315 @@
+//! LED footprint positions of the supported PCB layouts.
+//!
+//! Positions are taken from the KiCad PCB files in `kicad/` and are relative
+//! to the bottom left corner of the board outline, with the y axis pointing up.
+
+/// Position of a HD108 footprint (`U<led_number>`) on the PCB
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LedPosition {
+    pub led_number: u16,
+    pub x_mm: f32,
+    pub y_mm: f32,
+    pub rotation_deg: f32,
+}
+
+/// Defined with the circuit, see `firmware/tracks/zandvoort.json`
+pub const ZANDVOORT_10X10: &[LedPosition] = crate::circuit::ZANDVOORT.leds;
+
+pub const ZANDVOORT_20X20: &[LedPosition] = &[
+    LedPosition {
+        led_number: 1,
+        x_mm: 37.6,
+        y_mm: 109.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 2,
+        x_mm: 174.6,
+        y_mm: 95.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 3,
+        x_mm: 81.6,
+        y_mm: 111.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 4,
+        x_mm: 36.6,
+        y_mm: 106.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 5,
+        x_mm: 48.6,
+        y_mm: 106.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 6,
+        x_mm: 164.6,
+        y_mm: 77.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 7,
+        x_mm: 154.6,
+        y_mm: 95.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 8,
+        x_mm: 138.6,
+        y_mm: 120.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 9,
+        x_mm: 53.6,
+        y_mm: 151.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 10,
+        x_mm: 23.6,
+        y_mm: 76.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 11,
+        x_mm: 59.6,
+        y_mm: 166.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 12,
+        x_mm: 139.6,
+        y_mm: 63.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 13,
+        x_mm: 59.6,
+        y_mm: 48.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 14,
+        x_mm: 132.6,
+        y_mm: 119.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 15,
+        x_mm: 63.6,
+        y_mm: 124.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 16,
+        x_mm: 137.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 17,
+        x_mm: 70.6,
+        y_mm: 154.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 18,
+        x_mm: 63.6,
+        y_mm: 109.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 19,
+        x_mm: 46.6,
+        y_mm: 133.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 20,
+        x_mm: 58.6,
+        y_mm: 119.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 21,
+        x_mm: 176.6,
+        y_mm: 101.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 22,
+        x_mm: 116.6,
+        y_mm: 102.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 23,
+        x_mm: 130.6,
+        y_mm: 83.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 24,
+        x_mm: 59.6,
+        y_mm: 54.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 25,
+        x_mm: 54.6,
+        y_mm: 106.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 26,
+        x_mm: 54.6,
+        y_mm: 154.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 27,
+        x_mm: 142.6,
+        y_mm: 62.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 28,
+        x_mm: 165.6,
+        y_mm: 80.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 29,
+        x_mm: 93.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 30,
+        x_mm: 95.6,
+        y_mm: 97.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 31,
+        x_mm: 18.6,
+        y_mm: 55.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 32,
+        x_mm: 65.6,
+        y_mm: 139.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 33,
+        x_mm: 39.6,
+        y_mm: 37.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 34,
+        x_mm: 162.6,
+        y_mm: 119.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 35,
+        x_mm: 59.6,
+        y_mm: 51.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 36,
+        x_mm: 32.6,
+        y_mm: 97.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 37,
+        x_mm: 175.6,
+        y_mm: 110.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 38,
+        x_mm: 131.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 39,
+        x_mm: 104.6,
+        y_mm: 100.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 40,
+        x_mm: 21.6,
+        y_mm: 70.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 41,
+        x_mm: 64.6,
+        y_mm: 127.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 42,
+        x_mm: 25.6,
+        y_mm: 79.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 43,
+        x_mm: 173.6,
+        y_mm: 113.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 44,
+        x_mm: 176.6,
+        y_mm: 104.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 45,
+        x_mm: 119.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 46,
+        x_mm: 98.6,
+        y_mm: 98.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 47,
+        x_mm: 162.6,
+        y_mm: 74.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 48,
+        x_mm: 159.6,
+        y_mm: 68.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 49,
+        x_mm: 22.6,
+        y_mm: 46.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 50,
+        x_mm: 59.6,
+        y_mm: 42.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 51,
+        x_mm: 57.6,
+        y_mm: 39.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 52,
+        x_mm: 92.6,
+        y_mm: 96.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 53,
+        x_mm: 34.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 54,
+        x_mm: 24.6,
+        y_mm: 43.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 55,
+        x_mm: 61.6,
+        y_mm: 121.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 56,
+        x_mm: 45.6,
+        y_mm: 111.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 57,
+        x_mm: 127.6,
+        y_mm: 67.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 58,
+        x_mm: 33.6,
+        y_mm: 100.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 59,
+        x_mm: 135.6,
+        y_mm: 120.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 60,
+        x_mm: 54.6,
+        y_mm: 90.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 61,
+        x_mm: 169.6,
+        y_mm: 86.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 62,
+        x_mm: 48.6,
+        y_mm: 37.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 63,
+        x_mm: 110.6,
+        y_mm: 102.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 64,
+        x_mm: 61.6,
+        y_mm: 169.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 65,
+        x_mm: 41.6,
+        y_mm: 121.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 66,
+        x_mm: 69.6,
+        y_mm: 111.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 67,
+        x_mm: 128.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 68,
+        x_mm: 54.6,
+        y_mm: 81.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 69,
+        x_mm: 54.6,
+        y_mm: 78.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 70,
+        x_mm: 154.6,
+        y_mm: 63.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 71,
+        x_mm: 171.6,
+        y_mm: 89.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 72,
+        x_mm: 45.6,
+        y_mm: 130.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 73,
+        x_mm: 153.6,
+        y_mm: 119.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 74,
+        x_mm: 90.6,
+        y_mm: 109.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 75,
+        x_mm: 59.6,
+        y_mm: 57.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 76,
+        x_mm: 145.6,
+        y_mm: 62.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 77,
+        x_mm: 75.6,
+        y_mm: 111.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 78,
+        x_mm: 58.6,
+        y_mm: 60.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 79,
+        x_mm: 102.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 80,
+        x_mm: 60.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 81,
+        x_mm: 136.6,
+        y_mm: 83.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 82,
+        x_mm: 145.6,
+        y_mm: 86.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 83,
+        x_mm: 117.6,
+        y_mm: 111.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 84,
+        x_mm: 47.6,
+        y_mm: 136.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 85,
+        x_mm: 72.6,
+        y_mm: 111.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 86,
+        x_mm: 84.6,
+        y_mm: 110.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 87,
+        x_mm: 160.6,
+        y_mm: 71.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 88,
+        x_mm: 122.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 89,
+        x_mm: 83.6,
+        y_mm: 93.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 90,
+        x_mm: 148.6,
+        y_mm: 87.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 91,
+        x_mm: 51.6,
+        y_mm: 145.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 92,
+        x_mm: 50.6,
+        y_mm: 142.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 93,
+        x_mm: 57.6,
+        y_mm: 160.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 94,
+        x_mm: 120.6,
+        y_mm: 113.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 95,
+        x_mm: 124.6,
+        y_mm: 79.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 96,
+        x_mm: 64.6,
+        y_mm: 130.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 97,
+        x_mm: 67.6,
+        y_mm: 145.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 98,
+        x_mm: 87.6,
+        y_mm: 110.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 99,
+        x_mm: 129.6,
+        y_mm: 118.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 100,
+        x_mm: 99.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 101,
+        x_mm: 78.6,
+        y_mm: 111.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 102,
+        x_mm: 59.6,
+        y_mm: 93.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 103,
+        x_mm: 173.6,
+        y_mm: 92.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 104,
+        x_mm: 151.6,
+        y_mm: 89.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 105,
+        x_mm: 139.6,
+        y_mm: 84.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 106,
+        x_mm: 52.6,
+        y_mm: 117.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 107,
+        x_mm: 73.6,
+        y_mm: 169.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 108,
+        x_mm: 70.6,
+        y_mm: 171.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 109,
+        x_mm: 43.6,
+        y_mm: 127.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 110,
+        x_mm: 157.6,
+        y_mm: 65.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 111,
+        x_mm: 40.6,
+        y_mm: 118.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 112,
+        x_mm: 133.6,
+        y_mm: 83.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 113,
+        x_mm: 101.6,
+        y_mm: 99.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 114,
+        x_mm: 146.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 115,
+        x_mm: 159.6,
+        y_mm: 119.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 116,
+        x_mm: 72.6,
+        y_mm: 160.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 117,
+        x_mm: 144.6,
+        y_mm: 120.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 118,
+        x_mm: 77.6,
+        y_mm: 91.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 119,
+        x_mm: 167.6,
+        y_mm: 83.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 120,
+        x_mm: 42.6,
+        y_mm: 124.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 121,
+        x_mm: 96.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 122,
+        x_mm: 156.6,
+        y_mm: 119.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 123,
+        x_mm: 18.6,
+        y_mm: 64.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 124,
+        x_mm: 125.6,
+        y_mm: 70.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 125,
+        x_mm: 27.6,
+        y_mm: 41.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 126,
+        x_mm: 140.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 127,
+        x_mm: 27.6,
+        y_mm: 85.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 128,
+        x_mm: 57.6,
+        y_mm: 69.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 129,
+        x_mm: 143.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 130,
+        x_mm: 130.6,
+        y_mm: 66.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 131,
+        x_mm: 18.6,
+        y_mm: 61.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 132,
+        x_mm: 113.6,
+        y_mm: 102.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 133,
+        x_mm: 58.6,
+        y_mm: 163.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 134,
+        x_mm: 51.6,
+        y_mm: 105.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 135,
+        x_mm: 133.6,
+        y_mm: 65.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 136,
+        x_mm: 42.6,
+        y_mm: 37.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 137,
+        x_mm: 68.6,
+        y_mm: 148.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 138,
+        x_mm: 175.6,
+        y_mm: 98.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 139,
+        x_mm: 151.6,
+        y_mm: 62.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 140,
+        x_mm: 52.6,
+        y_mm: 148.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 141,
+        x_mm: 154.6,
+        y_mm: 98.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 142,
+        x_mm: 126.6,
+        y_mm: 116.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 143,
+        x_mm: 31.6,
+        y_mm: 94.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 144,
+        x_mm: 19.6,
+        y_mm: 52.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 145,
+        x_mm: 66.6,
+        y_mm: 142.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 146,
+        x_mm: 64.6,
+        y_mm: 136.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 147,
+        x_mm: 141.6,
+        y_mm: 120.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 148,
+        x_mm: 20.6,
+        y_mm: 49.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 149,
+        x_mm: 123.6,
+        y_mm: 76.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 150,
+        x_mm: 39.6,
+        y_mm: 115.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 151,
+        x_mm: 105.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 152,
+        x_mm: 57.6,
+        y_mm: 107.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 153,
+        x_mm: 89.6,
+        y_mm: 95.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 154,
+        x_mm: 26.6,
+        y_mm: 82.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 155,
+        x_mm: 45.6,
+        y_mm: 37.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 156,
+        x_mm: 152.6,
+        y_mm: 101.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 157,
+        x_mm: 62.6,
+        y_mm: 92.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 158,
+        x_mm: 29.6,
+        y_mm: 91.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 159,
+        x_mm: 108.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 160,
+        x_mm: 114.6,
+        y_mm: 110.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 161,
+        x_mm: 71.6,
+        y_mm: 88.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 162,
+        x_mm: 56.6,
+        y_mm: 72.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 163,
+        x_mm: 67.6,
+        y_mm: 171.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 164,
+        x_mm: 49.6,
+        y_mm: 139.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 165,
+        x_mm: 150.6,
+        y_mm: 120.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 166,
+        x_mm: 64.6,
+        y_mm: 171.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 167,
+        x_mm: 56.6,
+        y_mm: 93.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 168,
+        x_mm: 74.6,
+        y_mm: 90.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 169,
+        x_mm: 171.6,
+        y_mm: 116.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 170,
+        x_mm: 46.6,
+        y_mm: 114.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 171,
+        x_mm: 22.6,
+        y_mm: 73.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 172,
+        x_mm: 73.6,
+        y_mm: 166.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 173,
+        x_mm: 33.6,
+        y_mm: 38.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 174,
+        x_mm: 125.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 175,
+        x_mm: 149.6,
+        y_mm: 102.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 176,
+        x_mm: 107.6,
+        y_mm: 101.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 177,
+        x_mm: 127.6,
+        y_mm: 82.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 178,
+        x_mm: 38.6,
+        y_mm: 112.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 179,
+        x_mm: 54.6,
+        y_mm: 84.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 180,
+        x_mm: 19.6,
+        y_mm: 67.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 181,
+        x_mm: 55.6,
+        y_mm: 157.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 182,
+        x_mm: 71.6,
+        y_mm: 157.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 183,
+        x_mm: 147.6,
+        y_mm: 120.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 184,
+        x_mm: 66.6,
+        y_mm: 110.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 185,
+        x_mm: 57.6,
+        y_mm: 66.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 186,
+        x_mm: 123.6,
+        y_mm: 114.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 187,
+        x_mm: 18.6,
+        y_mm: 58.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 188,
+        x_mm: 30.6,
+        y_mm: 39.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 189,
+        x_mm: 64.6,
+        y_mm: 133.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 190,
+        x_mm: 69.6,
+        y_mm: 151.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 191,
+        x_mm: 154.6,
+        y_mm: 92.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 192,
+        x_mm: 28.6,
+        y_mm: 88.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 193,
+        x_mm: 57.6,
+        y_mm: 63.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 194,
+        x_mm: 59.6,
+        y_mm: 45.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 195,
+        x_mm: 55.6,
+        y_mm: 118.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 196,
+        x_mm: 111.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 197,
+        x_mm: 134.6,
+        y_mm: 103.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 198,
+        x_mm: 142.6,
+        y_mm: 85.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 199,
+        x_mm: 54.6,
+        y_mm: 38.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 200,
+        x_mm: 136.6,
+        y_mm: 64.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 201,
+        x_mm: 123.6,
+        y_mm: 73.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 202,
+        x_mm: 36.6,
+        y_mm: 37.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 203,
+        x_mm: 168.6,
+        y_mm: 117.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 204,
+        x_mm: 86.6,
+        y_mm: 94.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 205,
+        x_mm: 176.6,
+        y_mm: 107.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 206,
+        x_mm: 45.6,
+        y_mm: 108.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 207,
+        x_mm: 65.6,
+        y_mm: 89.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 208,
+        x_mm: 148.6,
+        y_mm: 62.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 209,
+        x_mm: 51.6,
+        y_mm: 37.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 210,
+        x_mm: 165.6,
+        y_mm: 118.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 211,
+        x_mm: 73.6,
+        y_mm: 163.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 212,
+        x_mm: 49.6,
+        y_mm: 116.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 213,
+        x_mm: 54.6,
+        y_mm: 87.75,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 214,
+        x_mm: 55.75,
+        y_mm: 75.65,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 215,
+        x_mm: 80.6,
+        y_mm: 93.075,
+        rotation_deg: 0.0,
+    },
+    LedPosition {
+        led_number: 216,
+        x_mm: 68.5,
+        y_mm: 88.2,
+        rotation_deg: 0.0,
+    },
+];
@@ -1,6 +1,32 @@
 #![no_std]
 
+pub mod animation;
+mod block_writer;
+pub mod board;
+pub mod captive_portal;
+pub mod circuit;
+pub mod connection;
+pub mod console;
 pub mod data_frame;
+pub mod download;
+pub mod led_index;
+pub mod led_layout;
+#[cfg(test)]
+mod mem_flash;
+pub mod mqtt;
+pub mod ota;
+pub mod partition_table;
+pub mod provisioning;
+pub mod race;
+pub mod race_store;
+pub mod selftest;
+pub mod settings;
+pub mod status;
+pub mod stream;
+pub mod track;
+pub mod transform;
+pub mod version;
+pub mod web;
 
 #[allow(dead_code)]
 fn add(x: i32, y: i32) -> i32 {
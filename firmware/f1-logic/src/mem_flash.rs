@@ -0,0 +1,80 @@
+//! In-memory NOR flash for the storage tests.
+
+extern crate std;
+
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+use std::vec;
+use std::vec::Vec;
+
+pub const SECTOR_SIZE: usize = 256;
+
+/// NOR flash model: erasing sets bytes to 0xFF and writes can only clear bits
+pub struct MemFlash {
+    pub data: Vec<u8>,
+    pub erase_counts: Vec<u32>,
+    /// Number of bytes the next write programs before "losing power"
+    pub torn_write: Option<usize>,
+}
+
+impl MemFlash {
+    pub fn new() -> Self {
+        Self::with_sectors(8)
+    }
+
+    pub fn with_sectors(sectors: usize) -> Self {
+        Self {
+            data: vec![0xFF; SECTOR_SIZE * sectors],
+            erase_counts: vec![0; sectors],
+            torn_write: None,
+        }
+    }
+}
+
+impl ErrorType for MemFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for MemFlash {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let offset = offset as usize;
+        if offset % Self::READ_SIZE != 0 || bytes.len() % Self::READ_SIZE != 0 {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for MemFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        for sector in from as usize / SECTOR_SIZE..to as usize / SECTOR_SIZE {
+            self.data[sector * SECTOR_SIZE..(sector + 1) * SECTOR_SIZE].fill(0xFF);
+            self.erase_counts[sector] += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let offset = offset as usize;
+        if offset % Self::WRITE_SIZE != 0 || bytes.len() % Self::WRITE_SIZE != 0 {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        let len = self.torn_write.take().unwrap_or(bytes.len());
+        for (i, byte) in bytes[..len].iter().enumerate() {
+            self.data[offset + i] &= byte;
+        }
+        if len < bytes.len() {
+            return Err(NorFlashErrorKind::Other);
+        }
+        Ok(())
+    }
+}
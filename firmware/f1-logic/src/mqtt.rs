@@ -0,0 +1,1210 @@
+//! MQTT publishing and remote control.
+//!
+//! The board connects to the broker at the `mqtt://host[:port]` URL in the
+//! settings as an MQTT 3.1.1 client, everything is sent and received at QoS 0.
+//! All topics of a board start with `f1-led-circuit/<id>`, where the id is
+//! the MAC address in hex:
+//!
+//! - `.../availability` is `online` while the board is connected, the broker
+//!   publishes `offline` when the connection drops. Retained.
+//! - `.../state` carries the JSON of `/api/status` (see [`crate::web`]) every
+//!   [`STATE_INTERVAL_MS`].
+//! - `.../command/start`, `.../command/stop`, `.../command/seek` with the
+//!   position in seconds and `.../command/brightness` with 0-255 control the
+//!   board.
+//!
+//! The board announces its [`ENTITIES`] under the Home Assistant discovery
+//! prefix `homeassistant`, so they show up as one device there.
+
+use crate::download::Url;
+use crate::settings::MAX_BROKER_URL_LEN;
+use crate::version::VersionInfo;
+use crate::web::Action;
+use core::fmt::{self, Write as _};
+use embedded_io_async::{Read, Write};
+use heapless::String;
+
+pub const MQTT_PORT: u16 = 1883;
+
+/// The broker drops the board after 1.5 times this without packets
+pub const KEEP_ALIVE_S: u16 = 60;
+
+/// Time between two state updates, also keeps the connection alive
+pub const STATE_INTERVAL_MS: u64 = 5000;
+
+/// Largest packet sent or received, room for a discovery configuration
+pub const MAX_PACKET_SIZE: usize = 1024;
+
+/// Longest topic of the board
+pub const TOPIC_SIZE: usize = 96;
+
+/// Room for the configuration of one entity
+pub const CONFIG_SIZE: usize = 768;
+
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+const TOPIC_PREFIX: &str = "f1-led-circuit";
+
+/// Parse the `mqtt://host[:port]` URL of the broker
+pub fn parse_broker(url: &str) -> Option<Url<'_>> {
+    if url.len() > MAX_BROKER_URL_LEN {
+        return None;
+    }
+    Url::parse_scheme(url, "mqtt", MQTT_PORT).filter(|url| url.path == "/")
+}
+
+/// Message published by the broker when the client disconnects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Will<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub retain: bool,
+}
+
+/// The MQTT 3.1.1 packets the board uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// Connect with a clean session
+    Connect {
+        client_id: &'a str,
+        keep_alive_s: u16,
+        will: Option<Will<'a>>,
+    },
+    ConnAck {
+        return_code: u8,
+    },
+    /// Publish at QoS 0
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+        retain: bool,
+    },
+    /// Subscribe to one topic filter at QoS 0
+    Subscribe {
+        packet_id: u16,
+        filter: &'a str,
+    },
+    SubAck {
+        packet_id: u16,
+        return_code: u8,
+    },
+    PingReq,
+    PingResp,
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// More data is needed to decode the packet
+    Incomplete,
+    Malformed,
+}
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+const PROTOCOL_NAME: &str = "MQTT";
+const PROTOCOL_LEVEL: u8 = 4;
+
+// Connect flags
+const CLEAN_SESSION: u8 = 0x02;
+const WILL: u8 = 0x04;
+const WILL_RETAIN: u8 = 0x20;
+
+// Publish flags
+const RETAIN: u8 = 0x01;
+const QOS: u8 = 0x06;
+
+/// Largest remaining length field
+const MAX_LENGTH_SIZE: usize = 4;
+
+struct Writer<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.out
+            .get_mut(self.len..self.len + bytes.len())?
+            .copy_from_slice(bytes);
+        self.len += bytes.len();
+        Some(())
+    }
+
+    fn u8(&mut self, value: u8) -> Option<()> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Option<()> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Length prefixed string or binary data
+    fn data(&mut self, data: &[u8]) -> Option<()> {
+        self.u16(data.len().try_into().ok()?)?;
+        self.bytes(data)
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn data(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()?;
+        self.bytes(len as usize)
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        core::str::from_utf8(self.data()?).ok()
+    }
+
+    /// `value`, when the whole packet was read
+    fn finish<T>(self, value: T) -> Option<T> {
+        self.buf.is_empty().then_some(value)
+    }
+}
+
+impl<'a> Packet<'a> {
+    /// Encode the packet into `out`, returns its length or `None` when
+    /// `out` is too small
+    pub fn encode(&self, out: &mut [u8]) -> Option<usize> {
+        // The body goes after room for the fixed header, which needs its length
+        let (first_byte, body_len) = {
+            let mut body = Writer {
+                out: out.get_mut(1 + MAX_LENGTH_SIZE..)?,
+                len: 0,
+            };
+            let first_byte = self.encode_body(&mut body)?;
+            (first_byte, body.len)
+        };
+
+        let mut header = [first_byte, 0, 0, 0, 0];
+        let mut header_len = 1;
+        let mut remaining = body_len;
+        loop {
+            let byte = (remaining % 128) as u8;
+            remaining /= 128;
+            header[header_len] = if remaining > 0 { byte | 0x80 } else { byte };
+            header_len += 1;
+            if remaining == 0 {
+                break;
+            }
+            if header_len == header.len() {
+                return None;
+            }
+        }
+        out.copy_within(
+            1 + MAX_LENGTH_SIZE..1 + MAX_LENGTH_SIZE + body_len,
+            header_len,
+        );
+        out[..header_len].copy_from_slice(&header[..header_len]);
+        Some(header_len + body_len)
+    }
+
+    /// Write the variable header and payload, returns the first byte of the
+    /// fixed header
+    fn encode_body(&self, w: &mut Writer) -> Option<u8> {
+        match *self {
+            Packet::Connect {
+                client_id,
+                keep_alive_s,
+                will,
+            } => {
+                w.data(PROTOCOL_NAME.as_bytes())?;
+                w.u8(PROTOCOL_LEVEL)?;
+                w.u8(match will {
+                    Some(Will { retain: true, .. }) => CLEAN_SESSION | WILL | WILL_RETAIN,
+                    Some(_) => CLEAN_SESSION | WILL,
+                    None => CLEAN_SESSION,
+                })?;
+                w.u16(keep_alive_s)?;
+                w.data(client_id.as_bytes())?;
+                if let Some(will) = will {
+                    w.data(will.topic.as_bytes())?;
+                    w.data(will.payload)?;
+                }
+                Some(CONNECT << 4)
+            }
+            Packet::ConnAck { return_code } => {
+                w.bytes(&[0, return_code])?;
+                Some(CONNACK << 4)
+            }
+            Packet::Publish {
+                topic,
+                payload,
+                retain,
+            } => {
+                w.data(topic.as_bytes())?;
+                w.bytes(payload)?;
+                Some(PUBLISH << 4 | if retain { RETAIN } else { 0 })
+            }
+            Packet::Subscribe { packet_id, filter } => {
+                w.u16(packet_id)?;
+                w.data(filter.as_bytes())?;
+                w.u8(0)?;
+                // The reserved flags of SUBSCRIBE are fixed
+                Some(SUBSCRIBE << 4 | 0x02)
+            }
+            Packet::SubAck {
+                packet_id,
+                return_code,
+            } => {
+                w.u16(packet_id)?;
+                w.u8(return_code)?;
+                Some(SUBACK << 4)
+            }
+            Packet::PingReq => Some(PINGREQ << 4),
+            Packet::PingResp => Some(PINGRESP << 4),
+            Packet::Disconnect => Some(DISCONNECT << 4),
+        }
+    }
+
+    /// Decode the packet at the start of `buf`, returns it and its length
+    pub fn decode(buf: &'a [u8]) -> Result<(Self, usize), DecodeError> {
+        let first_byte = *buf.first().ok_or(DecodeError::Incomplete)?;
+        let mut body_len = 0;
+        let mut header_len = 1;
+        loop {
+            let byte = *buf.get(header_len).ok_or(DecodeError::Incomplete)?;
+            body_len += ((byte & 0x7F) as usize) << (7 * (header_len - 1));
+            header_len += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if header_len > MAX_LENGTH_SIZE {
+                return Err(DecodeError::Malformed);
+            }
+        }
+        let body = buf
+            .get(header_len..header_len + body_len)
+            .ok_or(DecodeError::Incomplete)?;
+        let packet =
+            Self::decode_body(first_byte, Reader { buf: body }).ok_or(DecodeError::Malformed)?;
+        Ok((packet, header_len + body_len))
+    }
+
+    fn decode_body(first_byte: u8, mut r: Reader<'a>) -> Option<Self> {
+        let flags = first_byte & 0x0F;
+        match first_byte >> 4 {
+            CONNECT => {
+                if r.str()? != PROTOCOL_NAME || r.u8()? != PROTOCOL_LEVEL {
+                    return None;
+                }
+                let connect_flags = r.u8()?;
+                let keep_alive_s = r.u16()?;
+                let client_id = r.str()?;
+                let will = match connect_flags & WILL {
+                    0 => None,
+                    _ => Some(Will {
+                        topic: r.str()?,
+                        payload: r.data()?,
+                        retain: connect_flags & WILL_RETAIN != 0,
+                    }),
+                };
+                r.finish(Packet::Connect {
+                    client_id,
+                    keep_alive_s,
+                    will,
+                })
+            }
+            CONNACK => {
+                let _session_present = r.u8()?;
+                let return_code = r.u8()?;
+                r.finish(Packet::ConnAck { return_code })
+            }
+            PUBLISH => {
+                let topic = r.str()?;
+                if flags & QOS != 0 {
+                    // Packet id, only sent at QoS 1 and 2
+                    r.u16()?;
+                }
+                Some(Packet::Publish {
+                    topic,
+                    payload: r.buf,
+                    retain: flags & RETAIN != 0,
+                })
+            }
+            SUBSCRIBE => {
+                let packet_id = r.u16()?;
+                let filter = r.str()?;
+                let _qos = r.u8()?;
+                r.finish(Packet::Subscribe { packet_id, filter })
+            }
+            SUBACK => {
+                let packet_id = r.u16()?;
+                let return_code = r.u8()?;
+                r.finish(Packet::SubAck {
+                    packet_id,
+                    return_code,
+                })
+            }
+            PINGREQ => r.finish(Packet::PingReq),
+            PINGRESP => r.finish(Packet::PingResp),
+            DISCONNECT => r.finish(Packet::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+/// A Home Assistant entity of the board
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entity {
+    /// Home Assistant platform, e.g. `sensor`
+    pub component: &'static str,
+    pub object_id: &'static str,
+    pub name: &'static str,
+    /// Member of the state JSON shown by the entity
+    value: Option<&'static str>,
+    /// Command topic after `command/`
+    command: Option<&'static str>,
+    /// Further members of the configuration
+    extra: &'static str,
+}
+
+pub const ENTITIES: [Entity; 7] = [
+    Entity {
+        component: "sensor",
+        object_id: "state",
+        name: "State",
+        value: Some("state"),
+        command: None,
+        extra: r#""icon":"mdi:go-kart-track""#,
+    },
+    Entity {
+        component: "sensor",
+        object_id: "temperature",
+        name: "Temperature",
+        value: Some("temperature_c"),
+        command: None,
+        extra: r#""device_class":"temperature","state_class":"measurement","unit_of_measurement":"°C""#,
+    },
+    Entity {
+        component: "sensor",
+        object_id: "position",
+        name: "Race position",
+        value: Some("position_s"),
+        command: None,
+        extra: r#""device_class":"duration","unit_of_measurement":"s""#,
+    },
+    Entity {
+        component: "button",
+        object_id: "start",
+        name: "Start",
+        value: None,
+        command: Some("start"),
+        extra: r#""icon":"mdi:play""#,
+    },
+    Entity {
+        component: "button",
+        object_id: "stop",
+        name: "Stop",
+        value: None,
+        command: Some("stop"),
+        extra: r#""icon":"mdi:stop""#,
+    },
+    Entity {
+        component: "number",
+        object_id: "seek",
+        name: "Seek",
+        value: Some("position_s"),
+        command: Some("seek"),
+        extra: r#""min":0,"max":14400,"step":1,"mode":"box","unit_of_measurement":"s""#,
+    },
+    Entity {
+        component: "number",
+        object_id: "brightness",
+        name: "Brightness",
+        value: Some("brightness"),
+        command: Some("brightness"),
+        extra: r#""min":0,"max":255,"step":1,"mode":"slider","icon":"mdi:brightness-6""#,
+    },
+];
+
+pub type Topic = String<TOPIC_SIZE>;
+
+/// Topics and discovery of one board
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    id: String<12>,
+}
+
+impl Node {
+    /// The node of the board with MAC address `mac`
+    pub fn new(mac: [u8; 6]) -> Self {
+        let mut id = String::new();
+        for byte in mac {
+            // 12 digits fit
+            let _ = write!(id, "{:02x}", byte);
+        }
+        Self { id }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Client id, short enough for any broker
+    pub fn client_id(&self) -> String<16> {
+        let mut client_id = String::new();
+        let _ = write!(client_id, "f1-{}", self.id);
+        client_id
+    }
+
+    fn topic(&self, leaf: &str) -> Topic {
+        let mut topic = String::new();
+        // Leaves are short constants, see TOPIC_SIZE
+        let _ = write!(topic, "{}/{}/{}", TOPIC_PREFIX, self.id, leaf);
+        topic
+    }
+
+    pub fn state_topic(&self) -> Topic {
+        self.topic("state")
+    }
+
+    pub fn availability_topic(&self) -> Topic {
+        self.topic("availability")
+    }
+
+    /// Filter for all command topics
+    pub fn command_filter(&self) -> Topic {
+        self.topic("command/+")
+    }
+
+    /// Where the configuration of `entity` is published for discovery
+    pub fn config_topic(&self, entity: &Entity) -> Topic {
+        let mut topic = String::new();
+        let _ = write!(
+            topic,
+            "{}/{}/{}-{}/{}/config",
+            DISCOVERY_PREFIX, entity.component, TOPIC_PREFIX, self.id, entity.object_id
+        );
+        topic
+    }
+
+    /// Write the discovery configuration of `entity`
+    pub fn write_config(
+        &self,
+        out: &mut impl fmt::Write,
+        entity: &Entity,
+        version: &VersionInfo,
+    ) -> fmt::Result {
+        write!(
+            out,
+            "{{\"name\":\"{}\",\"unique_id\":\"{}-{}-{}\",\"availability_topic\":\"{}\",",
+            entity.name,
+            TOPIC_PREFIX,
+            self.id,
+            entity.object_id,
+            self.availability_topic()
+        )?;
+        if let Some(value) = entity.value {
+            write!(
+                out,
+                "\"state_topic\":\"{}\",\"value_template\":\"{{{{ value_json.{} }}}}\",",
+                self.state_topic(),
+                value
+            )?;
+        }
+        if let Some(command) = entity.command {
+            write!(
+                out,
+                "\"command_topic\":\"{}\",",
+                self.topic(command_leaf(command).as_str())
+            )?;
+        }
+        write!(
+            out,
+            "{},\"device\":{{\"identifiers\":[\"{}-{}\"],\"name\":\"F1 LED Circuit\",\"model\":\"{}\",\"sw_version\":\"{}\"}}}}",
+            entity.extra, TOPIC_PREFIX, self.id, version.board, version.version
+        )
+    }
+
+    /// The command published to `topic`, `None` for other topics and
+    /// invalid values
+    pub fn command(&self, topic: &str, payload: &[u8]) -> Option<Action> {
+        let command = topic
+            .strip_prefix(TOPIC_PREFIX)?
+            .strip_prefix('/')?
+            .strip_prefix(self.id.as_str())?
+            .strip_prefix("/command/")?;
+        let value = || parse_number(payload);
+        match command {
+            "start" => Some(Action::Play),
+            "stop" => Some(Action::Stop),
+            "seek" => Some(Action::Seek { seconds: value()? }),
+            "brightness" => Some(Action::Brightness(value()?.try_into().ok()?)),
+            _ => None,
+        }
+    }
+}
+
+fn command_leaf(command: &str) -> String<32> {
+    let mut leaf = String::new();
+    let _ = write!(leaf, "command/{}", command);
+    leaf
+}
+
+/// A whole number, Home Assistant sends numbers like `90.0`
+fn parse_number(payload: &[u8]) -> Option<u32> {
+    let text = core::str::from_utf8(payload).ok()?.trim();
+    text.parse().ok().or_else(|| {
+        let number: f32 = text.parse().ok()?;
+        (number.is_finite() && number >= 0.0 && number <= u32::MAX as f32)
+            .then_some((number + 0.5) as u32)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MqttError<E> {
+    Io(E),
+    /// The broker closed the connection
+    Closed,
+    /// The broker sent something that is not MQTT
+    Malformed,
+    /// A packet larger than [`MAX_PACKET_SIZE`]
+    TooLarge,
+    /// The broker refused the connection with this return code
+    Refused(u8),
+    /// The broker sent a packet that makes no sense at this point
+    Unexpected,
+}
+
+impl<E> From<E> for MqttError<E> {
+    fn from(err: E) -> Self {
+        MqttError::Io(err)
+    }
+}
+
+/// Send `packet` to the broker
+pub async fn send<W: Write>(
+    socket: &mut W,
+    packet: &Packet<'_>,
+) -> Result<(), MqttError<W::Error>> {
+    let mut buf = [0; MAX_PACKET_SIZE];
+    let len = packet.encode(&mut buf).ok_or(MqttError::TooLarge)?;
+    socket.write_all(&buf[..len]).await?;
+    socket.flush().await?;
+    Ok(())
+}
+
+/// Reads packets from the broker
+pub struct Receiver {
+    buf: [u8; MAX_PACKET_SIZE],
+    len: usize,
+    /// Length of the packet returned last
+    consumed: usize,
+}
+
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Receiver {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_PACKET_SIZE],
+            len: 0,
+            consumed: 0,
+        }
+    }
+
+    /// Read the next packet. Cancelling it loses no data.
+    pub async fn receive<R: Read>(
+        &mut self,
+        socket: &mut R,
+    ) -> Result<Packet<'_>, MqttError<R::Error>> {
+        self.buf.copy_within(self.consumed..self.len, 0);
+        self.len -= self.consumed;
+        self.consumed = 0;
+        loop {
+            match Packet::decode(&self.buf[..self.len]) {
+                Ok((_, len)) => {
+                    self.consumed = len;
+                    break;
+                }
+                Err(DecodeError::Incomplete) if self.len < self.buf.len() => {}
+                Err(DecodeError::Incomplete) => return Err(MqttError::TooLarge),
+                Err(DecodeError::Malformed) => return Err(MqttError::Malformed),
+            }
+            match socket.read(&mut self.buf[self.len..]).await? {
+                0 => return Err(MqttError::Closed),
+                n => self.len += n,
+            }
+        }
+        Packet::decode(&self.buf[..self.consumed])
+            .map(|(packet, _)| packet)
+            .map_err(|_| MqttError::Malformed)
+    }
+}
+
+/// Connect to the broker as `node`. The broker marks the node offline when
+/// the connection drops.
+pub async fn connect<R: Read, W: Write<Error = R::Error>>(
+    reader: &mut R,
+    writer: &mut W,
+    receiver: &mut Receiver,
+    node: &Node,
+) -> Result<(), MqttError<R::Error>> {
+    let availability = node.availability_topic();
+    let client_id = node.client_id();
+    let connect = Packet::Connect {
+        client_id: &client_id,
+        keep_alive_s: KEEP_ALIVE_S,
+        will: Some(Will {
+            topic: &availability,
+            payload: b"offline",
+            retain: true,
+        }),
+    };
+    send(writer, &connect).await?;
+    match receiver.receive(reader).await? {
+        Packet::ConnAck { return_code: 0 } => Ok(()),
+        Packet::ConnAck { return_code } => Err(MqttError::Refused(return_code)),
+        _ => Err(MqttError::Unexpected),
+    }
+}
+
+/// Publish the discovery configuration, mark the node online and subscribe
+/// to its commands
+pub async fn announce<W: Write>(
+    writer: &mut W,
+    node: &Node,
+    version: &VersionInfo,
+) -> Result<(), MqttError<W::Error>> {
+    for entity in &ENTITIES {
+        let mut config: String<CONFIG_SIZE> = String::new();
+        node.write_config(&mut config, entity, version)
+            .map_err(|_| MqttError::TooLarge)?;
+        let topic = node.config_topic(entity);
+        send(writer, &publish(&topic, config.as_bytes(), true)).await?;
+    }
+    let availability = node.availability_topic();
+    send(writer, &publish(&availability, b"online", true)).await?;
+    let filter = node.command_filter();
+    send(
+        writer,
+        &Packet::Subscribe {
+            packet_id: 1,
+            filter: &filter,
+        },
+    )
+    .await
+}
+
+fn publish<'a>(topic: &'a str, payload: &'a [u8], retain: bool) -> Packet<'a> {
+    Packet::Publish {
+        topic,
+        payload,
+        retain,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::FirmwareState;
+    use crate::web::{self, Status};
+    use embassy_futures::block_on;
+
+    extern crate std;
+    use std::io::{self, Read as _, Write as _};
+    use std::net::{TcpListener, TcpStream};
+    use std::string::String as StdString;
+    use std::thread;
+    use std::vec::Vec;
+
+    const VERSION: VersionInfo = VersionInfo {
+        version: "0.1.0",
+        git_commit: "715838c",
+        build_timestamp: "2024-08-08T12:00:00Z",
+        board: "esp32c3",
+        features: "wifi,mqtt",
+    };
+
+    const MAC: [u8; 6] = [0x34, 0x85, 0x18, 0x0a, 0xbc, 0xde];
+
+    fn encode(packet: Packet) -> Vec<u8> {
+        let mut buf = [0; MAX_PACKET_SIZE];
+        let len = packet.encode(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn test_parse_broker() {
+        let url = parse_broker("mqtt://192.168.1.5").unwrap();
+        assert_eq!((url.host, url.port), ("192.168.1.5", MQTT_PORT));
+        let url = parse_broker("mqtt://broker.local:11883").unwrap();
+        assert_eq!((url.host, url.port), ("broker.local", 11883));
+        assert_eq!(parse_broker("mqtt://broker.local/topic"), None);
+        assert_eq!(parse_broker("mqtts://broker.local"), None);
+        assert_eq!(parse_broker("http://broker.local"), None);
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(
+            encode(Packet::Connect {
+                client_id: "f1",
+                keep_alive_s: 60,
+                will: Some(Will {
+                    topic: "a/b",
+                    payload: b"off",
+                    retain: true
+                }),
+            }),
+            b"\x10\x18\x00\x04MQTT\x04\x26\x00\x3c\x00\x02f1\x00\x03a/b\x00\x03off"
+        );
+        assert_eq!(
+            encode(Packet::Connect {
+                client_id: "f1",
+                keep_alive_s: 60,
+                will: None,
+            }),
+            b"\x10\x0e\x00\x04MQTT\x04\x02\x00\x3c\x00\x02f1"
+        );
+        assert_eq!(
+            encode(publish("a/b", b"42", true)),
+            b"\x31\x07\x00\x03a/b42"
+        );
+        assert_eq!(
+            encode(Packet::Subscribe {
+                packet_id: 1,
+                filter: "a/+"
+            }),
+            b"\x82\x08\x00\x01\x00\x03a/+\x00"
+        );
+        assert_eq!(encode(Packet::PingReq), b"\xc0\x00");
+        assert_eq!(encode(Packet::Disconnect), b"\xe0\x00");
+
+        // Two bytes of remaining length
+        let payload = [b'x'; 200];
+        let packet = encode(publish("t", &payload, false));
+        assert_eq!(&packet[..5], b"\x30\xcb\x01\x00\x01");
+        assert_eq!(packet.len(), 3 + 203);
+
+        let mut small = [0; 8];
+        assert_eq!(publish("t", &payload, false).encode(&mut small), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        for packet in [
+            Packet::Connect {
+                client_id: "f1-348518",
+                keep_alive_s: 30,
+                will: Some(Will {
+                    topic: "t",
+                    payload: b"offline",
+                    retain: false,
+                }),
+            },
+            Packet::ConnAck { return_code: 5 },
+            publish("a/b", &[b'x'; 300], true),
+            Packet::Subscribe {
+                packet_id: 7,
+                filter: "a/#",
+            },
+            Packet::SubAck {
+                packet_id: 7,
+                return_code: 0,
+            },
+            Packet::PingReq,
+            Packet::PingResp,
+            Packet::Disconnect,
+        ] {
+            let bytes = encode(packet);
+            assert_eq!(Packet::decode(&bytes), Ok((packet, bytes.len())));
+            assert_eq!(
+                Packet::decode(&bytes[..bytes.len() - 1]),
+                Err(DecodeError::Incomplete)
+            );
+        }
+
+        // QoS 1 publish from a broker that ignores the requested QoS
+        assert_eq!(
+            Packet::decode(b"\x32\x07\x00\x01t\x00\x0542"),
+            Ok((publish("t", b"42", false), 9))
+        );
+        // Trailing bytes belong to the next packet
+        assert_eq!(Packet::decode(b"\xd0\x00\x20"), Ok((Packet::PingResp, 2)));
+        assert_eq!(Packet::decode(b""), Err(DecodeError::Incomplete));
+        assert_eq!(Packet::decode(b"\xf0\x00"), Err(DecodeError::Malformed));
+        assert_eq!(Packet::decode(b"\xd0\x01\x00"), Err(DecodeError::Malformed));
+        assert_eq!(
+            Packet::decode(b"\x30\xff\xff\xff\xff\x01"),
+            Err(DecodeError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_topics() {
+        let node = Node::new(MAC);
+        assert_eq!(node.id(), "3485180abcde");
+        assert_eq!(node.client_id(), "f1-3485180abcde");
+        assert_eq!(node.state_topic(), "f1-led-circuit/3485180abcde/state");
+        assert_eq!(
+            node.command_filter(),
+            "f1-led-circuit/3485180abcde/command/+"
+        );
+        assert_eq!(
+            node.config_topic(&ENTITIES[1]),
+            "homeassistant/sensor/f1-led-circuit-3485180abcde/temperature/config"
+        );
+    }
+
+    #[test]
+    fn test_discovery() {
+        let node = Node::new(MAC);
+        let config = |object_id: &str| {
+            let entity = ENTITIES.iter().find(|e| e.object_id == object_id).unwrap();
+            let mut config: String<CONFIG_SIZE> = String::new();
+            node.write_config(&mut config, entity, &VERSION).unwrap();
+            config
+        };
+
+        assert_eq!(
+            config("temperature"),
+            concat!(
+                r#"{"name":"Temperature","unique_id":"f1-led-circuit-3485180abcde-temperature","#,
+                r#""availability_topic":"f1-led-circuit/3485180abcde/availability","#,
+                r#""state_topic":"f1-led-circuit/3485180abcde/state","#,
+                r#""value_template":"{{ value_json.temperature_c }}","#,
+                r#""device_class":"temperature","state_class":"measurement","unit_of_measurement":"°C","#,
+                r#""device":{"identifiers":["f1-led-circuit-3485180abcde"],"name":"F1 LED Circuit","#,
+                r#""model":"esp32c3","sw_version":"0.1.0"}}"#
+            )
+        );
+        let start = config("start");
+        assert!(start.contains(r#""command_topic":"f1-led-circuit/3485180abcde/command/start""#));
+        assert!(!start.contains("state_topic"));
+        let brightness = config("brightness");
+        assert!(brightness.contains(r#""value_template":"{{ value_json.brightness }}""#));
+        assert!(brightness
+            .contains(r#""command_topic":"f1-led-circuit/3485180abcde/command/brightness""#));
+
+        // Every configuration fits a packet
+        for entity in &ENTITIES {
+            let config = config(entity.object_id);
+            let topic = node.config_topic(entity);
+            let mut buf = [0; MAX_PACKET_SIZE];
+            assert!(publish(&topic, config.as_bytes(), true)
+                .encode(&mut buf)
+                .is_some());
+        }
+
+        // The values exist in the state
+        let mut state: String<{ web::JSON_SIZE }> = String::new();
+        web::write_status(&mut state, &status()).unwrap();
+        for value in ENTITIES.iter().filter_map(|e| e.value) {
+            let mut member: String<32> = String::new();
+            write!(member, "\"{}\":", value).unwrap();
+            assert!(state.contains(member.as_str()), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let node = Node::new(MAC);
+        let command = |leaf: &str, payload: &str| {
+            let mut topic: String<TOPIC_SIZE> = String::new();
+            write!(topic, "f1-led-circuit/3485180abcde/{}", leaf).unwrap();
+            node.command(&topic, payload.as_bytes())
+        };
+        assert_eq!(command("command/start", "PRESS"), Some(Action::Play));
+        assert_eq!(command("command/stop", ""), Some(Action::Stop));
+        assert_eq!(
+            command("command/seek", "90"),
+            Some(Action::Seek { seconds: 90 })
+        );
+        assert_eq!(
+            command("command/seek", "90.6"),
+            Some(Action::Seek { seconds: 91 })
+        );
+        assert_eq!(
+            command("command/brightness", " 40.0\n"),
+            Some(Action::Brightness(40))
+        );
+        assert_eq!(command("command/brightness", "256"), None);
+        assert_eq!(command("command/seek", "-1"), None);
+        assert_eq!(command("command/seek", "soon"), None);
+        assert_eq!(command("command/reboot", ""), None);
+        assert_eq!(command("state", ""), None);
+        assert_eq!(
+            node.command("f1-led-circuit/000000000000/command/start", b""),
+            None
+        );
+    }
+
+    fn status() -> Status {
+        Status {
+            state: FirmwareState::Racing,
+            playing: true,
+            frame: 1200,
+            frame_count: 36000,
+            frame_interval_ms: 50,
+            session_key: 9158,
+            brightness: 128,
+            temperature_c: Some(41.5),
+            version: VERSION,
+        }
+    }
+
+    /// Blocking socket for the async client
+    struct Socket(TcpStream);
+
+    impl embedded_io_async::ErrorType for Socket {
+        type Error = io::Error;
+    }
+
+    impl Read for Socket {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for Socket {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+            self.0.write(buf)
+        }
+    }
+
+    /// What the broker stand-in received, decoded
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Received {
+        Connect {
+            client_id: StdString,
+            will: (StdString, Vec<u8>, bool),
+        },
+        Publish(StdString, Vec<u8>, bool),
+        Subscribe(StdString),
+        Disconnect,
+    }
+
+    /// Stand-in for a broker, serving one client. It answers the
+    /// subscription with `commands` published to the client and returns
+    /// what it received once the client disconnects.
+    fn start_broker(
+        commands: Vec<(StdString, Vec<u8>)>,
+    ) -> (u16, thread::JoinHandle<Vec<Received>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut buf = Vec::new();
+            loop {
+                let (packet, len) = loop {
+                    match Packet::decode(&buf) {
+                        Ok((_, len)) => break (Packet::decode(&buf).unwrap().0, len),
+                        Err(DecodeError::Incomplete) => {}
+                        Err(err) => panic!("{:?}", err),
+                    }
+                    let mut chunk = [0; 256];
+                    let n = stream.read(&mut chunk).unwrap();
+                    assert!(n > 0, "client hung up without DISCONNECT");
+                    buf.extend_from_slice(&chunk[..n]);
+                };
+                let reply = |stream: &mut TcpStream, packet: Packet| {
+                    stream.write_all(&encode(packet)).unwrap();
+                };
+                match packet {
+                    Packet::Connect {
+                        client_id, will, ..
+                    } => {
+                        let will = will.unwrap();
+                        received.push(Received::Connect {
+                            client_id: client_id.into(),
+                            will: (will.topic.into(), will.payload.to_vec(), will.retain),
+                        });
+                        reply(&mut stream, Packet::ConnAck { return_code: 0 });
+                    }
+                    Packet::Publish {
+                        topic,
+                        payload,
+                        retain,
+                    } => received.push(Received::Publish(topic.into(), payload.to_vec(), retain)),
+                    Packet::Subscribe { packet_id, filter } => {
+                        received.push(Received::Subscribe(filter.into()));
+                        reply(
+                            &mut stream,
+                            Packet::SubAck {
+                                packet_id,
+                                return_code: 0,
+                            },
+                        );
+                        // Sent in one write, the client splits them
+                        let mut out = Vec::new();
+                        for (topic, payload) in &commands {
+                            out.extend(encode(publish(topic, payload, false)));
+                        }
+                        stream.write_all(&out).unwrap();
+                    }
+                    Packet::PingReq => reply(&mut stream, Packet::PingResp),
+                    Packet::Disconnect => {
+                        received.push(Received::Disconnect);
+                        return received;
+                    }
+                    packet => panic!("unexpected {:?}", packet),
+                }
+                buf.drain(..len);
+            }
+        });
+        (port, handle)
+    }
+
+    #[test]
+    fn test_session_with_broker() {
+        let node = Node::new(MAC);
+        let command_topic =
+            |leaf: &str| std::format!("f1-led-circuit/3485180abcde/command/{}", leaf);
+        let (port, broker) = start_broker(std::vec![
+            (command_topic("seek"), b"90".to_vec()),
+            ("other/topic".into(), b"1".to_vec()),
+            (command_topic("brightness"), b"40.0".to_vec()),
+            (command_topic("start"), b"PRESS".to_vec()),
+        ]);
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut reader = Socket(stream.try_clone().unwrap());
+        let mut writer = Socket(stream);
+        let mut receiver = Receiver::new();
+        let actions = block_on(async {
+            connect(&mut reader, &mut writer, &mut receiver, &node)
+                .await
+                .unwrap();
+            announce(&mut writer, &node, &VERSION).await.unwrap();
+
+            let mut actions = Vec::new();
+            while actions.len() < 3 {
+                match receiver.receive(&mut reader).await.unwrap() {
+                    Packet::SubAck { return_code, .. } => assert_eq!(return_code, 0),
+                    Packet::Publish { topic, payload, .. } => {
+                        actions.extend(node.command(topic, payload))
+                    }
+                    packet => panic!("unexpected {:?}", packet),
+                }
+            }
+
+            let mut state: String<{ web::JSON_SIZE }> = String::new();
+            web::write_status(&mut state, &status()).unwrap();
+            let state_topic = node.state_topic();
+            send(&mut writer, &publish(&state_topic, state.as_bytes(), false))
+                .await
+                .unwrap();
+            send(&mut writer, &Packet::PingReq).await.unwrap();
+            assert_eq!(
+                receiver.receive(&mut reader).await.unwrap(),
+                Packet::PingResp
+            );
+            send(&mut writer, &Packet::Disconnect).await.unwrap();
+            actions
+        });
+        assert_eq!(
+            actions,
+            [
+                Action::Seek { seconds: 90 },
+                Action::Brightness(40),
+                Action::Play
+            ]
+        );
+
+        let received = broker.join().unwrap();
+        assert_eq!(
+            received[0],
+            Received::Connect {
+                client_id: "f1-3485180abcde".into(),
+                will: (
+                    "f1-led-circuit/3485180abcde/availability".into(),
+                    b"offline".to_vec(),
+                    true
+                ),
+            }
+        );
+        // Discovery, then online, then the subscription
+        let configs: Vec<_> = received[1..=ENTITIES.len()]
+            .iter()
+            .map(|r| match r {
+                Received::Publish(topic, _, true) => topic.as_str(),
+                r => panic!("expected a retained config, got {:?}", r),
+            })
+            .collect();
+        assert!(configs
+            .iter()
+            .all(|t| t.starts_with("homeassistant/") && t.ends_with("/config")));
+        let rest = &received[1 + ENTITIES.len()..];
+        assert_eq!(
+            rest[0],
+            Received::Publish(
+                "f1-led-circuit/3485180abcde/availability".into(),
+                b"online".to_vec(),
+                true
+            )
+        );
+        assert_eq!(
+            rest[1],
+            Received::Subscribe("f1-led-circuit/3485180abcde/command/+".into())
+        );
+        match &rest[2] {
+            Received::Publish(topic, payload, false) => {
+                assert_eq!(topic, "f1-led-circuit/3485180abcde/state");
+                assert!(payload.starts_with(br#"{"state":"racing","playing":true"#));
+            }
+            r => panic!("expected the state, got {:?}", r),
+        }
+        assert_eq!(rest[3], Received::Disconnect);
+        assert_eq!(rest.len(), 4);
+    }
+
+    #[test]
+    fn test_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 256];
+            let _ = stream.read(&mut buf).unwrap();
+            // Not authorized
+            stream.write_all(b"\x20\x02\x00\x05").unwrap();
+        });
+
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut reader = Socket(stream.try_clone().unwrap());
+        let mut writer = Socket(stream);
+        let result = block_on(connect(
+            &mut reader,
+            &mut writer,
+            &mut Receiver::new(),
+            &Node::new(MAC),
+        ));
+        assert!(matches!(result, Err(MqttError::Refused(5))));
+        broker.join().unwrap();
+    }
+}
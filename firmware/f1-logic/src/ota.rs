@@ -0,0 +1,907 @@
+//! Over-the-air firmware updates.
+//!
+//! An update file is an [`ImageHeader`] followed by the app image, as
+//! written by `espflash save-image`, see the `ota_image` tool. [`OtaWriter`]
+//! checks the header against the running firmware before it erases anything,
+//! writes the image to the app partition that is not running and checks its
+//! size and SHA-256, also after reading it back from flash.
+//!
+//! [`OtaData`] selects the app partition the bootloader starts, in the
+//! `otadata` format of ESP-IDF. The bootloader flashed by espflash does not
+//! roll back, the firmware does: a new image starts in trial, and a trial
+//! image that restarts before it marks itself valid is aborted. The
+//! bootloader then starts the previous image again. Both partitions come
+//! from the flashed partition table, see `partition_table` and
+//! `partitions-ota.csv`.
+//!
+//! The header, strings NUL padded and numbers little endian:
+//!
+//! | offset | content                                  |
+//! |--------|------------------------------------------|
+//! | 0      | magic `F1OT`                             |
+//! | 4      | image size                               |
+//! | 8      | SHA-256 of the image                     |
+//! | 40     | firmware version, e.g. `0.2.0`           |
+//! | 56     | git commit                               |
+//! | 72     | board                                    |
+//! | 124    | CRC-32 of the header before it           |
+
+use crate::block_writer::{BlockWriter, BLOCK_SIZE};
+use crate::download::Storage;
+use crate::race_store::Progress;
+use crate::settings::{crc32, crc32_update};
+use crate::version::VersionInfo;
+use core::fmt;
+use core::ops::Range;
+use embedded_storage::nor_flash::NorFlash;
+use heapless::String;
+use sha2::{Digest, Sha256};
+
+pub const HEADER_SIZE: usize = 128;
+
+const MAGIC: [u8; 4] = *b"F1OT";
+
+/// First byte of an ESP app image
+const IMAGE_MAGIC: u8 = 0xE9;
+
+const VERSION_FIELD: Range<usize> = 40..56;
+const COMMIT_FIELD: Range<usize> = 56..72;
+const BOARD_FIELD: Range<usize> = 72..88;
+const CRC_OFFSET: usize = HEADER_SIZE - 4;
+
+/// A `major.minor.patch` firmware version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u16,
+    pub minor: u16,
+    pub patch: u16,
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.split('.').map(|part| part.parse().ok());
+        let version = Self {
+            major: parts.next()??,
+            minor: parts.next()??,
+            patch: parts.next()??,
+        };
+        parts.next().is_none().then_some(version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageError {
+    /// Not an update file, or the header is damaged
+    InvalidHeader,
+    /// Built for another board
+    WrongBoard,
+    /// Older than the running firmware
+    Downgrade,
+    /// Empty, larger than the app partition or not the size of the header
+    InvalidSize,
+    /// The data after the header is not an app image
+    NotAnImage,
+    /// The image does not match the SHA-256 of the header
+    HashMismatch,
+}
+
+/// Describes the image of an update file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub image_size: u32,
+    pub sha256: [u8; 32],
+    pub version: Version,
+    pub git_commit: String<16>,
+    pub board: String<16>,
+}
+
+impl ImageHeader {
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4..8].copy_from_slice(&self.image_size.to_le_bytes());
+        bytes[8..40].copy_from_slice(&self.sha256);
+        let mut version: String<16> = String::new();
+        // The longest version, 65535.65535.65535, fits
+        let _ = fmt::Write::write_fmt(&mut version, format_args!("{}", self.version));
+        put_str(&mut bytes[VERSION_FIELD], &version);
+        put_str(&mut bytes[COMMIT_FIELD], &self.git_commit);
+        put_str(&mut bytes[BOARD_FIELD], &self.board);
+        let crc = crc32(&bytes[..CRC_OFFSET]);
+        bytes[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8; HEADER_SIZE]) -> Result<Self, ImageError> {
+        let word =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        if bytes[..4] != MAGIC || word(CRC_OFFSET) != crc32(&bytes[..CRC_OFFSET]) {
+            return Err(ImageError::InvalidHeader);
+        }
+        let version: String<16> =
+            get_str(&bytes[VERSION_FIELD]).ok_or(ImageError::InvalidHeader)?;
+        Ok(Self {
+            image_size: word(4),
+            sha256: bytes[8..40].try_into().unwrap(),
+            version: Version::parse(&version).ok_or(ImageError::InvalidHeader)?,
+            git_commit: get_str(&bytes[COMMIT_FIELD]).ok_or(ImageError::InvalidHeader)?,
+            board: get_str(&bytes[BOARD_FIELD]).ok_or(ImageError::InvalidHeader)?,
+        })
+    }
+
+    /// Check that the image can replace the `running` firmware in an app
+    /// partition of `max_size` bytes
+    pub fn check(&self, running: &VersionInfo, max_size: u32) -> Result<(), ImageError> {
+        if self.board != running.board {
+            return Err(ImageError::WrongBoard);
+        }
+        // A development build with an odd version can install any update
+        if Version::parse(running.version).is_some_and(|version| self.version < version) {
+            return Err(ImageError::Downgrade);
+        }
+        if self.image_size == 0 || self.image_size > max_size {
+            return Err(ImageError::InvalidSize);
+        }
+        Ok(())
+    }
+}
+
+fn put_str(field: &mut [u8], s: &str) {
+    let len = s.len().min(field.len());
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+fn get_str<const N: usize>(field: &[u8]) -> Option<String<N>> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    let s = core::str::from_utf8(&field[..len]).ok()?;
+    s.try_into().ok()
+}
+
+/// Checks an update file as it arrives
+pub struct ImageVerifier {
+    running: VersionInfo,
+    max_size: u32,
+    head: [u8; HEADER_SIZE],
+    head_len: usize,
+    header: Option<ImageHeader>,
+    sha256: Sha256,
+    received: u32,
+}
+
+impl ImageVerifier {
+    /// Verify an update of the `running` firmware for an app partition of
+    /// `max_size` bytes
+    pub fn new(running: VersionInfo, max_size: u32) -> Self {
+        Self {
+            running,
+            max_size,
+            head: [0; HEADER_SIZE],
+            head_len: 0,
+            header: None,
+            sha256: Sha256::new(),
+            received: 0,
+        }
+    }
+
+    /// The header, once it arrived and passed the checks
+    pub fn header(&self) -> Option<&ImageHeader> {
+        self.header.as_ref()
+    }
+
+    /// Check the next part of the file. Returns the part of `data` that
+    /// belongs to the image.
+    pub fn push<'d>(&mut self, mut data: &'d [u8]) -> Result<&'d [u8], ImageError> {
+        if self.header.is_none() {
+            let n = data.len().min(HEADER_SIZE - self.head_len);
+            self.head[self.head_len..self.head_len + n].copy_from_slice(&data[..n]);
+            self.head_len += n;
+            data = &data[n..];
+            if self.head_len < HEADER_SIZE {
+                return Ok(&[]);
+            }
+            let header = ImageHeader::parse(&self.head)?;
+            header.check(&self.running, self.max_size)?;
+            self.header = Some(header);
+        }
+
+        let Some(header) = &self.header else {
+            return Ok(&[]);
+        };
+        if data.len() as u32 > header.image_size - self.received {
+            return Err(ImageError::InvalidSize);
+        }
+        if self.received == 0 && data.first().is_some_and(|&b| b != IMAGE_MAGIC) {
+            return Err(ImageError::NotAnImage);
+        }
+        self.sha256.update(data);
+        self.received += data.len() as u32;
+        Ok(data)
+    }
+
+    /// Check the complete file
+    pub fn finish(self) -> Result<ImageHeader, ImageError> {
+        let header = self.header.ok_or(ImageError::InvalidSize)?;
+        if self.received != header.image_size {
+            return Err(ImageError::InvalidSize);
+        }
+        if self.sha256.finalize()[..] != header.sha256 {
+            return Err(ImageError::HashMismatch);
+        }
+        Ok(header)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OtaError<E> {
+    Flash(E),
+    Image(ImageError),
+    /// A partition is not sector aligned or too small, or there are no app
+    /// partitions to update
+    InvalidPartition,
+    /// Data without an update in progress, or more than announced
+    UnexpectedData,
+    /// The data read back differs from the data written
+    Corrupted,
+}
+
+impl<E> From<E> for OtaError<E> {
+    fn from(err: E) -> Self {
+        OtaError::Flash(err)
+    }
+}
+
+/// Writes an update file to an app partition, see the module documentation
+pub struct OtaWriter<F> {
+    flash: F,
+    slot: Range<u32>,
+    running: VersionInfo,
+    verifier: Option<ImageVerifier>,
+    progress: Progress,
+    writer: BlockWriter,
+}
+
+impl<F: NorFlash> OtaWriter<F> {
+    const SECTOR_SIZE: u32 = F::ERASE_SIZE as u32;
+
+    /// Write an update of the `running` firmware to the app partition `slot`
+    pub fn new(
+        flash: F,
+        slot: Range<u32>,
+        running: VersionInfo,
+    ) -> Result<Self, OtaError<F::Error>> {
+        debug_assert!(BLOCK_SIZE % F::WRITE_SIZE == 0 && BLOCK_SIZE % F::READ_SIZE == 0);
+        if slot.start % Self::SECTOR_SIZE != 0
+            || slot.end % Self::SECTOR_SIZE != 0
+            || slot.end <= slot.start
+            || slot.end as usize > flash.capacity()
+        {
+            return Err(OtaError::InvalidPartition);
+        }
+        Ok(Self {
+            flash,
+            running,
+            verifier: None,
+            progress: Progress {
+                source_id: 0,
+                stored: 0,
+                size: 0,
+            },
+            writer: BlockWriter::new(slot.start),
+            slot,
+        })
+    }
+
+    fn max_image_size(&self) -> u32 {
+        self.slot.end - self.slot.start
+    }
+
+    fn read_back_sha256(&mut self, size: u32) -> Result<[u8; 32], F::Error> {
+        let mut sha256 = Sha256::new();
+        let mut buf = [0; BLOCK_SIZE];
+        for offset in (0..size).step_by(BLOCK_SIZE) {
+            let len = (size - offset).min(BLOCK_SIZE as u32) as usize;
+            let read_len = len.next_multiple_of(F::READ_SIZE);
+            self.flash
+                .read(self.slot.start + offset, &mut buf[..read_len])?;
+            sha256.update(&buf[..len]);
+        }
+        Ok(sha256.finalize().into())
+    }
+
+    /// Release the flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+}
+
+impl<F: NorFlash> Storage for OtaWriter<F> {
+    type Error = OtaError<F::Error>;
+    type Info = ImageHeader;
+
+    /// Updates are small, an interrupted one starts over
+    fn progress(&self) -> Option<Progress> {
+        None
+    }
+
+    fn begin(&mut self, size: u32, source_id: u32) -> Result<(), Self::Error> {
+        self.verifier = None;
+        if size <= HEADER_SIZE as u32 || size - HEADER_SIZE as u32 > self.max_image_size() {
+            return Err(OtaError::Image(ImageError::InvalidSize));
+        }
+        self.verifier = Some(ImageVerifier::new(self.running, self.max_image_size()));
+        self.progress = Progress {
+            source_id,
+            stored: 0,
+            size,
+        };
+        self.writer = BlockWriter::new(self.slot.start);
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<Progress, Self::Error> {
+        let Some(verifier) = &mut self.verifier else {
+            return Err(OtaError::UnexpectedData);
+        };
+        if data.len() as u32 > self.progress.size - self.progress.stored {
+            return Err(OtaError::UnexpectedData);
+        }
+        let mut image = match verifier.push(data) {
+            Ok(image) => image,
+            Err(err) => {
+                self.verifier = None;
+                return Err(OtaError::Image(err));
+            }
+        };
+        self.progress.stored += data.len() as u32;
+
+        while !image.is_empty() {
+            image = self.writer.push(image);
+            if self.writer.is_full() {
+                self.writer.flush(&mut self.flash)?;
+            }
+        }
+        Ok(self.progress)
+    }
+
+    /// Check the image in flash. It boots once the app partition is
+    /// [activated](OtaData::activate).
+    fn finish(&mut self) -> Result<ImageHeader, Self::Error> {
+        if self.progress.stored != self.progress.size {
+            return Err(OtaError::UnexpectedData);
+        }
+        let verifier = self.verifier.take().ok_or(OtaError::UnexpectedData)?;
+        self.writer.flush(&mut self.flash)?;
+        let header = verifier.finish().map_err(OtaError::Image)?;
+        if self.read_back_sha256(header.image_size)? != header.sha256 {
+            return Err(OtaError::Corrupted);
+        }
+        Ok(header)
+    }
+}
+
+/// State of an app partition in `otadata`, as in ESP-IDF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageState {
+    /// Activated, not started yet
+    New,
+    /// Started in trial
+    PendingVerify,
+    Valid,
+    Invalid,
+    /// The trial failed
+    Aborted,
+    Undefined,
+}
+
+impl ImageState {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            0 => ImageState::New,
+            1 => ImageState::PendingVerify,
+            2 => ImageState::Valid,
+            3 => ImageState::Invalid,
+            4 => ImageState::Aborted,
+            _ => ImageState::Undefined,
+        }
+    }
+
+    fn to_raw(self) -> u32 {
+        match self {
+            ImageState::New => 0,
+            ImageState::PendingVerify => 1,
+            ImageState::Valid => 2,
+            ImageState::Invalid => 3,
+            ImageState::Aborted => 4,
+            ImageState::Undefined => 0xFFFF_FFFF,
+        }
+    }
+}
+
+/// How the running image started, see [`OtaData::boot`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boot {
+    /// A valid image, or the one flashed with espflash
+    Normal,
+    /// The first start of an update, it has to mark itself valid
+    Trial,
+    /// An update restarted in trial and is aborted now. The previous image
+    /// starts after a reset.
+    RolledBack,
+}
+
+/// An `otadata` entry, the bootloader starts the app partition of the valid
+/// entry with the highest sequence number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Entry {
+    seq: u32,
+    state: ImageState,
+}
+
+impl Entry {
+    const SIZE: usize = 32;
+
+    /// The app partition of the entry among `apps` of them
+    fn slot(&self, apps: usize) -> usize {
+        (self.seq as usize - 1) % apps
+    }
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        // The label stays erased
+        let mut bytes = [0xFF; Self::SIZE];
+        bytes[..4].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.state.to_raw().to_le_bytes());
+        bytes[28..].copy_from_slice(&seq_crc(self.seq).to_le_bytes());
+        bytes
+    }
+
+    fn parse(bytes: &[u8; Self::SIZE]) -> Option<Self> {
+        let word =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let entry = Self {
+            seq: word(0),
+            state: ImageState::from_raw(word(24)),
+        };
+        let valid = entry.seq != 0
+            && entry.seq != 0xFFFF_FFFF
+            && word(28) == seq_crc(entry.seq)
+            && !matches!(entry.state, ImageState::Invalid | ImageState::Aborted);
+        valid.then_some(entry)
+    }
+}
+
+/// CRC of the ROM `crc32_le(UINT32_MAX, &seq, 4)` the bootloader checks
+fn seq_crc(seq: u32) -> u32 {
+    !crc32_update(0, &seq.to_le_bytes())
+}
+
+/// The `otadata` partition: one sector for each of two entries, the
+/// previous entry stays when the other one is written
+pub struct OtaData<F> {
+    flash: F,
+    start: u32,
+    // Number of `ota_<n>` app partitions the entries count over
+    apps: usize,
+}
+
+impl<F: NorFlash> OtaData<F> {
+    const SECTOR_SIZE: u32 = F::ERASE_SIZE as u32;
+
+    /// The `otadata` `partition` selecting one of `apps` app partitions
+    pub fn new(flash: F, partition: Range<u32>, apps: usize) -> Result<Self, OtaError<F::Error>> {
+        debug_assert!(Entry::SIZE % F::WRITE_SIZE == 0 && Entry::SIZE % F::READ_SIZE == 0);
+        if apps == 0
+            || partition.start % Self::SECTOR_SIZE != 0
+            || partition.end.saturating_sub(partition.start) != 2 * Self::SECTOR_SIZE
+            || partition.end as usize > flash.capacity()
+        {
+            return Err(OtaError::InvalidPartition);
+        }
+        Ok(Self {
+            flash,
+            start: partition.start,
+            apps,
+        })
+    }
+
+    fn read_entry(&mut self, sector: u32) -> Result<Option<Entry>, F::Error> {
+        let mut bytes = [0; Entry::SIZE];
+        self.flash
+            .read(self.start + sector * Self::SECTOR_SIZE, &mut bytes)?;
+        Ok(Entry::parse(&bytes))
+    }
+
+    fn write_entry(&mut self, sector: u32, entry: Entry) -> Result<(), F::Error> {
+        let offset = self.start + sector * Self::SECTOR_SIZE;
+        self.flash.erase(offset, offset + Self::SECTOR_SIZE)?;
+        self.flash.write(offset, &entry.to_bytes())
+    }
+
+    /// The sector and entry the bootloader follows
+    fn active(&mut self) -> Result<Option<(u32, Entry)>, F::Error> {
+        let first = self.read_entry(0)?.map(|entry| (0, entry));
+        let second = self.read_entry(1)?.map(|entry| (1, entry));
+        Ok(first
+            .into_iter()
+            .chain(second)
+            .max_by_key(|(_, entry)| entry.seq))
+    }
+
+    /// The app partition that is running. Without a valid entry the
+    /// bootloader starts the first one.
+    pub fn running_slot(&mut self) -> Result<usize, F::Error> {
+        Ok(self.active()?.map_or(0, |(_, entry)| entry.slot(self.apps)))
+    }
+
+    /// The app partition for an update
+    pub fn inactive_slot(&mut self) -> Result<usize, F::Error> {
+        Ok((self.running_slot()? + 1) % self.apps)
+    }
+
+    /// State of the running image, `None` without a valid entry
+    pub fn state(&mut self) -> Result<Option<ImageState>, F::Error> {
+        Ok(self.active()?.map(|(_, entry)| entry.state))
+    }
+
+    /// Start the app partition `slot` with the next boot, in trial
+    pub fn activate(&mut self, slot: usize) -> Result<(), F::Error> {
+        let (sector, seq) = match self.active()? {
+            Some((sector, entry)) => {
+                let mut seq = entry.seq + 1;
+                while (seq as usize - 1) % self.apps != slot {
+                    seq += 1;
+                }
+                (1 - sector, seq)
+            }
+            None => (0, slot as u32 + 1),
+        };
+        self.write_entry(
+            sector,
+            Entry {
+                seq,
+                state: ImageState::New,
+            },
+        )
+    }
+
+    fn set_state(&mut self, from: &[ImageState], state: ImageState) -> Result<bool, F::Error> {
+        match self.active()? {
+            Some((sector, entry)) if from.contains(&entry.state) => {
+                self.write_entry(sector, Entry { state, ..entry })?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Called once at startup: a new image starts its trial, an image that
+    /// restarted in trial is aborted
+    pub fn boot(&mut self) -> Result<Boot, F::Error> {
+        if self.set_state(&[ImageState::New], ImageState::PendingVerify)? {
+            Ok(Boot::Trial)
+        } else if self.set_state(&[ImageState::PendingVerify], ImageState::Aborted)? {
+            Ok(Boot::RolledBack)
+        } else {
+            Ok(Boot::Normal)
+        }
+    }
+
+    /// End the trial of the running image, it keeps starting from now on
+    pub fn mark_valid(&mut self) -> Result<(), F::Error> {
+        self.set_state(&[ImageState::PendingVerify], ImageState::Valid)?;
+        Ok(())
+    }
+
+    /// Give up the trial of the running image, the previous image starts
+    /// after a reset. Returns false when the image is not in trial.
+    pub fn abort(&mut self) -> Result<bool, F::Error> {
+        self.set_state(&[ImageState::PendingVerify], ImageState::Aborted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_flash::{MemFlash, SECTOR_SIZE};
+    use embedded_storage::nor_flash::NorFlashErrorKind;
+
+    extern crate std;
+    use std::vec::Vec;
+
+    const RUNNING: VersionInfo = VersionInfo {
+        version: "0.2.0",
+        git_commit: "715838c",
+        build_timestamp: "2024-08-08T12:00:00Z",
+        board: "esp32c3",
+        features: "wifi,ota",
+    };
+
+    const SLOT: Range<u32> = 0..(8 * SECTOR_SIZE as u32);
+
+    fn image(size: usize) -> Vec<u8> {
+        let mut image: Vec<u8> = (0..size).map(|i| (i * 7 % 251) as u8).collect();
+        image[0] = IMAGE_MAGIC;
+        image
+    }
+
+    fn header(image: &[u8], version: &str) -> ImageHeader {
+        ImageHeader {
+            image_size: image.len() as u32,
+            sha256: Sha256::digest(image).into(),
+            version: Version::parse(version).unwrap(),
+            git_commit: "9f0c2aa".try_into().unwrap(),
+            board: "esp32c3".try_into().unwrap(),
+        }
+    }
+
+    fn update_file(header: &ImageHeader, image: &[u8]) -> Vec<u8> {
+        let mut file = header.to_bytes().to_vec();
+        file.extend_from_slice(image);
+        file
+    }
+
+    /// Store `file` in parts of `chunk` bytes, like a download
+    fn store(
+        writer: &mut OtaWriter<&mut MemFlash>,
+        file: &[u8],
+        chunk: usize,
+    ) -> Result<ImageHeader, OtaError<NorFlashErrorKind>> {
+        writer.begin(file.len() as u32, 1)?;
+        for part in file.chunks(chunk) {
+            writer.write(part)?;
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            Version::parse("1.12.3"),
+            Some(Version {
+                major: 1,
+                minor: 12,
+                patch: 3
+            })
+        );
+        assert_eq!(Version::parse("1.2"), None);
+        assert_eq!(Version::parse("1.2.3.4"), None);
+        assert_eq!(Version::parse("1.2.x"), None);
+        assert!(Version::parse("0.10.0") > Version::parse("0.9.9"));
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = header(&image(1000), "0.3.1");
+        let bytes = header.to_bytes();
+        assert_eq!(&bytes[..4], b"F1OT");
+        assert_eq!(ImageHeader::parse(&bytes), Ok(header));
+
+        // A changed byte breaks the CRC
+        let mut damaged = bytes;
+        damaged[50] ^= 1;
+        assert_eq!(ImageHeader::parse(&damaged), Err(ImageError::InvalidHeader));
+    }
+
+    #[test]
+    fn test_check_header() {
+        let image = image(1000);
+        assert_eq!(header(&image, "0.2.0").check(&RUNNING, 4096), Ok(()));
+        assert_eq!(header(&image, "0.3.0").check(&RUNNING, 4096), Ok(()));
+        assert_eq!(
+            header(&image, "0.1.9").check(&RUNNING, 4096),
+            Err(ImageError::Downgrade)
+        );
+        assert_eq!(
+            header(&image, "0.3.0").check(&RUNNING, 999),
+            Err(ImageError::InvalidSize)
+        );
+
+        let mut other_board = header(&image, "0.3.0");
+        other_board.board = "esp32s3".try_into().unwrap();
+        assert_eq!(
+            other_board.check(&RUNNING, 4096),
+            Err(ImageError::WrongBoard)
+        );
+
+        // A build without a proper version installs anything
+        let dev = VersionInfo {
+            version: "dev",
+            ..RUNNING
+        };
+        assert_eq!(header(&image, "0.0.1").check(&dev, 4096), Ok(()));
+    }
+
+    #[test]
+    fn test_verifier() {
+        let image = image(1000);
+        let file = update_file(&header(&image, "0.3.0"), &image);
+
+        let mut verifier = ImageVerifier::new(RUNNING, 4096);
+        let mut received = Vec::new();
+        for part in file.chunks(50) {
+            received.extend_from_slice(verifier.push(part).unwrap());
+        }
+        assert_eq!(received, image);
+        assert_eq!(verifier.finish().unwrap(), header(&image, "0.3.0"));
+
+        // Truncated
+        let mut verifier = ImageVerifier::new(RUNNING, 4096);
+        verifier.push(&file[..900]).unwrap();
+        assert_eq!(verifier.finish(), Err(ImageError::InvalidSize));
+
+        // Too long
+        let mut verifier = ImageVerifier::new(RUNNING, 4096);
+        verifier.push(&file).unwrap();
+        assert_eq!(verifier.push(&[0]), Err(ImageError::InvalidSize));
+
+        // Changed on the way
+        let mut damaged = file.clone();
+        damaged[HEADER_SIZE + 500] ^= 0x10;
+        let mut verifier = ImageVerifier::new(RUNNING, 4096);
+        verifier.push(&damaged).unwrap();
+        assert_eq!(verifier.finish(), Err(ImageError::HashMismatch));
+
+        // Not an app image
+        let mut data = image.clone();
+        data[0] = 0;
+        let file = update_file(&header(&data, "0.3.0"), &data);
+        let mut verifier = ImageVerifier::new(RUNNING, 4096);
+        assert_eq!(verifier.push(&file), Err(ImageError::NotAnImage));
+
+        // A race file is not an update
+        let mut verifier = ImageVerifier::new(RUNNING, 4096);
+        assert_eq!(
+            verifier.push(&[1; HEADER_SIZE]),
+            Err(ImageError::InvalidHeader)
+        );
+    }
+
+    #[test]
+    fn test_write_update() {
+        let mut flash = MemFlash::new();
+        let image = image(1000);
+        let file = update_file(&header(&image, "0.3.0"), &image);
+
+        let mut writer = OtaWriter::new(&mut flash, SLOT, RUNNING).unwrap();
+        let header = store(&mut writer, &file, 100).unwrap();
+        assert_eq!(header.image_size, 1000);
+        assert_eq!(flash.data[..1000], image);
+        // Only the sectors the image needs are erased
+        assert_eq!(flash.erase_counts[..5], [1, 1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn test_rejected_update_keeps_flash() {
+        let mut flash = MemFlash::new();
+        let image = image(1000);
+        let file = update_file(&header(&image, "0.1.0"), &image);
+
+        let mut writer = OtaWriter::new(&mut flash, SLOT, RUNNING).unwrap();
+        assert_eq!(
+            store(&mut writer, &file, 100),
+            Err(OtaError::Image(ImageError::Downgrade))
+        );
+        assert_eq!(writer.write(&file[..4]), Err(OtaError::UnexpectedData));
+        assert!(flash.erase_counts.iter().all(|&count| count == 0));
+
+        // Larger than the partition
+        let image = self::image(3000);
+        let file = update_file(&self::header(&image, "0.3.0"), &image);
+        let mut writer = OtaWriter::new(&mut flash, 0..(2 * SECTOR_SIZE as u32), RUNNING).unwrap();
+        assert_eq!(
+            writer.begin(file.len() as u32, 1),
+            Err(OtaError::Image(ImageError::InvalidSize))
+        );
+    }
+
+    #[test]
+    fn test_corrupted_write() {
+        let mut flash = MemFlash::new();
+        let image = image(1000);
+        let file = update_file(&header(&image, "0.3.0"), &image);
+        let mut writer = OtaWriter::new(&mut flash, SLOT, RUNNING).unwrap();
+        writer.begin(file.len() as u32, 1).unwrap();
+        writer.write(&file[..600]).unwrap();
+        // A bit flips in flash
+        writer.flash.data[10] ^= 0x01;
+        writer.write(&file[600..]).unwrap();
+        assert_eq!(writer.finish(), Err(OtaError::Corrupted));
+    }
+
+    #[test]
+    fn test_seq_crc() {
+        // otadata written by ESP-IDF for the first OTA partition
+        assert_eq!(seq_crc(1), 0x4743_989A);
+    }
+
+    fn otadata(flash: &mut MemFlash) -> OtaData<&mut MemFlash> {
+        OtaData::new(flash, 0..(2 * SECTOR_SIZE as u32), 2).unwrap()
+    }
+
+    #[test]
+    fn test_update_and_confirm() {
+        let mut flash = MemFlash::new();
+        let mut otadata = otadata(&mut flash);
+
+        // Freshly flashed, the bootloader starts the first partition
+        assert_eq!(otadata.running_slot(), Ok(0));
+        assert_eq!(otadata.boot(), Ok(Boot::Normal));
+        assert_eq!(otadata.inactive_slot(), Ok(1));
+
+        otadata.activate(1).unwrap();
+        assert_eq!(otadata.running_slot(), Ok(1));
+        assert_eq!(otadata.state(), Ok(Some(ImageState::New)));
+
+        // The update starts in trial and confirms itself
+        assert_eq!(otadata.boot(), Ok(Boot::Trial));
+        assert_eq!(otadata.state(), Ok(Some(ImageState::PendingVerify)));
+        otadata.mark_valid().unwrap();
+        assert_eq!(otadata.boot(), Ok(Boot::Normal));
+        assert_eq!(otadata.running_slot(), Ok(1));
+
+        // The next update goes to the first partition again
+        otadata.activate(0).unwrap();
+        assert_eq!(otadata.running_slot(), Ok(0));
+        assert_eq!(otadata.active().unwrap().unwrap().1.seq, 3);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut flash = MemFlash::new();
+        let mut otadata = otadata(&mut flash);
+        otadata.activate(1).unwrap();
+        assert_eq!(otadata.boot(), Ok(Boot::Trial));
+        otadata.mark_valid().unwrap();
+
+        otadata.activate(0).unwrap();
+        assert_eq!(otadata.boot(), Ok(Boot::Trial));
+        // Restarted before it marked itself valid
+        assert_eq!(otadata.boot(), Ok(Boot::RolledBack));
+        assert_eq!(otadata.running_slot(), Ok(1));
+        assert_eq!(otadata.state(), Ok(Some(ImageState::Valid)));
+        assert_eq!(otadata.boot(), Ok(Boot::Normal));
+
+        // Given up while running
+        otadata.activate(0).unwrap();
+        assert_eq!(otadata.boot(), Ok(Boot::Trial));
+        assert_eq!(otadata.abort(), Ok(true));
+        assert_eq!(otadata.running_slot(), Ok(1));
+        assert_eq!(otadata.abort(), Ok(false));
+    }
+
+    #[test]
+    fn test_rollback_to_flashed_image() {
+        let mut flash = MemFlash::new();
+        let mut otadata = otadata(&mut flash);
+        otadata.activate(1).unwrap();
+        assert_eq!(otadata.boot(), Ok(Boot::Trial));
+        assert_eq!(otadata.boot(), Ok(Boot::RolledBack));
+        // No valid entry left, the bootloader starts the first partition
+        assert_eq!(otadata.running_slot(), Ok(0));
+        assert_eq!(otadata.state(), Ok(None));
+    }
+
+    #[test]
+    fn test_three_app_partitions() {
+        let mut flash = MemFlash::new();
+        let mut otadata = OtaData::new(&mut flash, 0..(2 * SECTOR_SIZE as u32), 3).unwrap();
+        otadata.activate(2).unwrap();
+        assert_eq!(otadata.active().unwrap().unwrap().1.seq, 3);
+        assert_eq!(otadata.running_slot(), Ok(2));
+        assert_eq!(otadata.inactive_slot(), Ok(0));
+
+        otadata.activate(1).unwrap();
+        assert_eq!(otadata.active().unwrap().unwrap().1.seq, 5);
+        assert_eq!(otadata.running_slot(), Ok(1));
+
+        assert_eq!(
+            OtaData::new(&mut flash, 0..(2 * SECTOR_SIZE as u32), 0).err(),
+            Some(OtaError::InvalidPartition)
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! ESP-IDF partition table.
+//!
+//! espflash writes the partition table of the CSV it is given, e.g.
+//! `partitions-wifi.csv`, to [`TABLE_OFFSET`]. The firmware looks its data
+//! partitions up there instead of assuming a layout, so a board flashed with
+//! another table refuses to use them rather than erase its own firmware.
+//!
+//! Every entry has 32 bytes, numbers little endian:
+//!
+//! | offset | content                     |
+//! |--------|-----------------------------|
+//! | 0      | magic `AA 50`               |
+//! | 2      | type and subtype            |
+//! | 4      | offset of the partition     |
+//! | 8      | size of the partition       |
+//! | 12     | label, 16 bytes NUL padded  |
+//! | 28     | flags                       |
+//!
+//! An MD5 entry starting with `EB EB` or an erased entry ends the table.
+
+use core::ops::Range;
+use embedded_storage::nor_flash::ReadNorFlash;
+use heapless::Vec;
+
+/// Flash offset of the partition table
+pub const TABLE_OFFSET: u32 = 0x8000;
+
+/// Room for the table, entries included
+pub const TABLE_SIZE: usize = 0xC00;
+
+const ENTRY_SIZE: usize = 32;
+const MAX_ENTRIES: usize = TABLE_SIZE / ENTRY_SIZE;
+
+const MAGIC: [u8; 2] = [0xAA, 0x50];
+
+pub const TYPE_APP: u8 = 0x00;
+pub const TYPE_DATA: u8 = 0x01;
+
+/// Data subtype of the `otadata` partition
+pub const SUBTYPE_OTA_DATA: u8 = 0x00;
+/// App subtype of `ota_0`, `ota_<n>` is `n` more
+pub const SUBTYPE_OTA_0: u8 = 0x10;
+/// Most `ota_<n>` app partitions the bootloader knows
+pub const MAX_OTA_APPS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionError<E> {
+    Flash(E),
+    /// No partition table at [`TABLE_OFFSET`], or a damaged one
+    InvalidTable,
+    /// The table has no such partition
+    Missing,
+    /// The data partition shares flash with an app partition
+    OverlapsApp,
+}
+
+impl<E> From<E> for PartitionError<E> {
+    fn from(err: E) -> Self {
+        PartitionError::Flash(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+    pub kind: u8,
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+    label: [u8; 16],
+}
+
+impl Partition {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let word =
+            |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        if bytes[..2] != MAGIC {
+            return None;
+        }
+        let partition = Self {
+            kind: bytes[2],
+            subtype: bytes[3],
+            offset: word(4),
+            size: word(8),
+            label: bytes[12..28].try_into().unwrap(),
+        };
+        partition
+            .offset
+            .checked_add(partition.size)
+            .map(|_| partition)
+    }
+
+    pub fn label(&self) -> &str {
+        let len = self.label.iter().position(|&b| b == 0).unwrap_or(16);
+        core::str::from_utf8(&self.label[..len]).unwrap_or("")
+    }
+
+    pub fn range(&self) -> Range<u32> {
+        self.offset..self.offset + self.size
+    }
+
+    pub fn is_app(&self) -> bool {
+        self.kind == TYPE_APP
+    }
+
+    fn overlaps(&self, other: &Partition) -> bool {
+        self.offset < other.offset + other.size && other.offset < self.offset + self.size
+    }
+}
+
+/// The partitions flashed on the board
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+    partitions: Vec<Partition, MAX_ENTRIES>,
+}
+
+impl PartitionTable {
+    /// Read the table at [`TABLE_OFFSET`]
+    pub fn read<F: ReadNorFlash>(flash: &mut F) -> Result<Self, PartitionError<F::Error>> {
+        let mut buf = [0; TABLE_SIZE];
+        flash.read(TABLE_OFFSET, &mut buf)?;
+        Self::parse(&buf).ok_or(PartitionError::InvalidTable)
+    }
+
+    /// Parse the entries of a table, `None` without any
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut partitions = Vec::new();
+        for bytes in data.chunks_exact(ENTRY_SIZE).take(MAX_ENTRIES) {
+            match Partition::parse(bytes) {
+                Some(partition) => partitions.push(partition).ok()?,
+                None => break,
+            }
+        }
+        (!partitions.is_empty()).then_some(Self { partitions })
+    }
+
+    pub fn partitions(&self) -> &[Partition] {
+        &self.partitions
+    }
+
+    pub fn find(&self, label: &str) -> Option<&Partition> {
+        self.partitions.iter().find(|p| p.label() == label)
+    }
+
+    fn check_data<E>(&self, partition: &Partition) -> Result<Range<u32>, PartitionError<E>> {
+        if partition.kind != TYPE_DATA {
+            return Err(PartitionError::Missing);
+        }
+        if self
+            .partitions
+            .iter()
+            .any(|p| p.is_app() && p.overlaps(partition))
+        {
+            return Err(PartitionError::OverlapsApp);
+        }
+        Ok(partition.range())
+    }
+
+    /// The data partition `label`, which must not share flash with an app
+    pub fn data<E>(&self, label: &str) -> Result<Range<u32>, PartitionError<E>> {
+        let partition = self.find(label).ok_or(PartitionError::Missing)?;
+        self.check_data(partition)
+    }
+
+    /// The `otadata` partition, checked like [`data`](Self::data)
+    pub fn ota_data<E>(&self) -> Result<Range<u32>, PartitionError<E>> {
+        let partition = self
+            .partitions
+            .iter()
+            .find(|p| p.kind == TYPE_DATA && p.subtype == SUBTYPE_OTA_DATA)
+            .ok_or(PartitionError::Missing)?;
+        self.check_data(partition)
+    }
+
+    /// The `ota_<n>` app partitions in the order of `n`, the bootloader
+    /// counts the slots of `otadata` over them. `Missing` with fewer than
+    /// two.
+    pub fn ota_apps<E>(&self) -> Result<Vec<Range<u32>, MAX_OTA_APPS>, PartitionError<E>> {
+        let mut apps = Vec::new();
+        for n in 0..MAX_OTA_APPS as u8 {
+            if let Some(p) = self
+                .partitions
+                .iter()
+                .find(|p| p.is_app() && p.subtype == SUBTYPE_OTA_0 + n)
+            {
+                // At most MAX_OTA_APPS subtypes
+                apps.push(p.range()).unwrap();
+            }
+        }
+        if apps.len() < 2 {
+            return Err(PartitionError::Missing);
+        }
+        Ok(apps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_flash::MemFlash;
+    use embedded_storage::nor_flash::NorFlashErrorKind;
+
+    extern crate std;
+    use std::vec::Vec;
+
+    type Error = PartitionError<NorFlashErrorKind>;
+
+    /// The binary table espflash writes for `csv`
+    fn table(csv: &str) -> std::vec::Vec<u8> {
+        let number = |s: &str| match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).unwrap(),
+            None => s.parse().unwrap(),
+        };
+        let mut data = Vec::new();
+        for line in csv.lines().filter(|l| !l.starts_with('#')) {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let (kind, subtype) = match (fields[1], fields[2]) {
+                ("app", "factory") => (TYPE_APP, 0x00),
+                ("app", ota) => (TYPE_APP, SUBTYPE_OTA_0 + number(&ota[4..]) as u8),
+                ("data", "ota") => (TYPE_DATA, SUBTYPE_OTA_DATA),
+                ("data", "phy") => (TYPE_DATA, 0x01),
+                ("data", "nvs") => (TYPE_DATA, 0x02),
+                ("data", "undefined") => (TYPE_DATA, 0x06),
+                other => panic!("{:?}", other),
+            };
+            let mut entry = [0; ENTRY_SIZE];
+            entry[..2].copy_from_slice(&MAGIC);
+            entry[2] = kind;
+            entry[3] = subtype;
+            entry[4..8].copy_from_slice(&number(fields[3]).to_le_bytes());
+            entry[8..12].copy_from_slice(&number(fields[4]).to_le_bytes());
+            entry[12..12 + fields[0].len()].copy_from_slice(fields[0].as_bytes());
+            data.extend_from_slice(&entry);
+        }
+        // The MD5 entry
+        data.extend_from_slice(&[0xEB; ENTRY_SIZE]);
+        data.resize(TABLE_SIZE, 0xFF);
+        data
+    }
+
+    fn parse(csv: &str) -> PartitionTable {
+        PartitionTable::parse(&table(csv)).unwrap()
+    }
+
+    #[test]
+    fn test_shipped_tables() {
+        let offline = parse(include_str!("../../f1-hardware/partitions.csv"));
+        assert_eq!(offline.partitions().len(), 4);
+        assert_eq!(offline.data::<()>("race"), Err(PartitionError::Missing));
+        assert_eq!(offline.data::<()>("settings"), Ok(0x3F_0000..0x40_0000));
+
+        let wifi = parse(include_str!("../../f1-hardware/partitions-wifi.csv"));
+        assert_eq!(wifi.data::<()>("race"), Ok(0x15_0000..0x3F_0000));
+        assert_eq!(wifi.ota_data::<()>(), Err(PartitionError::Missing));
+        assert_eq!(wifi.ota_apps::<()>(), Err(PartitionError::Missing));
+
+        let ota = parse(include_str!("../../f1-hardware/partitions-ota.csv"));
+        assert_eq!(ota.data::<()>("race"), Ok(0x29_0000..0x3F_0000));
+        assert_eq!(ota.ota_data::<()>(), Ok(0xD000..0xF000));
+        assert_eq!(
+            ota.ota_apps::<()>().unwrap(),
+            [0x1_0000..0x15_0000, 0x15_0000..0x29_0000]
+        );
+        assert_eq!(ota.find("ota_1").unwrap().label(), "ota_1");
+    }
+
+    #[test]
+    fn test_overlapping_app() {
+        let table = parse(
+            "factory, app, factory, 0x10000, 0x3e0000\n\
+             race, data, undefined, 0x150000, 0x2a0000\n\
+             otadata, data, ota, 0x3f0000, 0x2000\n\
+             ota_0, app, ota_0, 0x3f2000, 0x1000\n\
+             ota_1, app, ota_1, 0x3f3000, 0x1000",
+        );
+        assert_eq!(table.data::<()>("race"), Err(PartitionError::OverlapsApp));
+        assert_eq!(table.ota_data::<()>(), Ok(0x3F_0000..0x3F_2000));
+        // Apps aren't data
+        assert_eq!(table.data::<()>("factory"), Err(PartitionError::Missing));
+    }
+
+    #[test]
+    fn test_read() {
+        let mut flash = MemFlash::with_sectors(TABLE_OFFSET as usize / 256 + TABLE_SIZE / 256);
+        assert_eq!(PartitionTable::read(&mut flash), Err(Error::InvalidTable));
+        let data = table(include_str!("../../f1-hardware/partitions-wifi.csv"));
+        let start = TABLE_OFFSET as usize;
+        flash.data[start..start + TABLE_SIZE].copy_from_slice(&data);
+        let table = PartitionTable::read(&mut flash).unwrap();
+        assert_eq!(
+            table.data::<NorFlashErrorKind>("race"),
+            Ok(0x15_0000..0x3F_0000)
+        );
+
+        // A partition that runs past the end of the address space
+        let mut data = data;
+        data[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(PartitionTable::parse(&data), None);
+    }
+}
@@ -0,0 +1,332 @@
+//! Wi-Fi provisioning web page.
+//!
+//! Without credentials, or after connecting failed repeatedly, the board
+//! starts an access point with a captive portal. The portal serves a form
+//! for the network name and password and stores them in the settings.
+//! This module handles the HTTP side without any network stack.
+
+use crate::settings::Network;
+use core::fmt;
+use heapless::String;
+
+/// Name of the provisioning access point
+pub const AP_SSID: &str = "F1-LED-CIRCUIT";
+
+/// Consecutive failed connection rounds before falling back to provisioning
+pub const MAX_CONNECT_FAILURES: u8 = 5;
+
+const FORM_PAGE: &str = "<!DOCTYPE html>
+<html><head><meta name=\"viewport\" content=\"width=device-width\"><title>F1 LED Circuit</title></head>
+<body><h1>F1 LED Circuit Wi-Fi</h1>
+<form method=\"post\" action=\"/save\">
+<p><label>Network <input name=\"ssid\" maxlength=\"32\" required></label></p>
+<p><label>Password <input name=\"password\" type=\"password\" maxlength=\"63\"></label></p>
+<p><button type=\"submit\">Connect</button></p>
+</form></body></html>";
+
+const SAVED_PAGE: &str = "<!DOCTYPE html>
+<html><head><meta name=\"viewport\" content=\"width=device-width\"><title>F1 LED Circuit</title></head>
+<body><h1>Saved</h1><p>The board restarts and connects to the network.</p></body></html>";
+
+const INVALID_PAGE: &str = "<!DOCTYPE html>
+<html><head><meta name=\"viewport\" content=\"width=device-width\"><title>F1 LED Circuit</title></head>
+<body><h1>Invalid network settings</h1>
+<p>The network name must be 1 to 32 characters, the password empty or 8 to 63 characters.</p>
+<p><a href=\"/\">Try again</a></p></body></html>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: Method,
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpError {
+    /// More data is needed to complete the request
+    Incomplete,
+    BadRequest,
+}
+
+/// Parse an HTTP request from the bytes received so far
+pub fn parse_request(buf: &[u8]) -> Result<Request<'_>, HttpError> {
+    let head_end = buf
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or(HttpError::Incomplete)?;
+    let head = core::str::from_utf8(&buf[..head_end]).map_err(|_| HttpError::BadRequest)?;
+    let mut lines = head.split("\r\n");
+
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = match request_line.next() {
+        Some("GET") => Method::Get,
+        Some("POST") => Method::Post,
+        Some(_) => Method::Other,
+        None => return Err(HttpError::BadRequest),
+    };
+    let path = request_line.next().ok_or(HttpError::BadRequest)?;
+
+    let mut content_length = 0;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| HttpError::BadRequest)?;
+            }
+        }
+    }
+
+    let body_start = head_end + 4;
+    let body_end = body_start
+        .checked_add(content_length)
+        .ok_or(HttpError::BadRequest)?;
+    let body = buf.get(body_start..body_end).ok_or(HttpError::Incomplete)?;
+
+    Ok(Request { method, path, body })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    /// Redirect target for 302 responses
+    pub location: Option<&'static str>,
+    pub body: &'static str,
+}
+
+impl Response {
+    fn page(status: u16, body: &'static str) -> Self {
+        Self {
+            status,
+            location: None,
+            body,
+        }
+    }
+
+    fn redirect(location: &'static str) -> Self {
+        Self {
+            status: 302,
+            location: Some(location),
+            body: "",
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            302 => "Found",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "",
+        }
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP/1.1 {} {}\r\n", self.status, self.reason())?;
+        if let Some(location) = self.location {
+            write!(f, "Location: {}\r\n", location)?;
+        }
+        write!(
+            f,
+            "Content-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// Handle a request to the portal. Returns the credentials when the form
+/// was submitted with valid values.
+pub fn handle_request(request: &Request, portal_url: &'static str) -> (Response, Option<Network>) {
+    match (request.method, request.path) {
+        (Method::Get, "/") => (Response::page(200, FORM_PAGE), None),
+        (Method::Post, "/save") => match parse_form(request.body) {
+            Some(credentials) => (Response::page(200, SAVED_PAGE), Some(credentials)),
+            None => (Response::page(400, INVALID_PAGE), None),
+        },
+        (Method::Get, _) => {
+            // Connectivity checks of phones and laptops ask for pages like
+            // /generate_204 or /hotspot-detect.html, redirecting them opens
+            // the portal
+            (Response::redirect(portal_url), None)
+        }
+        _ => (Response::page(405, ""), None),
+    }
+}
+
+/// Parse and validate the submitted form
+pub fn parse_form(body: &[u8]) -> Option<Network> {
+    let body = core::str::from_utf8(body).ok()?;
+    let mut ssid = None;
+    let mut password = None;
+
+    for field in body.split('&') {
+        let (name, value) = field.split_once('=').unwrap_or((field, ""));
+        match name {
+            "ssid" => ssid = Some(url_decode::<32>(value)?),
+            "password" => password = Some(url_decode::<64>(value)?),
+            _ => {}
+        }
+    }
+
+    let credentials = Network {
+        ssid: ssid?,
+        password: password.unwrap_or_default(),
+    };
+    // WPA2 passphrases have 8 to 63 characters, open networks none
+    let password_len = credentials.password.len();
+    let valid =
+        !credentials.ssid.is_empty() && (password_len == 0 || (8..=63).contains(&password_len));
+    valid.then_some(credentials)
+}
+
+/// Decode a form encoded value
+fn url_decode<const N: usize>(value: &str) -> Option<String<N>> {
+    let mut bytes = heapless::Vec::<u8, N>::new();
+    let mut input = value.bytes();
+    while let Some(byte) = input.next() {
+        let decoded = match byte {
+            b'+' => b' ',
+            b'%' => {
+                let hex = [input.next()?, input.next()?];
+                u8::from_str_radix(core::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            _ => byte,
+        };
+        bytes.push(decoded).ok()?;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Counts failed connection attempts to decide when to fall back to provisioning
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConnectionAttempts {
+    failures: u8,
+}
+
+impl ConnectionAttempts {
+    pub fn connected(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Record a failed attempt. Returns true when provisioning should start.
+    pub fn failed(&mut self) -> bool {
+        self.failures = self.failures.saturating_add(1);
+        self.failures >= MAX_CONNECT_FAILURES
+    }
+
+    pub fn failures(&self) -> u8 {
+        self.failures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    const PORTAL: &str = "http://192.168.2.1/";
+
+    #[test]
+    fn test_parse_request() {
+        let get = b"GET /generate_204 HTTP/1.1\r\nHost: connectivitycheck.gstatic.com\r\n\r\n";
+        let request = parse_request(get).unwrap();
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/generate_204");
+        assert!(request.body.is_empty());
+
+        let post = b"POST /save HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\ncontent-length: 27\r\n\r\nssid=Pit+Lane&password=abcd";
+        let request = parse_request(post).unwrap();
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.body, b"ssid=Pit+Lane&password=abcd");
+
+        // Partial requests ask for more data
+        assert_eq!(parse_request(&get[..20]), Err(HttpError::Incomplete));
+        assert_eq!(
+            parse_request(&post[..post.len() - 1]),
+            Err(HttpError::Incomplete)
+        );
+        assert_eq!(parse_request(b"\r\n\r\n"), Err(HttpError::BadRequest));
+        // A body that can't be addressed
+        let huge = b"POST /save HTTP/1.1\r\nContent-Length: 18446744073709551615\r\n\r\nssid=a";
+        assert_eq!(parse_request(huge), Err(HttpError::BadRequest));
+    }
+
+    #[test]
+    fn test_parse_form() {
+        let credentials = parse_form(b"ssid=Pit+Lane%21&password=p%40ss+word").unwrap();
+        assert_eq!(credentials.ssid, "Pit Lane!");
+        assert_eq!(credentials.password, "p@ss word");
+
+        // Open network
+        let credentials = parse_form(b"ssid=Paddock&password=").unwrap();
+        assert_eq!(credentials.password, "");
+        assert_eq!(parse_form(b"password=&ssid=Paddock"), Some(credentials));
+
+        assert_eq!(parse_form(b"ssid=&password=12345678"), None);
+        assert_eq!(parse_form(b"ssid=Paddock&password=short"), None);
+        assert_eq!(parse_form(b"password=12345678"), None);
+        assert_eq!(parse_form(b"ssid=%ZZ"), None);
+        assert_eq!(
+            parse_form(b"ssid=a-network-name-that-is-longer-than-32-bytes"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_handle_request() {
+        let request = |method, path, body| Request { method, path, body };
+
+        let (response, credentials) = handle_request(&request(Method::Get, "/", b""), PORTAL);
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains("<form"));
+        assert_eq!(credentials, None);
+
+        let (response, _) =
+            handle_request(&request(Method::Get, "/hotspot-detect.html", b""), PORTAL);
+        assert_eq!(response.status, 302);
+        assert_eq!(response.location, Some(PORTAL));
+
+        let form = b"ssid=Paddock&password=12345678";
+        let (response, credentials) = handle_request(&request(Method::Post, "/save", form), PORTAL);
+        assert_eq!(response.status, 200);
+        assert_eq!(credentials.unwrap().ssid, "Paddock");
+
+        let (response, credentials) =
+            handle_request(&request(Method::Post, "/save", b"ssid="), PORTAL);
+        assert_eq!(response.status, 400);
+        assert_eq!(credentials, None);
+    }
+
+    #[test]
+    fn test_response_format() {
+        let mut s: String<256> = String::new();
+        write!(s, "{}", Response::redirect(PORTAL)).unwrap();
+        assert_eq!(
+            s,
+            "HTTP/1.1 302 Found\r\nLocation: http://192.168.2.1/\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_connection_attempts() {
+        let mut attempts = ConnectionAttempts::default();
+        for _ in 1..MAX_CONNECT_FAILURES {
+            assert!(!attempts.failed());
+        }
+        attempts.connected();
+        assert_eq!(attempts.failures(), 0);
+        for _ in 1..MAX_CONNECT_FAILURES {
+            assert!(!attempts.failed());
+        }
+        assert!(attempts.failed());
+    }
+}
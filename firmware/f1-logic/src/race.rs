@@ -0,0 +1,440 @@
+//! Race analysis.
+//!
+//! Frames only say which LED every car is at. A [`Race`] follows the cars
+//! over the frames of a race on a [`Circuit`]: it counts their laps at the
+//! start/finish line, times the sectors they drive and orders them by how
+//! far they have come, so lapped cars end up behind the cars that lapped
+//! them.
+//!
+//! Cars move forward by at most half a lap between two frames. A car
+//! showing up further back than it was jitters between two LEDs and keeps
+//! its position.
+
+use crate::board::MAX_LED_COUNT;
+use crate::circuit::{Circuit, SECTOR_COUNT};
+use crate::data_frame::{UpdateFrame, NUM_DRIVERS};
+use core::cmp::Reverse;
+use heapless::Vec;
+
+/// Most events one frame can bring
+pub const MAX_EVENTS: usize = NUM_DRIVERS * (SECTOR_COUNT + 1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// `driver_number` finished lap `lap`, counted from 1
+    Lap {
+        driver_number: u8,
+        lap: u16,
+        time_ms: Option<u32>,
+    },
+    /// `driver_number` entered `sector`, counted from 0, after `time_ms` in
+    /// the sector before
+    Sector {
+        driver_number: u8,
+        sector: usize,
+        time_ms: Option<u32>,
+    },
+}
+
+/// Distance to the car in front of the field
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gap {
+    Leader,
+    /// Time since the leader was at the same place
+    Time(u32),
+    Laps(u16),
+    /// LEDs behind the leader, before the leader's time there is known
+    Leds(u32),
+}
+
+/// Where a car is in the race
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Standing {
+    /// Running order, from 1
+    pub position: usize,
+    pub driver_number: u8,
+    pub led_number: u16,
+    /// Completed laps
+    pub laps: u16,
+    pub sector: usize,
+    pub gap: Gap,
+    pub last_lap_ms: Option<u32>,
+    /// Time of the last pass through every sector
+    pub sector_times_ms: [Option<u32>; SECTOR_COUNT],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Driver {
+    number: u8,
+    led_number: u16,
+    /// LEDs from the start/finish line, negative before the first crossing
+    distance: i32,
+    /// When the car reached `distance`
+    reached_ms: u32,
+    sector: usize,
+    sector_start_ms: Option<u32>,
+    lap_start_ms: Option<u32>,
+    last_lap_ms: Option<u32>,
+    sector_times_ms: [Option<u32>; SECTOR_COUNT],
+}
+
+pub struct Race<'a> {
+    circuit: &'a Circuit,
+    drivers: Vec<Driver, NUM_DRIVERS>,
+    /// Distance and time of the first car at every lap position
+    passes: [(i32, u32); MAX_LED_COUNT],
+}
+
+impl<'a> Race<'a> {
+    pub fn new(circuit: &'a Circuit) -> Self {
+        Self {
+            circuit,
+            drivers: Vec::new(),
+            passes: [(i32::MIN, 0); MAX_LED_COUNT],
+        }
+    }
+
+    pub fn circuit(&self) -> &'a Circuit {
+        self.circuit
+    }
+
+    /// Forget all cars, for a new race
+    pub fn reset(&mut self) {
+        self.drivers.clear();
+        self.passes = [(i32::MIN, 0); MAX_LED_COUNT];
+    }
+
+    fn lap_length(&self) -> i32 {
+        self.circuit.led_count() as i32
+    }
+
+    /// Move the cars to their LEDs in `frame`, `time_ms` into the race
+    pub fn update(&mut self, time_ms: u32, frame: &UpdateFrame) -> Vec<Event, MAX_EVENTS> {
+        let mut events = Vec::new();
+        let count = self.lap_length();
+        for data in frame.frame.iter().filter(|d| d.driver_number != 0) {
+            let led_number = data.led_num as u16;
+            let Some(position) = self.circuit.lap_position(led_number) else {
+                continue;
+            };
+            let position = position as i32;
+            let Some(index) = self
+                .drivers
+                .iter()
+                .position(|d| d.number == data.driver_number)
+            else {
+                // Cars first seen in the second half of the lap are on the
+                // grid behind the line
+                let distance = if position > count / 2 {
+                    position - count
+                } else {
+                    position
+                };
+                let driver = Driver {
+                    number: data.driver_number,
+                    led_number,
+                    distance,
+                    reached_ms: time_ms,
+                    sector: self.circuit.sector(led_number).unwrap_or(0),
+                    sector_start_ms: None,
+                    lap_start_ms: None,
+                    last_lap_ms: None,
+                    sector_times_ms: [None; SECTOR_COUNT],
+                };
+                // Not necessarily the first car there, no pass
+                let _ = self.drivers.push(driver);
+                continue;
+            };
+
+            let from = self.drivers[index].distance;
+            let step = (position - from).rem_euclid(count);
+            if step == 0 || step > count / 2 {
+                continue;
+            }
+            let driver = &mut self.drivers[index];
+            driver.distance += step;
+            driver.led_number = led_number;
+            driver.reached_ms = time_ms;
+            self.cross(index, from, time_ms, &mut events);
+            for distance in from + 1..=from + step {
+                self.pass(distance, time_ms);
+            }
+        }
+        events
+    }
+
+    /// Note the first car at `distance`
+    fn pass(&mut self, distance: i32, time_ms: u32) {
+        let slot = distance.rem_euclid(self.lap_length()) as usize;
+        if let Some(pass) = self.passes.get_mut(slot) {
+            if pass.0 < distance {
+                *pass = (distance, time_ms);
+            }
+        }
+    }
+
+    /// Count the lines a car crossed driving on from `from`
+    fn cross(
+        &mut self,
+        index: usize,
+        from: i32,
+        time_ms: u32,
+        events: &mut Vec<Event, MAX_EVENTS>,
+    ) {
+        let count = self.lap_length();
+        let starts = self
+            .circuit
+            .sector_leds
+            .map(|led| self.circuit.lap_position(led).unwrap_or(0) as i32);
+        let driver = &mut self.drivers[index];
+        let to = driver.distance;
+        for lap in from.div_euclid(count)..=to.div_euclid(count) {
+            for (sector, start) in starts.iter().enumerate() {
+                let line = lap * count + start;
+                if line <= from || line > to {
+                    continue;
+                }
+                if sector == 0 {
+                    // The first crossing starts the race
+                    if lap > 0 {
+                        let lap_ms = driver.lap_start_ms.map(|s| time_ms.saturating_sub(s));
+                        driver.last_lap_ms = lap_ms;
+                        let _ = events.push(Event::Lap {
+                            driver_number: driver.number,
+                            lap: lap as u16,
+                            time_ms: lap_ms,
+                        });
+                    }
+                    driver.lap_start_ms = Some(time_ms);
+                }
+                let sector_ms = driver.sector_start_ms.map(|s| time_ms.saturating_sub(s));
+                if sector_ms.is_some() {
+                    driver.sector_times_ms[driver.sector] = sector_ms;
+                }
+                driver.sector = sector;
+                driver.sector_start_ms = Some(time_ms);
+                let _ = events.push(Event::Sector {
+                    driver_number: driver.number,
+                    sector,
+                    time_ms: sector_ms,
+                });
+            }
+        }
+    }
+
+    /// All cars in running order
+    pub fn standings(&self) -> Vec<Standing, NUM_DRIVERS> {
+        let mut order: Vec<&Driver, NUM_DRIVERS> = self.drivers.iter().collect();
+        order.sort_unstable_by_key(|d| (Reverse(d.distance), d.reached_ms, d.number));
+        let count = self.lap_length();
+        let leader = order.first().map_or(0, |d| d.distance);
+        order
+            .iter()
+            .enumerate()
+            .map(|(index, driver)| {
+                let behind = leader - driver.distance;
+                let slot = driver.distance.rem_euclid(count) as usize;
+                let gap = match self.passes.get(slot) {
+                    _ if index == 0 => Gap::Leader,
+                    _ if behind >= count => Gap::Laps((behind / count) as u16),
+                    Some(&(distance, time_ms)) if distance == driver.distance => {
+                        Gap::Time(driver.reached_ms.saturating_sub(time_ms))
+                    }
+                    _ => Gap::Leds(behind as u32),
+                };
+                Standing {
+                    position: index + 1,
+                    driver_number: driver.number,
+                    led_number: driver.led_number,
+                    laps: driver.distance.div_euclid(count).max(0) as u16,
+                    sector: driver.sector,
+                    gap,
+                    last_lap_ms: driver.last_lap_ms,
+                    sector_times_ms: driver.sector_times_ms,
+                }
+            })
+            .collect()
+    }
+
+    /// Where `driver_number` is in the race
+    pub fn standing(&self, driver_number: u8) -> Option<Standing> {
+        self.standings()
+            .into_iter()
+            .find(|s| s.driver_number == driver_number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::circuit::{Direction, ZANDVOORT};
+    use crate::data_frame::DriverData;
+
+    fn frame(cars: &[(u8, u16)]) -> UpdateFrame {
+        let mut frame = UpdateFrame::default();
+        for (data, &(driver_number, led)) in frame.frame.iter_mut().zip(cars) {
+            *data = DriverData {
+                driver_number,
+                led_num: led as u8,
+            };
+        }
+        frame
+    }
+
+    /// Drive `driver_number` over `leds`, one LED every 100 ms
+    fn drive(
+        race: &mut Race,
+        driver_number: u8,
+        leds: impl Iterator<Item = u16>,
+        time_ms: &mut u32,
+    ) -> std::vec::Vec<Event> {
+        let mut events = std::vec::Vec::new();
+        for led in leds {
+            *time_ms += 100;
+            events.extend(race.update(*time_ms, &frame(&[(driver_number, led)])));
+        }
+        events
+    }
+
+    #[test]
+    fn test_laps_and_sectors() {
+        let mut race = Race::new(&ZANDVOORT);
+        let mut time_ms = 0;
+        // On the grid behind the line
+        assert!(race.update(time_ms, &frame(&[(1, 48)])).is_empty());
+        let standing = race.standing(1).unwrap();
+        assert_eq!((standing.laps, standing.sector), (0, 2));
+
+        let events = drive(&mut race, 1, 49..=75, &mut time_ms);
+        assert_eq!(
+            events,
+            [
+                Event::Sector {
+                    driver_number: 1,
+                    sector: 0,
+                    time_ms: None
+                },
+                Event::Sector {
+                    driver_number: 1,
+                    sector: 1,
+                    time_ms: Some(2000)
+                },
+            ]
+        );
+        let events = drive(&mut race, 1, (76..=96).chain(1..=51), &mut time_ms);
+        assert_eq!(
+            events,
+            [
+                Event::Sector {
+                    driver_number: 1,
+                    sector: 2,
+                    time_ms: Some(4900)
+                },
+                Event::Lap {
+                    driver_number: 1,
+                    lap: 1,
+                    time_ms: Some(9600)
+                },
+                Event::Sector {
+                    driver_number: 1,
+                    sector: 0,
+                    time_ms: Some(2700)
+                },
+            ]
+        );
+        let standing = race.standing(1).unwrap();
+        assert_eq!(standing.laps, 1);
+        assert_eq!(standing.led_number, 51);
+        assert_eq!(standing.last_lap_ms, Some(9600));
+        assert_eq!(
+            standing.sector_times_ms,
+            [Some(2000), Some(4900), Some(2700)]
+        );
+    }
+
+    #[test]
+    fn test_jitter_and_unknown_leds() {
+        let mut race = Race::new(&ZANDVOORT);
+        race.update(0, &frame(&[(1, 10), (2, 200), (0, 12)]));
+        race.update(100, &frame(&[(1, 12)]));
+        race.update(200, &frame(&[(1, 11)]));
+        let standings = race.standings();
+        assert_eq!(standings.len(), 1);
+        assert_eq!(standings[0].led_number, 12);
+        // Too far to drive in one frame
+        race.update(300, &frame(&[(1, 70)]));
+        assert_eq!(race.standing(1).unwrap().led_number, 12);
+
+        race.reset();
+        assert!(race.standings().is_empty());
+    }
+
+    #[test]
+    fn test_running_order() {
+        let mut race = Race::new(&ZANDVOORT);
+        race.update(0, &frame(&[(1, 49), (44, 48), (16, 47)]));
+        assert_eq!(race.standing(1).unwrap().gap, Gap::Leader);
+        assert_eq!(race.standing(16).unwrap().gap, Gap::Leds(2));
+
+        // 1 drives two LEDs per frame, 44 one, 16 stays put
+        let mut time_ms = 0;
+        for step in 1..=60 {
+            time_ms += 100;
+            let leader = (49 + 2 * step - 1) % 96 + 1;
+            let second = (48 + step - 1) % 96 + 1;
+            race.update(time_ms, &frame(&[(1, leader), (44, second), (16, 47)]));
+        }
+        let standings = race.standings();
+        let order: std::vec::Vec<(u8, u16, Gap)> = standings
+            .iter()
+            .map(|s| (s.driver_number, s.laps, s.gap))
+            .collect();
+        // 44 is where 1 was 30 frames ago, 16 is a lap down
+        assert_eq!(
+            order,
+            [
+                (1, 1, Gap::Leader),
+                (44, 0, Gap::Time(3000)),
+                (16, 0, Gap::Laps(1)),
+            ]
+        );
+        assert_eq!(standings[2].position, 3);
+    }
+
+    #[test]
+    fn test_same_place() {
+        let mut race = Race::new(&ZANDVOORT);
+        race.update(0, &frame(&[(11, 9), (4, 8)]));
+        race.update(100, &frame(&[(11, 10), (4, 9)]));
+        race.update(200, &frame(&[(11, 10), (4, 10)]));
+        let standings = race.standings();
+        // The car there first leads
+        assert_eq!(standings[0].driver_number, 11);
+        assert_eq!(standings[1].gap, Gap::Time(100));
+    }
+
+    #[test]
+    fn test_descending() {
+        let circuit = Circuit {
+            direction: Direction::Descending,
+            sector_leds: [50, 23, 70],
+            ..ZANDVOORT
+        };
+        assert_eq!(circuit.check(), Ok(()));
+        let mut race = Race::new(&circuit);
+        let mut time_ms = 0;
+        race.update(time_ms, &frame(&[(1, 51)]));
+        let events = drive(&mut race, 1, (16..=50).rev(), &mut time_ms);
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1],
+            Event::Sector {
+                driver_number: 1,
+                sector: 1,
+                time_ms: Some(2700)
+            }
+        );
+        assert_eq!(race.standing(1).unwrap().led_number, 16);
+    }
+}
@@ -0,0 +1,580 @@
+//! Race file storage in flash.
+//!
+//! A race file is a sequence of serialized [`UpdateFrame`]s, as written by
+//! `csv_to_bin`. A download writes it to the race partition as the data
+//! arrives, an interrupted download continues where it stopped, also after
+//! a restart.
+//!
+//! The first sector of the partition describes the file, the data follows
+//! in the next sectors:
+//!
+//! | offset | content                                                  |
+//! |--------|----------------------------------------------------------|
+//! | 0      | magic, file size, source id and the CRC of these         |
+//! | 16     | CRC of the file and its complement, once complete        |
+//! | 32     | progress markers, bytes stored when a data sector filled |
+//!
+//! Each field is written once after erasing the sector, as NOR flash needs.
+
+use crate::block_writer::{BlockWriter, BLOCK_SIZE};
+use crate::data_frame::{UpdateFrame, NUM_DRIVERS};
+use crate::settings::crc32_update;
+use core::ops::Range;
+use embedded_storage::nor_flash::NorFlash;
+
+/// Label of the race data partition in the partition table, see
+/// `partitions-wifi.csv` and `partitions-ota.csv`
+pub const RACE_PARTITION_LABEL: &str = "race";
+
+const MAGIC: u32 = 0x4352_3146;
+const ERASED: u32 = 0xFFFF_FFFF;
+
+const START_RECORD: u32 = 0;
+const COMPLETE_RECORD: u32 = 16;
+const MARKERS: u32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaceError<E> {
+    Flash(E),
+    /// The partition is not sector aligned or too small
+    InvalidPartition,
+    /// The file is empty, not a whole number of frames or does not fit
+    InvalidSize,
+    /// A frame repeats or leaves out drivers
+    InvalidFrame,
+    /// Data without a download in progress, or more than announced
+    UnexpectedData,
+    /// The data read back differs from the data written
+    Corrupted,
+}
+
+impl<E> From<E> for RaceError<E> {
+    fn from(err: E) -> Self {
+        RaceError::Flash(err)
+    }
+}
+
+/// A completely stored race file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaceInfo {
+    pub size: u32,
+    pub crc32: u32,
+}
+
+impl RaceInfo {
+    pub fn frame_count(&self) -> usize {
+        self.size as usize / UpdateFrame::SERIALIZED_SIZE
+    }
+}
+
+/// State of a download in progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Identifies the file being downloaded, see [`RaceStore::begin`]
+    pub source_id: u32,
+    pub stored: u32,
+    pub size: u32,
+}
+
+impl Progress {
+    pub fn percent(&self) -> u8 {
+        (self.stored as u64 * 100 / self.size.max(1) as u64) as u8
+    }
+}
+
+/// Checks that every frame has the same drivers, each once
+#[derive(Debug, Clone)]
+struct FrameValidator {
+    frame: [u8; UpdateFrame::SERIALIZED_SIZE],
+    len: usize,
+    drivers: Option<[u8; NUM_DRIVERS]>,
+}
+
+impl FrameValidator {
+    fn new() -> Self {
+        Self {
+            frame: [0; UpdateFrame::SERIALIZED_SIZE],
+            len: 0,
+            drivers: None,
+        }
+    }
+
+    fn push(&mut self, mut data: &[u8]) -> bool {
+        while !data.is_empty() {
+            let n = data.len().min(self.frame.len() - self.len);
+            self.frame[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+            if self.len == self.frame.len() {
+                self.len = 0;
+                if !self.check_frame() {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn check_frame(&mut self) -> bool {
+        let mut drivers = [0; NUM_DRIVERS];
+        for (driver, data) in drivers.iter_mut().zip(self.frame.chunks_exact(2)) {
+            *driver = data[0];
+        }
+        let mut sorted = drivers;
+        sorted.sort_unstable();
+        if sorted[0] == 0 || sorted.windows(2).any(|w| w[0] == w[1]) {
+            return false;
+        }
+        *self.drivers.get_or_insert(drivers) == drivers
+    }
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    Empty,
+    Downloading { progress: Progress, crc: u32 },
+    Complete(RaceInfo),
+}
+
+/// Race file storage on a flash partition
+pub struct RaceStore<F> {
+    flash: F,
+    start: u32,
+    end: u32,
+    state: State,
+    validator: FrameValidator,
+    writer: BlockWriter,
+}
+
+impl<F: NorFlash> RaceStore<F> {
+    const SECTOR_SIZE: u32 = F::ERASE_SIZE as u32;
+
+    /// Open the store on the flash `partition`. A download in progress is
+    /// checked and continues with the next [`write`](Self::write).
+    pub fn new(flash: F, partition: Range<u32>) -> Result<Self, RaceError<F::Error>> {
+        debug_assert!(BLOCK_SIZE % F::WRITE_SIZE == 0 && 4 % F::READ_SIZE == 0);
+        let size = partition.end.saturating_sub(partition.start);
+        // Every data sector needs a progress marker in the first sector
+        let marker_count = (Self::SECTOR_SIZE - MARKERS) / 4;
+        if partition.start % Self::SECTOR_SIZE != 0
+            || size % Self::SECTOR_SIZE != 0
+            || size / Self::SECTOR_SIZE < 2
+            || size / Self::SECTOR_SIZE - 1 > marker_count
+            || partition.end as usize > flash.capacity()
+        {
+            return Err(RaceError::InvalidPartition);
+        }
+
+        let mut store = Self {
+            flash,
+            start: partition.start,
+            end: partition.end,
+            state: State::Empty,
+            validator: FrameValidator::new(),
+            writer: BlockWriter::new(partition.start + Self::SECTOR_SIZE),
+        };
+        store.state = store.read_state()?;
+        Ok(store)
+    }
+
+    fn data_start(&self) -> u32 {
+        self.start + Self::SECTOR_SIZE
+    }
+
+    /// Largest race file that fits
+    pub fn capacity(&self) -> u32 {
+        self.end - self.data_start()
+    }
+
+    fn read_word(&mut self, offset: u32) -> Result<u32, F::Error> {
+        let mut word = [0; 4];
+        self.flash.read(self.start + offset, &mut word)?;
+        Ok(u32::from_le_bytes(word))
+    }
+
+    fn write_words(&mut self, offset: u32, words: &[u32]) -> Result<(), F::Error> {
+        let mut buf = [0; 16];
+        for (chunk, word) in buf.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        self.flash
+            .write(self.start + offset, &buf[..words.len() * 4])
+    }
+
+    fn read_state(&mut self) -> Result<State, RaceError<F::Error>> {
+        let magic = self.read_word(START_RECORD)?;
+        let size = self.read_word(START_RECORD + 4)?;
+        let source_id = self.read_word(START_RECORD + 8)?;
+        let check = self.read_word(START_RECORD + 12)?;
+        if magic != MAGIC || check != start_check(size, source_id) || !self.valid_size(size) {
+            return Ok(State::Empty);
+        }
+
+        let crc = self.read_word(COMPLETE_RECORD)?;
+        if crc == !self.read_word(COMPLETE_RECORD + 4)? {
+            return Ok(State::Complete(RaceInfo { size, crc32: crc }));
+        }
+
+        // The last marker tells how much data made it to flash
+        let mut stored = 0;
+        for marker in (MARKERS..Self::SECTOR_SIZE).step_by(4) {
+            match self.read_word(marker)? {
+                ERASED => break,
+                value if value > stored && value <= size => stored = value,
+                _ => return Ok(State::Empty),
+            }
+        }
+        self.resume(Progress {
+            source_id,
+            stored,
+            size,
+        })
+    }
+
+    // Check the stored part of a download again to continue the CRC and
+    // the frame validation
+    fn resume(&mut self, progress: Progress) -> Result<State, RaceError<F::Error>> {
+        let mut crc = ERASED;
+        self.validator = FrameValidator::new();
+        let mut buf = [0; BLOCK_SIZE];
+        let mut offset = 0;
+        while offset < progress.stored {
+            let len = (progress.stored - offset).min(BLOCK_SIZE as u32);
+            let data = &mut buf[..len as usize];
+            self.flash.read(self.data_start() + offset, data)?;
+            crc = crc32_update(crc, data);
+            if !self.validator.push(data) {
+                return Ok(State::Empty);
+            }
+            offset += len;
+        }
+        // The next sector may hold data written after the last marker
+        self.writer = BlockWriter::resume(self.data_start(), progress.stored);
+        Ok(State::Downloading { progress, crc })
+    }
+
+    fn valid_size(&self, size: u32) -> bool {
+        size > 0 && size % UpdateFrame::SERIALIZED_SIZE as u32 == 0 && size <= self.capacity()
+    }
+
+    /// The stored race, if complete
+    pub fn race(&self) -> Option<RaceInfo> {
+        match self.state {
+            State::Complete(info) => Some(info),
+            _ => None,
+        }
+    }
+
+    /// Progress of an unfinished download
+    pub fn progress(&self) -> Option<Progress> {
+        match self.state {
+            State::Downloading { progress, .. } => Some(progress),
+            _ => None,
+        }
+    }
+
+    /// Start storing a new race file of `size` bytes. `source_id` identifies
+    /// the file, a download only continues with data of the same file.
+    /// The stored race is gone from here on.
+    pub fn begin(&mut self, size: u32, source_id: u32) -> Result<(), RaceError<F::Error>> {
+        if !self.valid_size(size) {
+            return Err(RaceError::InvalidSize);
+        }
+        self.state = State::Empty;
+        self.flash
+            .erase(self.start, self.start + Self::SECTOR_SIZE)?;
+        self.write_words(
+            START_RECORD,
+            &[MAGIC, size, source_id, start_check(size, source_id)],
+        )?;
+        self.state = State::Downloading {
+            progress: Progress {
+                source_id,
+                stored: 0,
+                size,
+            },
+            crc: ERASED,
+        };
+        self.validator = FrameValidator::new();
+        self.writer = BlockWriter::new(self.data_start());
+        Ok(())
+    }
+
+    /// Store the next part of the race file
+    pub fn write(&mut self, mut data: &[u8]) -> Result<Progress, RaceError<F::Error>> {
+        let State::Downloading { progress, crc, .. } = &mut self.state else {
+            return Err(RaceError::UnexpectedData);
+        };
+        if data.len() as u32 > progress.size - progress.stored {
+            return Err(RaceError::UnexpectedData);
+        }
+        if !self.validator.push(data) {
+            self.state = State::Empty;
+            return Err(RaceError::InvalidFrame);
+        }
+        *crc = crc32_update(*crc, data);
+        progress.stored += data.len() as u32;
+        let progress = *progress;
+
+        while !data.is_empty() {
+            data = self.writer.push(data);
+            if self.writer.is_full() {
+                self.flush()?;
+            }
+        }
+        Ok(progress)
+    }
+
+    // Write the buffered block, padded to the write size. After a flash
+    // error the download starts over.
+    fn flush(&mut self) -> Result<(), RaceError<F::Error>> {
+        let result = self.write_block();
+        if result.is_err() {
+            self.state = State::Empty;
+        }
+        result
+    }
+
+    fn write_block(&mut self) -> Result<(), RaceError<F::Error>> {
+        if !matches!(self.state, State::Downloading { .. }) {
+            return Ok(());
+        }
+        let added = self.writer.flush(&mut self.flash)?;
+
+        // Mark the sectors that are complete now
+        for sector_end in (added.start / Self::SECTOR_SIZE + 1)..=(added.end / Self::SECTOR_SIZE) {
+            let marker = sector_end * Self::SECTOR_SIZE;
+            self.write_words(MARKERS + (sector_end - 1) * 4, &[marker])?;
+        }
+        Ok(())
+    }
+
+    /// Complete the download after the last [`write`](Self::write). The data
+    /// is read back and checked before the race counts as stored.
+    pub fn finish(&mut self) -> Result<RaceInfo, RaceError<F::Error>> {
+        let (size, crc) = match &self.state {
+            State::Downloading { progress, crc, .. } if progress.stored == progress.size => {
+                (progress.size, *crc ^ ERASED)
+            }
+            _ => return Err(RaceError::UnexpectedData),
+        };
+        self.flush()?;
+
+        let mut buf = [0; BLOCK_SIZE];
+        let mut check = ERASED;
+        for offset in (0..size).step_by(BLOCK_SIZE) {
+            let len = (size - offset).min(BLOCK_SIZE as u32) as usize;
+            let read_len = len.next_multiple_of(F::READ_SIZE);
+            self.flash
+                .read(self.data_start() + offset, &mut buf[..read_len])?;
+            check = crc32_update(check, &buf[..len]);
+        }
+        if check ^ ERASED != crc {
+            self.state = State::Empty;
+            return Err(RaceError::Corrupted);
+        }
+
+        self.write_words(COMPLETE_RECORD, &[crc, !crc])?;
+        let info = RaceInfo { size, crc32: crc };
+        self.state = State::Complete(info);
+        Ok(info)
+    }
+
+    /// Read frame `index` of the stored race
+    pub fn read_frame(&mut self, index: usize) -> Result<UpdateFrame, RaceError<F::Error>> {
+        let Some(info) = self.race() else {
+            return Err(RaceError::UnexpectedData);
+        };
+        if index >= info.frame_count() {
+            return Err(RaceError::InvalidSize);
+        }
+        let mut buf = [0; UpdateFrame::SERIALIZED_SIZE];
+        let offset = (index * UpdateFrame::SERIALIZED_SIZE) as u32;
+        self.flash.read(self.data_start() + offset, &mut buf)?;
+        UpdateFrame::try_from_bytes(&buf).map_err(|_| RaceError::InvalidFrame)
+    }
+
+    /// Release the flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+}
+
+fn start_check(size: u32, source_id: u32) -> u32 {
+    let mut crc = crc32_update(ERASED, &MAGIC.to_le_bytes());
+    crc = crc32_update(crc, &size.to_le_bytes());
+    crc32_update(crc, &source_id.to_le_bytes()) ^ ERASED
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::data_frame::DriverData;
+    use crate::mem_flash::{MemFlash, SECTOR_SIZE};
+    use embedded_storage::nor_flash::NorFlashErrorKind;
+
+    extern crate std;
+    use std::vec::Vec;
+
+    // Header sector and 15 data sectors
+    pub const PARTITION: Range<u32> = (SECTOR_SIZE * 4) as u32..(SECTOR_SIZE * 20) as u32;
+
+    /// A race file of `frames` frames where the cars move one LED per frame
+    pub fn race_file(frames: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..frames {
+            let mut frame: [DriverData; NUM_DRIVERS] = Default::default();
+            for (j, driver) in frame.iter_mut().enumerate() {
+                driver.driver_number = j as u8 + 1;
+                driver.led_num = ((i + j) % 96) as u8 + 1;
+            }
+            data.extend_from_slice(&UpdateFrame { frame }.to_bytes().unwrap());
+        }
+        data
+    }
+
+    fn store(flash: MemFlash) -> RaceStore<MemFlash> {
+        RaceStore::new(flash, PARTITION).unwrap()
+    }
+
+    #[test]
+    fn empty_partition() {
+        let mut store = store(MemFlash::with_sectors(20));
+        assert_eq!(store.race(), None);
+        assert_eq!(store.progress(), None);
+        assert_eq!(store.capacity(), 15 * SECTOR_SIZE as u32);
+        assert_eq!(store.read_frame(0), Err(RaceError::UnexpectedData));
+        assert_eq!(store.write(&[0; 40]), Err(RaceError::UnexpectedData));
+
+        let err = |range| RaceStore::new(MemFlash::with_sectors(20), range).err();
+        assert_eq!(
+            err(0..SECTOR_SIZE as u32),
+            Some(RaceError::InvalidPartition)
+        );
+        assert_eq!(err(1..4097), Some(RaceError::InvalidPartition));
+        // More data sectors than progress markers
+        let markers = (SECTOR_SIZE - MARKERS as usize) / 4;
+        let err = RaceStore::new(
+            MemFlash::with_sectors(markers + 2),
+            0..((markers + 2) * SECTOR_SIZE) as u32,
+        )
+        .err();
+        assert_eq!(err, Some(RaceError::InvalidPartition));
+    }
+
+    #[test]
+    fn store_and_read_race() {
+        let data = race_file(50);
+        let mut store = store(MemFlash::with_sectors(20));
+        store.begin(data.len() as u32, 7).unwrap();
+        // Chunks that don't line up with frames or blocks
+        for chunk in data.chunks(77) {
+            store.write(chunk).unwrap();
+        }
+        let info = store.finish().unwrap();
+        assert_eq!(info.frame_count(), 50);
+        assert_eq!(info.crc32, crate::settings::crc32(&data));
+
+        let flash = store.release();
+        let mut store = RaceStore::new(flash, PARTITION).unwrap();
+        assert_eq!(store.race(), Some(info));
+        for i in [0, 1, 49] {
+            let frame = store.read_frame(i).unwrap();
+            assert_eq!(frame.to_bytes().unwrap(), data[i * 40..(i + 1) * 40]);
+        }
+        assert_eq!(store.read_frame(50), Err(RaceError::InvalidSize));
+    }
+
+    #[test]
+    fn invalid_files() {
+        let mut store = store(MemFlash::with_sectors(20));
+        assert_eq!(store.begin(0, 1), Err(RaceError::InvalidSize));
+        assert_eq!(store.begin(41, 1), Err(RaceError::InvalidSize));
+        assert_eq!(store.begin(4000, 1), Err(RaceError::InvalidSize));
+
+        // A driver twice in a frame
+        let mut data = race_file(2);
+        data[42] = data[40];
+        store.begin(data.len() as u32, 1).unwrap();
+        assert_eq!(store.write(&data), Err(RaceError::InvalidFrame));
+        assert_eq!(store.progress(), None);
+
+        // More data than announced
+        let data = race_file(2);
+        store.begin(40, 1).unwrap();
+        assert_eq!(store.write(&data), Err(RaceError::UnexpectedData));
+        assert_eq!(store.finish(), Err(RaceError::UnexpectedData));
+    }
+
+    #[test]
+    fn corrupted_flash_is_detected() {
+        let data = race_file(10);
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        store.begin(data.len() as u32, 1).unwrap();
+        store.write(&data).unwrap();
+        // A bit flipped in the first data block
+        store.flash.data[PARTITION.start as usize + SECTOR_SIZE + 10] ^= 0x01;
+        assert_eq!(store.finish(), Err(RaceError::Corrupted));
+        assert_eq!(store.race(), None);
+        store.release();
+        assert_eq!(RaceStore::new(&mut flash, PARTITION).unwrap().race(), None);
+    }
+
+    #[test]
+    fn resume_after_restart() {
+        let data = race_file(90);
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        store.begin(data.len() as u32, 42).unwrap();
+        store.write(&data[..3000]).unwrap();
+        assert_eq!(store.progress().unwrap().stored, 3000);
+        store.release();
+
+        // Only completely written sectors count after a restart
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        let progress = store.progress().unwrap();
+        assert_eq!(progress.source_id, 42);
+        assert_eq!(progress.size, data.len() as u32);
+        assert_eq!(progress.stored, 11 * SECTOR_SIZE as u32);
+        assert_eq!(progress.percent(), 78);
+
+        store.write(&data[progress.stored as usize..]).unwrap();
+        let info = store.finish().unwrap();
+        assert_eq!(info.crc32, crate::settings::crc32(&data));
+        assert_eq!(
+            store.read_frame(89).unwrap().to_bytes().unwrap(),
+            data[3560..]
+        );
+    }
+
+    #[test]
+    fn flash_error_starts_over() {
+        let data = race_file(20);
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        store.begin(data.len() as u32, 1).unwrap();
+        store.flash.torn_write = Some(8);
+        assert_eq!(
+            store.write(&data),
+            Err(RaceError::Flash(NorFlashErrorKind::Other))
+        );
+        assert_eq!(store.progress(), None);
+    }
+
+    #[test]
+    fn new_download_replaces_race() {
+        let mut flash = MemFlash::with_sectors(20);
+        let mut store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        store.begin(400, 1).unwrap();
+        store.write(&race_file(10)).unwrap();
+        store.finish().unwrap();
+
+        store.begin(800, 2).unwrap();
+        assert_eq!(store.race(), None);
+        store.release();
+        let store = RaceStore::new(&mut flash, PARTITION).unwrap();
+        assert_eq!(store.race(), None);
+        assert_eq!(store.progress().unwrap().stored, 0);
+    }
+}
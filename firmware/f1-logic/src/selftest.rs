@@ -0,0 +1,274 @@
+//! LED self test for freshly assembled boards.
+//!
+//! The test first shows red, green, blue and white on all LEDs at every
+//! HD108 current gain level, then lights the LEDs one by one in chain order
+//! so the order can be compared with the `U1..Un` footprint numbering.
+
+use core::fmt;
+
+/// Number of HD108 current gain levels
+pub const GAIN_LEVELS: u8 = 32;
+
+/// Gain level used for the LED index walk and normal operation
+pub const DEFAULT_GAIN: u8 = 2;
+
+/// Highest board temperature. The self test aborts above it and the
+/// firmware shows `Overheated`.
+pub const MAX_TEMPERATURE_C: f32 = 60.0;
+
+/// Upper limit for the estimated LED current during the test. Steps that
+/// would draw more are dimmed with PWM to stay below it.
+pub const CURRENT_BUDGET_MA: u32 = 1500;
+
+/// Constant current of a single HD108 channel at `gain`, in µA.
+///
+/// Linear approximation of the datasheet: 0.5 mA at level 0,
+/// 2.24 mA at level 2 and 0.87 mA per step.
+pub fn channel_current_ua(gain: u8) -> u32 {
+    500 + 870 * gain.min(GAIN_LEVELS - 1) as u32
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestColor {
+    Red,
+    Green,
+    Blue,
+    White,
+}
+
+impl TestColor {
+    pub const ALL: [TestColor; 4] = [
+        TestColor::Red,
+        TestColor::Green,
+        TestColor::Blue,
+        TestColor::White,
+    ];
+
+    /// Which of the red, green and blue channels are on
+    pub fn channels(&self) -> (bool, bool, bool) {
+        match self {
+            TestColor::Red => (true, false, false),
+            TestColor::Green => (false, true, false),
+            TestColor::Blue => (false, false, true),
+            TestColor::White => (true, true, true),
+        }
+    }
+
+    fn channel_count(&self) -> u32 {
+        let (r, g, b) = self.channels();
+        r as u32 + g as u32 + b as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestStep {
+    /// All LEDs show `color` at current `gain`
+    Color { color: TestColor, gain: u8 },
+    /// Only LED `led` (1-based chain position) is lit white
+    Index { led: usize },
+}
+
+impl TestStep {
+    pub fn gain(&self) -> u8 {
+        match self {
+            TestStep::Color { gain, .. } => *gain,
+            TestStep::Index { .. } => DEFAULT_GAIN,
+        }
+    }
+
+    pub fn color(&self) -> TestColor {
+        match self {
+            TestStep::Color { color, .. } => *color,
+            TestStep::Index { .. } => TestColor::White,
+        }
+    }
+
+    pub fn leds_lit(&self, led_count: usize) -> usize {
+        match self {
+            TestStep::Color { .. } => led_count,
+            TestStep::Index { .. } => 1,
+        }
+    }
+
+    /// 16 bit PWM value for the lit channels, limited by [`CURRENT_BUDGET_MA`]
+    pub fn pwm(&self, led_count: usize) -> u16 {
+        let full_ua = self.full_current_ua(led_count);
+        let budget_ua = CURRENT_BUDGET_MA as u64 * 1000;
+        if full_ua <= budget_ua {
+            u16::MAX
+        } else {
+            (u16::MAX as u64 * budget_ua / full_ua) as u16
+        }
+    }
+
+    /// Estimated LED current of this step in mA
+    pub fn estimated_current_ma(&self, led_count: usize) -> u32 {
+        let ua = self.full_current_ua(led_count) * self.pwm(led_count) as u64 / u16::MAX as u64;
+        (ua / 1000) as u32
+    }
+
+    // Current at 100% PWM in µA
+    fn full_current_ua(&self, led_count: usize) -> u64 {
+        self.leds_lit(led_count) as u64
+            * self.color().channel_count() as u64
+            * channel_current_ua(self.gain()) as u64
+    }
+}
+
+/// Iterator over all steps of the self test
+#[derive(Debug, Clone)]
+pub struct SelfTest {
+    led_count: usize,
+    index: usize,
+}
+
+impl SelfTest {
+    pub fn new(led_count: usize) -> Self {
+        Self {
+            led_count,
+            index: 0,
+        }
+    }
+
+    pub fn step_count(&self) -> usize {
+        Self::COLOR_STEPS + self.led_count
+    }
+
+    const COLOR_STEPS: usize = GAIN_LEVELS as usize * TestColor::ALL.len();
+}
+
+impl Iterator for SelfTest {
+    type Item = TestStep;
+
+    fn next(&mut self) -> Option<TestStep> {
+        let index = self.index;
+        if index >= self.step_count() {
+            return None;
+        }
+        self.index += 1;
+
+        if index < Self::COLOR_STEPS {
+            Some(TestStep::Color {
+                color: TestColor::ALL[index % TestColor::ALL.len()],
+                gain: (index / TestColor::ALL.len()) as u8,
+            })
+        } else {
+            Some(TestStep::Index {
+                led: index - Self::COLOR_STEPS + 1,
+            })
+        }
+    }
+}
+
+/// Measurements taken during a single test step
+#[derive(Debug, Clone, Copy)]
+pub struct StepReport {
+    pub step: TestStep,
+    pub current_ma: u32,
+    pub temperature_start_c: Option<f32>,
+    pub temperature_end_c: Option<f32>,
+}
+
+impl StepReport {
+    pub fn temperature_rise_c(&self) -> Option<f32> {
+        Some(self.temperature_end_c? - self.temperature_start_c?)
+    }
+}
+
+impl fmt::Display for StepReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.step {
+            TestStep::Color { color, gain } => write!(f, "{:?} gain {:2}", color, gain)?,
+            TestStep::Index { led } => write!(f, "LED U{}", led)?,
+        }
+        write!(f, ": ~{} mA", self.current_ma)?;
+        if let (Some(end), Some(rise)) = (self.temperature_end_c, self.temperature_rise_c()) {
+            write!(f, ", {:.1} °C ({:+.1} °C)", end, rise)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use heapless::String;
+
+    #[test]
+    fn test_step_order() {
+        let mut test = SelfTest::new(96);
+        assert_eq!(test.step_count(), 32 * 4 + 96);
+        assert_eq!(
+            test.next(),
+            Some(TestStep::Color {
+                color: TestColor::Red,
+                gain: 0
+            })
+        );
+        assert_eq!(
+            test.nth(3),
+            Some(TestStep::Color {
+                color: TestColor::Red,
+                gain: 1
+            })
+        );
+
+        let steps = SelfTest::new(96);
+        let index_steps: heapless::Vec<usize, 96> = steps
+            .filter_map(|s| match s {
+                TestStep::Index { led } => Some(led),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(index_steps.len(), 96);
+        assert!(index_steps.iter().enumerate().all(|(i, led)| *led == i + 1));
+        assert_eq!(SelfTest::new(96).count(), 32 * 4 + 96);
+    }
+
+    #[test]
+    fn test_current_estimate() {
+        assert_eq!(channel_current_ua(DEFAULT_GAIN), 2240);
+
+        // A single white LED at the default gain
+        let step = TestStep::Index { led: 1 };
+        assert_eq!(step.pwm(96), u16::MAX);
+        assert_eq!(step.estimated_current_ma(96), 6);
+
+        // 96 red LEDs at gain 0
+        let step = TestStep::Color {
+            color: TestColor::Red,
+            gain: 0,
+        };
+        assert_eq!(step.estimated_current_ma(96), 48);
+    }
+
+    #[test]
+    fn current_stays_within_budget() {
+        for led_count in [96, 216] {
+            for step in SelfTest::new(led_count) {
+                assert!(step.estimated_current_ma(led_count) <= CURRENT_BUDGET_MA);
+            }
+        }
+
+        // All white at the highest gain must be dimmed
+        let step = TestStep::Color {
+            color: TestColor::White,
+            gain: GAIN_LEVELS - 1,
+        };
+        assert!(step.pwm(96) < u16::MAX);
+    }
+
+    #[test]
+    fn test_report_display() {
+        let report = StepReport {
+            step: TestStep::Index { led: 7 },
+            current_ma: 6,
+            temperature_start_c: Some(25.0),
+            temperature_end_c: Some(25.5),
+        };
+        let mut s: String<64> = String::new();
+        write!(s, "{}", report).unwrap();
+        assert_eq!(s, "LED U7: ~6 mA, 25.5 °C (+0.5 °C)");
+    }
+}
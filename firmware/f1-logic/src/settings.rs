@@ -0,0 +1,647 @@
+//! Persistent settings.
+//!
+//! Settings are stored as records in a flash partition. Each record has a
+//! header with a format version and sequence number, the encoded settings
+//! and a CRC-32. New records are appended after the previous one, moving to
+//! the next sector when a sector is full, so every save writes to fresh flash
+//! and the erases are spread over the whole partition. At boot the valid
+//! record with the highest sequence number wins, so an interrupted save falls
+//! back to the previous settings.
+//!
+//! Newer format versions only append fields. Records written by an older
+//! firmware decode with defaults for the fields they don't have, and
+//! [`migrate`] adjusts values whose meaning changed.
+
+use crate::animation::StartupAnimation;
+use core::ops::Range;
+use embedded_storage::nor_flash::NorFlash;
+use heapless::{String, Vec};
+
+/// Version of the settings format written by this firmware
+pub const SETTINGS_VERSION: u16 = 4;
+
+/// Location of the `settings` partition in the firmware's partitions.csv
+pub const SETTINGS_PARTITION: Range<u32> = 0x3F_0000..0x40_0000;
+
+/// Largest encoded settings size
+pub const MAX_PAYLOAD_SIZE: usize = 640;
+
+/// Number of Wi-Fi networks that can be stored
+pub const MAX_NETWORKS: usize = 4;
+
+/// Longest race download URL
+pub const MAX_URL_LEN: usize = 128;
+
+/// Longest MQTT broker URL, shorter than other URLs to keep the settings
+/// within [`MAX_PAYLOAD_SIZE`]
+pub const MAX_BROKER_URL_LEN: usize = 64;
+
+/// How the second driver of a team is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeammateColors {
+    /// Both drivers use the team color
+    Same,
+    /// The second driver uses a dimmed team color
+    Dimmed,
+}
+
+impl TeammateColors {
+    pub const ALL: [TeammateColors; 2] = [TeammateColors::Same, TeammateColors::Dimmed];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TeammateColors::Same => "same",
+            TeammateColors::Dimmed => "dimmed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| t.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Network {
+    pub ssid: String<32>,
+    /// Empty for open networks
+    pub password: String<64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub brightness: u8,
+    pub startup_animation: StartupAnimation,
+    pub teammate_colors: TeammateColors,
+    /// OpenF1 session key of the selected race
+    pub race_session_key: u32,
+    /// Wi-Fi networks in the order they are tried
+    pub networks: Vec<Network, MAX_NETWORKS>,
+    /// Where the race file is downloaded from, empty when not set
+    pub race_url: String<MAX_URL_LEN>,
+    /// MQTT broker to publish to, empty when not set
+    pub mqtt_url: String<MAX_BROKER_URL_LEN>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            brightness: 255,
+            startup_animation: StartupAnimation::Train,
+            teammate_colors: TeammateColors::Same,
+            // 2023 Dutch Grand Prix
+            race_session_key: 9149,
+            networks: Vec::new(),
+            race_url: String::new(),
+            mqtt_url: String::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Encode the settings in the current format
+    pub fn encode(&self) -> Vec<u8, MAX_PAYLOAD_SIZE> {
+        let mut payload = Vec::new();
+        // The fields are far smaller than the payload
+        let _ = payload.push(self.brightness);
+        let _ = payload.push(index_of(&StartupAnimation::ALL, self.startup_animation));
+        let _ = payload.push(index_of(&TeammateColors::ALL, self.teammate_colors));
+        let _ = payload.extend_from_slice(&self.race_session_key.to_le_bytes());
+        // Version 1 stored a single network, keep it in the same place so
+        // older firmware still finds the first network
+        let (first, others) = match self.networks.split_first() {
+            Some((first, others)) => (Some(first), others),
+            None => (None, &[][..]),
+        };
+        push_str(&mut payload, first.map_or("", |n| &n.ssid));
+        push_str(&mut payload, first.map_or("", |n| &n.password));
+        // Version 2
+        let _ = payload.push(others.len() as u8);
+        for network in others {
+            push_str(&mut payload, &network.ssid);
+            push_str(&mut payload, &network.password);
+        }
+        // Version 3
+        push_str(&mut payload, &self.race_url);
+        // Version 4
+        push_str(&mut payload, &self.mqtt_url);
+        payload
+    }
+
+    /// Add `network` in front of the stored networks, replacing a network
+    /// with the same name. The last network is dropped when the list is full.
+    pub fn add_network(&mut self, network: Network) {
+        self.networks.retain(|n| n.ssid != network.ssid);
+        if self.networks.is_full() {
+            self.networks.pop();
+        }
+        let _ = self.networks.insert(0, network);
+    }
+
+    /// Decode settings written in format `version`. Missing or invalid
+    /// fields keep their default value.
+    pub fn decode(version: u16, payload: &[u8]) -> Self {
+        let mut settings = Settings::default();
+        let mut reader = Reader(payload);
+        settings.read_fields(&mut reader);
+        migrate(version, &mut settings);
+        settings
+    }
+
+    // Returns at the first field that is missing
+    fn read_fields(&mut self, reader: &mut Reader) -> Option<()> {
+        self.brightness = reader.u8()?;
+        if let Some(animation) = StartupAnimation::ALL.get(reader.u8()? as usize) {
+            self.startup_animation = *animation;
+        }
+        if let Some(teammate_colors) = TeammateColors::ALL.get(reader.u8()? as usize) {
+            self.teammate_colors = *teammate_colors;
+        }
+        self.race_session_key = reader.u32()?;
+        self.read_network(reader)?;
+        // Version 2
+        for _ in 0..reader.u8()? {
+            self.read_network(reader)?;
+        }
+        // Version 3
+        if let Ok(race_url) = String::try_from(reader.str()?) {
+            self.race_url = race_url;
+        }
+        // Version 4
+        if let Ok(mqtt_url) = String::try_from(reader.str()?) {
+            self.mqtt_url = mqtt_url;
+        }
+        Some(())
+    }
+
+    fn read_network(&mut self, reader: &mut Reader) -> Option<()> {
+        let (ssid, password) = (reader.str()?, reader.str()?);
+        if let (Ok(ssid), Ok(password)) = (String::try_from(ssid), String::try_from(password)) {
+            if !ssid.is_empty() {
+                let _ = self.networks.push(Network { ssid, password });
+            }
+        }
+        Some(())
+    }
+}
+
+/// Update settings read from a record in format `version` to the current format
+pub fn migrate(version: u16, settings: &mut Settings) {
+    // Versions 2 to 4 only added fields, no values changed meaning yet
+    let _ = (version, settings);
+}
+
+fn index_of<T: PartialEq>(all: &[T], value: T) -> u8 {
+    all.iter().position(|v| *v == value).unwrap_or(0) as u8
+}
+
+fn push_str(payload: &mut Vec<u8, MAX_PAYLOAD_SIZE>, s: &str) {
+    let _ = payload.push(s.len() as u8);
+    let _ = payload.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        let len = self.u8()? as usize;
+        core::str::from_utf8(self.bytes(len)?).ok()
+    }
+}
+
+/// CRC-32 (IEEE 802.3)
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0xFFFF_FFFF, data) ^ 0xFFFF_FFFF
+}
+
+pub(crate) fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+const MAGIC: u16 = 0x5E71;
+const ERASED: u16 = 0xFFFF;
+
+// Magic, version, payload length, reserved and sequence number
+const HEADER_SIZE: usize = 12;
+const CRC_SIZE: usize = 4;
+const MAX_RECORD_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD_SIZE + CRC_SIZE;
+
+/// Records start at multiples of this, it must be a multiple of the flash
+/// read and write sizes
+const ALIGN: usize = 4;
+
+fn record_size(payload_len: usize) -> usize {
+    (HEADER_SIZE + payload_len + CRC_SIZE).next_multiple_of(ALIGN)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError<E> {
+    Flash(E),
+    /// The partition is not sector aligned or has less than two sectors
+    InvalidPartition,
+}
+
+impl<E> From<E> for StoreError<E> {
+    fn from(err: E) -> Self {
+        StoreError::Flash(err)
+    }
+}
+
+// Position of a valid record
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    sequence: u32,
+    address: u32,
+}
+
+/// Wear leveled settings storage on a flash partition
+pub struct SettingsStore<F> {
+    flash: F,
+    start: u32,
+    sector_count: u32,
+    newest: Option<Record>,
+    // Where the next record is written
+    sector: u32,
+    offset: u32,
+}
+
+impl<F: NorFlash> SettingsStore<F> {
+    const SECTOR_SIZE: u32 = F::ERASE_SIZE as u32;
+
+    /// Open the store on the flash `partition` and find the newest record
+    pub fn new(flash: F, partition: Range<u32>) -> Result<Self, StoreError<F::Error>> {
+        debug_assert!(ALIGN % F::READ_SIZE == 0 && ALIGN % F::WRITE_SIZE == 0);
+        let size = partition.end.saturating_sub(partition.start);
+        if partition.start % Self::SECTOR_SIZE != 0
+            || size % Self::SECTOR_SIZE != 0
+            || size / Self::SECTOR_SIZE < 2
+            || partition.end as usize > flash.capacity()
+        {
+            return Err(StoreError::InvalidPartition);
+        }
+
+        let mut store = Self {
+            flash,
+            start: partition.start,
+            sector_count: size / Self::SECTOR_SIZE,
+            newest: None,
+            // Without records the first save erases and uses sector 0
+            sector: size / Self::SECTOR_SIZE - 1,
+            offset: Self::SECTOR_SIZE,
+        };
+        store.scan()?;
+        Ok(store)
+    }
+
+    fn scan(&mut self) -> Result<(), F::Error> {
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        for sector in 0..self.sector_count {
+            let mut offset = 0;
+            let mut newest_in_sector = false;
+
+            while offset + HEADER_SIZE as u32 <= Self::SECTOR_SIZE {
+                let address = self.start + sector * Self::SECTOR_SIZE + offset;
+                self.flash.read(address, &mut buf[..HEADER_SIZE])?;
+                let magic = u16::from_le_bytes([buf[0], buf[1]]);
+                let payload_len = u16::from_le_bytes([buf[4], buf[5]]) as usize;
+                let size = record_size(payload_len) as u32;
+
+                if magic == ERASED {
+                    break;
+                }
+                if magic != MAGIC
+                    || payload_len > MAX_PAYLOAD_SIZE
+                    || offset + size > Self::SECTOR_SIZE
+                {
+                    // Garbage, don't append to this sector
+                    offset = Self::SECTOR_SIZE;
+                    break;
+                }
+
+                self.flash.read(address, &mut buf[..size as usize])?;
+                if let Some(sequence) = check_record(&buf[..size as usize]) {
+                    if self.newest.map_or(true, |r| sequence > r.sequence) {
+                        self.newest = Some(Record { sequence, address });
+                        newest_in_sector = true;
+                    }
+                }
+                offset += size;
+            }
+
+            if newest_in_sector {
+                self.sector = sector;
+                self.offset = offset;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the newest settings, or the defaults when nothing was saved yet
+    pub fn load(&mut self) -> Result<Settings, StoreError<F::Error>> {
+        let Some(record) = self.newest else {
+            return Ok(Settings::default());
+        };
+
+        let mut buf = [0u8; MAX_RECORD_SIZE];
+        self.flash.read(record.address, &mut buf[..HEADER_SIZE])?;
+        let payload_len = u16::from_le_bytes([buf[4], buf[5]]) as usize;
+        let size = record_size(payload_len);
+        self.flash.read(record.address, &mut buf[..size])?;
+
+        let version = u16::from_le_bytes([buf[2], buf[3]]);
+        Ok(Settings::decode(
+            version,
+            &buf[HEADER_SIZE..HEADER_SIZE + payload_len],
+        ))
+    }
+
+    /// Append a record with `settings`
+    pub fn save(&mut self, settings: &Settings) -> Result<(), StoreError<F::Error>> {
+        let sequence = self.newest.map_or(0, |r| r.sequence.wrapping_add(1));
+        let payload = settings.encode();
+        let size = record_size(payload.len());
+
+        let mut buf = [0xFFu8; MAX_RECORD_SIZE];
+        buf[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[2..4].copy_from_slice(&SETTINGS_VERSION.to_le_bytes());
+        buf[4..6].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buf[8..12].copy_from_slice(&sequence.to_le_bytes());
+        buf[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(&payload);
+        let crc_offset = HEADER_SIZE + payload.len();
+        let crc = crc32(&buf[..crc_offset]);
+        buf[crc_offset..crc_offset + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+
+        if self.offset + size as u32 > Self::SECTOR_SIZE {
+            // The current sector still holds the newest record until the
+            // new one is written, so an interrupted save loses nothing
+            self.sector = (self.sector + 1) % self.sector_count;
+            self.offset = 0;
+            let sector_start = self.start + self.sector * Self::SECTOR_SIZE;
+            self.flash
+                .erase(sector_start, sector_start + Self::SECTOR_SIZE)?;
+        }
+
+        let address = self.start + self.sector * Self::SECTOR_SIZE + self.offset;
+        // Advance first, a failed write leaves garbage that must be skipped
+        self.offset += size as u32;
+        self.flash.write(address, &buf[..size])?;
+        self.newest = Some(Record { sequence, address });
+        Ok(())
+    }
+
+    /// Release the flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+}
+
+// Sequence number of a complete record with a valid CRC
+fn check_record(record: &[u8]) -> Option<u32> {
+    let payload_len = u16::from_le_bytes([record[4], record[5]]) as usize;
+    let crc_offset = HEADER_SIZE + payload_len;
+    let crc = u32::from_le_bytes(record[crc_offset..crc_offset + CRC_SIZE].try_into().ok()?);
+    if crc32(&record[..crc_offset]) != crc {
+        return None;
+    }
+    Some(u32::from_le_bytes(record[8..12].try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem_flash::{MemFlash, SECTOR_SIZE};
+
+    fn settings(brightness: u8) -> Settings {
+        Settings {
+            brightness,
+            startup_animation: StartupAnimation::Rainbow,
+            teammate_colors: TeammateColors::Dimmed,
+            race_session_key: 9158,
+            networks: Vec::from_slice(&[network("paddock", "box box"), network("pit lane", "")])
+                .unwrap(),
+            race_url: String::try_from("http://relay.local:8080/races/9158.bin").unwrap(),
+            mqtt_url: String::try_from("mqtt://homeassistant.local").unwrap(),
+        }
+    }
+
+    fn network(ssid: &str, password: &str) -> Network {
+        Network {
+            ssid: String::try_from(ssid).unwrap(),
+            password: String::try_from(password).unwrap(),
+        }
+    }
+
+    // Partition of the last four sectors
+    const PARTITION: Range<u32> = (SECTOR_SIZE * 4) as u32..(SECTOR_SIZE * 8) as u32;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let settings = settings(40);
+        let payload = settings.encode();
+        assert_eq!(Settings::decode(SETTINGS_VERSION, &payload), settings);
+    }
+
+    #[test]
+    fn decode_older_format() {
+        // A record with only the first fields gets defaults for the others
+        let payload = settings(40).encode();
+        let settings = Settings::decode(1, &payload[..3]);
+        assert_eq!(settings.brightness, 40);
+        assert_eq!(settings.startup_animation, StartupAnimation::Rainbow);
+        assert_eq!(settings.race_session_key, 9149);
+        assert!(settings.networks.is_empty());
+
+        // Version 1 stored a single network
+        let mut payload = settings_v1().encode();
+        payload.truncate(payload.len() - 3);
+        let settings = Settings::decode(1, &payload);
+        assert_eq!(
+            settings.networks.as_slice(),
+            &[network("paddock", "box box")]
+        );
+
+        // Version 3 had no MQTT broker
+        let mut payload = self::settings(40).encode();
+        payload.truncate(payload.len() - 1 - "mqtt://homeassistant.local".len());
+        let settings = Settings::decode(3, &payload);
+        assert_eq!(settings.race_url, "http://relay.local:8080/races/9158.bin");
+        assert_eq!(settings.mqtt_url, "");
+
+        // Invalid values are replaced by defaults
+        let settings = Settings::decode(1, &[40, 200, 1]);
+        assert_eq!(settings.startup_animation, StartupAnimation::Train);
+        assert_eq!(settings.teammate_colors, TeammateColors::Dimmed);
+    }
+
+    // Settings with a single network, encoded like version 1 plus an empty
+    // list of additional networks and empty URLs
+    fn settings_v1() -> Settings {
+        let mut settings = settings(40);
+        settings.networks.truncate(1);
+        settings.race_url.clear();
+        settings.mqtt_url.clear();
+        settings
+    }
+
+    #[test]
+    fn test_add_network() {
+        let mut settings = Settings::default();
+        for ssid in ["a", "b", "c", "d"] {
+            settings.add_network(network(ssid, ""));
+        }
+        let ssids = |s: &Settings| {
+            s.networks
+                .iter()
+                .map(|n| n.ssid.clone())
+                .collect::<Vec<_, 4>>()
+        };
+        assert_eq!(ssids(&settings), ["d", "c", "b", "a"]);
+
+        // A known network moves to the front with the new password
+        settings.add_network(network("b", "password"));
+        assert_eq!(ssids(&settings), ["b", "d", "c", "a"]);
+        assert_eq!(settings.networks[0].password, "password");
+
+        // The last network is dropped when full
+        settings.add_network(network("e", ""));
+        assert_eq!(ssids(&settings), ["e", "b", "d", "c"]);
+    }
+
+    #[test]
+    fn empty_flash_loads_defaults() {
+        let mut store = SettingsStore::new(MemFlash::new(), PARTITION).unwrap();
+        assert_eq!(store.load().unwrap(), Settings::default());
+    }
+
+    #[test]
+    fn invalid_partition() {
+        let err = |range| SettingsStore::new(MemFlash::new(), range).err();
+        assert_eq!(err(0..256), Some(StoreError::InvalidPartition));
+        assert_eq!(err(100..612), Some(StoreError::InvalidPartition));
+        assert_eq!(err(1024..4096), Some(StoreError::InvalidPartition));
+    }
+
+    #[test]
+    fn test_save_and_reopen() {
+        let mut store = SettingsStore::new(MemFlash::new(), PARTITION).unwrap();
+        store.save(&settings(1)).unwrap();
+        store.save(&settings(2)).unwrap();
+        assert_eq!(store.load().unwrap(), settings(2));
+
+        let flash = store.release();
+        // Nothing is written outside the partition
+        assert!(flash.data[..PARTITION.start as usize]
+            .iter()
+            .all(|b| *b == 0xFF));
+
+        let mut store = SettingsStore::new(flash, PARTITION).unwrap();
+        assert_eq!(store.load().unwrap(), settings(2));
+        store.save(&settings(3)).unwrap();
+        let mut store = SettingsStore::new(store.release(), PARTITION).unwrap();
+        assert_eq!(store.load().unwrap(), settings(3));
+    }
+
+    #[test]
+    fn test_wear_leveling() {
+        let mut flash = MemFlash::new();
+        for brightness in 0..=255 {
+            let mut store = SettingsStore::new(flash, PARTITION).unwrap();
+            store.save(&settings(brightness)).unwrap();
+            flash = store.release();
+        }
+
+        let mut store = SettingsStore::new(flash, PARTITION).unwrap();
+        assert_eq!(store.load().unwrap(), settings(255));
+
+        // The erases are spread evenly over the partition
+        let counts = &store.release().erase_counts;
+        assert!(counts[..4].iter().all(|c| *c == 0));
+        let (min, max) = (counts[4..].iter().min(), counts[4..].iter().max());
+        assert!(*min.unwrap() > 5);
+        assert!(max.unwrap() - min.unwrap() <= 1);
+    }
+
+    #[test]
+    fn corrupted_record_falls_back() {
+        let mut store = SettingsStore::new(MemFlash::new(), PARTITION).unwrap();
+        store.save(&settings(1)).unwrap();
+        store.save(&settings(2)).unwrap();
+
+        // Flip a bit in the payload of the newest record
+        let mut flash = store.release();
+        let address = PARTITION.start as usize + record_size(settings(1).encode().len());
+        flash.data[address + HEADER_SIZE] ^= 0x01;
+
+        let mut store = SettingsStore::new(flash, PARTITION).unwrap();
+        assert_eq!(store.load().unwrap(), settings(1));
+    }
+
+    #[test]
+    fn interrupted_save_keeps_previous() {
+        let mut store = SettingsStore::new(MemFlash::new(), PARTITION).unwrap();
+        store.save(&settings(1)).unwrap();
+
+        let mut flash = store.release();
+        flash.torn_write = Some(8);
+        let mut store = SettingsStore::new(flash, PARTITION).unwrap();
+        assert!(store.save(&settings(2)).is_err());
+
+        let mut store = SettingsStore::new(store.release(), PARTITION).unwrap();
+        assert_eq!(store.load().unwrap(), settings(1));
+
+        // The next save skips the incomplete record
+        store.save(&settings(3)).unwrap();
+        let mut store = SettingsStore::new(store.release(), PARTITION).unwrap();
+        assert_eq!(store.load().unwrap(), settings(3));
+    }
+
+    #[test]
+    fn interrupted_erase_of_new_sector() {
+        // Fill the first sector, then lose power while writing the first
+        // record of the next one
+        let mut flash = MemFlash::new();
+        let per_sector = SECTOR_SIZE / record_size(settings(0).encode().len());
+        for brightness in 0..per_sector as u8 {
+            let mut store = SettingsStore::new(flash, PARTITION).unwrap();
+            store.save(&settings(brightness)).unwrap();
+            flash = store.release();
+        }
+        flash.torn_write = Some(4);
+        let mut store = SettingsStore::new(flash, PARTITION).unwrap();
+        assert!(store.save(&settings(100)).is_err());
+
+        let mut store = SettingsStore::new(store.release(), PARTITION).unwrap();
+        assert_eq!(store.load().unwrap(), settings(per_sector as u8 - 1));
+    }
+}
@@ -0,0 +1,273 @@
+use embedded_hal::digital::OutputPin;
+
+/// High level state of the firmware, shown on the status indicator LED
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareState {
+    Idle,
+    Connecting,
+    Downloading,
+    Racing,
+    Paused,
+    Overheated,
+    Error,
+}
+
+/// A single on/off period of a blink pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Step {
+    pub on: bool,
+    pub duration_ms: u32,
+}
+
+const fn on(duration_ms: u32) -> Step {
+    Step {
+        on: true,
+        duration_ms,
+    }
+}
+
+const fn off(duration_ms: u32) -> Step {
+    Step {
+        on: false,
+        duration_ms,
+    }
+}
+
+/// Blink pattern for a firmware state. The pattern repeats forever.
+/// `color` is used when the indicator is an RGB LED, a single color
+/// LED only follows the on/off steps.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Pattern {
+    pub color: (u8, u8, u8),
+    pub steps: &'static [Step],
+}
+
+impl FirmwareState {
+    pub const ALL: [FirmwareState; 7] = [
+        FirmwareState::Idle,
+        FirmwareState::Connecting,
+        FirmwareState::Downloading,
+        FirmwareState::Racing,
+        FirmwareState::Paused,
+        FirmwareState::Overheated,
+        FirmwareState::Error,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FirmwareState::Idle => "idle",
+            FirmwareState::Connecting => "connecting",
+            FirmwareState::Downloading => "downloading",
+            FirmwareState::Racing => "racing",
+            FirmwareState::Paused => "paused",
+            FirmwareState::Overheated => "overheated",
+            FirmwareState::Error => "error",
+        }
+    }
+
+    pub fn pattern(&self) -> &'static Pattern {
+        match self {
+            FirmwareState::Idle => &IDLE,
+            FirmwareState::Connecting => &CONNECTING,
+            FirmwareState::Downloading => &DOWNLOADING,
+            FirmwareState::Racing => &RACING,
+            FirmwareState::Paused => &PAUSED,
+            FirmwareState::Overheated => &OVERHEATED,
+            FirmwareState::Error => &ERROR,
+        }
+    }
+}
+
+// Short heartbeat every 2 seconds
+static IDLE: Pattern = Pattern {
+    color: (255, 255, 255),
+    steps: &[on(100), off(1900)],
+};
+
+// Fast even blinking
+static CONNECTING: Pattern = Pattern {
+    color: (0, 0, 255),
+    steps: &[on(150), off(150)],
+};
+
+// Double blink
+static DOWNLOADING: Pattern = Pattern {
+    color: (0, 255, 255),
+    steps: &[on(100), off(100), on(100), off(700)],
+};
+
+// Solid on
+static RACING: Pattern = Pattern {
+    color: (0, 255, 0),
+    steps: &[on(1000)],
+};
+
+// Slow even blinking
+static PAUSED: Pattern = Pattern {
+    color: (255, 160, 0),
+    steps: &[on(500), off(500)],
+};
+
+// Very fast flicker
+static OVERHEATED: Pattern = Pattern {
+    color: (255, 0, 0),
+    steps: &[on(50), off(50)],
+};
+
+// Triple blink followed by a pause
+static ERROR: Pattern = Pattern {
+    color: (255, 0, 0),
+    steps: &[on(100), off(100), on(100), off(100), on(100), off(1000)],
+};
+
+/// Non-blocking player for status patterns.
+///
+/// The player never waits itself: `update` drives the pin for the given
+/// time and returns the time at which it wants to be called again.
+pub struct PatternPlayer<P> {
+    pin: P,
+    state: FirmwareState,
+    step: usize,
+    step_started_ms: u64,
+}
+
+impl<P> PatternPlayer<P>
+where
+    P: OutputPin,
+{
+    pub fn new(pin: P, state: FirmwareState, now_ms: u64) -> Result<Self, P::Error> {
+        let mut player = Self {
+            pin,
+            state,
+            step: 0,
+            step_started_ms: now_ms,
+        };
+        player.apply()?;
+        Ok(player)
+    }
+
+    pub fn state(&self) -> FirmwareState {
+        self.state
+    }
+
+    /// Switch to the pattern of `state`. The pattern restarts from its
+    /// first step, unless the state did not change.
+    pub fn set_state(&mut self, state: FirmwareState, now_ms: u64) -> Result<(), P::Error> {
+        if state == self.state {
+            return Ok(());
+        }
+        self.state = state;
+        self.step = 0;
+        self.step_started_ms = now_ms;
+        self.apply()
+    }
+
+    /// Advance the pattern to `now_ms` and return the time in ms
+    /// at which the next step starts.
+    pub fn update(&mut self, now_ms: u64) -> Result<u64, P::Error> {
+        let steps = self.state.pattern().steps;
+        let mut changed = false;
+        loop {
+            let deadline = self.step_started_ms + steps[self.step].duration_ms as u64;
+            if now_ms < deadline {
+                break;
+            }
+            self.step = (self.step + 1) % steps.len();
+            self.step_started_ms = deadline;
+            changed = true;
+        }
+        if changed {
+            self.apply()?;
+        }
+        Ok(self.step_started_ms + steps[self.step].duration_ms as u64)
+    }
+
+    pub fn release(self) -> P {
+        self.pin
+    }
+
+    fn apply(&mut self) -> Result<(), P::Error> {
+        if self.state.pattern().steps[self.step].on {
+            self.pin.set_high()
+        } else {
+            self.pin.set_low()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::Infallible;
+    use embedded_hal::digital::ErrorType;
+    use heapless::Vec;
+
+    /// Output pin that records every level written to it
+    #[derive(Default)]
+    struct MockPin {
+        writes: Vec<bool, 64>,
+    }
+
+    impl ErrorType for MockPin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for MockPin {
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.writes.push(false).unwrap();
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.writes.push(true).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn patterns_are_distinct() {
+        for (i, a) in FirmwareState::ALL.iter().enumerate() {
+            for b in &FirmwareState::ALL[i + 1..] {
+                assert_ne!(a.pattern(), b.pattern(), "{:?} and {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn plays_pattern_steps() {
+        let mut player = PatternPlayer::new(MockPin::default(), FirmwareState::Idle, 0).unwrap();
+        assert_eq!(player.update(0).unwrap(), 100);
+        assert_eq!(player.update(99).unwrap(), 100);
+        assert_eq!(player.update(100).unwrap(), 2000);
+        assert_eq!(player.update(2000).unwrap(), 2100);
+
+        let pin = player.release();
+        assert_eq!(&pin.writes[..], &[true, false, true]);
+    }
+
+    #[test]
+    fn catches_up_after_missed_steps() {
+        let mut player =
+            PatternPlayer::new(MockPin::default(), FirmwareState::Downloading, 0).unwrap();
+        // Skip the whole first double blink and land in the second "on" step
+        assert_eq!(player.update(1250).unwrap(), 1300);
+
+        let pin = player.release();
+        assert_eq!(&pin.writes[..], &[true, true]);
+    }
+
+    #[test]
+    fn state_change_restarts_pattern() {
+        let mut player = PatternPlayer::new(MockPin::default(), FirmwareState::Idle, 0).unwrap();
+        player.update(500).unwrap();
+        player.set_state(FirmwareState::Racing, 500).unwrap();
+        assert_eq!(player.state(), FirmwareState::Racing);
+        assert_eq!(player.update(500).unwrap(), 1500);
+        // Setting the same state again does not restart the pattern
+        player.set_state(FirmwareState::Racing, 800).unwrap();
+        assert_eq!(player.update(800).unwrap(), 1500);
+
+        let pin = player.release();
+        assert_eq!(&pin.writes[..], &[true, false, true]);
+    }
+}
@@ -0,0 +1,1049 @@
+//! Live race streaming from the relay.
+//!
+//! The board subscribes to a session with a [`Request`] sent to the relay's
+//! [`STREAM_PORT`], the relay answers with a stream of [`Message`]s, one per
+//! UDP datagram:
+//!
+//! - `Hello` starts a stream and describes the session.
+//! - `Keyframe` carries a whole frame. The relay sends one every
+//!   [`KEYFRAME_INTERVAL_MS`] and when the board asks for a resync.
+//! - `Frame` carries the drivers that moved since the last keyframe, so a
+//!   lost frame only loses itself.
+//! - `Heartbeat` keeps the stream alive while the relay has no frames.
+//! - `End` follows the last frame.
+//!
+//! Frames are numbered and carry the race time they belong to. The board
+//! plays them through a [`JitterBuffer`], which delays playback by a fixed
+//! time so frames arriving late or out of order still play on time.
+//!
+//! Every message starts with the magic `F1`, the protocol version and the
+//! message kind, numbers are little endian.
+
+use crate::data_frame::{DriverData, UpdateFrame, NUM_DRIVERS};
+use heapless::Vec;
+
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// UDP port of the relay's stream server
+pub const STREAM_PORT: u16 = 8081;
+
+/// Largest message, a frame where every driver moved
+pub const MAX_MESSAGE_SIZE: usize = HEADER_SIZE + 13 + NUM_DRIVERS * 2;
+
+/// Time between two keyframes
+pub const KEYFRAME_INTERVAL_MS: u32 = 1_000;
+
+/// The relay sends a heartbeat when it has sent nothing for this long
+pub const HEARTBEAT_INTERVAL_MS: u32 = 1_000;
+
+/// The board repeats its subscription this often
+pub const KEEPALIVE_INTERVAL_MS: u32 = 2_000;
+
+/// The relay stops streaming to a board it has not heard from for this long
+pub const SUBSCRIPTION_TIMEOUT_MS: u32 = 3 * KEEPALIVE_INTERVAL_MS;
+
+/// The board gives up on a stream after this long without a message
+pub const STREAM_TIMEOUT_MS: u32 = 5_000;
+
+/// Playback delay of the board, the jitter the stream can absorb
+pub const DEFAULT_DELAY_MS: u32 = 2_000;
+
+const MAGIC: [u8; 2] = *b"F1";
+const HEADER_SIZE: usize = 4;
+
+const KIND_HELLO: u8 = 0x01;
+const KIND_KEYFRAME: u8 = 0x02;
+const KIND_FRAME: u8 = 0x03;
+const KIND_HEARTBEAT: u8 = 0x04;
+const KIND_END: u8 = 0x05;
+const KIND_SUBSCRIBE: u8 = 0x81;
+const KIND_RESYNC: u8 = 0x82;
+const KIND_UNSUBSCRIBE: u8 = 0x83;
+
+/// Message from the relay to the board
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Hello {
+        session_key: u32,
+        frame_interval_ms: u16,
+        /// Frames in the session, 0 when unknown
+        frame_count: u32,
+    },
+    Keyframe {
+        sequence: u32,
+        timestamp_ms: u32,
+        frame: UpdateFrame,
+    },
+    Frame {
+        sequence: u32,
+        timestamp_ms: u32,
+        /// Sequence number of the keyframe the changes apply to
+        keyframe: u32,
+        changes: Vec<DriverData, NUM_DRIVERS>,
+    },
+    Heartbeat {
+        /// Sequence number of the next frame
+        sequence: u32,
+    },
+    End {
+        /// Number of frames in the stream
+        sequence: u32,
+    },
+}
+
+/// Message from the board to the relay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Request {
+    /// Stream `session_key` from frame `sequence` on. Repeated every
+    /// [`KEEPALIVE_INTERVAL_MS`] to keep the stream going.
+    Subscribe {
+        session_key: u32,
+        sequence: u32,
+    },
+    /// Send a keyframe, the board can't decode the frames after `sequence`
+    Resync {
+        sequence: u32,
+    },
+    Unsubscribe,
+}
+
+struct Writer<'a> {
+    out: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn new(out: &'a mut [u8], kind: u8) -> Option<Self> {
+        let mut writer = Self { out, len: 0 };
+        writer.bytes(&MAGIC)?;
+        writer.bytes(&[PROTOCOL_VERSION, kind])?;
+        Some(writer)
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        self.out
+            .get_mut(self.len..self.len + bytes.len())?
+            .copy_from_slice(bytes);
+        self.len += bytes.len();
+        Some(())
+    }
+
+    fn u32(&mut self, value: u32) -> Option<()> {
+        self.bytes(&value.to_le_bytes())
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    /// Check the header, returns the message kind
+    fn new(buf: &'a [u8]) -> Option<(Self, u8)> {
+        match buf {
+            [m0, m1, PROTOCOL_VERSION, kind, rest @ ..] if [*m0, *m1] == MAGIC => {
+                Some((Self { buf: rest }, *kind))
+            }
+            _ => None,
+        }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    /// `value`, when the whole message was read
+    fn finish<T>(self, value: T) -> Option<T> {
+        self.buf.is_empty().then_some(value)
+    }
+}
+
+impl Message {
+    /// Encode the message into `out`, returns its length or `None` when
+    /// `out` is too small
+    pub fn encode(&self, out: &mut [u8]) -> Option<usize> {
+        let kind = match self {
+            Message::Hello { .. } => KIND_HELLO,
+            Message::Keyframe { .. } => KIND_KEYFRAME,
+            Message::Frame { .. } => KIND_FRAME,
+            Message::Heartbeat { .. } => KIND_HEARTBEAT,
+            Message::End { .. } => KIND_END,
+        };
+        let mut writer = Writer::new(out, kind)?;
+        match self {
+            Message::Hello {
+                session_key,
+                frame_interval_ms,
+                frame_count,
+            } => {
+                writer.u32(*session_key)?;
+                writer.bytes(&frame_interval_ms.to_le_bytes())?;
+                writer.u32(*frame_count)?;
+            }
+            Message::Keyframe {
+                sequence,
+                timestamp_ms,
+                frame,
+            } => {
+                writer.u32(*sequence)?;
+                writer.u32(*timestamp_ms)?;
+                writer.bytes(&frame.to_bytes().ok()?)?;
+            }
+            Message::Frame {
+                sequence,
+                timestamp_ms,
+                keyframe,
+                changes,
+            } => {
+                writer.u32(*sequence)?;
+                writer.u32(*timestamp_ms)?;
+                writer.u32(*keyframe)?;
+                writer.bytes(&[changes.len() as u8])?;
+                for change in changes {
+                    writer.bytes(&[change.driver_number, change.led_num])?;
+                }
+            }
+            Message::Heartbeat { sequence } | Message::End { sequence } => {
+                writer.u32(*sequence)?;
+            }
+        }
+        Some(writer.len)
+    }
+
+    /// Decode a message, `None` when it is invalid or from another protocol
+    /// version
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let (mut reader, kind) = Reader::new(buf)?;
+        let message = match kind {
+            KIND_HELLO => Message::Hello {
+                session_key: reader.u32()?,
+                frame_interval_ms: reader.u16()?,
+                frame_count: reader.u32()?,
+            },
+            KIND_KEYFRAME => Message::Keyframe {
+                sequence: reader.u32()?,
+                timestamp_ms: reader.u32()?,
+                frame: UpdateFrame::try_from_bytes(reader.bytes(UpdateFrame::SERIALIZED_SIZE)?)
+                    .ok()?,
+            },
+            KIND_FRAME => {
+                let sequence = reader.u32()?;
+                let timestamp_ms = reader.u32()?;
+                let keyframe = reader.u32()?;
+                let mut changes = Vec::new();
+                for _ in 0..reader.u8()? {
+                    changes
+                        .push(DriverData {
+                            driver_number: reader.u8()?,
+                            led_num: reader.u8()?,
+                        })
+                        .ok()?;
+                }
+                Message::Frame {
+                    sequence,
+                    timestamp_ms,
+                    keyframe,
+                    changes,
+                }
+            }
+            KIND_HEARTBEAT => Message::Heartbeat {
+                sequence: reader.u32()?,
+            },
+            KIND_END => Message::End {
+                sequence: reader.u32()?,
+            },
+            _ => return None,
+        };
+        reader.finish(message)
+    }
+}
+
+impl Request {
+    /// Encode the request into `out`, returns its length or `None` when
+    /// `out` is too small
+    pub fn encode(&self, out: &mut [u8]) -> Option<usize> {
+        let writer = match self {
+            Request::Subscribe {
+                session_key,
+                sequence,
+            } => {
+                let mut writer = Writer::new(out, KIND_SUBSCRIBE)?;
+                writer.u32(*session_key)?;
+                writer.u32(*sequence)?;
+                writer
+            }
+            Request::Resync { sequence } => {
+                let mut writer = Writer::new(out, KIND_RESYNC)?;
+                writer.u32(*sequence)?;
+                writer
+            }
+            Request::Unsubscribe => Writer::new(out, KIND_UNSUBSCRIBE)?,
+        };
+        Some(writer.len)
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let (mut reader, kind) = Reader::new(buf)?;
+        let request = match kind {
+            KIND_SUBSCRIBE => Request::Subscribe {
+                session_key: reader.u32()?,
+                sequence: reader.u32()?,
+            },
+            KIND_RESYNC => Request::Resync {
+                sequence: reader.u32()?,
+            },
+            KIND_UNSUBSCRIBE => Request::Unsubscribe,
+            _ => return None,
+        };
+        reader.finish(request)
+    }
+}
+
+/// Turns race frames into stream messages on the relay: a keyframe every
+/// `keyframe_interval` frames and when asked for, the changes since the
+/// last keyframe in between.
+#[derive(Debug)]
+pub struct StreamEncoder {
+    keyframe_interval: u32,
+    keyframe: Option<(u32, UpdateFrame)>,
+}
+
+impl StreamEncoder {
+    pub fn new(keyframe_interval: u32) -> Self {
+        Self {
+            keyframe_interval: keyframe_interval.max(1),
+            keyframe: None,
+        }
+    }
+
+    /// Send a keyframe next
+    pub fn request_keyframe(&mut self) {
+        self.keyframe = None;
+    }
+
+    pub fn encode(&mut self, sequence: u32, timestamp_ms: u32, frame: &UpdateFrame) -> Message {
+        if let Some((keyframe, previous)) = &self.keyframe {
+            let due = sequence < *keyframe || sequence - keyframe >= self.keyframe_interval;
+            let same_drivers = previous
+                .frame
+                .iter()
+                .zip(&frame.frame)
+                .all(|(a, b)| a.driver_number == b.driver_number);
+            if !due && same_drivers {
+                let mut changes = Vec::new();
+                for (driver, previous) in frame.frame.iter().zip(&previous.frame) {
+                    if driver.led_num != previous.led_num {
+                        // At most one change per driver
+                        changes.push(*driver).unwrap();
+                    }
+                }
+                return Message::Frame {
+                    sequence,
+                    timestamp_ms,
+                    keyframe: *keyframe,
+                    changes,
+                };
+            }
+        }
+
+        self.keyframe = Some((sequence, frame.clone()));
+        Message::Keyframe {
+            sequence,
+            timestamp_ms,
+            frame: frame.clone(),
+        }
+    }
+}
+
+/// Stream statistics of the board
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    pub played: u32,
+    /// Frames that never arrived, or whose keyframe never arrived
+    pub lost: u32,
+    /// Frames that arrived after their time to play
+    pub late: u32,
+}
+
+/// Largest increase of the clock offset per window, the clock drift it
+/// follows
+const MAX_OFFSET_STEP_MS: i64 = 20;
+
+/// Stream time over which the clock offset is estimated
+const CLOCK_WINDOW_MS: u32 = 10_000;
+
+/// Maps stream timestamps to local time.
+///
+/// The offset is the shortest transit time seen, from the frames that were
+/// delayed least. It is estimated again every [`CLOCK_WINDOW_MS`] of stream
+/// time, so drift between the relay and board clocks does not add up.
+#[derive(Debug, Default)]
+struct PlayoutClock {
+    offset: Option<i64>,
+    window_min: i64,
+    window_start: u32,
+}
+
+impl PlayoutClock {
+    fn observe(&mut self, timestamp_ms: u32, now_ms: u64) {
+        let transit = now_ms as i64 - timestamp_ms as i64;
+        let Some(offset) = self.offset else {
+            self.offset = Some(transit);
+            self.window_min = transit;
+            self.window_start = timestamp_ms;
+            return;
+        };
+
+        self.window_min = self.window_min.min(transit);
+        if timestamp_ms.saturating_sub(self.window_start) >= CLOCK_WINDOW_MS {
+            self.offset = Some(self.window_min.min(offset + MAX_OFFSET_STEP_MS));
+            self.window_min = transit;
+            self.window_start = timestamp_ms;
+        } else {
+            self.offset = Some(offset.min(transit));
+        }
+    }
+
+    /// Local time to play a frame at, without the playback delay
+    fn local_time(&self, timestamp_ms: u32) -> Option<i64> {
+        Some(timestamp_ms as i64 + self.offset?)
+    }
+}
+
+#[derive(Debug)]
+enum Content {
+    Keyframe(UpdateFrame),
+    /// Decoded when it plays, its keyframe may arrive after it
+    Changes {
+        keyframe: u32,
+        changes: Vec<DriverData, NUM_DRIVERS>,
+    },
+}
+
+#[derive(Debug)]
+struct Slot {
+    sequence: u32,
+    timestamp_ms: u32,
+    content: Content,
+}
+
+/// Reorders the stream on the board and plays it with a fixed delay.
+///
+/// Received messages go to [`receive`](Self::receive), [`poll`](Self::poll)
+/// returns the frame to show. Up to `N` frames are buffered, enough for the
+/// playback delay. A frame missing at its time to play is skipped.
+pub struct JitterBuffer<const N: usize> {
+    delay_ms: u32,
+    session_key: Option<u32>,
+    /// The last keyframe played
+    keyframe: Option<(u32, UpdateFrame)>,
+    slots: [Option<Slot>; N],
+    next_sequence: Option<u32>,
+    /// A frame was due, the stream can no longer start earlier
+    started: bool,
+    clock: PlayoutClock,
+    end: Option<u32>,
+    last_message_ms: Option<u64>,
+    /// Keyframe a resync was requested for
+    missing_keyframe: Option<u32>,
+    resync: bool,
+    stats: StreamStats,
+}
+
+impl<const N: usize> JitterBuffer<N> {
+    pub fn new(delay_ms: u32) -> Self {
+        Self {
+            delay_ms,
+            session_key: None,
+            keyframe: None,
+            slots: core::array::from_fn(|_| None),
+            next_sequence: None,
+            started: false,
+            clock: PlayoutClock::default(),
+            end: None,
+            last_message_ms: None,
+            missing_keyframe: None,
+            resync: false,
+            stats: StreamStats::default(),
+        }
+    }
+
+    pub fn session_key(&self) -> Option<u32> {
+        self.session_key
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.stats
+    }
+
+    /// Sequence number of the next frame to play, to continue the stream
+    /// after subscribing again
+    pub fn next_sequence(&self) -> u32 {
+        self.next_sequence.unwrap_or(0)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.slots.iter().all(Option::is_none)
+    }
+
+    /// The stream ended and every received frame was played
+    pub fn is_finished(&self) -> bool {
+        self.end.is_some() && self.is_empty()
+    }
+
+    /// No message for [`STREAM_TIMEOUT_MS`], also before the first message
+    pub fn is_stalled(&self, now_ms: u64) -> bool {
+        self.last_message_ms.map_or(true, |t| {
+            now_ms.saturating_sub(t) >= STREAM_TIMEOUT_MS as u64
+        })
+    }
+
+    /// Whether a [`Request::Resync`] should be sent, once per missing
+    /// keyframe
+    pub fn take_resync(&mut self) -> bool {
+        core::mem::take(&mut self.resync)
+    }
+
+    pub fn receive(&mut self, message: Message, now_ms: u64) {
+        self.last_message_ms = Some(now_ms);
+        match message {
+            Message::Hello { session_key, .. } => {
+                if self.session_key.is_none() {
+                    // Frames may arrive before the hello
+                    self.session_key = Some(session_key);
+                } else if self.session_key != Some(session_key) {
+                    let stats = self.stats;
+                    *self = Self::new(self.delay_ms);
+                    self.session_key = Some(session_key);
+                    self.last_message_ms = Some(now_ms);
+                    self.stats = stats;
+                } else if self.is_empty() {
+                    // The stream continues after subscribing again, the
+                    // timestamps no longer match the clock
+                    self.clock = PlayoutClock::default();
+                    self.end = None;
+                }
+            }
+            Message::Keyframe {
+                sequence,
+                timestamp_ms,
+                frame,
+            } => self.insert(sequence, timestamp_ms, Content::Keyframe(frame), now_ms),
+            Message::Frame {
+                sequence,
+                timestamp_ms,
+                keyframe,
+                changes,
+            } => self.insert(
+                sequence,
+                timestamp_ms,
+                Content::Changes { keyframe, changes },
+                now_ms,
+            ),
+            Message::Heartbeat { .. } => {}
+            Message::End { sequence } => self.end = Some(sequence),
+        }
+    }
+
+    fn insert(&mut self, sequence: u32, timestamp_ms: u32, content: Content, now_ms: u64) {
+        let mut next = *self.next_sequence.get_or_insert(sequence);
+        if sequence < next {
+            // Frames may arrive out of order before the first one plays
+            let last = self.slots.iter().flatten().map(|s| s.sequence).max();
+            if self.started || last.is_some_and(|last| last - sequence >= N as u32) {
+                self.stats.late += 1;
+                return;
+            }
+            self.next_sequence = Some(sequence);
+            next = sequence;
+        }
+        if sequence - next >= N as u32 {
+            // Too far ahead for the buffer, give up the oldest frames
+            self.skip_to(sequence + 1 - N as u32);
+        }
+
+        self.clock.observe(timestamp_ms, now_ms);
+        self.slots[sequence as usize % N] = Some(Slot {
+            sequence,
+            timestamp_ms,
+            content,
+        });
+    }
+
+    /// Drop the frames before `sequence`, counting them as lost. The
+    /// frames after a dropped keyframe still need it.
+    fn skip_to(&mut self, sequence: u32) {
+        let next = self.next_sequence.unwrap_or(sequence);
+        for skipped in next..sequence.min(next + N as u32) {
+            if let Some(Slot {
+                sequence,
+                content: Content::Keyframe(frame),
+                ..
+            }) = self.slots[skipped as usize % N].take()
+            {
+                if sequence == skipped {
+                    self.keyframe = Some((sequence, frame));
+                }
+            }
+        }
+        self.stats.lost += sequence.saturating_sub(next);
+        self.next_sequence = Some(sequence);
+    }
+
+    fn is_due(&self, timestamp_ms: u32, now_ms: u64) -> bool {
+        self.clock
+            .local_time(timestamp_ms)
+            .is_some_and(|t| t + self.delay_ms as i64 <= now_ms as i64)
+    }
+
+    /// The frame to show at `now_ms` when it changed since the last poll
+    pub fn poll(&mut self, now_ms: u64) -> Option<UpdateFrame> {
+        let mut shown = None;
+        while let Some(next) = self.next_sequence {
+            let index = next as usize % N;
+            match &self.slots[index] {
+                Some(slot) if slot.sequence == next => {
+                    if !self.is_due(slot.timestamp_ms, now_ms) {
+                        break;
+                    }
+                    let slot = self.slots[index].take().unwrap();
+                    self.next_sequence = Some(next + 1);
+                    self.started = true;
+                    match self.decode(slot) {
+                        Some(frame) => {
+                            self.stats.played += 1;
+                            shown = Some(frame);
+                        }
+                        None => self.stats.lost += 1,
+                    }
+                }
+                _ => {
+                    // Wait for the missing frame until a later one is due
+                    let later = self
+                        .slots
+                        .iter()
+                        .flatten()
+                        .filter(|slot| slot.sequence > next)
+                        .min_by_key(|slot| slot.sequence);
+                    match later {
+                        Some(slot) if self.is_due(slot.timestamp_ms, now_ms) => {
+                            self.started = true;
+                            self.skip_to(slot.sequence)
+                        }
+                        _ => break,
+                    }
+                }
+            }
+        }
+        shown
+    }
+
+    fn decode(&mut self, slot: Slot) -> Option<UpdateFrame> {
+        match slot.content {
+            Content::Keyframe(frame) => {
+                self.keyframe = Some((slot.sequence, frame.clone()));
+                Some(frame)
+            }
+            Content::Changes { keyframe, changes } => match &self.keyframe {
+                Some((k, frame)) if *k == keyframe => {
+                    let mut frame = frame.clone();
+                    for change in &changes {
+                        if let Some(driver) = frame
+                            .frame
+                            .iter_mut()
+                            .find(|d| d.driver_number == change.driver_number)
+                        {
+                            driver.led_num = change.led_num;
+                        }
+                    }
+                    Some(frame)
+                }
+                _ => {
+                    if self.missing_keyframe != Some(keyframe) {
+                        self.missing_keyframe = Some(keyframe);
+                        self.resync = true;
+                    }
+                    None
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+    use std::vec::Vec as StdVec;
+
+    const INTERVAL_MS: u32 = 50;
+
+    /// A race where driver `j` moves one LED every `j + 1` frames
+    fn race(frames: u32) -> StdVec<UpdateFrame> {
+        (0..frames)
+            .map(|i| {
+                let mut frame = UpdateFrame::default();
+                for (j, driver) in frame.frame.iter_mut().enumerate() {
+                    driver.driver_number = j as u8 + 1;
+                    driver.led_num = ((i as usize / (j + 1) + j * 4) % 96) as u8 + 1;
+                }
+                frame
+            })
+            .collect()
+    }
+
+    /// The datagrams the relay sends for `race`
+    fn record(race: &[UpdateFrame]) -> StdVec<(u32, StdVec<u8>)> {
+        let mut encoder = StreamEncoder::new(KEYFRAME_INTERVAL_MS / INTERVAL_MS);
+        let mut messages = StdVec::from([Message::Hello {
+            session_key: 9149,
+            frame_interval_ms: INTERVAL_MS as u16,
+            frame_count: race.len() as u32,
+        }]);
+        for (i, frame) in race.iter().enumerate() {
+            messages.push(encoder.encode(i as u32, i as u32 * INTERVAL_MS, frame));
+        }
+        messages.push(Message::End {
+            sequence: race.len() as u32,
+        });
+
+        messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                let mut buf = [0; MAX_MESSAGE_SIZE];
+                let len = message.encode(&mut buf).unwrap();
+                // The hello and the first frame are sent at once
+                (
+                    i.saturating_sub(1) as u32 * INTERVAL_MS,
+                    buf[..len].to_vec(),
+                )
+            })
+            .collect()
+    }
+
+    /// Deterministic pseudo random numbers
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self, max: u32) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 % max
+        }
+    }
+
+    struct Replay {
+        /// Sequence number and local time of the frames played
+        played: StdVec<(usize, u64)>,
+        resyncs: u32,
+        buffer: JitterBuffer<64>,
+    }
+
+    impl Replay {
+        /// Largest difference between the times the frames played and a
+        /// fixed schedule, while the clock offset settles
+        fn schedule_error(&self) -> u64 {
+            let starts = self
+                .played
+                .iter()
+                .map(|(sequence, time)| time - *sequence as u64 * INTERVAL_MS as u64);
+            starts.clone().max().unwrap() - starts.min().unwrap()
+        }
+    }
+
+    /// Replay a recorded stream through a jitter buffer. Each datagram is
+    /// lost with `loss_percent` probability, the others arrive with up to
+    /// `jitter_ms` of extra delay.
+    fn replay(
+        race: &[UpdateFrame],
+        recording: &[(u32, StdVec<u8>)],
+        loss_percent: u32,
+        jitter_ms: u32,
+    ) -> Replay {
+        let mut random = Random(0x1234_5678);
+        let mut arrivals: StdVec<(u64, &[u8])> = recording
+            .iter()
+            .filter_map(|(sent, datagram)| {
+                let lost = random.next(100) < loss_percent;
+                let arrival = 1_000 + *sent as u64 + 30 + random.next(jitter_ms + 1) as u64;
+                (!lost).then_some((arrival, datagram.as_slice()))
+            })
+            .collect();
+        arrivals.sort_by_key(|(arrival, _)| *arrival);
+
+        let mut replay = Replay {
+            played: StdVec::new(),
+            resyncs: 0,
+            buffer: JitterBuffer::new(500),
+        };
+        let mut arrivals = arrivals.into_iter().peekable();
+        let mut now = 0;
+        // Without the end message the stream times out
+        while !replay.buffer.is_finished() {
+            if arrivals.peek().is_none() && replay.buffer.is_stalled(now) {
+                break;
+            }
+            while let Some((_, datagram)) = arrivals.next_if(|(arrival, _)| *arrival <= now) {
+                replay
+                    .buffer
+                    .receive(Message::decode(datagram).unwrap(), now);
+            }
+            if replay.buffer.take_resync() {
+                replay.resyncs += 1;
+            }
+            if let Some(frame) = replay.buffer.poll(now) {
+                let sequence = race.iter().position(|f| *f == frame).unwrap();
+                replay.played.push((sequence, now));
+            }
+            now += 1;
+            assert!(now < 60_000, "the stream did not finish");
+        }
+        replay
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let race = race(2);
+        let messages = [
+            Message::Hello {
+                session_key: 9149,
+                frame_interval_ms: 50,
+                frame_count: 1234,
+            },
+            Message::Keyframe {
+                sequence: 20,
+                timestamp_ms: 1000,
+                frame: race[0].clone(),
+            },
+            Message::Frame {
+                sequence: 21,
+                timestamp_ms: 1050,
+                keyframe: 20,
+                changes: race[0].frame.iter().copied().collect(),
+            },
+            Message::Frame {
+                sequence: 22,
+                timestamp_ms: 1100,
+                keyframe: 20,
+                changes: Vec::new(),
+            },
+            Message::Heartbeat { sequence: 23 },
+            Message::End { sequence: 24 },
+        ];
+        for message in messages {
+            let mut buf = [0; MAX_MESSAGE_SIZE + 1];
+            let len = message.encode(&mut buf).unwrap();
+            assert_eq!(Message::decode(&buf[..len]), Some(message.clone()));
+            assert_eq!(Request::decode(&buf[..len]), None);
+            // Truncated or with extra data
+            assert_eq!(Message::decode(&buf[..len - 1]), None);
+            assert_eq!(Message::decode(&buf[..len + 1]), None);
+            assert_eq!(message.encode(&mut buf[..len - 1]), None);
+        }
+
+        let requests = [
+            Request::Subscribe {
+                session_key: 9149,
+                sequence: 12,
+            },
+            Request::Resync { sequence: 40 },
+            Request::Unsubscribe,
+        ];
+        for request in requests {
+            let mut buf = [0; MAX_MESSAGE_SIZE + 1];
+            let len = request.encode(&mut buf).unwrap();
+            assert_eq!(Request::decode(&buf[..len]), Some(request));
+            assert_eq!(Message::decode(&buf[..len]), None);
+            assert_eq!(Request::decode(&buf[..len + 1]), None);
+        }
+
+        // Another protocol version
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let len = Message::End { sequence: 1 }.encode(&mut buf).unwrap();
+        buf[2] = PROTOCOL_VERSION + 1;
+        assert_eq!(Message::decode(&buf[..len]), None);
+        assert_eq!(Message::decode(b"HTTP/1.1 200 OK"), None);
+    }
+
+    #[test]
+    fn test_encoder() {
+        let race = race(25);
+        let mut encoder = StreamEncoder::new(10);
+        let kinds: StdVec<_> = race
+            .iter()
+            .enumerate()
+            .map(|(i, frame)| match encoder.encode(i as u32, 0, frame) {
+                Message::Keyframe { .. } => 'K',
+                Message::Frame {
+                    keyframe, changes, ..
+                } => {
+                    // Only the drivers that moved since the keyframe
+                    let moved = race[i]
+                        .frame
+                        .iter()
+                        .zip(&race[keyframe as usize].frame)
+                        .filter(|(a, b)| a != b)
+                        .count();
+                    assert_eq!(changes.len(), moved);
+                    'F'
+                }
+                message => panic!("unexpected message {:?}", message),
+            })
+            .collect();
+        assert_eq!(
+            kinds.iter().collect::<std::string::String>(),
+            "KFFFFFFFFFKFFFFFFFFFKFFFF"
+        );
+
+        encoder.request_keyframe();
+        assert!(matches!(
+            encoder.encode(25, 0, &race[24]),
+            Message::Keyframe { sequence: 25, .. }
+        ));
+
+        // Other drivers need a keyframe
+        let mut frame = race[24].clone();
+        frame.frame[3].driver_number = 99;
+        assert!(matches!(
+            encoder.encode(26, 0, &frame),
+            Message::Keyframe { sequence: 26, .. }
+        ));
+    }
+
+    #[test]
+    fn test_replay_with_jitter() {
+        let race = race(200);
+        let recording = record(&race);
+        let replay = replay(&race, &recording, 0, 300);
+
+        // Every frame plays in order, exactly one frame interval apart
+        let sequences: StdVec<usize> = replay.played.iter().map(|(s, _)| *s).collect();
+        assert_eq!(sequences, (0..200).collect::<StdVec<_>>());
+        assert!(replay.schedule_error() < INTERVAL_MS as u64);
+        assert_eq!(replay.resyncs, 0);
+        assert_eq!(
+            replay.buffer.stats(),
+            StreamStats {
+                played: 200,
+                lost: 0,
+                late: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_replay_with_packet_loss() {
+        let race = race(400);
+        let recording = record(&race);
+        let replay = replay(&race, &recording, 10, 200);
+
+        let sequences: StdVec<usize> = replay.played.iter().map(|(s, _)| *s).collect();
+        assert!(sequences.windows(2).all(|w| w[0] < w[1]));
+        // Missing frames are skipped rather than delaying the others
+        assert!(replay.schedule_error() < INTERVAL_MS as u64);
+
+        let stats = replay.buffer.stats();
+        assert_eq!(stats.played as usize, sequences.len());
+        assert_eq!(stats.late, 0);
+        // Lost frames, and frames whose keyframe was lost
+        assert!(stats.lost > 40 && stats.lost < 120, "{:?}", stats);
+        let (first, last) = (sequences[0], sequences[sequences.len() - 1]);
+        assert_eq!((stats.played + stats.lost) as usize, last - first + 1);
+        assert!(replay.resyncs > 0);
+    }
+
+    #[test]
+    fn test_late_frames() {
+        let race = race(30);
+        let mut buffer = JitterBuffer::<8>::new(100);
+        let mut encoder = StreamEncoder::new(10);
+        let mut send = |buffer: &mut JitterBuffer<8>, sequence: u32, now| {
+            let message = encoder.encode(sequence, sequence * 50, &race[sequence as usize]);
+            buffer.receive(message, now);
+        };
+
+        send(&mut buffer, 0, 1000);
+        assert_eq!(buffer.poll(1099), None);
+        assert_eq!(buffer.poll(1100), Some(race[0].clone()));
+        // Frame 1 is missing until frame 2 is due
+        send(&mut buffer, 2, 1100);
+        assert_eq!(buffer.poll(1199), None);
+        assert_eq!(buffer.poll(1200), Some(race[2].clone()));
+        send(&mut buffer, 1, 1210);
+        assert_eq!(buffer.stats().late, 1);
+        assert_eq!(buffer.stats().lost, 1);
+
+        // More frames than the buffer holds
+        for sequence in 3..20 {
+            send(&mut buffer, sequence, 1210);
+        }
+        assert_eq!(buffer.next_sequence(), 12);
+        assert_eq!(buffer.stats().lost, 1 + 9);
+        assert_eq!(buffer.poll(2000), Some(race[19].clone()));
+
+        assert!(!buffer.is_stalled(1210 + STREAM_TIMEOUT_MS as u64 - 1));
+        assert!(buffer.is_stalled(1210 + STREAM_TIMEOUT_MS as u64));
+    }
+
+    #[test]
+    fn test_missing_keyframe() {
+        let race = race(30);
+        let mut buffer = JitterBuffer::<64>::new(100);
+        let mut encoder = StreamEncoder::new(10);
+        buffer.receive(
+            Message::Hello {
+                session_key: 9149,
+                frame_interval_ms: 50,
+                frame_count: 30,
+            },
+            1000,
+        );
+        let mut messages: StdVec<Message> = (0..30)
+            .map(|i| encoder.encode(i, i * 50, &race[i as usize]))
+            .collect();
+
+        // The second keyframe is lost, one resync for its frames
+        messages.retain(|m| !matches!(m, Message::Keyframe { sequence: 10, .. }));
+        for message in messages {
+            buffer.receive(message, 1000);
+        }
+        assert_eq!(buffer.poll(10_000), Some(race[29].clone()));
+        assert!(buffer.take_resync());
+        assert!(!buffer.take_resync());
+        let stats = buffer.stats();
+        assert_eq!((stats.played, stats.lost), (20, 10));
+
+        // A new stream starts over
+        buffer.receive(
+            Message::Hello {
+                session_key: 9158,
+                frame_interval_ms: 50,
+                frame_count: 0,
+            },
+            20_000,
+        );
+        assert_eq!(buffer.session_key(), Some(9158));
+        assert_eq!(buffer.next_sequence(), 0);
+        assert_eq!(buffer.stats(), stats);
+    }
+}
@@ -0,0 +1,442 @@
+//! Track geometry.
+//!
+//! The LEDs of a circuit lie on its centerline in driving order. A
+//! [`Centerline`] joins them into a closed polyline, so a position can be
+//! projected onto the track to find how far along the lap it is, and that
+//! distance maps to the LED covering it.
+//!
+//! Where the track doubles back, a position can be about as close to a
+//! later part of the lap as to the part the car is on. A [`TrackFollower`]
+//! remembers where a car was and prefers the parts of the track it can
+//! have reached since, in its direction of travel.
+//!
+//! Positions are only projected onto the segments near them, found with a
+//! [`LedIndex`] over the points.
+
+use crate::led_index::LedIndex;
+use heapless::Vec;
+use libm::{fmodf, sqrtf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    fn sub(self, other: Point) -> Point {
+        Point::new(self.x - other.x, self.y - other.y)
+    }
+
+    fn dot(self, other: Point) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+    fn distance_squared(self, other: Point) -> f32 {
+        let d = self.sub(other);
+        d.dot(d)
+    }
+}
+
+/// Where a position lies on the track
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Projection {
+    /// Distance along the lap from the first point
+    pub distance: f32,
+    /// Distance from the centerline
+    pub offset: f32,
+}
+
+/// Closed polyline through up to `N` points in driving order
+#[derive(Debug, Clone)]
+pub struct Centerline<const N: usize> {
+    points: Vec<Point, N>,
+    /// Distance along the lap of every point
+    distances: Vec<f32, N>,
+    length: f32,
+    index: LedIndex<N>,
+    longest_segment: f32,
+}
+
+impl<const N: usize> Centerline<N> {
+    /// The centerline through `points`, `None` for fewer than two distinct
+    /// points or more than `N`
+    pub fn new(points: impl IntoIterator<Item = Point>) -> Option<Self> {
+        let mut line_points: Vec<Point, N> = Vec::new();
+        for point in points {
+            line_points.push(point).ok()?;
+        }
+        let mut line = Self {
+            index: LedIndex::new(line_points.iter().copied())?,
+            points: line_points,
+            distances: Vec::new(),
+            length: 0.0,
+            longest_segment: 0.0,
+        };
+        for i in 0..line.points.len() {
+            let segment_length = sqrtf(line.points[i].distance_squared(line.next(i)));
+            line.distances.push(line.length).ok()?;
+            line.length += segment_length;
+            line.longest_segment = line.longest_segment.max(segment_length);
+        }
+        (line.points.len() >= 2 && line.length > 0.0).then_some(line)
+    }
+
+    /// Number of points
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Length of a lap
+    pub fn length(&self) -> f32 {
+        self.length
+    }
+
+    /// Distance along the lap of point `index`
+    pub fn distance_of(&self, index: usize) -> f32 {
+        self.distances[index]
+    }
+
+    fn next(&self, index: usize) -> Point {
+        self.points[(index + 1) % self.points.len()]
+    }
+
+    /// Project `point` onto the segment from point `index` to the next one
+    fn project_on(&self, index: usize, point: Point) -> Projection {
+        let start = self.points[index];
+        let segment = self.next(index).sub(start);
+        let segment_length_squared = segment.dot(segment);
+        let t = if segment_length_squared > 0.0 {
+            (point.sub(start).dot(segment) / segment_length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let closest = Point::new(start.x + t * segment.x, start.y + t * segment.y);
+        Projection {
+            distance: self.distances[index] + t * sqrtf(segment_length_squared),
+            offset: sqrtf(point.distance_squared(closest)),
+        }
+    }
+
+    /// Projections of `point` in track order onto every segment less than
+    /// `range` further from it than the closest one, and maybe a few more
+    fn projections(&self, point: Point, range: f32) -> Vec<Projection, N> {
+        // The track runs through the points, so it is no further away than
+        // the closest one. A segment at some distance has an end point
+        // within that distance plus half its length.
+        let closest = self.index.nearest(point).unwrap();
+        let radius = sqrtf(self.points[closest].distance_squared(point))
+            + range
+            + self.longest_segment / 2.0;
+        let count = self.points.len();
+        let mut near = [false; N];
+        // Some slack for rounding
+        self.index.within(point, radius * 1.001, |i| {
+            near[i] = true;
+            near[(i + count - 1) % count] = true;
+        });
+        (0..count)
+            .filter(|&i| near[i])
+            .map(|i| self.project_on(i, point))
+            .collect()
+    }
+
+    /// The closest projection of `point` onto the track
+    pub fn project(&self, point: Point) -> Projection {
+        closest(&self.projections(point, 0.0))
+    }
+
+    /// Index of the point closest along the track to `distance`
+    pub fn index_at(&self, distance: f32) -> usize {
+        let distance = self.wrap(distance);
+        // The last point at or before the distance
+        let before = self.distances.partition_point(|&d| d <= distance) - 1;
+        let after = (before + 1) % self.points.len();
+        let end = if after == 0 {
+            self.length
+        } else {
+            self.distances[after]
+        };
+        if end - distance < distance - self.distances[before] {
+            after
+        } else {
+            before
+        }
+    }
+
+    /// Forward distance along the lap from `from` to `to`
+    fn ahead(&self, from: f32, to: f32) -> f32 {
+        self.wrap(to - from)
+    }
+
+    /// `distance` within `0..length`
+    fn wrap(&self, distance: f32) -> f32 {
+        let wrapped = fmodf(distance, self.length);
+        if wrapped < 0.0 {
+            wrapped + self.length
+        } else {
+            wrapped
+        }
+    }
+}
+
+/// Follows a car along the track
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackFollower {
+    /// Longest distance a car travels between two positions
+    max_step: f32,
+    distance: Option<f32>,
+}
+
+impl TrackFollower {
+    /// A follower for positions at most `max_step` apart along the track
+    pub fn new(max_step: f32) -> Self {
+        Self {
+            max_step,
+            distance: None,
+        }
+    }
+
+    /// Distance along the lap of the last position
+    pub fn distance(&self) -> Option<f32> {
+        self.distance
+    }
+
+    /// Move to `point`, returns its distance along the lap.
+    ///
+    /// Parts of the track up to `max_step` ahead, or a little behind for
+    /// noisy positions, win over other parts unless the car is clearly
+    /// elsewhere, e.g. after missing positions.
+    pub fn update<const N: usize>(&mut self, line: &Centerline<N>, point: Point) -> f32 {
+        let projections = line.projections(point, self.max_step);
+        let closest = closest(&projections);
+        let projection = match self.distance {
+            None => closest,
+            Some(last) => {
+                let reachable = |p: &Projection| {
+                    let ahead = line.ahead(last, p.distance);
+                    ahead <= self.max_step || line.length() - ahead <= self.max_step / 4.0
+                };
+                projections
+                    .iter()
+                    .copied()
+                    .filter(reachable)
+                    .min_by(|a, b| a.offset.total_cmp(&b.offset))
+                    .filter(|p| p.offset <= closest.offset + self.max_step)
+                    .unwrap_or(closest)
+            }
+        };
+        self.distance = Some(projection.distance);
+        projection.distance
+    }
+
+    /// Forget the car, the next position is placed on the closest part of
+    /// the track
+    pub fn reset(&mut self) {
+        self.distance = None;
+    }
+}
+
+/// The projection with the smallest offset, the first of equal ones
+fn closest(projections: &[Projection]) -> Projection {
+    *projections
+        .iter()
+        .min_by(|a, b| a.offset.total_cmp(&b.offset))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::TAU;
+    use libm::{cosf, sinf};
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn square() -> Centerline<4> {
+        Centerline::new([
+            Point::new(0.0, 0.0),
+            Point::new(100.0, 0.0),
+            Point::new(100.0, 100.0),
+            Point::new(0.0, 100.0),
+        ])
+        .unwrap()
+    }
+
+    /// `count` points on a circle, counterclockwise
+    fn circle<const N: usize>(count: usize, radius: f32) -> Centerline<N> {
+        Centerline::new((0..count).map(|i| {
+            let angle = TAU * i as f32 / count as f32;
+            Point::new(radius * cosf(angle), radius * sinf(angle))
+        }))
+        .unwrap()
+    }
+
+    /// Two straights 60 apart joined by hairpins: out along y = 0, back
+    /// along y = 60, LEDs every 100
+    fn hairpin() -> Centerline<24> {
+        let out = (0..=10).map(|i| Point::new(i as f32 * 100.0, 0.0));
+        let back = (0..=10).rev().map(|i| Point::new(i as f32 * 100.0, 60.0));
+        Centerline::new(out.chain(back)).unwrap()
+    }
+
+    #[test]
+    fn test_centerline() {
+        let line = square();
+        assert_eq!(line.len(), 4);
+        assert_near(line.length(), 400.0);
+        assert_near(line.distance_of(2), 200.0);
+
+        assert!(Centerline::<4>::new([Point::new(1.0, 1.0)]).is_none());
+        assert!(Centerline::<4>::new([Point::new(1.0, 1.0); 3]).is_none());
+        assert!(Centerline::<2>::new(square().points).is_none());
+    }
+
+    #[test]
+    fn test_project() {
+        let line = square();
+        let p = line.project(Point::new(30.0, -5.0));
+        assert_near(p.distance, 30.0);
+        assert_near(p.offset, 5.0);
+        // On the closing segment back to the first point
+        let p = line.project(Point::new(-2.0, 40.0));
+        assert_near(p.distance, 360.0);
+        assert_near(p.offset, 2.0);
+        // Beyond a corner the corner is closest
+        let p = line.project(Point::new(110.0, -10.0));
+        assert_near(p.distance, 100.0);
+        assert_near(p.offset, sqrtf(200.0));
+    }
+
+    #[test]
+    fn test_projections_near() {
+        // Projecting onto the nearby segments finds what projecting onto
+        // every segment does
+        for line in [hairpin(), circle::<24>(24, 500.0)] {
+            for i in -20..=130 {
+                for j in -80..=80 {
+                    let point = Point::new(i as f32 * 10.0 - 100.0, j as f32 * 10.0);
+                    let all: Vec<Projection, 24> =
+                        (0..line.len()).map(|i| line.project_on(i, point)).collect();
+                    assert_eq!(line.project(point), closest(&all), "at {:?}", point);
+                    let near = line.projections(point, 150.0);
+                    for p in all
+                        .iter()
+                        .filter(|p| p.offset <= closest(&all).offset + 150.0)
+                    {
+                        assert!(near.contains(p), "{:?} missing at {:?}", p, point);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_index_at() {
+        let line = square();
+        assert_eq!(line.index_at(0.0), 0);
+        assert_eq!(line.index_at(49.0), 0);
+        assert_eq!(line.index_at(51.0), 1);
+        assert_eq!(line.index_at(260.0), 3);
+        // Closer to the finish than to the last point
+        assert_eq!(line.index_at(360.0), 0);
+        assert_eq!(line.index_at(400.0), 0);
+        assert_eq!(line.index_at(-30.0), 0);
+        assert_eq!(line.index_at(-60.0), 3);
+    }
+
+    #[test]
+    fn test_lap_trace() {
+        // 96 LEDs like Zandvoort, a car sampled 400 times per lap for two
+        // laps, weaving across the track
+        let line = circle::<96>(96, 1000.0);
+        let mut follower = TrackFollower::new(100.0);
+        let mut previous = 0;
+        let mut changes = 0;
+        for sample in 0..800 {
+            let angle = TAU * sample as f32 / 400.0;
+            let radius = 1000.0 + 20.0 * sinf(sample as f32 * 1.7);
+            let point = Point::new(radius * cosf(angle), radius * sinf(angle));
+            let index = line.index_at(follower.update(&line, point));
+
+            // The nearest LED by angle
+            let expected = (angle / TAU * 96.0 + 0.5) as usize % 96;
+            assert!(
+                index == expected || index == (expected + 95) % 96,
+                "sample {}: LED {} instead of {}",
+                sample,
+                index,
+                expected
+            );
+            // The car only moves forward, one LED at a time
+            assert!(index == previous || index == (previous + 1) % 96);
+            changes += (index != previous) as u32;
+            previous = index;
+        }
+        assert_eq!(changes, 2 * 96);
+    }
+
+    #[test]
+    fn test_doubling_back() {
+        let line = hairpin();
+        let mut follower = TrackFollower::new(150.0);
+
+        // Out along the first straight, drifting towards the way back
+        let mut distances = [0.0; 10];
+        for (i, distance) in distances.iter_mut().enumerate() {
+            let drift = if i % 2 == 0 { 5.0 } else { 40.0 };
+            *distance = follower.update(&line, Point::new(50.0 + i as f32 * 80.0, drift));
+        }
+        for (i, distance) in distances.iter().enumerate() {
+            assert_near(*distance, 50.0 + i as f32 * 80.0);
+        }
+        // The closest point alone puts the car on the way back
+        let closest = line.project(Point::new(720.0, 40.0));
+        assert_near(closest.distance, 1000.0 + 60.0 + 280.0);
+
+        // Through the hairpin and back, closer to the first straight
+        for (point, expected) in [
+            (Point::new(800.0, 10.0), 800.0),
+            (Point::new(900.0, 5.0), 900.0),
+            (Point::new(1000.0, 30.0), 1030.0),
+            (Point::new(950.0, 50.0), 1110.0),
+            (Point::new(850.0, 25.0), 1210.0),
+        ] {
+            assert_near(follower.update(&line, point), expected);
+        }
+    }
+
+    #[test]
+    fn test_noise_and_jumps() {
+        let line = square();
+        let mut follower = TrackFollower::new(40.0);
+        assert_near(follower.update(&line, Point::new(90.0, 0.0)), 90.0);
+        // Slightly behind the last position, a noisy sample
+        assert_near(follower.update(&line, Point::new(85.0, 3.0)), 85.0);
+        assert_near(follower.update(&line, Point::new(100.0, 20.0)), 120.0);
+        follower.reset();
+        assert_near(follower.update(&line, Point::new(0.0, 95.0)), 305.0);
+        assert_near(follower.update(&line, Point::new(0.0, 70.0)), 330.0);
+        assert_near(follower.update(&line, Point::new(0.0, 10.0)), 390.0);
+        // Across the finish line
+        assert_near(follower.update(&line, Point::new(10.0, 0.0)), 10.0);
+        // Far from every reachable part, e.g. after missing positions
+        assert_near(follower.update(&line, Point::new(100.0, 60.0)), 160.0);
+        assert_eq!(follower.distance(), Some(160.0));
+    }
+}
@@ -0,0 +1,295 @@
+//! Coordinate transforms between PCB and telemetry space.
+//!
+//! LED positions come from the KiCad files in millimetres, while OpenF1
+//! reports car positions in its own units, rotated and offset per circuit.
+//! A [`Transform`] fitted to a few LEDs with known telemetry positions, the
+//! control points, registers a whole layout against the telemetry.
+
+use crate::track::Point;
+
+/// Affine transform `x' = a x + b y + tx`, `y' = c x + d y + ty`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+/// A point in the source space and where it lies in the target space
+pub type ControlPoint = (Point, Point);
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        a: 1.0,
+        b: 0.0,
+        c: 0.0,
+        d: 1.0,
+        tx: 0.0,
+        ty: 0.0,
+    };
+
+    pub fn apply(&self, point: Point) -> Point {
+        Point::new(
+            self.a * point.x + self.b * point.y + self.tx,
+            self.c * point.x + self.d * point.y + self.ty,
+        )
+    }
+
+    /// The transform back, `None` if this one collapses the plane
+    pub fn inverse(&self) -> Option<Transform> {
+        let det = self.a * self.d - self.b * self.c;
+        if det == 0.0 || !det.is_finite() {
+            return None;
+        }
+        let (a, b, c, d) = (self.d / det, -self.b / det, -self.c / det, self.a / det);
+        Some(Transform {
+            a,
+            b,
+            c,
+            d,
+            tx: -(a * self.tx + b * self.ty),
+            ty: -(c * self.tx + d * self.ty),
+        })
+    }
+
+    /// Least squares fit of rotation, uniform scale and offset, `None` for
+    /// fewer than two distinct control points.
+    ///
+    /// Keeps the shape of the layout, the right fit for a board drawn to
+    /// scale from the circuit.
+    pub fn fit_similarity(points: &[ControlPoint]) -> Option<Transform> {
+        let (source, target) = centroids(points)?;
+        let (mut dot, mut cross, mut norm) = (0.0, 0.0, 0.0);
+        for (p, q) in points {
+            let (px, py) = (p.x as f64 - source.0, p.y as f64 - source.1);
+            let (qx, qy) = (q.x as f64 - target.0, q.y as f64 - target.1);
+            dot += px * qx + py * qy;
+            cross += px * qy - py * qx;
+            norm += px * px + py * py;
+        }
+        if norm == 0.0 {
+            return None;
+        }
+        let (a, c) = (dot / norm, cross / norm);
+        Some(from_linear(a, -c, c, a, source, target))
+    }
+
+    /// Least squares fit of a general affine transform, `None` for fewer
+    /// than three control points that are not on one line.
+    ///
+    /// Also absorbs shear and different x and y scales, e.g. from a layout
+    /// squeezed to fit the board.
+    pub fn fit_affine(points: &[ControlPoint]) -> Option<Transform> {
+        let (source, target) = centroids(points)?;
+        // Normal equations of the centered points
+        let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+        let (mut x_qx, mut y_qx, mut x_qy, mut y_qy) = (0.0, 0.0, 0.0, 0.0);
+        for (p, q) in points {
+            let (px, py) = (p.x as f64 - source.0, p.y as f64 - source.1);
+            let (qx, qy) = (q.x as f64 - target.0, q.y as f64 - target.1);
+            sxx += px * px;
+            sxy += px * py;
+            syy += py * py;
+            x_qx += px * qx;
+            y_qx += py * qx;
+            x_qy += px * qy;
+            y_qy += py * qy;
+        }
+        let det = sxx * syy - sxy * sxy;
+        // Collinear points leave a direction undetermined
+        if det <= 1e-9 * sxx * syy {
+            return None;
+        }
+        let solve = |u: f64, v: f64| ((syy * u - sxy * v) / det, (sxx * v - sxy * u) / det);
+        let (a, b) = solve(x_qx, y_qx);
+        let (c, d) = solve(x_qy, y_qy);
+        Some(from_linear(a, b, c, d, source, target))
+    }
+
+    /// Largest distance between a transformed control point and its target
+    pub fn max_error(&self, points: &[ControlPoint]) -> f32 {
+        points
+            .iter()
+            .map(|&(p, q)| {
+                let p = self.apply(p);
+                libm::hypotf(p.x - q.x, p.y - q.y)
+            })
+            .fold(0.0, f32::max)
+    }
+}
+
+/// Mean source and target points
+fn centroids(points: &[ControlPoint]) -> Option<((f64, f64), (f64, f64))> {
+    if points.is_empty() {
+        return None;
+    }
+    let n = points.len() as f64;
+    let mut sum = (0.0, 0.0, 0.0, 0.0);
+    for (p, q) in points {
+        sum.0 += p.x as f64;
+        sum.1 += p.y as f64;
+        sum.2 += q.x as f64;
+        sum.3 += q.y as f64;
+    }
+    Some(((sum.0 / n, sum.1 / n), (sum.2 / n, sum.3 / n)))
+}
+
+/// The transform with linear part `a b c d` that maps `source` to `target`
+fn from_linear(
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    source: (f64, f64),
+    target: (f64, f64),
+) -> Transform {
+    Transform {
+        a: a as f32,
+        b: b as f32,
+        c: c as f32,
+        d: d as f32,
+        tx: (target.0 - a * source.0 - b * source.1) as f32,
+        ty: (target.1 - c * source.0 - d * source.1) as f32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::FRAC_PI_6;
+    use libm::{cosf, sinf};
+
+    fn assert_near(actual: f32, expected: f32, tolerance: f32) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    fn assert_same(actual: &Transform, expected: &Transform) {
+        assert_near(actual.a, expected.a, 1e-4);
+        assert_near(actual.b, expected.b, 1e-4);
+        assert_near(actual.c, expected.c, 1e-4);
+        assert_near(actual.d, expected.d, 1e-4);
+        assert_near(actual.tx, expected.tx, 0.05);
+        assert_near(actual.ty, expected.ty, 0.05);
+    }
+
+    /// PCB millimetres to telemetry units, rotated by 30°
+    fn rotated() -> Transform {
+        let (sin, cos) = (sinf(FRAC_PI_6), cosf(FRAC_PI_6));
+        Transform {
+            a: 108.0 * cos,
+            b: -108.0 * sin,
+            c: 108.0 * sin,
+            d: 108.0 * cos,
+            tx: -1500.0,
+            ty: 3200.0,
+        }
+    }
+
+    fn control_points(transform: &Transform) -> [ControlPoint; 5] {
+        [
+            Point::new(74.6, 33.5),
+            Point::new(20.1, 80.0),
+            Point::new(50.0, 50.0),
+            Point::new(90.3, 75.2),
+            Point::new(10.0, 12.5),
+        ]
+        .map(|p| (p, transform.apply(p)))
+    }
+
+    #[test]
+    fn test_apply_and_inverse() {
+        let transform = rotated();
+        let p = Point::new(74.6, 33.5);
+        assert_eq!(Transform::IDENTITY.apply(p), p);
+
+        let back = transform.inverse().unwrap().apply(transform.apply(p));
+        assert_near(back.x, p.x, 1e-3);
+        assert_near(back.y, p.y, 1e-3);
+
+        let flat = Transform {
+            c: 2.0,
+            d: 0.0,
+            ..Transform::IDENTITY
+        };
+        assert!(flat.inverse().is_none());
+    }
+
+    #[test]
+    fn test_fit_similarity() {
+        let transform = rotated();
+        let points = control_points(&transform);
+        let fitted = Transform::fit_similarity(&points).unwrap();
+        assert_same(&fitted, &transform);
+        assert!(fitted.max_error(&points) < 0.05);
+
+        // Two points are enough
+        assert_same(
+            &Transform::fit_similarity(&points[..2]).unwrap(),
+            &transform,
+        );
+        assert!(Transform::fit_similarity(&points[..1]).is_none());
+        assert!(Transform::fit_similarity(&[points[0]; 3]).is_none());
+        assert!(Transform::fit_similarity(&[]).is_none());
+    }
+
+    #[test]
+    fn test_fit_affine() {
+        // Mirrored and sheared
+        let transform = Transform {
+            a: 95.0,
+            b: 12.0,
+            c: -4.0,
+            d: -110.0,
+            tx: 250.0,
+            ty: -80.0,
+        };
+        let points = control_points(&transform);
+        let fitted = Transform::fit_affine(&points).unwrap();
+        assert_same(&fitted, &transform);
+
+        // A similarity can't follow the shear
+        let similarity = Transform::fit_similarity(&points).unwrap();
+        assert!(similarity.max_error(&points) > 100.0);
+
+        assert!(Transform::fit_affine(&points[..2]).is_none());
+        let collinear = [0.0, 1.0, 2.0, 5.0].map(|x| {
+            let p = Point::new(x, 2.0 * x + 1.0);
+            (p, transform.apply(p))
+        });
+        assert!(Transform::fit_affine(&collinear).is_none());
+    }
+
+    #[test]
+    fn test_fit_noise() {
+        let transform = rotated();
+        let mut points = control_points(&transform);
+        // Targets off by up to 20 units
+        for (i, (_, q)) in points.iter_mut().enumerate() {
+            let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+            q.x += sign * 20.0;
+            q.y -= sign * 10.0;
+        }
+        let fitted = Transform::fit_similarity(&points).unwrap();
+        let error = fitted.max_error(&points);
+        assert!(error > 0.0 && error < 40.0);
+        // The fit spreads the error, the exact transform doesn't do better
+        let residual = |t: &Transform| {
+            points
+                .iter()
+                .map(|&(p, q)| {
+                    let p = t.apply(p);
+                    (p.x - q.x).powi(2) + (p.y - q.y).powi(2)
+                })
+                .sum::<f32>()
+        };
+        assert!(residual(&fitted) <= residual(&transform));
+    }
+}
@@ -0,0 +1,61 @@
+use core::fmt;
+
+/// Version and build metadata of a firmware image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// Crate version, e.g. `0.1.0`
+    pub version: &'static str,
+    /// Short git commit hash, with a `-dirty` suffix for uncommitted changes
+    pub git_commit: &'static str,
+    /// UTC build time in ISO 8601 format, of the last build that reran
+    /// the build script rather than of the image
+    pub build_timestamp: &'static str,
+    /// Board the firmware was built for
+    pub board: &'static str,
+    /// Comma separated list of enabled cargo features
+    pub features: &'static str,
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "v{} ({}) built {} for {}",
+            self.version, self.git_commit, self.build_timestamp, self.board
+        )?;
+        if !self.features.is_empty() {
+            write!(f, " [{}]", self.features)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use heapless::String;
+
+    #[test]
+    fn test_display() {
+        let mut info = VersionInfo {
+            version: "0.1.0",
+            git_commit: "715838c",
+            build_timestamp: "2024-08-08T12:00:00Z",
+            board: "esp32c3",
+            features: "",
+        };
+
+        let mut s: String<128> = String::new();
+        write!(s, "{}", info).unwrap();
+        assert_eq!(s, "v0.1.0 (715838c) built 2024-08-08T12:00:00Z for esp32c3");
+
+        info.features = "wifi,mqtt";
+        s.clear();
+        write!(s, "{}", info).unwrap();
+        assert_eq!(
+            s,
+            "v0.1.0 (715838c) built 2024-08-08T12:00:00Z for esp32c3 [wifi,mqtt]"
+        );
+    }
+}
@@ -0,0 +1,625 @@
+//! HTTP control API and web page.
+//!
+//! On the station network the board serves a page at `/` that controls it
+//! through a small JSON API:
+//!
+//! | request                | body                   | answer                   |
+//! |------------------------|------------------------|--------------------------|
+//! | `GET /api/status`      |                        | [`Status`]               |
+//! | `GET /api/version`     |                        | firmware version         |
+//! | `GET /api/temperature` |                        | `{"temperature_c":41.5}` |
+//! | `GET /api/brightness`  |                        | `{"brightness":128}`     |
+//! | `POST /api/brightness` | `{"brightness":128}`   | `{"brightness":128}`     |
+//! | `POST /api/play`       |                        | `{"ok":true}`            |
+//! | `POST /api/pause`      |                        | `{"ok":true}`            |
+//! | `POST /api/stop`       |                        | `{"ok":true}`            |
+//! | `POST /api/seek`       | `{"seconds":90}`       | `{"ok":true}`            |
+//! | `POST /api/race`       | `{"session_key":9158}` | `{"ok":true}`            |
+//!
+//! Selecting a race downloads the session from the relay at the host of the
+//! race URL.
+//!
+//! Commands are answered with `202 Accepted` once they are handed to the
+//! firmware, errors with a 4xx status and `{"error":"<reason>"}`.
+//! This module routes the requests and reads and writes the JSON without
+//! any network stack, requests are parsed with
+//! [`parse_request`](crate::provisioning::parse_request).
+
+use crate::provisioning::{Method, Request};
+use crate::status::FirmwareState;
+use crate::version::VersionInfo;
+use core::fmt::{self, Write};
+use heapless::{String, Vec};
+
+/// Room for the longest JSON answer
+pub const JSON_SIZE: usize = 384;
+
+/// Paths of the API, other methods on them are not allowed
+const API_PATHS: [&str; 9] = [
+    "/api/status",
+    "/api/version",
+    "/api/temperature",
+    "/api/brightness",
+    "/api/play",
+    "/api/pause",
+    "/api/stop",
+    "/api/seek",
+    "/api/race",
+];
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta name="viewport" content="width=device-width"><title>F1 LED Circuit</title>
+<style>body{font-family:sans-serif;max-width:30em;margin:auto;padding:1em}button{margin:.2em}</style>
+</head><body><h1>F1 LED Circuit</h1>
+<p><b id="state">...</b> <span id="position"></span></p>
+<p><progress id="progress" max="1" value="0" style="width:100%"></progress></p>
+<p><button onclick="post('play')">Play</button><button onclick="post('pause')">Pause</button><button onclick="post('stop')">Stop</button></p>
+<p><label>Seek to <input id="seconds" type="number" min="0" size="6"> s</label>
+<button onclick="post('seek',{seconds:+seconds.value})">Seek</button></p>
+<p><label>Race session <input id="session" type="number" min="0" size="6"></label>
+<button onclick="post('race',{session_key:+session.value})">Select</button></p>
+<p><label>Brightness <input id="brightness" type="range" min="0" max="255"
+ onchange="post('brightness',{brightness:+this.value})"></label></p>
+<p>Temperature: <span id="temperature"></span></p>
+<p><small id="version"></small></p>
+<script>
+function time(s){return Math.floor(s/60)+':'+String(s%60).padStart(2,'0')}
+function post(action,body){fetch('/api/'+action,{method:'POST',body:JSON.stringify(body||{})}).then(update)}
+function update(){fetch('/api/status').then(r=>r.json()).then(s=>{
+state.textContent=s.state;position.textContent=time(s.position_s)+' / '+time(s.duration_s);
+progress.max=s.frame_count||1;progress.value=s.frame;
+if(document.activeElement!=session)session.placeholder=s.session_key;
+if(document.activeElement!=brightness)brightness.value=s.brightness;
+temperature.textContent=s.temperature_c==null?'unknown':s.temperature_c+' °C';
+version.textContent='Firmware '+s.firmware})}
+update();setInterval(update,1000);
+</script></body></html>"#;
+
+/// State of the board for `/api/status`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Status {
+    pub state: FirmwareState,
+    pub playing: bool,
+    pub frame: usize,
+    pub frame_count: usize,
+    pub frame_interval_ms: u32,
+    pub session_key: u32,
+    pub brightness: u8,
+    pub temperature_c: Option<f32>,
+    pub version: VersionInfo,
+}
+
+/// A command for the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Play,
+    /// Stop the race and keep its position, [`Action::Play`] continues
+    Pause,
+    Stop,
+    Seek {
+        seconds: u32,
+    },
+    /// Download the session from the relay
+    SelectRace(u32),
+    Brightness(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    /// The page, a JSON answer when `None`
+    page: Option<&'static str>,
+    json: String<JSON_SIZE>,
+}
+
+impl Response {
+    fn json(status: u16, write: impl FnOnce(&mut String<JSON_SIZE>) -> fmt::Result) -> Self {
+        let mut json = String::new();
+        // The answers are short, see JSON_SIZE
+        if write(&mut json).is_err() {
+            json.clear();
+        }
+        Self {
+            status,
+            page: None,
+            json,
+        }
+    }
+
+    fn error(status: u16, reason: &str) -> Self {
+        Self::json(status, |out| {
+            write!(out, "{{\"error\":{}}}", JsonStr(reason))
+        })
+    }
+
+    fn accepted() -> Self {
+        Self::json(202, |out| out.write_str("{\"ok\":true}"))
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self.page {
+            Some(_) => "text/html; charset=utf-8",
+            None => "application/json",
+        }
+    }
+
+    pub fn body(&self) -> &str {
+        self.page.unwrap_or(&self.json)
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "",
+        }
+    }
+
+    /// Write the status line and the headers, the body follows them
+    pub fn write_head(&self, out: &mut impl Write) -> fmt::Result {
+        write!(
+            out,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.reason(),
+            self.content_type(),
+            self.body().len()
+        )
+    }
+}
+
+/// Handle a request to the board. Returns the command to carry out, the
+/// answer assumes it is.
+pub fn handle_request(request: &Request, status: &Status) -> (Response, Option<Action>) {
+    let path = request.path.split('?').next().unwrap_or_default();
+    let action = match (request.method, path) {
+        (Method::Get, "/") => {
+            return (
+                Response {
+                    status: 200,
+                    page: Some(PAGE),
+                    json: String::new(),
+                },
+                None,
+            )
+        }
+        (Method::Get, "/api/status") => {
+            return (Response::json(200, |out| write_status(out, status)), None)
+        }
+        (Method::Get, "/api/version") => {
+            return (
+                Response::json(200, |out| write_version(out, &status.version)),
+                None,
+            )
+        }
+        (Method::Get, "/api/temperature") => {
+            return (
+                Response::json(200, |out| {
+                    write!(
+                        out,
+                        "{{\"temperature_c\":{}}}",
+                        JsonTemperature(status.temperature_c)
+                    )
+                }),
+                None,
+            )
+        }
+        (Method::Get, "/api/brightness") => {
+            return (brightness_response(status.brightness), None);
+        }
+        (Method::Post, "/api/brightness") => {
+            number_field(request.body, "brightness").map(Action::Brightness)
+        }
+        (Method::Post, "/api/play") => Ok(Action::Play),
+        (Method::Post, "/api/pause") => Ok(Action::Pause),
+        (Method::Post, "/api/stop") => Ok(Action::Stop),
+        (Method::Post, "/api/seek") => {
+            number_field(request.body, "seconds").map(|seconds| Action::Seek { seconds })
+        }
+        (Method::Post, "/api/race") => {
+            number_field(request.body, "session_key").map(Action::SelectRace)
+        }
+        (_, path) if API_PATHS.contains(&path) => {
+            return (Response::error(405, "method not allowed"), None)
+        }
+        _ => return (Response::error(404, "not found"), None),
+    };
+
+    match action {
+        Ok(Action::Brightness(brightness)) => (
+            brightness_response(brightness),
+            Some(Action::Brightness(brightness)),
+        ),
+        Ok(action) => (Response::accepted(), Some(action)),
+        Err(reason) => (Response::error(400, reason), None),
+    }
+}
+
+fn brightness_response(brightness: u8) -> Response {
+    Response::json(200, |out| write!(out, "{{\"brightness\":{}}}", brightness))
+}
+
+/// The JSON of `/api/status`, also published over MQTT
+pub fn write_status(out: &mut impl Write, status: &Status) -> fmt::Result {
+    let seconds = |frames: usize| frames as u64 * status.frame_interval_ms as u64 / 1000;
+    write!(
+        out,
+        "{{\"state\":{},\"playing\":{},\"frame\":{},\"frame_count\":{},\"position_s\":{},\"duration_s\":{},",
+        JsonStr(status.state.name()),
+        status.playing,
+        status.frame,
+        status.frame_count,
+        seconds(status.frame),
+        seconds(status.frame_count)
+    )?;
+    write!(
+        out,
+        "\"session_key\":{},\"brightness\":{},\"temperature_c\":{},\"firmware\":{}}}",
+        status.session_key,
+        status.brightness,
+        JsonTemperature(status.temperature_c),
+        JsonStr(status.version.version)
+    )
+}
+
+fn write_version(out: &mut impl Write, version: &VersionInfo) -> fmt::Result {
+    write!(
+        out,
+        "{{\"version\":{},\"git_commit\":{},\"build_timestamp\":{},\"board\":{},\"features\":{}}}",
+        JsonStr(version.version),
+        JsonStr(version.git_commit),
+        JsonStr(version.build_timestamp),
+        JsonStr(version.board),
+        JsonStr(version.features)
+    )
+}
+
+/// A JSON string
+struct JsonStr<'a>(&'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// A temperature with one decimal, `null` when unknown
+struct JsonTemperature(Option<f32>);
+
+impl fmt::Display for JsonTemperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(temperature_c) if temperature_c.is_finite() => write!(f, "{:.1}", temperature_c),
+            _ => f.write_str("null"),
+        }
+    }
+}
+
+/// A value of a flat JSON object. Strings keep their escapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Value<'a> {
+    Number(&'a str),
+    String(&'a str),
+    Bool(bool),
+    Null,
+}
+
+/// Most members of an object, enough for the status
+const MAX_MEMBERS: usize = 12;
+
+/// The members of a JSON object without nested objects or arrays, `None`
+/// when `body` is something else
+fn parse_object(body: &[u8]) -> Option<Vec<(&str, Value<'_>), MAX_MEMBERS>> {
+    let mut parser = Parser {
+        input: core::str::from_utf8(body).ok()?,
+        pos: 0,
+    };
+    let mut members = Vec::new();
+    parser.expect(b'{')?;
+    if !parser.eat(b'}') {
+        loop {
+            let name = parser.string()?;
+            parser.expect(b':')?;
+            let value = parser.value()?;
+            members.push((name, value)).ok()?;
+            if parser.eat(b'}') {
+                break;
+            }
+            parser.expect(b',')?;
+        }
+    }
+    parser.skip_whitespace();
+    (parser.pos == parser.input.len()).then_some(members)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        let found = self.peek() == Some(byte);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        self.eat(byte).then_some(())
+    }
+
+    fn string(&mut self) -> Option<&'a str> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        let mut escaped = false;
+        for (i, &byte) in self.input.as_bytes()[start..].iter().enumerate() {
+            match byte {
+                b'"' if !escaped => {
+                    self.pos = start + i + 1;
+                    return Some(&self.input[start..start + i]);
+                }
+                b'\\' => escaped = !escaped,
+                _ => escaped = false,
+            }
+        }
+        None
+    }
+
+    fn value(&mut self) -> Option<Value<'a>> {
+        match self.peek()? {
+            b'"' => self.string().map(Value::String),
+            b'-' | b'0'..=b'9' => {
+                let start = self.pos;
+                let len = self.input[start..]
+                    .find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E'))
+                    .unwrap_or(self.input.len() - start);
+                self.pos += len;
+                Some(Value::Number(&self.input[start..start + len]))
+            }
+            _ => {
+                let rest = &self.input[self.pos..];
+                let (value, len) = [
+                    ("true", Value::Bool(true)),
+                    ("false", Value::Bool(false)),
+                    ("null", Value::Null),
+                ]
+                .into_iter()
+                .find(|(word, _)| rest.starts_with(word))
+                .map(|(word, value)| (value, word.len()))?;
+                self.pos += len;
+                Some(value)
+            }
+        }
+    }
+}
+
+/// The whole number member `name` of the JSON object in `body`
+fn number_field<T: core::str::FromStr>(body: &[u8], name: &str) -> Result<T, &'static str> {
+    let members = parse_object(body).ok_or("expected a JSON object")?;
+    match members.iter().find(|(member, _)| *member == name) {
+        Some((_, Value::Number(number))) => number.parse().map_err(|_| "number out of range"),
+        Some(_) => Err("expected a number"),
+        None => Err("missing member"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provisioning::parse_request;
+
+    const STATUS: Status = Status {
+        state: FirmwareState::Racing,
+        playing: true,
+        frame: 1200,
+        frame_count: 36000,
+        frame_interval_ms: 50,
+        session_key: 9158,
+        brightness: 128,
+        temperature_c: Some(41.25),
+        version: VersionInfo {
+            version: "0.1.0",
+            git_commit: "715838c",
+            build_timestamp: "2024-08-08T12:00:00Z",
+            board: "esp32c3",
+            features: "wifi",
+        },
+    };
+
+    /// Handle a raw request as it comes off the socket
+    fn handle(raw: &str) -> (Response, Option<Action>) {
+        handle_request(&parse_request(raw.as_bytes()).unwrap(), &STATUS)
+    }
+
+    fn post(path: &str, body: &str) -> (Response, Option<Action>) {
+        let mut raw: String<256> = String::new();
+        write!(
+            raw,
+            "POST {} HTTP/1.1\r\nHost: 192.168.1.20\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        handle(&raw)
+    }
+
+    #[test]
+    fn test_page() {
+        let (response, action) = handle("GET / HTTP/1.1\r\nHost: 192.168.1.20\r\n\r\n");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.content_type(), "text/html; charset=utf-8");
+        assert!(response.body().contains("/api/status"));
+        assert_eq!(action, None);
+    }
+
+    #[test]
+    fn test_status() {
+        let (response, action) = handle("GET /api/status HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.content_type(), "application/json");
+        assert_eq!(
+            response.body(),
+            r#"{"state":"racing","playing":true,"frame":1200,"frame_count":36000,"position_s":60,"duration_s":1800,"session_key":9158,"brightness":128,"temperature_c":41.2,"firmware":"0.1.0"}"#
+        );
+        assert_eq!(action, None);
+
+        // The answer is valid JSON for the parser too
+        let members = parse_object(response.body().as_bytes()).unwrap();
+        assert_eq!(members[0], ("state", Value::String("racing")));
+
+        let status = Status {
+            temperature_c: None,
+            ..STATUS
+        };
+        let request = parse_request(b"GET /api/status?t=1 HTTP/1.1\r\n\r\n").unwrap();
+        let (response, _) = handle_request(&request, &status);
+        assert!(response.body().contains("\"temperature_c\":null"));
+    }
+
+    #[test]
+    fn test_readings() {
+        let (response, _) = handle("GET /api/version HTTP/1.1\r\n\r\n");
+        assert_eq!(
+            response.body(),
+            r#"{"version":"0.1.0","git_commit":"715838c","build_timestamp":"2024-08-08T12:00:00Z","board":"esp32c3","features":"wifi"}"#
+        );
+        let (response, _) = handle("GET /api/temperature HTTP/1.1\r\n\r\n");
+        assert_eq!(response.body(), r#"{"temperature_c":41.2}"#);
+        let (response, _) = handle("GET /api/brightness HTTP/1.1\r\n\r\n");
+        assert_eq!(response.body(), r#"{"brightness":128}"#);
+    }
+
+    #[test]
+    fn test_commands() {
+        for (path, expected) in [
+            ("/api/play", Action::Play),
+            ("/api/pause", Action::Pause),
+            ("/api/stop", Action::Stop),
+        ] {
+            let (response, action) = post(path, "");
+            assert_eq!(response.status, 202);
+            assert_eq!(response.body(), r#"{"ok":true}"#);
+            assert_eq!(action, Some(expected));
+        }
+
+        let (response, action) = post("/api/seek", r#"{"seconds": 90}"#);
+        assert_eq!(response.status, 202);
+        assert_eq!(action, Some(Action::Seek { seconds: 90 }));
+
+        let (_, action) = post(
+            "/api/race",
+            r#"{ "session_key" : 9159, "note": "a \"quoted\" name" }"#,
+        );
+        assert_eq!(action, Some(Action::SelectRace(9159)));
+
+        let (response, action) = post("/api/brightness", r#"{"brightness":200}"#);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body(), r#"{"brightness":200}"#);
+        assert_eq!(action, Some(Action::Brightness(200)));
+    }
+
+    #[test]
+    fn test_bad_requests() {
+        for (path, body, reason) in [
+            (
+                "/api/brightness",
+                r#"{"brightness":256}"#,
+                "number out of range",
+            ),
+            (
+                "/api/brightness",
+                r#"{"brightness":"max"}"#,
+                "expected a number",
+            ),
+            ("/api/seek", r#"{"seconds":-5}"#, "number out of range"),
+            ("/api/seek", r#"{"position":5}"#, "missing member"),
+            ("/api/seek", "seconds=5", "expected a JSON object"),
+            (
+                "/api/race",
+                r#"{"session_key":{"id":1}}"#,
+                "expected a JSON object",
+            ),
+            (
+                "/api/race",
+                r#"{"session_key":1} trailing"#,
+                "expected a JSON object",
+            ),
+        ] {
+            let (response, action) = post(path, body);
+            assert_eq!(response.status, 400, "{}", body);
+            let members = parse_object(response.body().as_bytes()).unwrap();
+            assert_eq!(members[0], ("error", Value::String(reason)), "{}", body);
+            assert_eq!(action, None);
+        }
+
+        let (response, _) = handle("GET /api/play HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status, 405);
+        let (response, _) = post("/api/status", "");
+        assert_eq!(response.status, 405);
+        let (response, _) = handle("GET /favicon.ico HTTP/1.1\r\n\r\n");
+        assert_eq!(response.status, 404);
+        assert_eq!(response.body(), r#"{"error":"not found"}"#);
+    }
+
+    #[test]
+    fn test_response_head() {
+        let (response, _) = post("/api/play", "");
+        let mut head: String<256> = String::new();
+        response.write_head(&mut head).unwrap();
+        assert_eq!(
+            head,
+            "HTTP/1.1 202 Accepted\r\nContent-Type: application/json\r\nContent-Length: 11\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_json_str() {
+        let mut s: String<64> = String::new();
+        write!(s, "{}", JsonStr("a \"b\"\\\n")).unwrap();
+        assert_eq!(s, r#""a \"b\"\\\u000a""#);
+    }
+
+    #[test]
+    fn test_parse_object() {
+        assert_eq!(parse_object(b" { } ").unwrap().len(), 0);
+        let members = parse_object(br#"{"a":true,"b":null,"c":-1.5e3,"d":"x"}"#).unwrap();
+        assert_eq!(
+            members[..],
+            [
+                ("a", Value::Bool(true)),
+                ("b", Value::Null),
+                ("c", Value::Number("-1.5e3")),
+                ("d", Value::String("x")),
+            ]
+        );
+        assert_eq!(parse_object(b"[1]"), None);
+        assert_eq!(parse_object(br#"{"a":1,}"#), None);
+        assert_eq!(parse_object(br#"{"a":"unterminated}"#), None);
+        assert_eq!(parse_object(br#"{"a":tru}"#), None);
+    }
+}
@@ -0,0 +1,79 @@
+//! Nearest-LED lookups with the k-d tree against scanning every LED.
+//!
+//! `cargo bench --bench nearest_led`
+
+use f1_logic::led_index::{nearest_linear, LedIndex};
+use f1_logic::led_layout::ZANDVOORT_20X20;
+use f1_logic::track::Point;
+use f1_simulation::led_data::LED_DATA;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// About five minutes of location samples of 20 drivers
+const SAMPLES: usize = 20 * 5 * 60 * 4;
+
+/// Positions spread over the track, each within about an LED of one
+fn samples(leds: &[Point]) -> Vec<Point> {
+    // Distance from the first LED to its closest neighbour
+    let spacing = leds[1..]
+        .iter()
+        .map(|led| (led.x - leds[0].x).hypot(led.y - leds[0].y))
+        .fold(f32::INFINITY, f32::min);
+    let mut seed = 0x2545_f491_u32;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed as f32 / u32::MAX as f32 - 0.5
+    };
+    (0..SAMPLES)
+        .map(|i| {
+            let led = leds[i * 7 % leds.len()];
+            Point::new(led.x + random() * spacing, led.y + random() * spacing)
+        })
+        .collect()
+}
+
+fn bench(name: &str, leds: &[Point]) {
+    let samples = samples(leds);
+    let index = LedIndex::<216>::new(leds.iter().copied()).unwrap();
+
+    let start = Instant::now();
+    let linear: Vec<usize> = samples
+        .iter()
+        .map(|&point| nearest_linear(black_box(leds), point).unwrap())
+        .collect();
+    let linear_time = start.elapsed();
+
+    let start = Instant::now();
+    let indexed: Vec<usize> = samples
+        .iter()
+        .map(|&point| black_box(&index).nearest(point).unwrap())
+        .collect();
+    let index_time = start.elapsed();
+
+    assert_eq!(linear, indexed, "{}: the index differs from the scan", name);
+    println!(
+        "{}: {} LEDs, {} samples, linear {:?}, k-d tree {:?} ({:.1}x)",
+        name,
+        leds.len(),
+        samples.len(),
+        linear_time,
+        index_time,
+        linear_time.as_secs_f64() / index_time.as_secs_f64()
+    );
+}
+
+fn main() {
+    let zandvoort: Vec<Point> = LED_DATA
+        .iter()
+        .map(|led| Point::new(led.x_led, led.y_led))
+        .collect();
+    bench("Zandvoort in OpenF1 units", &zandvoort);
+
+    let board: Vec<Point> = ZANDVOORT_20X20
+        .iter()
+        .map(|led| Point::new(led.x_mm, led.y_mm))
+        .collect();
+    bench("Zandvoort 20x20 board", &board);
+}
@@ -0,0 +1,84 @@
+//! Race relay for boards on the LAN, see `f1_simulation::relay`.
+
+use f1_logic::stream::STREAM_PORT;
+use f1_simulation::relay::{Relay, RelayConfig};
+use tokio::net::{TcpListener, UdpSocket};
+
+const USAGE: &str = "\
+usage: f1-relay [--listen <addr>] [--stream <addr>] [--openf1 <url>] [--interval-ms <ms>]
+
+  --listen <addr>      HTTP address to listen on, default 0.0.0.0:8080
+  --stream <addr>      UDP address for live streams, default 0.0.0.0:8081
+  --openf1 <url>       OpenF1 API base URL, default https://api.openf1.org/v1
+  --interval-ms <ms>   time between race frames, default 50";
+
+struct Args {
+    listen: String,
+    stream: String,
+    config: RelayConfig,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut parsed = Args {
+        listen: "0.0.0.0:8080".to_string(),
+        // The board expects the stream server on this port
+        stream: format!("0.0.0.0:{}", STREAM_PORT),
+        config: RelayConfig::default(),
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--listen" => parsed.listen = value()?,
+            "--stream" => parsed.stream = value()?,
+            "--openf1" => parsed.config.openf1_url = value()?,
+            "--interval-ms" => {
+                parsed.config.frame_interval_ms = value()?
+                    .parse()
+                    .ok()
+                    .filter(|&ms| ms > 0)
+                    .ok_or("Invalid frame interval")?
+            }
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ => return Err(format!("Unknown argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+    Ok(parsed)
+}
+
+fn exit_with(message: String) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(1);
+}
+
+#[tokio::main]
+async fn main() {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    });
+
+    let listener = TcpListener::bind(&args.listen)
+        .await
+        .unwrap_or_else(|err| exit_with(format!("Failed to listen on {}: {}", args.listen, err)));
+    let socket = UdpSocket::bind(&args.stream)
+        .await
+        .unwrap_or_else(|err| exit_with(format!("Failed to listen on {}: {}", args.stream, err)));
+    let addr = listener.local_addr().unwrap();
+    println!("Relaying {} on {}", args.config.openf1_url, addr);
+    println!(
+        "  race file:    http://<host>:{}/races/<session_key>.bin",
+        addr.port()
+    );
+    println!(
+        "  frame stream: ws://<host>:{}/races/<session_key>/stream",
+        addr.port()
+    );
+    println!("  live stream:  udp://{}", socket.local_addr().unwrap());
+
+    let relay = Relay::new(args.config);
+    let result = tokio::try_join!(relay.clone().serve(listener), relay.serve_stream(socket));
+    if let Err(err) = result {
+        exit_with(format!("Relay failed: {}", err));
+    }
+}
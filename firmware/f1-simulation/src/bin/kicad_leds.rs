@@ -0,0 +1,121 @@
+//! Extracts the LED positions of a KiCad PCB, see `f1_simulation::kicad`.
+//!
+//! cargo run --bin kicad_leds -- ../../kicad/zandvoort_10x10/f1-led-circuit-10x10.kicad_pcb --track ../tracks/zandvoort.json
+//!
+//! Prints a circuit definition with the LEDs of the board, the rest of the
+//! definition from `--track`. Compile it with `track_table` afterwards.
+//! LEDs are numbered in the order the data chain on the board passes them,
+//! which should be the order of their references.
+
+use f1_simulation::kicad::{self, Origin, Sexp};
+use f1_simulation::led_chain::{self, Chain};
+use f1_simulation::track_file::TrackFile;
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+usage: kicad_leds <board.kicad_pcb> [--origin <origin>] [--track <track.json>] [--name <name>] [--led-data]
+
+  --origin <origin>    edge (bottom left of the board outline, default), grid, aux or <x>,<y>
+  --track <track.json> definition to take everything but the LEDs from
+  --name <name>        circuit name of a new definition, default the PCB file name
+  --led-data           print LED_DATA for the simulator instead, through the
+                       transform of the definition";
+
+struct Args {
+    pcb: String,
+    origin: Origin,
+    track: Option<String>,
+    name: Option<String>,
+    led_data: bool,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut pcb = None;
+    let mut parsed = Args {
+        pcb: String::new(),
+        origin: Origin::BoardOutline,
+        track: None,
+        name: None,
+        led_data: false,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--origin" => {
+                let origin = value()?;
+                parsed.origin =
+                    Origin::parse(&origin).ok_or(format!("Invalid origin {}", origin))?
+            }
+            "--track" => parsed.track = Some(value()?),
+            "--name" => parsed.name = Some(value()?),
+            "--led-data" => parsed.led_data = true,
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ if pcb.is_none() && !arg.starts_with('-') => pcb = Some(arg),
+            _ => return Err(format!("Unknown argument {}\n\n{}", arg, USAGE)),
+        }
+    }
+    parsed.pcb = pcb.ok_or(USAGE)?;
+    Ok(parsed)
+}
+
+fn run(args: &Args) -> Result<String, String> {
+    let text = std::fs::read_to_string(&args.pcb)
+        .map_err(|e| format!("Failed to read {}: {}", args.pcb, e))?;
+    let pcb = Sexp::parse(&text).map_err(|e| format!("{}: {}", args.pcb, e))?;
+    let origin = args.origin.locate(&pcb)?;
+    let leds = kicad::led_positions(&pcb, origin)?;
+    let chain = Chain::trace(&led_chain::pcb_pins(&pcb))?;
+    for (position, led) in chain.mismatches() {
+        eprintln!("U{} is LED {} of the chain", led, position);
+    }
+    let leds = chain.number(&leds)?;
+    eprintln!(
+        "{} LEDs, origin at {}, {} in KiCad coordinates",
+        leds.len(),
+        origin.0,
+        origin.1
+    );
+
+    let leds = leds.into_iter().map(Into::into).collect();
+    let track = match &args.track {
+        Some(path) => TrackFile {
+            leds,
+            ..TrackFile::load(Path::new(path))?
+        },
+        None => {
+            let name = args.name.clone().unwrap_or_else(|| {
+                let stem = Path::new(&args.pcb).file_stem().unwrap_or_default();
+                stem.to_string_lossy()
+                    .replace(['-', ' '], "_")
+                    .to_lowercase()
+            });
+            TrackFile::new(&name, leds)
+        }
+    };
+    // The LEDs may no longer fit the rest of the definition
+    let track = TrackFile::parse(&track.to_json())?;
+    Ok(if args.led_data {
+        track.led_data_source()
+    } else {
+        track.to_json()
+    })
+}
+
+fn main() -> ExitCode {
+    let args = parse_args().unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    });
+    match run(&args) {
+        Ok(output) => {
+            print!("{}", output);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
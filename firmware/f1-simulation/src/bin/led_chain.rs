@@ -0,0 +1,44 @@
+//! Checks the LED chain of a board, see `f1_simulation::led_chain`.
+//!
+//! cargo run --bin led_chain -- ../../kicad/zandvoort_10x10/f1-led-circuit-10x10.kicad_pcb
+//!
+//! Takes a `.kicad_pcb` file or a netlist of the schematic, made with
+//! `kicad-cli sch export netlist <board.kicad_sch>`. Fails when LED N of
+//! `LED_DATA` and the circuit definitions isn't the Nth LED of the chain.
+
+use f1_simulation::kicad::Sexp;
+use f1_simulation::led_chain::{self, Chain};
+use std::process::ExitCode;
+
+fn run(path: &str) -> Result<bool, String> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let file = Sexp::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+    let chain = Chain::trace(&led_chain::pins(&file)?)?;
+    let mismatches = chain.mismatches();
+    for &(position, led) in &mismatches {
+        println!("LED {} of the chain is U{}", position, led);
+    }
+    println!(
+        "{} LEDs, {} out of order",
+        chain.leds.len(),
+        mismatches.len()
+    );
+    Ok(mismatches.is_empty())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [path] = &args[..] else {
+        eprintln!("usage: led_chain <board.kicad_pcb | netlist.net>");
+        return ExitCode::from(2);
+    };
+    match run(path) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("{}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
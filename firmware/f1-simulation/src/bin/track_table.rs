@@ -0,0 +1,41 @@
+//! Compiles a circuit definition into the firmware's const table, see
+//! `f1_simulation::track_file`.
+//!
+//! cargo run --bin track_table -- ../tracks/zandvoort.json ../f1-logic/src/circuit/zandvoort.rs
+//!
+//! New circuits also need a `mod` and an entry in `f1_logic::circuit::CIRCUITS`.
+
+use f1_simulation::track_file::TrackFile;
+use std::path::Path;
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [input, output] = &args[..] else {
+        eprintln!("usage: track_table <track.json> <output.rs>");
+        return ExitCode::from(2);
+    };
+    let track = match TrackFile::load(Path::new(input)) {
+        Ok(track) => track,
+        Err(err) => {
+            eprintln!("{}", err);
+            return ExitCode::FAILURE;
+        }
+    };
+    // The path as the table names it, from the firmware directory
+    let source = match input.find("tracks/") {
+        Some(start) => format!("firmware/{}", &input[start..]),
+        None => input.clone(),
+    };
+    if let Err(err) = std::fs::write(output, track.to_rust(&source)) {
+        eprintln!("Failed to write {}: {}", output, err);
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "{}: {} LEDs, const {}",
+        track.name,
+        track.leds.len(),
+        track.const_name()
+    );
+    ExitCode::SUCCESS
+}
@@ -1,6 +1,8 @@
 pub struct DriverInfo {
     pub number: u32,
     pub name: &'static str,
+    /// Three letter abbreviation of timing screens
+    pub code: &'static str,
     pub team: &'static str,
     pub color: (u8, u8, u8),
 }
@@ -9,120 +11,140 @@ pub const DRIVERS: &[DriverInfo] = &[
     DriverInfo {
         number: 1,
         name: "Max Verstappen",
+        code: "VER",
         team: "Red Bull",
         color: (30, 65, 255),
     },
     DriverInfo {
         number: 2,
         name: "Logan Sargeant",
+        code: "SAR",
         team: "Williams",
         color: (0, 82, 255),
     },
     DriverInfo {
         number: 4,
         name: "Lando Norris",
+        code: "NOR",
         team: "McLaren",
         color: (255, 135, 0),
     },
     DriverInfo {
         number: 10,
         name: "Pierre Gasly",
+        code: "GAS",
         team: "Alpine",
         color: (2, 144, 240),
     },
     DriverInfo {
         number: 11,
         name: "Sergio Perez",
+        code: "PER",
         team: "Red Bull",
         color: (30, 65, 255),
     },
     DriverInfo {
         number: 14,
         name: "Fernando Alonso",
+        code: "ALO",
         team: "Aston Martin",
         color: (0, 110, 120),
     },
     DriverInfo {
         number: 16,
         name: "Charles Leclerc",
+        code: "LEC",
         team: "Ferrari",
         color: (220, 0, 0),
     },
     DriverInfo {
         number: 18,
         name: "Lance Stroll",
+        code: "STR",
         team: "Aston Martin",
         color: (0, 110, 120),
     },
     DriverInfo {
         number: 20,
         name: "Kevin Magnussen",
+        code: "MAG",
         team: "Haas",
         color: (160, 207, 205),
     },
     DriverInfo {
         number: 22,
         name: "Yuki Tsunoda",
+        code: "TSU",
         team: "AlphaTauri",
         color: (60, 130, 200),
     },
     DriverInfo {
         number: 23,
         name: "Alex Albon",
+        code: "ALB",
         team: "Williams",
         color: (0, 82, 255),
     },
     DriverInfo {
         number: 24,
         name: "Zhou Guanyu",
+        code: "ZHO",
         team: "Stake F1",
         color: (165, 160, 155),
     },
     DriverInfo {
         number: 27,
         name: "Nico Hulkenberg",
+        code: "HUL",
         team: "Haas",
         color: (160, 207, 205),
     },
     DriverInfo {
         number: 31,
         name: "Esteban Ocon",
+        code: "OCO",
         team: "Alpine",
         color: (2, 144, 240),
     },
     DriverInfo {
         number: 40,
         name: "Liam Lawson",
+        code: "LAW",
         team: "AlphaTauri",
         color: (60, 130, 200),
     },
     DriverInfo {
         number: 44,
         name: "Lewis Hamilton",
+        code: "HAM",
         team: "Mercedes",
         color: (0, 210, 190),
     },
     DriverInfo {
         number: 55,
         name: "Carlos Sainz",
+        code: "SAI",
         team: "Ferrari",
         color: (220, 0, 0),
     },
     DriverInfo {
         number: 63,
         name: "George Russell",
+        code: "RUS",
         team: "Mercedes",
         color: (0, 210, 190),
     },
     DriverInfo {
         number: 77,
         name: "Valtteri Bottas",
+        code: "BOT",
         team: "Stake F1",
         color: (165, 160, 155),
     },
     DriverInfo {
         number: 81,
         name: "Oscar Piastri",
+        code: "PIA",
         team: "McLaren",
         color: (255, 135, 0),
     },
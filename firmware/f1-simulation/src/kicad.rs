@@ -0,0 +1,378 @@
+//! LED positions from KiCad PCB files.
+//!
+//! Reads the HD108 footprints `U1`…`Un` of a `.kicad_pcb` file, the
+//! positions `kicad_leds` turns into circuit definitions. Positions are
+//! relative to an origin on the board with the y axis pointing up, like
+//! `f1_logic::led_layout`.
+
+use f1_logic::led_layout::LedPosition;
+
+/// Footprints of the LEDs contain this in their library ID
+pub const LED_FOOTPRINT: &str = "HD108";
+
+/// Node of an S-expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sexp {
+    Atom(String),
+    List(Vec<Sexp>),
+}
+
+impl Sexp {
+    pub fn parse(text: &str) -> Result<Sexp, String> {
+        let mut stack: Vec<Vec<Sexp>> = Vec::new();
+        let mut root = None;
+        let mut chars = text.char_indices().peekable();
+        while let Some((offset, c)) = chars.next() {
+            let node = match c {
+                '(' => {
+                    stack.push(Vec::new());
+                    continue;
+                }
+                ')' => {
+                    let list = stack
+                        .pop()
+                        .ok_or(format!("Unexpected ) at byte {}", offset))?;
+                    Sexp::List(list)
+                }
+                '"' => {
+                    let mut atom = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) => match chars.next() {
+                                Some((_, 'n')) => atom.push('\n'),
+                                Some((_, c)) => atom.push(c),
+                                None => break,
+                            },
+                            Some((_, c)) => atom.push(c),
+                            None => return Err(format!("Unterminated string at byte {}", offset)),
+                        }
+                    }
+                    Sexp::Atom(atom)
+                }
+                c if c.is_whitespace() => continue,
+                _ => {
+                    let mut atom = c.to_string();
+                    while let Some(&(_, c)) = chars.peek() {
+                        if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                            break;
+                        }
+                        atom.push(c);
+                        chars.next();
+                    }
+                    Sexp::Atom(atom)
+                }
+            };
+            match stack.last_mut() {
+                Some(parent) => parent.push(node),
+                None if root.is_none() => root = Some(node),
+                None => return Err(format!("Trailing data at byte {}", offset)),
+            }
+        }
+        if !stack.is_empty() {
+            return Err("Unterminated list".to_string());
+        }
+        root.ok_or("Empty file".to_string())
+    }
+
+    fn items(&self) -> &[Sexp] {
+        match self {
+            Sexp::List(items) => items,
+            Sexp::Atom(_) => &[],
+        }
+    }
+
+    /// The atom at `index` of a list
+    pub fn atom(&self, index: usize) -> Option<&str> {
+        match self.items().get(index)? {
+            Sexp::Atom(atom) => Some(atom),
+            Sexp::List(_) => None,
+        }
+    }
+
+    pub fn number(&self, index: usize) -> Option<f64> {
+        self.atom(index)?.parse().ok()
+    }
+
+    /// Child lists starting with `name`
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Sexp> + 'a {
+        self.items()
+            .iter()
+            .filter(move |child| child.atom(0) == Some(name))
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Sexp> {
+        self.items()
+            .iter()
+            .find(|child| child.atom(0) == Some(name))
+    }
+}
+
+/// A footprint placed on the PCB, in KiCad coordinates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Footprint {
+    pub reference: String,
+    pub library_id: String,
+    pub x: f64,
+    pub y: f64,
+    pub rotation: f64,
+}
+
+/// All footprints of a PCB
+pub fn footprints(pcb: &Sexp) -> Vec<Footprint> {
+    // KiCad 5 called them modules
+    let footprints = pcb.children("footprint").chain(pcb.children("module"));
+    footprints
+        .filter_map(|fp| {
+            let reference = reference(fp)?;
+            let at = fp.child("at")?;
+            Some(Footprint {
+                reference: reference.to_string(),
+                library_id: fp.atom(1)?.to_string(),
+                x: at.number(1)?,
+                y: at.number(2)?,
+                rotation: at.number(3).unwrap_or(0.0),
+            })
+        })
+        .collect()
+}
+
+/// Reference designator of a footprint
+pub(crate) fn reference(footprint: &Sexp) -> Option<&str> {
+    // `property` since KiCad 8, `fp_text` before
+    footprint
+        .children("property")
+        .find(|p| p.atom(1) == Some("Reference"))
+        .or_else(|| {
+            footprint
+                .children("fp_text")
+                .find(|t| t.atom(1) == Some("reference"))
+        })?
+        .atom(2)
+}
+
+/// Point the positions are relative to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    /// Bottom left corner of the board outline
+    BoardOutline,
+    /// The grid origin set in the PCB editor
+    Grid,
+    /// The drill/place file origin
+    Aux,
+    /// KiCad coordinates in mm
+    Point(f64, f64),
+}
+
+impl Origin {
+    /// `edge`, `grid`, `aux` or `<x>,<y>`
+    pub fn parse(s: &str) -> Option<Origin> {
+        match s {
+            "edge" => Some(Origin::BoardOutline),
+            "grid" => Some(Origin::Grid),
+            "aux" => Some(Origin::Aux),
+            _ => {
+                let (x, y) = s.split_once(',')?;
+                Some(Origin::Point(
+                    x.trim().parse().ok()?,
+                    y.trim().parse().ok()?,
+                ))
+            }
+        }
+    }
+
+    /// The origin in KiCad coordinates
+    pub fn locate(&self, pcb: &Sexp) -> Result<(f64, f64), String> {
+        let setup_point = |name: &str| {
+            // KiCad leaves out origins at 0, 0
+            pcb.child("setup")
+                .and_then(|setup| setup.child(name))
+                .map_or(Some((0.0, 0.0)), |at| Some((at.number(1)?, at.number(2)?)))
+                .ok_or(format!("Invalid {}", name))
+        };
+        match *self {
+            Origin::BoardOutline => {
+                let (min_x, max_y) = outline_points(pcb)
+                    .fold(None, |corner: Option<(f64, f64)>, (x, y)| {
+                        Some(corner.map_or((x, y), |(cx, cy)| (cx.min(x), cy.max(y))))
+                    })
+                    .ok_or("The PCB has no board outline on Edge.Cuts")?;
+                Ok((min_x, max_y))
+            }
+            Origin::Grid => setup_point("grid_origin"),
+            Origin::Aux => setup_point("aux_axis_origin"),
+            Origin::Point(x, y) => Ok((x, y)),
+        }
+    }
+}
+
+/// Points of the drawings on Edge.Cuts
+fn outline_points(pcb: &Sexp) -> impl Iterator<Item = (f64, f64)> + '_ {
+    let shapes = ["gr_line", "gr_rect", "gr_arc", "gr_poly"]
+        .into_iter()
+        .flat_map(|kind| pcb.children(kind));
+    shapes
+        .filter(|shape| {
+            shape
+                .child("layer")
+                .is_some_and(|l| l.atom(1) == Some("Edge.Cuts"))
+        })
+        .flat_map(|shape| {
+            let ends = ["start", "mid", "end"]
+                .into_iter()
+                .filter_map(|name| shape.child(name));
+            let corners = shape
+                .child("pts")
+                .into_iter()
+                .flat_map(|pts| pts.children("xy"));
+            ends.chain(corners)
+                .filter_map(|point| Some((point.number(1)?, point.number(2)?)))
+                .collect::<Vec<_>>()
+        })
+}
+
+/// The LEDs `U1`…`Un` in mm relative to `origin`, y pointing up
+pub fn led_positions(pcb: &Sexp, origin: (f64, f64)) -> Result<Vec<LedPosition>, String> {
+    let mut leds: Vec<(u16, &Footprint)> = Vec::new();
+    let footprints = footprints(pcb);
+    for footprint in &footprints {
+        if !footprint.library_id.contains(LED_FOOTPRINT) {
+            continue;
+        }
+        let led_number = footprint
+            .reference
+            .strip_prefix('U')
+            .and_then(|n| n.parse().ok())
+            .ok_or(format!(
+                "{} footprint {}",
+                LED_FOOTPRINT, footprint.reference
+            ))?;
+        leds.push((led_number, footprint));
+    }
+    leds.sort_by_key(|&(led_number, _)| led_number);
+    if leds.is_empty() {
+        return Err(format!("No {} footprints", LED_FOOTPRINT));
+    }
+    for (index, &(led_number, _)) in leds.iter().enumerate() {
+        match (index + 1) as u16 {
+            expected if led_number < expected => return Err(format!("Duplicate U{}", led_number)),
+            expected if led_number > expected => return Err(format!("Missing U{}", expected)),
+            _ => {}
+        }
+    }
+    Ok(leds
+        .into_iter()
+        .map(|(led_number, footprint)| LedPosition {
+            led_number,
+            x_mm: micrometres(footprint.x - origin.0),
+            y_mm: micrometres(origin.1 - footprint.y),
+            rotation_deg: footprint.rotation as f32,
+        })
+        .collect())
+}
+
+/// Rounded to whole µm, finer positions are editor noise
+fn micrometres(mm: f64) -> f32 {
+    ((mm * 1000.0).round() / 1000.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::track_file::TrackFile;
+    use f1_logic::circuit::ZANDVOORT;
+    use f1_logic::led_layout::ZANDVOORT_20X20;
+
+    fn load(path: &str) -> Sexp {
+        let path = format!("{}/../../kicad/{}", env!("CARGO_MANIFEST_DIR"), path);
+        Sexp::parse(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let sexp = Sexp::parse("(a \"b c\" (d 1.5) (d \"e\\\"\") f)").unwrap();
+        assert_eq!(sexp.atom(0), Some("a"));
+        assert_eq!(sexp.atom(1), Some("b c"));
+        assert_eq!(sexp.child("d").unwrap().number(1), Some(1.5));
+        assert_eq!(sexp.children("d").nth(1).unwrap().atom(1), Some("e\""));
+        assert_eq!(sexp.atom(2), None);
+        assert_eq!(sexp.atom(4), Some("f"));
+
+        assert!(Sexp::parse("(a (b)").is_err());
+        assert!(Sexp::parse("(a))").is_err());
+        assert!(Sexp::parse("(a) (b)").is_err());
+        assert!(Sexp::parse("(a \"b)").is_err());
+        assert!(Sexp::parse(" ").is_err());
+    }
+
+    #[test]
+    fn test_older_kicad() {
+        let pcb = Sexp::parse(
+            r#"(kicad_pcb (version 20211014)
+                (setup (aux_axis_origin 50 60))
+                (footprint "lib:HD108-2020" (layer "F.Cu") (at 60 40)
+                  (fp_text reference "U2" (at 0 2)))
+                (footprint "lib:HD108-2020" (layer "F.Cu") (at 55.5 45 90)
+                  (fp_text reference "U1" (at 0 2)))
+                (footprint "lib:R_0603" (at 1 1) (fp_text reference "R1" (at 0 0))))"#,
+        )
+        .unwrap();
+        assert_eq!(footprints(&pcb).len(), 3);
+        let origin = Origin::Aux.locate(&pcb).unwrap();
+        assert_eq!(origin, (50.0, 60.0));
+        assert_eq!(Origin::Grid.locate(&pcb).unwrap(), (0.0, 0.0));
+        assert!(Origin::BoardOutline.locate(&pcb).is_err());
+        let leds = led_positions(&pcb, origin).unwrap();
+        assert_eq!(
+            leds[0],
+            LedPosition {
+                led_number: 1,
+                x_mm: 5.5,
+                y_mm: 15.0,
+                rotation_deg: 90.0,
+            }
+        );
+        assert_eq!(leds[1].y_mm, 20.0);
+    }
+
+    #[test]
+    fn test_missing_led() {
+        let pcb = Sexp::parse(
+            r#"(kicad_pcb
+                (footprint "lib:HD108-2020" (at 1 1) (property "Reference" "U1"))
+                (footprint "lib:HD108-2020" (at 2 1) (property "Reference" "U3")))"#,
+        )
+        .unwrap();
+        assert_eq!(led_positions(&pcb, (0.0, 0.0)).unwrap_err(), "Missing U2");
+    }
+
+    #[test]
+    fn test_origin() {
+        assert_eq!(Origin::parse("edge"), Some(Origin::BoardOutline));
+        assert_eq!(
+            Origin::parse("100, 149.95"),
+            Some(Origin::Point(100.0, 149.95))
+        );
+        assert_eq!(Origin::parse("center"), None);
+    }
+
+    #[test]
+    fn test_committed_boards() {
+        let pcb = load("zandvoort_10x10/f1-led-circuit-10x10.kicad_pcb");
+        let origin = Origin::BoardOutline.locate(&pcb).unwrap();
+        assert_eq!(origin, (100.0, 149.95));
+        let leds = led_positions(&pcb, origin).unwrap();
+        assert_eq!(leds, ZANDVOORT.leds);
+        // The committed definition is up to date
+        let json = include_str!("../../tracks/zandvoort.json");
+        let track = TrackFile {
+            leds: leds.into_iter().map(Into::into).collect(),
+            ..TrackFile::parse(json).unwrap()
+        };
+        assert_eq!(track.to_json(), json);
+
+        let pcb = load("zandvoort_20x20/f1-led-circuit_20x20.kicad_pcb");
+        let origin = Origin::BoardOutline.locate(&pcb).unwrap();
+        assert_eq!(led_positions(&pcb, origin).unwrap(), ZANDVOORT_20X20);
+    }
+}
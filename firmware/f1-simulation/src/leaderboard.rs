@@ -0,0 +1,136 @@
+//! Leaderboard of the simulator.
+//!
+//! Runs the frames the simulator shows through `f1_logic::race`, the way a
+//! display mode on the board would see them.
+
+use crate::driver_info::DRIVERS;
+use crate::led_data::UpdateFrame;
+use f1_logic::circuit::Circuit;
+use f1_logic::race::{Gap, Race};
+
+/// A line of the leaderboard
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub position: usize,
+    pub driver_number: u32,
+    pub code: String,
+    pub color: (u8, u8, u8),
+    pub gap: String,
+    /// Completed laps
+    pub laps: u16,
+    pub led_number: u32,
+}
+
+pub struct Leaderboard {
+    race: Race<'static>,
+    /// Timestamp of the first frame
+    start: Option<u64>,
+}
+
+impl Leaderboard {
+    /// A leaderboard for frames on the LEDs of `circuit`
+    pub fn new(circuit: &'static Circuit) -> Self {
+        Self {
+            race: Race::new(circuit),
+            start: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.race.reset();
+        self.start = None;
+    }
+
+    /// Move the cars of `frame`, frames come in time order
+    pub fn update(&mut self, frame: &UpdateFrame) {
+        let start = *self.start.get_or_insert(frame.timestamp);
+        let time_ms = frame.timestamp.saturating_sub(start) as u32;
+        self.race.update(time_ms, &frame.race_frame());
+    }
+
+    /// The cars in running order
+    pub fn rows(&self) -> Vec<Row> {
+        self.race
+            .standings()
+            .iter()
+            .map(|standing| {
+                let driver_number = standing.driver_number as u32;
+                let driver = DRIVERS.iter().find(|d| d.number == driver_number);
+                Row {
+                    position: standing.position,
+                    driver_number,
+                    code: driver.map_or(format!("#{}", driver_number), |d| d.code.to_string()),
+                    color: driver.map_or((128, 128, 128), |d| d.color),
+                    gap: format_gap(standing.gap),
+                    laps: standing.laps,
+                    led_number: standing.led_number as u32,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The gap as timing screens show it, empty while it isn't known
+pub fn format_gap(gap: Gap) -> String {
+    match gap {
+        Gap::Leader => "Leader".to_string(),
+        Gap::Time(ms) => format!("+{}.{:03}", ms / 1000, ms % 1000),
+        Gap::Laps(1) => "+1 lap".to_string(),
+        Gap::Laps(laps) => format!("+{} laps", laps),
+        Gap::Leds(_) => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use f1_logic::circuit::ZANDVOORT;
+
+    fn frame(timestamp: u64, cars: &[(u32, u32)]) -> UpdateFrame {
+        let mut frame = UpdateFrame::new(timestamp);
+        for &(driver_number, led_number) in cars {
+            frame.set_driver_led(driver_number, led_number, (255, 255, 255));
+        }
+        frame
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut leaderboard = Leaderboard::new(&ZANDVOORT);
+        let start = 1_693_141_136_200;
+        for step in 0..=40 {
+            // 44 is 1.5 s behind 1, 2 doesn't move
+            let timestamp = start + step * 500;
+            let mut cars = vec![(1, (48 + step as u32) % 96 + 1), (2, 43)];
+            if step >= 3 {
+                cars.push((44, (45 + step as u32) % 96 + 1));
+            }
+            leaderboard.update(&frame(timestamp, &cars));
+        }
+        let rows = leaderboard.rows();
+        let summary: Vec<(usize, &str, &str, u16)> = rows
+            .iter()
+            .map(|r| (r.position, &*r.code, &*r.gap, r.laps))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (1, "VER", "Leader", 0),
+                (2, "HAM", "+1.500", 0),
+                (3, "SAR", "", 0),
+            ]
+        );
+        assert_eq!(rows[0].led_number, 89);
+        assert_eq!(rows[1].color, (0, 210, 190));
+
+        leaderboard.reset();
+        assert!(leaderboard.rows().is_empty());
+    }
+
+    #[test]
+    fn test_format_gap() {
+        assert_eq!(format_gap(Gap::Time(61_042)), "+61.042");
+        assert_eq!(format_gap(Gap::Laps(1)), "+1 lap");
+        assert_eq!(format_gap(Gap::Laps(3)), "+3 laps");
+    }
+}
@@ -0,0 +1,424 @@
+//! Order of the LEDs in the SPI daisy chain.
+//!
+//! The firmware drives the HD108 LEDs as one chain: the data and clock
+//! outputs (`DOUT`, `DCKO`) of every LED feed the inputs (`DIN`, `DCKI`)
+//! of the next one. LED number N is expected to be the Nth LED of the
+//! chain, so footprint `UN`. The chain is traced from the nets of a
+//! `.kicad_pcb` file or of a netlist exported from the schematic with
+//! `kicad-cli sch export netlist`.
+
+use crate::kicad::{self, Sexp, LED_FOOTPRINT};
+use f1_logic::led_layout::LedPosition;
+use std::collections::{HashMap, HashSet};
+
+/// HD108 pin functions and pad numbers
+const DIN: (&str, &str) = ("DIN", "6");
+const DOUT: (&str, &str) = ("DOUT", "3");
+const DCKI: (&str, &str) = ("DCKI", "5");
+const DCKO: (&str, &str) = ("DCKO", "2");
+
+/// The nets on the chain pins of an LED
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LedPins {
+    pub reference: String,
+    pub din: Option<String>,
+    pub dout: Option<String>,
+    pub dcki: Option<String>,
+    pub dcko: Option<String>,
+}
+
+impl LedPins {
+    fn new(reference: &str) -> LedPins {
+        LedPins {
+            reference: reference.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Connect the pin with `function` or pad number `pad` to `net`
+    fn connect(&mut self, function: Option<&str>, pad: Option<&str>, net: &str) {
+        let is = |(name, number): (&str, &str)| match function {
+            Some(function) => function == name,
+            None => pad == Some(number),
+        };
+        let pin = if is(DIN) {
+            &mut self.din
+        } else if is(DOUT) {
+            &mut self.dout
+        } else if is(DCKI) {
+            &mut self.dcki
+        } else if is(DCKO) {
+            &mut self.dcko
+        } else {
+            return;
+        };
+        *pin = Some(net.to_string());
+    }
+
+    /// Number N of footprint `UN`
+    fn led_number(&self) -> Option<u16> {
+        self.reference.strip_prefix('U')?.parse().ok()
+    }
+}
+
+/// The LEDs of a PCB with the nets on their pads
+pub fn pcb_pins(pcb: &Sexp) -> Vec<LedPins> {
+    let footprints = pcb.children("footprint").chain(pcb.children("module"));
+    footprints
+        .filter(|fp| fp.atom(1).is_some_and(|id| id.contains(LED_FOOTPRINT)))
+        .filter_map(|fp| {
+            let mut pins = LedPins::new(kicad::reference(fp)?);
+            for pad in fp.children("pad") {
+                // `(net <code> <name>)`, pads without a net are unconnected
+                let Some(net) = pad.child("net").and_then(|net| net.atom(2)) else {
+                    continue;
+                };
+                let function = pad.child("pinfunction").and_then(|f| f.atom(1));
+                pins.connect(function, pad.atom(1), net);
+            }
+            Some(pins)
+        })
+        .collect()
+}
+
+/// The LEDs of a KiCad netlist with the nets on their pins
+pub fn netlist_pins(netlist: &Sexp) -> Vec<LedPins> {
+    let components = netlist.child("components").into_iter();
+    let mut leds: Vec<LedPins> = components
+        .flat_map(|components| components.children("comp"))
+        .filter(|comp| {
+            let footprint = comp.child("footprint").and_then(|f| f.atom(1));
+            footprint.is_some_and(|f| f.contains(LED_FOOTPRINT))
+        })
+        .filter_map(|comp| Some(LedPins::new(comp.child("ref")?.atom(1)?)))
+        .collect();
+    let nets = netlist.child("nets").into_iter();
+    for net in nets.flat_map(|nets| nets.children("net")) {
+        let Some(name) = net.child("name").and_then(|n| n.atom(1)) else {
+            continue;
+        };
+        for node in net.children("node") {
+            let reference = node.child("ref").and_then(|r| r.atom(1));
+            let Some(pins) = leds.iter_mut().find(|l| Some(&*l.reference) == reference) else {
+                continue;
+            };
+            let function = node.child("pinfunction").and_then(|f| f.atom(1));
+            let pin = node.child("pin").and_then(|p| p.atom(1));
+            pins.connect(function, pin, name);
+        }
+    }
+    leds
+}
+
+/// The pins of a `.kicad_pcb` file or a netlist
+pub fn pins(file: &Sexp) -> Result<Vec<LedPins>, String> {
+    match file.atom(0) {
+        Some("kicad_pcb") => Ok(pcb_pins(file)),
+        Some("export") => Ok(netlist_pins(file)),
+        _ => Err("Neither a KiCad PCB nor a KiCad netlist".to_string()),
+    }
+}
+
+/// LED numbers in chain order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chain {
+    pub leds: Vec<u16>,
+}
+
+impl Chain {
+    /// Follow the data and clock nets from the LED the controller drives
+    pub fn trace(pins: &[LedPins]) -> Result<Chain, String> {
+        if pins.is_empty() {
+            return Err(format!("No {} LEDs", LED_FOOTPRINT));
+        }
+        let data = follow(pins, "Data", |p| &p.din, |p| &p.dout)?;
+        let clock = follow(pins, "Clock", |p| &p.dcki, |p| &p.dcko)?;
+        if let Some(index) = data.iter().zip(&clock).position(|(d, c)| d != c) {
+            return Err(match index {
+                0 => format!(
+                    "Data chain starts at {}, clock chain at {}",
+                    pins[data[0]].reference, pins[clock[0]].reference
+                ),
+                _ => format!(
+                    "{} passes data to {} but the clock to {}",
+                    pins[data[index - 1]].reference,
+                    pins[data[index]].reference,
+                    pins[clock[index]].reference
+                ),
+            });
+        }
+        let leds = data
+            .iter()
+            .map(|&index| {
+                let pins = &pins[index];
+                pins.led_number()
+                    .ok_or(format!("{} footprint {}", LED_FOOTPRINT, pins.reference))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Chain { leds })
+    }
+
+    /// `leds` numbered by their position in the chain
+    pub fn number(&self, leds: &[LedPosition]) -> Result<Vec<LedPosition>, String> {
+        if leds.len() != self.leds.len() {
+            return Err(format!(
+                "{} LEDs but {} in the chain",
+                leds.len(),
+                self.leds.len()
+            ));
+        }
+        (1..)
+            .zip(&self.leds)
+            .map(|(position, &led)| {
+                let led = leds
+                    .iter()
+                    .find(|l| l.led_number == led)
+                    .ok_or(format!("U{} is in the chain but has no position", led))?;
+                Ok(LedPosition {
+                    led_number: position,
+                    ..*led
+                })
+            })
+            .collect()
+    }
+
+    /// Chain positions, from 1, and the LED there where LED N is not the
+    /// Nth of the chain
+    pub fn mismatches(&self) -> Vec<(usize, u16)> {
+        (1..)
+            .zip(self.leds.iter().copied())
+            .filter(|&(position, led)| led as usize != position)
+            .collect()
+    }
+}
+
+/// Indices into `pins` along the chain of one signal
+fn follow(
+    pins: &[LedPins],
+    signal: &str,
+    input: impl Fn(&LedPins) -> &Option<String>,
+    output: impl Fn(&LedPins) -> &Option<String>,
+) -> Result<Vec<usize>, String> {
+    let outputs: HashSet<&str> = pins.iter().filter_map(|p| output(p).as_deref()).collect();
+    let mut inputs: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, p) in pins.iter().enumerate() {
+        if let Some(net) = input(p) {
+            inputs.entry(net).or_default().push(index);
+        }
+    }
+    let references = |indices: &[usize]| {
+        let names: Vec<&str> = indices.iter().map(|&i| &*pins[i].reference).collect();
+        names.join(", ")
+    };
+
+    // Fed by the controller rather than by another LED
+    let starts: Vec<usize> = (0..pins.len())
+        .filter(|&i| {
+            input(&pins[i])
+                .as_deref()
+                .map_or(true, |net| !outputs.contains(net))
+        })
+        .collect();
+    let &[start] = &starts[..] else {
+        return Err(match starts.len() {
+            0 => format!("{} chain is a loop", signal),
+            _ => format!("{} chain starts at {}", signal, references(&starts)),
+        });
+    };
+
+    let mut chain = vec![start];
+    let mut visited = vec![false; pins.len()];
+    visited[start] = true;
+    let mut current = start;
+    while let Some(net) = output(&pins[current]) {
+        let next = inputs.get(net.as_str()).map_or(&[][..], Vec::as_slice);
+        current = match next {
+            [] => break,
+            &[next] if !visited[next] => next,
+            &[next] => {
+                return Err(format!(
+                    "{} chain loops from {} back to {}",
+                    signal, pins[current].reference, pins[next].reference
+                ))
+            }
+            _ => {
+                return Err(format!(
+                    "{} chain forks after {} to {}",
+                    signal,
+                    pins[current].reference,
+                    references(next)
+                ))
+            }
+        };
+        visited[current] = true;
+        chain.push(current);
+    }
+    if chain.len() < pins.len() {
+        let missing: Vec<usize> = (0..pins.len()).filter(|&i| !visited[i]).collect();
+        return Err(format!(
+            "{} chain ends at {}, {} not in it",
+            signal,
+            pins[current].reference,
+            references(&missing)
+        ));
+    }
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(path: &str) -> Sexp {
+        let path = format!("{}/../../kicad/{}", env!("CARGO_MANIFEST_DIR"), path);
+        Sexp::parse(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// LEDs chained in the order of `leds`, the first one fed by the
+    /// controller
+    fn chained(leds: &[u16]) -> Vec<LedPins> {
+        let net = |pin: &str, led: Option<u16>| match led {
+            Some(led) => Some(format!("Net-(U{}-{})", led, pin)),
+            None if pin == "DOUT" => Some("/FSPID".to_string()),
+            None => Some("/FSPICLK".to_string()),
+        };
+        let mut previous = None;
+        leds.iter()
+            .map(|&led| {
+                let pins = LedPins {
+                    reference: format!("U{}", led),
+                    din: net("DOUT", previous),
+                    dout: net("DOUT", Some(led)),
+                    dcki: net("DCKO", previous),
+                    dcko: net("DCKO", Some(led)),
+                };
+                previous = Some(led);
+                pins
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_trace() {
+        let mut pins = chained(&[1, 2, 3, 4]);
+        pins.reverse();
+        let chain = Chain::trace(&pins).unwrap();
+        assert_eq!(chain.leds, [1, 2, 3, 4]);
+        assert!(chain.mismatches().is_empty());
+
+        let chain = Chain::trace(&chained(&[1, 3, 2, 4])).unwrap();
+        assert_eq!(chain.mismatches(), [(2, 3), (3, 2)]);
+    }
+
+    #[test]
+    fn test_broken_chains() {
+        let mut pins = chained(&[1, 2, 3, 4]);
+        pins[2].din = None;
+        assert_eq!(
+            Chain::trace(&pins).unwrap_err(),
+            "Data chain starts at U1, U3"
+        );
+
+        let mut pins = chained(&[1, 2, 3, 4]);
+        let u2 = pins[1].clone();
+        pins[3].dcki.clone_from(&u2.dcki);
+        assert_eq!(
+            Chain::trace(&pins).unwrap_err(),
+            "Clock chain forks after U1 to U2, U4"
+        );
+
+        let mut pins = chained(&[1, 2, 3]);
+        let u3 = pins[2].clone();
+        pins[0].din.clone_from(&u3.dout);
+        assert_eq!(Chain::trace(&pins).unwrap_err(), "Data chain is a loop");
+
+        let mut pins = chained(&[1, 2, 3, 4]);
+        (pins[2].dcki, pins[3].dcki) = (pins[3].dcki.clone(), pins[2].dcki.clone());
+        (pins[2].dcko, pins[3].dcko) = (pins[3].dcko.clone(), pins[2].dcko.clone());
+        assert_eq!(
+            Chain::trace(&pins).unwrap_err(),
+            "U2 passes data to U3 but the clock to U4"
+        );
+
+        let mut pins = chained(&[1, 2, 3, 4]);
+        let u4 = pins[3].clone();
+        pins[2].din.clone_from(&u4.dout);
+        assert_eq!(
+            Chain::trace(&pins).unwrap_err(),
+            "Data chain ends at U2, U3, U4 not in it"
+        );
+    }
+
+    #[test]
+    fn test_number() {
+        let leds: Vec<LedPosition> = (1..=3)
+            .map(|led_number| LedPosition {
+                led_number,
+                x_mm: led_number as f32,
+                y_mm: 0.0,
+                rotation_deg: 0.0,
+            })
+            .collect();
+        let chain = Chain::trace(&chained(&[1, 3, 2])).unwrap();
+        let numbered = chain.number(&leds).unwrap();
+        let numbers: Vec<(u16, f32)> = numbered.iter().map(|l| (l.led_number, l.x_mm)).collect();
+        assert_eq!(numbers, [(1, 1.0), (2, 3.0), (3, 2.0)]);
+
+        let chain = Chain::trace(&chained(&[1, 2])).unwrap();
+        assert_eq!(
+            chain.number(&leds).unwrap_err(),
+            "3 LEDs but 2 in the chain"
+        );
+        let chain = Chain::trace(&chained(&[1, 2, 4])).unwrap();
+        assert_eq!(
+            chain.number(&leds).unwrap_err(),
+            "U4 is in the chain but has no position"
+        );
+    }
+
+    #[test]
+    fn test_netlist() {
+        let netlist = Sexp::parse(
+            r#"(export (version "E")
+                (components
+                  (comp (ref "U1") (value "HD108") (footprint "lib:HD108-2020"))
+                  (comp (ref "U2") (value "HD108") (footprint "lib:HD108-2020"))
+                  (comp (ref "R1") (value "10k") (footprint "lib:R_0402")))
+                (nets
+                  (net (code "1") (name "/FSPID")
+                    (node (ref "U2") (pin "6") (pinfunction "DIN") (pintype "input")))
+                  (net (code "2") (name "/FSPICLK")
+                    (node (ref "U2") (pin "5") (pintype "input")))
+                  (net (code "3") (name "Net-(U2-DOUT)")
+                    (node (ref "U2") (pin "3") (pinfunction "DOUT"))
+                    (node (ref "U1") (pin "6") (pinfunction "DIN")))
+                  (net (code "4") (name "Net-(U2-DCKO)")
+                    (node (ref "R1") (pin "1"))
+                    (node (ref "U2") (pin "2") (pinfunction "DCKO"))
+                    (node (ref "U1") (pin "5") (pinfunction "DCKI")))))"#,
+        )
+        .unwrap();
+        let pins = pins(&netlist).unwrap();
+        assert_eq!(pins.len(), 2);
+        assert_eq!(pins[1].dcki.as_deref(), Some("/FSPICLK"));
+        let chain = Chain::trace(&pins).unwrap();
+        assert_eq!(chain.leds, [2, 1]);
+        assert_eq!(chain.mismatches(), [(1, 2), (2, 1)]);
+    }
+
+    #[test]
+    fn test_committed_boards() {
+        for (path, count) in [
+            ("zandvoort_10x10/f1-led-circuit-10x10.kicad_pcb", 96),
+            ("zandvoort_20x20/f1-led-circuit_20x20.kicad_pcb", 216),
+        ] {
+            let pins = pins(&load(path)).unwrap();
+            let first = pins.iter().find(|p| p.reference == "U1").unwrap();
+            assert_eq!(first.din.as_deref(), Some("/FSPID"));
+            assert_eq!(first.dcki.as_deref(), Some("/FSPICLK"));
+            let chain = Chain::trace(&pins).unwrap();
+            assert_eq!(chain.leds.len(), count, "{}", path);
+            assert!(chain.mismatches().is_empty(), "{}", path);
+        }
+    }
+}
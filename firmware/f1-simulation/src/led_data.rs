@@ -9,6 +9,8 @@ pub struct LedCoordinate {
 pub struct UpdateFrame {
     pub timestamp: u64,
     pub led_states: Vec<(u32, (u8, u8, u8))>,
+    /// Driver number and LED of the cars in the frame
+    pub driver_leds: Vec<(u32, u32)>,
 }
 
 impl UpdateFrame {
@@ -16,12 +18,29 @@ impl UpdateFrame {
         Self {
             timestamp,
             led_states: Vec::new(),
+            driver_leds: Vec::new(),
         }
     }
 
     pub fn set_led_state(&mut self, led_number: u32, color: (u8, u8, u8)) {
         self.led_states.push((led_number, color));
     }
+
+    /// Light the LED of a car in its color
+    pub fn set_driver_led(&mut self, driver_number: u32, led_number: u32, color: (u8, u8, u8)) {
+        self.driver_leds.push((driver_number, led_number));
+        self.set_led_state(led_number, color);
+    }
+
+    /// The cars of the frame as the firmware gets them
+    pub fn race_frame(&self) -> f1_logic::data_frame::UpdateFrame {
+        let mut frame = f1_logic::data_frame::UpdateFrame::default();
+        for (data, &(driver_number, led_number)) in frame.frame.iter_mut().zip(&self.driver_leds) {
+            data.driver_number = driver_number as u8;
+            data.led_num = led_number as u8;
+        }
+        frame
+    }
 }
 
 pub const LED_DATA: &[LedCoordinate] = &[
@@ -0,0 +1,11 @@
+//! Shared code of the simulator and the race relay.
+
+pub mod driver_info;
+pub mod kicad;
+pub mod leaderboard;
+pub mod led_chain;
+pub mod led_data;
+pub mod openf1;
+pub mod relay;
+pub mod replay;
+pub mod track_file;
@@ -1,36 +1,52 @@
-mod driver_info;
-mod led_data;
-
 use chrono::{DateTime, Duration as ChronoDuration, Utc};
-use driver_info::DRIVERS;
+use f1_logic::animation::{Rgb, StartupAnimation, OFF};
+use f1_logic::circuit::ZANDVOORT;
+use f1_logic::data_frame::FRAME_INTERVAL_MS;
+use f1_simulation::driver_info::DRIVERS;
+use f1_simulation::leaderboard::Leaderboard;
+use f1_simulation::led_data::{LedCoordinate, UpdateFrame, LED_DATA};
+use f1_simulation::openf1::{self, OPENF1_URL};
+use f1_simulation::replay;
 use iced::alignment;
 use iced::executor;
 use iced::theme::{self, Theme};
 use iced::time;
-use iced::widget::{button, column, container, row, text};
+use iced::widget::{button, column, container, pick_list, row, scrollable, text};
 use iced::{
     mouse,
     widget::canvas::{self, Canvas, Frame, Path, Program},
     Alignment, Application, Color, Command, Element, Length, Point, Renderer, Settings, Size,
     Subscription,
 };
-use led_data::{LedCoordinate, UpdateFrame, LED_DATA};
 use reqwest::Client;
-use serde::Deserialize;
 use std::f32;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::time::sleep;
 
-#[derive(Debug, Deserialize)]
-struct LocationData {
-    x: f32,
-    y: f32,
-    date: String,
-    driver_number: u32,
+/// Without arguments the simulator fetches the race from OpenF1, with a
+/// race binary or grouped CSV it replays that offline:
+///
+/// cargo run -- ../f1-hardware/src/output.bin
+pub fn main() -> iced::Result {
+    let replay = std::env::args().nth(1).map(|path| {
+        let frames = replay::load(std::path::Path::new(&path)).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        });
+        println!("Replaying {} frames of {}", frames.len(), path);
+        Replay {
+            path,
+            frames: replay::simulator_frames(&frames),
+        }
+    });
+    RaceSimulation::run(Settings::with_flags(replay))
 }
 
-pub fn main() -> iced::Result {
-    RaceSimulation::run(Settings::default())
+/// A race file played instead of OpenF1 data
+struct Replay {
+    path: String,
+    frames: Vec<UpdateFrame>,
 }
 
 struct RaceSimulation {
@@ -48,12 +64,21 @@ struct RaceSimulation {
     next_data_fetch_start_time: DateTime<Utc>,
     next_data_fetch_end_time: DateTime<Utc>,
     application_start_time: Instant,
+    animation: StartupAnimation,
+    animation_frame: Vec<Rgb>,
+    leaderboard: Leaderboard,
+    selected_driver: Option<u32>,
+    replay: Option<Replay>,
+    /// Follows the drivers along the track from one fetched batch to the
+    /// next
+    led_mapper: Arc<Mutex<openf1::LedMapper>>,
 }
 
 enum SimulationState {
     IdleState,
     FetchingDataState,
     VisualizingState { last_tick: Instant },
+    PreviewState { started: Instant },
 }
 
 #[derive(Debug, Clone)]
@@ -64,15 +89,19 @@ enum SimulationMessage {
     ToggleLed,
     DriverDataFetched(Result<Vec<UpdateFrame>, String>),
     FetchNextDataBatch,
+    AnimationSelected(StartupAnimation),
+    TogglePreview,
+    PreviewTick(Instant),
+    DriverSelected(u32),
 }
 
 impl Application for RaceSimulation {
     type Message = SimulationMessage;
     type Theme = Theme;
     type Executor = executor::Default;
-    type Flags = ();
+    type Flags = Option<Replay>;
 
-    fn new(_flags: ()) -> (RaceSimulation, Command<SimulationMessage>) {
+    fn new(replay: Option<Replay>) -> (RaceSimulation, Command<SimulationMessage>) {
         let start_time = DateTime::parse_from_rfc3339("2023-08-27T12:58:56.200Z")
             .unwrap()
             .with_timezone(&Utc);
@@ -98,18 +127,45 @@ impl Application for RaceSimulation {
                 next_data_fetch_start_time,
                 next_data_fetch_end_time,
                 application_start_time: Instant::now(),
+                animation: StartupAnimation::Train,
+                animation_frame: vec![OFF; LED_DATA.len()],
+                // The circuit LED_DATA was made from
+                leaderboard: Leaderboard::new(&ZANDVOORT),
+                selected_driver: None,
+                replay,
+                led_mapper: Arc::default(),
             },
             Command::none(),
         )
     }
 
     fn title(&self) -> String {
-        String::from("F1-LED-CIRCUIT")
+        match &self.replay {
+            Some(replay) => format!("F1-LED-CIRCUIT - {}", replay.path),
+            None => String::from("F1-LED-CIRCUIT"),
+        }
     }
 
     fn update(&mut self, message: SimulationMessage) -> Command<SimulationMessage> {
         match message {
             SimulationMessage::ToggleSimulation => match self.state {
+                SimulationState::IdleState if self.replay.is_some() => {
+                    // Continue where the replay stopped, or from the start
+                    if self.frames_to_visualize.is_empty() {
+                        let frames = self.replay.as_ref().map(|r| r.frames.clone());
+                        self.frames_to_visualize = frames.unwrap_or_default();
+                        self.current_visualization_frame_index = 0;
+                        self.leaderboard.reset();
+                        if let Some(frame) = self.frames_to_visualize.first() {
+                            self.leaderboard.update(frame);
+                        }
+                    }
+                    if !self.frames_to_visualize.is_empty() {
+                        self.state = SimulationState::VisualizingState {
+                            last_tick: Instant::now(),
+                        };
+                    }
+                }
                 SimulationState::IdleState => {
                     println!(
                         "[{}] Data fetching started",
@@ -119,12 +175,14 @@ impl Application for RaceSimulation {
                     self.processed_update_frames.clear();
                     self.fetched_update_frames.clear();
                     self.current_visualization_frame_index = 0;
+                    self.led_mapper = Arc::default();
                     return Command::perform(
                         fetch_and_process_driver_data(
                             self.http_client.clone(),
                             self.driver_numbers.clone(),
                             self.start_time,
                             self.end_time,
+                            self.led_mapper.clone(),
                         ),
                         SimulationMessage::DriverDataFetched,
                     );
@@ -133,7 +191,7 @@ impl Application for RaceSimulation {
                     self.state = SimulationState::IdleState;
                     self.is_led_on = false;
                 }
-                SimulationState::VisualizingState { .. } => {
+                SimulationState::VisualizingState { .. } | SimulationState::PreviewState { .. } => {
                     self.state = SimulationState::IdleState;
                     self.is_led_on = false;
                 }
@@ -143,7 +201,7 @@ impl Application for RaceSimulation {
                     self.elapsed_time += now - *last_tick;
                     *last_tick = now;
 
-                    if self.current_visualization_frame_index < self.frames_to_visualize.len() {
+                    if self.current_visualization_frame_index + 1 < self.frames_to_visualize.len() {
                         self.current_visualization_frame_index += 1;
                         println!(
                             "[{}] Visualizing frame index {}",
@@ -152,6 +210,13 @@ impl Application for RaceSimulation {
                         );
                     } else {
                         self.current_visualization_frame_index = 0; // Restart visualization if we reach the end
+                        self.leaderboard.reset();
+                    }
+                    if let Some(frame) = self
+                        .frames_to_visualize
+                        .get(self.current_visualization_frame_index)
+                    {
+                        self.leaderboard.update(frame);
                     }
                 }
             }
@@ -162,6 +227,8 @@ impl Application for RaceSimulation {
                 self.processed_update_frames.clear();
                 self.frames_to_visualize.clear();
                 self.fetched_update_frames.clear();
+                self.leaderboard.reset();
+                self.selected_driver = None;
                 println!(
                     "[{}] Resetting all frames",
                     self.application_start_time.elapsed().as_secs()
@@ -184,6 +251,13 @@ impl Application for RaceSimulation {
                     new_frames.len()
                 );
 
+                // The first frame shows before the first tick
+                if self.frames_to_visualize.is_empty() {
+                    if let Some(frame) = new_frames.first() {
+                        self.leaderboard.update(frame);
+                    }
+                }
+
                 // Append new data to frames_to_visualize without clearing
                 self.fetched_update_frames.extend(new_frames);
                 self.frames_to_visualize
@@ -233,10 +307,39 @@ impl Application for RaceSimulation {
                         self.driver_numbers.clone(),
                         new_start_time,
                         new_end_time,
+                        self.led_mapper.clone(),
                     ),
                     SimulationMessage::DriverDataFetched,
                 );
             }
+            SimulationMessage::AnimationSelected(animation) => self.animation = animation,
+            SimulationMessage::DriverSelected(driver_number) => {
+                self.selected_driver = match self.selected_driver {
+                    Some(selected) if selected == driver_number => None,
+                    _ => Some(driver_number),
+                };
+            }
+            SimulationMessage::TogglePreview => match self.state {
+                SimulationState::IdleState => {
+                    self.state = SimulationState::PreviewState {
+                        started: Instant::now(),
+                    };
+                    return self.update(SimulationMessage::PreviewTick(Instant::now()));
+                }
+                SimulationState::PreviewState { .. } => self.state = SimulationState::IdleState,
+                _ => {}
+            },
+            SimulationMessage::PreviewTick(now) => {
+                if let SimulationState::PreviewState { started } = self.state {
+                    let t_ms = (now - started).as_millis() as u32;
+                    if !self
+                        .animation
+                        .render(t_ms, &team_colors(), &mut self.animation_frame)
+                    {
+                        self.state = SimulationState::IdleState;
+                    }
+                }
+            }
         }
 
         Command::none()
@@ -246,12 +349,23 @@ impl Application for RaceSimulation {
         let tick = match self.state {
             SimulationState::IdleState | SimulationState::FetchingDataState => Subscription::none(),
             SimulationState::VisualizingState { .. } => {
-                time::every(Duration::from_millis(100)).map(SimulationMessage::SimulationTick)
+                // Replays play at the pace of the board
+                let interval_ms = match self.replay {
+                    Some(_) => FRAME_INTERVAL_MS,
+                    None => 100,
+                };
+                time::every(Duration::from_millis(interval_ms))
+                    .map(SimulationMessage::SimulationTick)
+            }
+            SimulationState::PreviewState { .. } => {
+                time::every(Duration::from_millis(20)).map(SimulationMessage::PreviewTick)
             }
         };
 
         let blink = match self.state {
-            SimulationState::IdleState | SimulationState::FetchingDataState => Subscription::none(),
+            SimulationState::IdleState
+            | SimulationState::FetchingDataState
+            | SimulationState::PreviewState { .. } => Subscription::none(),
             SimulationState::VisualizingState { .. } => {
                 time::every(Duration::from_millis(100)).map(|_| SimulationMessage::ToggleLed)
             }
@@ -297,7 +411,9 @@ impl Application for RaceSimulation {
 
         let toggle_button = {
             let label = match self.state {
-                SimulationState::IdleState | SimulationState::FetchingDataState => "Start",
+                SimulationState::IdleState
+                | SimulationState::FetchingDataState
+                | SimulationState::PreviewState { .. } => "Start",
                 SimulationState::VisualizingState { .. } => "Stop",
             };
 
@@ -308,24 +424,94 @@ impl Application for RaceSimulation {
             .style(theme::Button::Destructive)
             .on_press(SimulationMessage::ResetSimulation);
 
+        let animation_list = pick_list(
+            &StartupAnimation::ALL[..],
+            Some(self.animation),
+            SimulationMessage::AnimationSelected,
+        );
+
+        let preview_button = {
+            let label = match self.state {
+                SimulationState::PreviewState { .. } => "Stop",
+                _ => "Preview",
+            };
+
+            button(label).on_press(SimulationMessage::TogglePreview)
+        };
+
         let content = row![
             container(duration).padding(10),
             container(toggle_button).padding(10),
-            container(reset_button).padding(10)
+            container(reset_button).padding(10),
+            container(animation_list).padding(10),
+            container(preview_button).padding(10)
         ]
         .align_items(Alignment::Center)
         .spacing(20);
 
+        let rows = self.leaderboard.rows();
+        let selected_led = rows
+            .iter()
+            .find(|r| Some(r.driver_number) == self.selected_driver)
+            .map(|r| r.led_number);
+        let header = row![
+            text("Pos").width(36),
+            text("Driver").width(68),
+            text("Gap").width(80),
+            text("Laps"),
+        ]
+        .spacing(8);
+        let leaderboard = rows.into_iter().fold(
+            column![text("Leaderboard").size(24), header].spacing(4),
+            |panel, entry| {
+                let (r, g, b) = entry.color;
+                let swatch = container(text(""))
+                    .width(6)
+                    .height(20)
+                    .style(container::Appearance {
+                        background: Some(Color::from_rgb8(r, g, b).into()),
+                        ..Default::default()
+                    });
+                let line = row![
+                    text(entry.position).width(28),
+                    swatch,
+                    text(&entry.code).width(46),
+                    text(&entry.gap).width(80),
+                    text(entry.laps),
+                ]
+                .spacing(8)
+                .align_items(Alignment::Center);
+                let style = if self.selected_driver == Some(entry.driver_number) {
+                    theme::Button::Primary
+                } else {
+                    theme::Button::Text
+                };
+                panel.push(
+                    iced::widget::button(line)
+                        .style(style)
+                        .padding(4)
+                        .width(Length::Fill)
+                        .on_press(SimulationMessage::DriverSelected(entry.driver_number)),
+                )
+            },
+        );
+        let leaderboard = container(scrollable(leaderboard)).width(260).padding(10);
+
         let canvas = Canvas::new(LedCircuitGraph {
             led_coordinates: LED_DATA.to_vec(),
             is_led_on: self.is_led_on,
             visualization_frames: self.frames_to_visualize.clone(),
             current_visualization_frame_index: self.current_visualization_frame_index,
+            animation_frame: match self.state {
+                SimulationState::PreviewState { .. } => Some(self.animation_frame.clone()),
+                _ => None,
+            },
+            selected_led,
         })
         .width(Length::Fill)
         .height(Length::Fill);
 
-        container(column![canvas, content])
+        container(row![column![canvas, content], leaderboard])
             .width(Length::Fill)
             .height(Length::Fill)
             .align_x(alignment::Horizontal::Center)
@@ -344,6 +530,9 @@ struct LedCircuitGraph {
     is_led_on: bool,
     visualization_frames: Vec<UpdateFrame>,
     current_visualization_frame_index: usize,
+    animation_frame: Option<Vec<Rgb>>,
+    /// LED of the driver selected on the leaderboard
+    selected_led: Option<u32>,
 }
 
 impl<Message> Program<Message> for LedCircuitGraph {
@@ -380,7 +569,16 @@ impl<Message> Program<Message> for LedCircuitGraph {
         let scale_y = (bounds.height - 2.0 * padding) / height;
 
         // Draw the LED rectangles
-        if !self.visualization_frames.is_empty() {
+        if let Some(animation_frame) = &self.animation_frame {
+            for led in &self.led_coordinates {
+                let x = (led.x_led - min_x) * scale_x + padding;
+                let y = bounds.height - (led.y_led - min_y) * scale_y - padding;
+
+                let col = animation_frame[led.led_number as usize - 1];
+                let point = Path::rectangle(Point::new(x, y), Size::new(10.0, 10.0));
+                frame.fill(&point, Color::from_rgb8(col.0, col.1, col.2));
+            }
+        } else if !self.visualization_frames.is_empty() {
             let frame_data = &self.visualization_frames[self.current_visualization_frame_index];
 
             for led in &self.led_coordinates {
@@ -397,54 +595,74 @@ impl<Message> Program<Message> for LedCircuitGraph {
                 let point = Path::rectangle(Point::new(x, y), Size::new(10.0, 10.0));
                 frame.fill(&point, color);
             }
+
+            let selected = self.selected_led.and_then(|number| {
+                self.led_coordinates
+                    .iter()
+                    .find(|led| led.led_number == number)
+            });
+            if let Some(led) = selected {
+                let x = (led.x_led - min_x) * scale_x + padding;
+                let y = bounds.height - (led.y_led - min_y) * scale_y - padding;
+
+                let ring = Path::circle(Point::new(x + 5.0, y + 5.0), 12.0);
+                frame.stroke(&ring, canvas::Stroke::default().with_width(3.0));
+            }
         }
 
         vec![frame.into_geometry()]
     }
 }
 
+/// One color per team, in driver order, for the team parade animation
+fn team_colors() -> Vec<Rgb> {
+    let mut colors = Vec::new();
+    for (i, driver) in DRIVERS.iter().enumerate() {
+        if DRIVERS[..i].iter().all(|d| d.team != driver.team) {
+            colors.push(driver.color);
+        }
+    }
+    colors
+}
+
 async fn fetch_and_process_driver_data(
     client: Client,
     driver_numbers: Vec<u32>,
     start_time: DateTime<Utc>,
     end_time: DateTime<Utc>,
+    mapper: Arc<Mutex<openf1::LedMapper>>,
 ) -> Result<Vec<UpdateFrame>, String> {
-    let session_key = "9149";
+    let session_key = 9149;
 
-    let mut all_data: Vec<LocationData> = Vec::new();
+    let mut all_data: Vec<openf1::LocationData> = Vec::new();
 
     for driver_number in driver_numbers {
         let mut fetched_entries = 0;
 
         while fetched_entries < 20 {
-            let url = format!(
-                "https://api.openf1.org/v1/location?session_key={}&driver_number={}&date>{}&date<{}",
-                session_key, driver_number, start_time.to_rfc3339(), end_time.to_rfc3339(),
+            let valid_data = match openf1::fetch_locations(
+                &client,
+                OPENF1_URL,
+                session_key,
+                driver_number,
+                Some((start_time, end_time)),
+            )
+            .await
+            {
+                Ok(data) => data,
+                Err(err) => {
+                    eprintln!("Failed to fetch data for driver {}: {}", driver_number, err);
+                    break;
+                }
+            };
+            fetched_entries += valid_data.len();
+            all_data.extend(valid_data);
+            println!(
+                "[{}] Fetched {} entries for driver {}",
+                Utc::now(),
+                fetched_entries,
+                driver_number
             );
-            //eprintln!("url: {}", url);
-            let resp = client.get(&url).send().await.map_err(|e| e.to_string())?;
-            if resp.status().is_success() {
-                let data: Vec<LocationData> = resp.json().await.map_err(|e| e.to_string())?;
-                let valid_data: Vec<LocationData> = data
-                    .into_iter()
-                    .filter(|d| d.x != 0.0 && d.y != 0.0)
-                    .collect();
-                fetched_entries += valid_data.len();
-                all_data.extend(valid_data);
-                println!(
-                    "[{}] Fetched {} entries for driver {}",
-                    Utc::now(),
-                    fetched_entries,
-                    driver_number
-                );
-            } else {
-                eprintln!(
-                    "Failed to fetch data for driver {}: HTTP {}",
-                    driver_number,
-                    resp.status()
-                );
-                break;
-            }
         }
     }
 
@@ -453,6 +671,7 @@ async fn fetch_and_process_driver_data(
 
     let mut update_frames = Vec::<UpdateFrame>::new();
     let mut current_frame: Option<UpdateFrame> = None;
+    let mut mapper = mapper.lock().unwrap();
 
     for data in all_data {
         let timestamp = DateTime::parse_from_rfc3339(&data.date)
@@ -472,25 +691,19 @@ async fn fetch_and_process_driver_data(
 
         let color = driver.color;
 
-        let nearest_led = LED_DATA
-            .iter()
-            .min_by(|a, b| {
-                let dist_a = ((a.x_led - x).powi(2) + (a.y_led - y).powi(2)).sqrt();
-                let dist_b = ((b.x_led - x).powi(2) + (b.y_led - y).powi(2)).sqrt();
-                dist_a.partial_cmp(&dist_b).unwrap()
-            })
-            .unwrap();
+        let nearest_led = mapper.led(driver_number, x, y);
 
         if let Some(frame) = &mut current_frame {
             if frame.timestamp == timestamp {
-                frame.set_led_state(nearest_led.led_number, color);
+                frame.set_driver_led(driver_number, nearest_led.led_number, color);
             } else {
                 update_frames.push(frame.clone());
                 current_frame = Some(UpdateFrame::new(timestamp));
-                current_frame
-                    .as_mut()
-                    .unwrap()
-                    .set_led_state(nearest_led.led_number, color);
+                current_frame.as_mut().unwrap().set_driver_led(
+                    driver_number,
+                    nearest_led.led_number,
+                    color,
+                );
                 println!(
                     "[{}] Created new frame for timestamp {}",
                     Utc::now(),
@@ -499,10 +712,11 @@ async fn fetch_and_process_driver_data(
             }
         } else {
             current_frame = Some(UpdateFrame::new(timestamp));
-            current_frame
-                .as_mut()
-                .unwrap()
-                .set_led_state(nearest_led.led_number, color);
+            current_frame.as_mut().unwrap().set_driver_led(
+                driver_number,
+                nearest_led.led_number,
+                color,
+            );
             println!(
                 "[{}] Created initial frame for timestamp {}",
                 Utc::now(),
@@ -0,0 +1,242 @@
+//! OpenF1 location data and its conversion for the LED circuit.
+//!
+//! Used by the simulation and by the relay, which converts whole sessions
+//! into the board's race file format.
+
+use crate::led_data::{LedCoordinate, LED_DATA};
+use chrono::{DateTime, Utc};
+use f1_logic::data_frame::{DriverData, UpdateFrame, NUM_DRIVERS};
+use f1_logic::track::{Centerline, Point, TrackFollower};
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+pub const OPENF1_URL: &str = "https://api.openf1.org/v1";
+
+/// Longest distance along the track a car covers between two positions,
+/// a few samples at top speed. The LEDs are about 440 apart.
+const MAX_STEP: f32 = 600.0;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocationData {
+    pub x: f32,
+    pub y: f32,
+    pub date: String,
+    pub driver_number: u32,
+}
+
+impl LocationData {
+    /// OpenF1 reports 0/0 while a car has no position
+    pub fn is_valid(&self) -> bool {
+        self.x != 0.0 && self.y != 0.0
+    }
+
+    pub fn timestamp_ms(&self) -> Result<i64, String> {
+        Ok(DateTime::parse_from_rfc3339(&self.date)
+            .map_err(|e| e.to_string())?
+            .timestamp_millis())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SessionDriver {
+    driver_number: u32,
+}
+
+/// Numbers of the drivers taking part in a session
+pub async fn fetch_drivers(
+    client: &Client,
+    base_url: &str,
+    session_key: u32,
+) -> Result<Vec<u32>, String> {
+    let url = format!("{}/drivers?session_key={}", base_url, session_key);
+    let drivers: Vec<SessionDriver> = get_json(client, &url).await?;
+    let mut numbers: Vec<u32> = drivers.iter().map(|d| d.driver_number).collect();
+    numbers.sort_unstable();
+    numbers.dedup();
+    Ok(numbers)
+}
+
+/// Valid location samples of a driver, the whole session or the samples
+/// between `window`
+pub async fn fetch_locations(
+    client: &Client,
+    base_url: &str,
+    session_key: u32,
+    driver_number: u32,
+    window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+) -> Result<Vec<LocationData>, String> {
+    let mut url = format!(
+        "{}/location?session_key={}&driver_number={}",
+        base_url, session_key, driver_number
+    );
+    if let Some((start, end)) = window {
+        url += &format!("&date>{}&date<{}", start.to_rfc3339(), end.to_rfc3339());
+    }
+    let data: Vec<LocationData> = get_json(client, &url).await?;
+    Ok(data.into_iter().filter(LocationData::is_valid).collect())
+}
+
+async fn get_json<T: for<'de> Deserialize<'de>>(client: &Client, url: &str) -> Result<T, String> {
+    let resp = client.get(url).send().await.map_err(|e| e.to_string())?;
+    if !resp.status().is_success() {
+        return Err(format!("HTTP {} for {}", resp.status(), url));
+    }
+    resp.json().await.map_err(|e| e.to_string())
+}
+
+/// Maps positions to LEDs by following every driver along the track, so a
+/// car stays on its part of the circuit where the track doubles back. The
+/// positions of a driver have to come in time order.
+pub struct LedMapper {
+    centerline: Centerline<{ LED_DATA.len() }>,
+    followers: HashMap<u32, TrackFollower>,
+}
+
+impl LedMapper {
+    pub fn new() -> Self {
+        let points = LED_DATA.iter().map(|led| Point::new(led.x_led, led.y_led));
+        Self {
+            centerline: Centerline::new(points).expect("LEDs along the track"),
+            followers: HashMap::new(),
+        }
+    }
+
+    /// The LED covering the position of a driver
+    pub fn led(&mut self, driver_number: u32, x: f32, y: f32) -> &'static LedCoordinate {
+        let follower = self
+            .followers
+            .entry(driver_number)
+            .or_insert_with(|| TrackFollower::new(MAX_STEP));
+        let distance = follower.update(&self.centerline, Point::new(x, y));
+        &LED_DATA[self.centerline.index_at(distance)]
+    }
+}
+
+impl Default for LedMapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Convert the location samples of a session into race frames, one every
+/// `interval_ms`. Each frame holds the last known LED of every driver, the
+/// first frame is at the time every driver has a position.
+pub fn race_frames(
+    locations: &[LocationData],
+    interval_ms: u64,
+) -> Result<Vec<UpdateFrame>, String> {
+    let mut valid = Vec::with_capacity(locations.len());
+    for location in locations.iter().filter(|l| l.is_valid()) {
+        let driver_number = u8::try_from(location.driver_number)
+            .ok()
+            .filter(|&n| n != 0)
+            .ok_or_else(|| format!("Invalid driver number {}", location.driver_number))?;
+        valid.push((location.timestamp_ms()?, driver_number, location));
+    }
+    // The mapper follows every driver in time order
+    valid.sort_by_key(|&(timestamp, driver_number, _)| (timestamp, driver_number));
+    let mut mapper = LedMapper::new();
+    let samples: Vec<(i64, u8, u8)> = valid
+        .into_iter()
+        .map(|(timestamp, driver_number, location)| {
+            let led = mapper.led(location.driver_number, location.x, location.y);
+            (timestamp, driver_number, led.led_number as u8)
+        })
+        .collect();
+
+    // The first position of every driver
+    let mut first_seen = BTreeMap::new();
+    for &(timestamp, driver_number, _) in &samples {
+        first_seen.entry(driver_number).or_insert(timestamp);
+    }
+    if first_seen.len() != NUM_DRIVERS {
+        return Err(format!(
+            "Race frames need {} drivers, the session has positions for {}",
+            NUM_DRIVERS,
+            first_seen.len()
+        ));
+    }
+    let (Some(&start), Some(&(end, _, _))) = (first_seen.values().max(), samples.last()) else {
+        return Err("No location data".to_string());
+    };
+
+    // Drivers in the order of their numbers
+    let mut positions: BTreeMap<u8, u8> = BTreeMap::new();
+    let mut next_sample = samples.iter().peekable();
+    let mut frames = Vec::new();
+    let mut time = start;
+    while time <= end {
+        while let Some(&(_, driver_number, led)) =
+            next_sample.next_if(|&&(timestamp, _, _)| timestamp <= time)
+        {
+            positions.insert(driver_number, led);
+        }
+
+        let mut frame: [DriverData; NUM_DRIVERS] = Default::default();
+        for (driver, (&driver_number, &led_num)) in frame.iter_mut().zip(&positions) {
+            *driver = DriverData {
+                driver_number,
+                led_num,
+            };
+        }
+        frames.push(UpdateFrame { frame });
+        time += interval_ms as i64;
+    }
+    Ok(frames)
+}
+
+/// Serialize frames into a race file for the board
+pub fn race_file(frames: &[UpdateFrame]) -> Vec<u8> {
+    let mut file = Vec::with_capacity(frames.len() * UpdateFrame::SERIALIZED_SIZE);
+    for frame in frames {
+        file.extend_from_slice(&frame.to_bytes().unwrap());
+    }
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use f1_logic::board::BoardRevision;
+    use f1_logic::transform::{ControlPoint, Transform};
+
+    #[test]
+    fn test_led_mapper_lap() {
+        let mut mapper = LedMapper::new();
+        // Two laps just past every LED, off the centerline
+        for _ in 0..2 {
+            for (i, led) in LED_DATA.iter().enumerate() {
+                let next = &LED_DATA[(i + 1) % LED_DATA.len()];
+                let x = led.x_led + (next.x_led - led.x_led) * 0.3 + 20.0;
+                let y = led.y_led + (next.y_led - led.y_led) * 0.3 - 20.0;
+                assert_eq!(mapper.led(44, x, y).led_number, led.led_number);
+            }
+        }
+    }
+
+    #[test]
+    fn test_board_registration() {
+        // The simulator's LEDs are those of the 10x10 board in OpenF1 units
+        let config = BoardRevision::Zandvoort10x10.config();
+        let points: Vec<ControlPoint> = config
+            .leds
+            .iter()
+            .zip(LED_DATA)
+            .map(|(pcb, led)| {
+                assert_eq!(pcb.led_number as u32, led.led_number);
+                (
+                    Point::new(pcb.x_mm, pcb.y_mm),
+                    Point::new(led.x_led, led.y_led),
+                )
+            })
+            .collect();
+        let fitted = Transform::fit_similarity(&points).unwrap();
+        let stored = config.openf1_transform.unwrap();
+        assert!(stored.max_error(&points) < 3.0);
+        assert!((fitted.a - stored.a).abs() < 1e-3);
+        assert!((fitted.c - stored.c).abs() < 1e-5);
+        assert!((fitted.tx - stored.tx).abs() < 0.1);
+        assert!((fitted.ty - stored.ty).abs() < 0.1);
+    }
+}
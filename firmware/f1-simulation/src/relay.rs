@@ -0,0 +1,692 @@
+//! Race relay for boards on the LAN.
+//!
+//! The board can't parse OpenF1's JSON. The relay fetches the location data
+//! of a session, converts it into race frames and serves them:
+//!
+//! - `GET /races/<session_key>.bin`: the whole race file, in the format of
+//!   `csv_to_bin`. `Range` requests let a board resume a download.
+//! - `GET /races/<session_key>/stream`: a WebSocket sending one binary
+//!   message with a serialized `UpdateFrame` per frame interval.
+//! - UDP on `f1_logic::stream::STREAM_PORT`: the live streaming protocol of
+//!   the board, see [`Relay::serve_stream`].
+//!
+//! Converted sessions are kept in memory.
+
+use crate::openf1::{self, OPENF1_URL};
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use f1_logic::data_frame::{UpdateFrame, FRAME_INTERVAL_MS};
+use f1_logic::settings::crc32;
+use f1_logic::stream::{
+    self, Request, StreamEncoder, HEARTBEAT_INTERVAL_MS, KEYFRAME_INTERVAL_MS, MAX_MESSAGE_SIZE,
+    SUBSCRIPTION_TIMEOUT_MS,
+};
+use reqwest::Client;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::OnceCell;
+
+/// Frame interval of the race files, the board plays them at this rate
+pub const DEFAULT_FRAME_INTERVAL_MS: u64 = FRAME_INTERVAL_MS;
+
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    /// Base URL of the OpenF1 API
+    pub openf1_url: String,
+    pub frame_interval_ms: u64,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            openf1_url: OPENF1_URL.to_string(),
+            frame_interval_ms: DEFAULT_FRAME_INTERVAL_MS,
+        }
+    }
+}
+
+/// A converted session
+pub struct Race {
+    pub file: Bytes,
+    pub etag: String,
+}
+
+#[derive(Clone)]
+pub struct Relay {
+    config: Arc<RelayConfig>,
+    client: Client,
+    races: Races,
+}
+
+/// Every session is converted once, requests for other sessions don't wait
+/// for it
+type Races = Arc<std::sync::Mutex<HashMap<u32, Arc<OnceCell<Arc<Race>>>>>>;
+
+/// A board following a session over UDP
+struct Subscriber {
+    /// Identifies the stream task serving the subscription
+    id: u64,
+    session_key: u32,
+    last_heard: Instant,
+    resync: bool,
+}
+
+type Subscribers = Arc<std::sync::Mutex<HashMap<SocketAddr, Subscriber>>>;
+
+impl Relay {
+    pub fn new(config: RelayConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            client: Client::new(),
+            races: Arc::default(),
+        }
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/races/:file", get(race_file))
+            .route("/races/:session_key/stream", get(race_stream))
+            .with_state(self)
+    }
+
+    pub async fn serve(self, listener: TcpListener) -> std::io::Result<()> {
+        axum::serve(listener, self.router()).await
+    }
+
+    /// Stream sessions to the boards subscribing on `socket`, with the
+    /// protocol of `f1_logic::stream`. Each subscription gets its own task
+    /// sending the frames in real time.
+    pub async fn serve_stream(self, socket: UdpSocket) -> std::io::Result<()> {
+        let socket = Arc::new(socket);
+        let subscribers = Subscribers::default();
+        let next_id = AtomicU64::new(0);
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        loop {
+            let (len, addr) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                // An ICMP error for a board that went away
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionRefused => continue,
+                Err(err) => return Err(err),
+            };
+            let Some(request) = Request::decode(&buf[..len]) else {
+                continue;
+            };
+
+            let mut subscribed = subscribers.lock().unwrap();
+            match request {
+                Request::Subscribe {
+                    session_key,
+                    sequence,
+                } => match subscribed.get_mut(&addr) {
+                    Some(subscriber) if subscriber.session_key == session_key => {
+                        subscriber.last_heard = Instant::now();
+                    }
+                    _ => {
+                        println!("{} follows session {}", addr, session_key);
+                        let id = next_id.fetch_add(1, Ordering::Relaxed);
+                        subscribed.insert(
+                            addr,
+                            Subscriber {
+                                id,
+                                session_key,
+                                last_heard: Instant::now(),
+                                resync: false,
+                            },
+                        );
+                        let stream = Stream {
+                            relay: self.clone(),
+                            socket: socket.clone(),
+                            subscribers: subscribers.clone(),
+                            addr,
+                            id,
+                        };
+                        tokio::spawn(stream.run(session_key, sequence));
+                    }
+                },
+                Request::Resync { .. } => {
+                    if let Some(subscriber) = subscribed.get_mut(&addr) {
+                        subscriber.resync = true;
+                    }
+                }
+                Request::Unsubscribe => {
+                    subscribed.remove(&addr);
+                }
+            }
+        }
+    }
+
+    /// The converted session, fetched from OpenF1 on first use
+    pub async fn race(&self, session_key: u32) -> Result<Arc<Race>, String> {
+        let race = self
+            .races
+            .lock()
+            .unwrap()
+            .entry(session_key)
+            .or_default()
+            .clone();
+        // A failed fetch leaves the cell empty for the next request
+        race.get_or_try_init(|| self.fetch_race(session_key))
+            .await
+            .cloned()
+    }
+
+    async fn fetch_race(&self, session_key: u32) -> Result<Arc<Race>, String> {
+        println!("Fetching session {}", session_key);
+        let base_url = &self.config.openf1_url;
+        let drivers = openf1::fetch_drivers(&self.client, base_url, session_key).await?;
+        let mut locations = Vec::new();
+        for driver_number in drivers {
+            let driver_locations =
+                openf1::fetch_locations(&self.client, base_url, session_key, driver_number, None)
+                    .await?;
+            println!(
+                "Fetched {} entries for driver {}",
+                driver_locations.len(),
+                driver_number
+            );
+            locations.extend(driver_locations);
+        }
+        let frames = openf1::race_frames(&locations, self.config.frame_interval_ms)?;
+        let file = openf1::race_file(&frames);
+        println!("Session {}: {} frames", session_key, frames.len());
+
+        Ok(Arc::new(Race {
+            etag: format!("\"{}-{:08x}\"", session_key, crc32(&file)),
+            file: file.into(),
+        }))
+    }
+}
+
+/// First and last byte of a `Range: bytes=<first>-[<last>]` header
+fn parse_range(value: &str, size: usize) -> Option<(usize, usize)> {
+    let (first, last) = value.strip_prefix("bytes=")?.split_once('-')?;
+    let first = first.trim().parse().ok()?;
+    let last = match last.trim() {
+        "" => size.checked_sub(1)?,
+        last => last.parse::<usize>().ok()?.min(size.checked_sub(1)?),
+    };
+    (first <= last).then_some((first, last))
+}
+
+async fn race_file(
+    State(relay): State<Relay>,
+    Path(file): Path<String>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(session_key) = file.strip_suffix(".bin").and_then(|key| key.parse().ok()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let race = match relay.race(session_key).await {
+        Ok(race) => race,
+        Err(err) => {
+            eprintln!("Failed to convert session {}: {}", session_key, err);
+            return (StatusCode::BAD_GATEWAY, err).into_response();
+        }
+    };
+
+    let size = race.file.len();
+    let headers_for = |content_range: Option<String>| {
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(
+            header::CONTENT_TYPE,
+            "application/octet-stream".parse().unwrap(),
+        );
+        response_headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+        response_headers.insert(header::ETAG, race.etag.parse().unwrap());
+        if let Some(content_range) = content_range {
+            response_headers.insert(header::CONTENT_RANGE, content_range.parse().unwrap());
+        }
+        response_headers
+    };
+
+    let Some(range) = headers.get(header::RANGE) else {
+        return (headers_for(None), race.file.clone()).into_response();
+    };
+    match range
+        .to_str()
+        .ok()
+        .and_then(|range| parse_range(range, size))
+    {
+        Some((first, last)) => (
+            StatusCode::PARTIAL_CONTENT,
+            headers_for(Some(format!("bytes {}-{}/{}", first, last, size))),
+            race.file.slice(first..=last),
+        )
+            .into_response(),
+        None => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            headers_for(Some(format!("bytes */{}", size))),
+        )
+            .into_response(),
+    }
+}
+
+async fn race_stream(
+    State(relay): State<Relay>,
+    Path(session_key): Path<u32>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let race = match relay.race(session_key).await {
+        Ok(race) => race,
+        Err(err) => {
+            eprintln!("Failed to convert session {}: {}", session_key, err);
+            return (StatusCode::BAD_GATEWAY, err).into_response();
+        }
+    };
+    let interval = Duration::from_millis(relay.config.frame_interval_ms);
+    ws.on_upgrade(move |socket| send_frames(socket, race, interval))
+}
+
+/// Send the frames in real time until the race ends or the client leaves
+async fn send_frames(mut socket: WebSocket, race: Arc<Race>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    for frame in race.file.chunks_exact(UpdateFrame::SERIALIZED_SIZE) {
+        ticker.tick().await;
+        if socket.send(Message::Binary(frame.to_vec())).await.is_err() {
+            return;
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// A session streamed to one board
+struct Stream {
+    relay: Relay,
+    socket: Arc<UdpSocket>,
+    subscribers: Subscribers,
+    addr: SocketAddr,
+    id: u64,
+}
+
+impl Stream {
+    async fn run(self, session_key: u32, sequence: u32) {
+        if self.send_session(session_key, sequence).await.is_none() {
+            println!("{} stopped following session {}", self.addr, session_key);
+        }
+        let mut subscribed = self.subscribers.lock().unwrap();
+        if subscribed.get(&self.addr).is_some_and(|s| s.id == self.id) {
+            subscribed.remove(&self.addr);
+        }
+    }
+
+    /// Whether a resync was requested, `None` when the board unsubscribed
+    /// or was not heard from for too long
+    fn poll_subscription(&self) -> Option<bool> {
+        let mut subscribed = self.subscribers.lock().unwrap();
+        let subscriber = subscribed.get_mut(&self.addr).filter(|s| s.id == self.id)?;
+        if subscriber.last_heard.elapsed() > Duration::from_millis(SUBSCRIPTION_TIMEOUT_MS.into()) {
+            return None;
+        }
+        Some(std::mem::take(&mut subscriber.resync))
+    }
+
+    async fn send(&self, message: &stream::Message) -> Option<()> {
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let len = message.encode(&mut buf)?;
+        match self.socket.send_to(&buf[..len], self.addr).await {
+            Ok(_) => Some(()),
+            Err(err) => {
+                eprintln!("Failed to send to {}: {}", self.addr, err);
+                None
+            }
+        }
+    }
+
+    /// Send the frames of the session from `sequence` on, `None` when the
+    /// stream stopped early
+    async fn send_session(&self, session_key: u32, sequence: u32) -> Option<()> {
+        // Keep the board waiting while the session is fetched
+        let fetch = self.relay.race(session_key);
+        tokio::pin!(fetch);
+        let mut heartbeat =
+            tokio::time::interval(Duration::from_millis(HEARTBEAT_INTERVAL_MS.into()));
+        let race = loop {
+            tokio::select! {
+                race = &mut fetch => break race,
+                _ = heartbeat.tick() => {
+                    self.poll_subscription()?;
+                    self.send(&stream::Message::Heartbeat { sequence }).await?;
+                }
+            }
+        };
+        let race = match race {
+            Ok(race) => race,
+            Err(err) => {
+                eprintln!("Failed to convert session {}: {}", session_key, err);
+                return None;
+            }
+        };
+
+        let interval_ms = self.relay.config.frame_interval_ms;
+        let frames: Vec<UpdateFrame> = race
+            .file
+            .chunks_exact(UpdateFrame::SERIALIZED_SIZE)
+            .map(|chunk| UpdateFrame::try_from_bytes(chunk).unwrap())
+            .collect();
+        self.send(&stream::Message::Hello {
+            session_key,
+            frame_interval_ms: u16::try_from(interval_ms).unwrap_or(u16::MAX),
+            frame_count: frames.len() as u32,
+        })
+        .await?;
+
+        let mut encoder = StreamEncoder::new((KEYFRAME_INTERVAL_MS as u64 / interval_ms) as u32);
+        let mut ticker = tokio::time::interval(Duration::from_millis(interval_ms));
+        for (sequence, frame) in frames.iter().enumerate().skip(sequence as usize) {
+            ticker.tick().await;
+            if self.poll_subscription()? {
+                encoder.request_keyframe();
+            }
+            let sequence = sequence as u32;
+            let timestamp_ms = (sequence as u64 * interval_ms) as u32;
+            self.send(&encoder.encode(sequence, timestamp_ms, frame))
+                .await?;
+        }
+        self.send(&stream::Message::End {
+            sequence: frames.len() as u32,
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::Query;
+    use axum::Json;
+    use f1_logic::stream::JitterBuffer;
+    use futures::StreamExt;
+    use serde_json::Value;
+    use tokio_tungstenite::tungstenite;
+
+    const DRIVERS: &str = include_str!("../fixtures/openf1/drivers_9149.json");
+    const LOCATIONS: &str = include_str!("../fixtures/openf1/location_9149.json");
+
+    /// Stand-in for the OpenF1 API serving recorded responses of session 9149
+    async fn fixture_server() -> SocketAddr {
+        fn matching(data: &str, query: &HashMap<String, String>) -> Json<Vec<Value>> {
+            let entries: Vec<Value> = serde_json::from_str(data).unwrap();
+            Json(
+                entries
+                    .into_iter()
+                    .filter(|entry| {
+                        query.iter().all(|(key, value)| {
+                            serde_json::from_str::<Value>(value).is_ok_and(|v| entry[key] == v)
+                        })
+                    })
+                    .collect(),
+            )
+        }
+
+        let app = Router::new()
+            .route(
+                "/v1/drivers",
+                get(|Query(query)| async move { matching(DRIVERS, &query) }),
+            )
+            .route(
+                "/v1/location",
+                get(|Query(query)| async move { matching(LOCATIONS, &query) }),
+            );
+        serve(app).await
+    }
+
+    async fn serve(app: Router) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    /// Frame interval of the tests, the fixture session has 14 frames
+    const INTERVAL_MS: u64 = 100;
+
+    async fn fixture_relay() -> Relay {
+        let openf1 = fixture_server().await;
+        Relay::new(RelayConfig {
+            openf1_url: format!("http://{}/v1", openf1),
+            frame_interval_ms: INTERVAL_MS,
+        })
+    }
+
+    async fn relay() -> SocketAddr {
+        serve(fixture_relay().await.router()).await
+    }
+
+    /// A relay with its stream server, and the stream server's address
+    async fn stream_relay() -> (Relay, SocketAddr) {
+        let relay = fixture_relay().await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(relay.clone().serve_stream(socket));
+        (relay, addr)
+    }
+
+    /// Subscribe to the fixture session from frame `sequence` on and record
+    /// the messages and their arrival times until the stream ends
+    async fn record_stream(relay: SocketAddr, sequence: u32) -> Vec<(u64, stream::Message)> {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(relay).await.unwrap();
+        let mut buf = [0; MAX_MESSAGE_SIZE];
+        let subscribe = Request::Subscribe {
+            session_key: 9149,
+            sequence,
+        };
+        let len = subscribe.encode(&mut buf).unwrap();
+        socket.send(&buf[..len]).await.unwrap();
+
+        let start = Instant::now();
+        let mut recording = Vec::new();
+        loop {
+            let len = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            let message = stream::Message::decode(&buf[..len]).unwrap();
+            let end = matches!(message, stream::Message::End { .. });
+            recording.push((start.elapsed().as_millis() as u64, message));
+            if end {
+                return recording;
+            }
+        }
+    }
+
+    /// LED of driver `index` at `time_ms` in the fixture: six samples 270 ms
+    /// apart, starting 13 ms after the previous driver, one LED per sample
+    fn fixture_led(index: usize, time_ms: u64) -> u8 {
+        let sample = (time_ms - index as u64 * 13) / 270;
+        ((index * 4 + sample.min(5) as usize) % 96) as u8 + 1
+    }
+
+    #[tokio::test]
+    async fn test_race_file() {
+        let relay = relay().await;
+        let url = format!("http://{}/races/9149.bin", relay);
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(header::ETAG));
+        let file = response.bytes().await.unwrap();
+
+        // Frames start once every driver has a position, 19 * 13 ms in
+        let frames: Vec<UpdateFrame> = file
+            .chunks(UpdateFrame::SERIALIZED_SIZE)
+            .map(|chunk| UpdateFrame::try_from_bytes(chunk).unwrap())
+            .collect();
+        assert_eq!(frames.len() as u64, (5 * 270) / INTERVAL_MS + 1);
+        for (i, frame) in frames.iter().enumerate() {
+            let time_ms = 19 * 13 + i as u64 * INTERVAL_MS;
+            let numbers: Vec<u8> = frame.frame.iter().map(|d| d.driver_number).collect();
+            assert_eq!(
+                numbers,
+                [1, 2, 4, 10, 11, 14, 16, 18, 20, 22, 23, 24, 27, 31, 40, 44, 55, 63, 77, 81]
+            );
+            for (index, driver) in frame.frame.iter().enumerate() {
+                assert_eq!(driver.led_num, fixture_led(index, time_ms), "frame {}", i);
+            }
+        }
+
+        // Resuming a download
+        let response = Client::new()
+            .get(&url)
+            .header(header::RANGE, "bytes=400-")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes 400-{}/{}", file.len() - 1, file.len()).as_str()
+        );
+        assert_eq!(response.bytes().await.unwrap(), file[400..]);
+
+        let response = Client::new()
+            .get(&url)
+            .header(header::RANGE, format!("bytes={}-", file.len()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_sessions() {
+        let relay = relay().await;
+        let response = reqwest::get(format!("http://{}/races/1234.bin", relay))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let response = reqwest::get(format!("http://{}/races/monza.bin", relay))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_frame_stream() {
+        let relay = relay().await;
+        let file = reqwest::get(format!("http://{}/races/9149.bin", relay))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+
+        let (mut stream, _) =
+            tokio_tungstenite::connect_async(format!("ws://{}/races/9149/stream", relay))
+                .await
+                .unwrap();
+        let mut received = Vec::new();
+        while let Some(message) = stream.next().await {
+            match message.unwrap() {
+                tungstenite::Message::Binary(frame) => {
+                    assert_eq!(frame.len(), UpdateFrame::SERIALIZED_SIZE);
+                    received.extend_from_slice(&frame);
+                }
+                tungstenite::Message::Close(_) => break,
+                message => panic!("unexpected message {:?}", message),
+            }
+        }
+        assert_eq!(received, file);
+    }
+
+    #[tokio::test]
+    async fn test_udp_stream() {
+        let (relay, addr) = stream_relay().await;
+        let recording = record_stream(addr, 0).await;
+        let race = relay.race(9149).await.unwrap();
+        let frames: Vec<UpdateFrame> = race
+            .file
+            .chunks(UpdateFrame::SERIALIZED_SIZE)
+            .map(|chunk| UpdateFrame::try_from_bytes(chunk).unwrap())
+            .collect();
+
+        // Heartbeats while the session is fetched
+        let hello = recording
+            .iter()
+            .position(|(_, m)| matches!(m, stream::Message::Hello { .. }))
+            .unwrap();
+        assert!(recording[..hello]
+            .iter()
+            .all(|(_, m)| *m == stream::Message::Heartbeat { sequence: 0 }));
+        assert_eq!(
+            recording[hello].1,
+            stream::Message::Hello {
+                session_key: 9149,
+                frame_interval_ms: INTERVAL_MS as u16,
+                frame_count: 14,
+            }
+        );
+
+        // Replay with every third frame and the second keyframe lost
+        let mut arrivals = recording
+            .into_iter()
+            .filter(|(_, message)| match message {
+                stream::Message::Frame { sequence, .. } => sequence % 3 != 1,
+                stream::Message::Keyframe { sequence, .. } => *sequence != 10,
+                _ => true,
+            })
+            .peekable();
+        let mut buffer = JitterBuffer::<32>::new(300);
+        let mut played = Vec::new();
+        for now in 0..10_000 {
+            while let Some((_, message)) = arrivals.next_if(|(arrival, _)| *arrival <= now) {
+                buffer.receive(message, now);
+            }
+            if let Some(frame) = buffer.poll(now) {
+                played.push(frame);
+            }
+            if buffer.is_finished() {
+                break;
+            }
+        }
+        assert!(buffer.is_finished());
+        let expected: Vec<UpdateFrame> = [0, 2, 3, 5, 6, 8, 9]
+            .iter()
+            .map(|&i| frames[i].clone())
+            .collect();
+        assert_eq!(played, expected);
+        // The frames after the lost keyframe can't be decoded
+        assert!(buffer.take_resync());
+        assert_eq!(buffer.stats().lost, 6);
+    }
+
+    #[tokio::test]
+    async fn test_udp_stream_resume() {
+        let (_relay, addr) = stream_relay().await;
+        let recording = record_stream(addr, 10).await;
+        let sequences: Vec<(bool, u32)> = recording
+            .iter()
+            .filter_map(|(_, message)| match message {
+                stream::Message::Keyframe { sequence, .. } => Some((true, *sequence)),
+                stream::Message::Frame { sequence, .. } => Some((false, *sequence)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            sequences,
+            [(true, 10), (false, 11), (false, 12), (false, 13)]
+        );
+        assert_eq!(
+            recording.last().unwrap().1,
+            stream::Message::End { sequence: 14 }
+        );
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-", 100), Some((0, 99)));
+        assert_eq!(parse_range("bytes=40-79", 100), Some((40, 79)));
+        assert_eq!(parse_range("bytes=40-500", 100), Some((40, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), None);
+        assert_eq!(parse_range("bytes=-20", 100), None);
+        assert_eq!(parse_range("items=0-", 100), None);
+    }
+}
@@ -0,0 +1,183 @@
+//! Offline replay of race files.
+//!
+//! Reads the races the board plays, either a race binary as `csv_to_bin`
+//! and the relay write them or the grouped CSV they are made from, and
+//! turns them into frames for the simulator, one every
+//! `FRAME_INTERVAL_MS` like on the board.
+
+use crate::driver_info::DRIVERS;
+use crate::led_data;
+use f1_logic::data_frame::{DriverData, UpdateFrame, FRAME_INTERVAL_MS, NUM_DRIVERS};
+use std::io::Read;
+use std::path::Path;
+
+/// Frames of a race binary
+pub fn read_race_file(bytes: &[u8]) -> Result<Vec<UpdateFrame>, String> {
+    if bytes.len() % UpdateFrame::SERIALIZED_SIZE != 0 {
+        return Err(format!(
+            "{} bytes is no whole number of {} byte frames",
+            bytes.len(),
+            UpdateFrame::SERIALIZED_SIZE
+        ));
+    }
+    bytes
+        .chunks_exact(UpdateFrame::SERIALIZED_SIZE)
+        .enumerate()
+        .map(|(index, chunk)| {
+            UpdateFrame::try_from_bytes(chunk).map_err(|_| format!("Invalid frame {}", index))
+        })
+        .collect()
+}
+
+/// Frames of a grouped CSV: a time column, then a column of LED numbers
+/// for every driver with the driver number as header
+pub fn read_grouped_csv(reader: impl Read) -> Result<Vec<UpdateFrame>, String> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    let driver_numbers = headers
+        .iter()
+        .skip(1)
+        .map(|header| {
+            header
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| format!("Invalid driver number {}", header))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if driver_numbers.len() > NUM_DRIVERS {
+        return Err(format!(
+            "{} drivers, frames hold {}",
+            driver_numbers.len(),
+            NUM_DRIVERS
+        ));
+    }
+
+    let mut frames = Vec::new();
+    for (index, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| e.to_string())?;
+        let mut frame = UpdateFrame::default();
+        for ((data, &driver_number), field) in frame
+            .frame
+            .iter_mut()
+            .zip(&driver_numbers)
+            .zip(record.iter().skip(1))
+        {
+            let led_num = field
+                .trim()
+                .parse()
+                .map_err(|_| format!("Row {}: invalid LED {}", index + 1, field))?;
+            *data = DriverData {
+                driver_number,
+                led_num,
+            };
+        }
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// Frames of a `.csv` or race binary file
+pub fn load(path: &Path) -> Result<Vec<UpdateFrame>, String> {
+    let read_error = |e| format!("Failed to read {}: {}", path.display(), e);
+    let frames = if path.extension().is_some_and(|e| e == "csv") {
+        read_grouped_csv(std::fs::File::open(path).map_err(read_error)?)
+    } else {
+        read_race_file(&std::fs::read(path).map_err(read_error)?)
+    };
+    frames.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// The frames as the simulator shows them, timed like on the board. Like
+/// the board it skips drivers it has no color for.
+pub fn simulator_frames(frames: &[UpdateFrame]) -> Vec<led_data::UpdateFrame> {
+    (0..)
+        .zip(frames)
+        .map(|(index, frame)| {
+            let mut simulator_frame = led_data::UpdateFrame::new(index * FRAME_INTERVAL_MS);
+            for data in &frame.frame {
+                let driver_number = data.driver_number as u32;
+                if let Some(driver) = DRIVERS.iter().find(|d| d.number == driver_number) {
+                    simulator_frame.set_driver_led(
+                        driver_number,
+                        data.led_num as u32,
+                        driver.color,
+                    );
+                }
+            }
+            simulator_frame
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::leaderboard::Leaderboard;
+    use crate::openf1::race_file;
+    use f1_logic::circuit::ZANDVOORT;
+
+    fn frame(cars: &[(u8, u8)]) -> UpdateFrame {
+        let mut frame = UpdateFrame::default();
+        for (data, &(driver_number, led_num)) in frame.frame.iter_mut().zip(cars) {
+            *data = DriverData {
+                driver_number,
+                led_num,
+            };
+        }
+        frame
+    }
+
+    #[test]
+    fn test_race_file() {
+        let frames = vec![frame(&[(1, 50), (44, 48)]), frame(&[(1, 51), (44, 49)])];
+        let bytes = race_file(&frames);
+        assert_eq!(read_race_file(&bytes).unwrap(), frames);
+        assert!(read_race_file(&bytes[1..]).is_err());
+        assert!(read_race_file(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_grouped_csv() {
+        let csv = "time,1,44\n0.0,50,48\n0.1,51,49\n";
+        let frames = read_grouped_csv(csv.as_bytes()).unwrap();
+        assert_eq!(
+            frames,
+            [frame(&[(1, 50), (44, 48)]), frame(&[(1, 51), (44, 49)])]
+        );
+
+        let err = read_grouped_csv("time,1,44\n0.0,50,\n".as_bytes()).unwrap_err();
+        assert_eq!(err, "Row 1: invalid LED ");
+        assert!(read_grouped_csv("time,VER\n0.0,50\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_simulator_frames() {
+        let frames = simulator_frames(&[frame(&[(1, 50), (99, 3)]), frame(&[(44, 49)])]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[1].timestamp, FRAME_INTERVAL_MS);
+        assert_eq!(frames[0].driver_leds, [(1, 50)]);
+        assert_eq!(frames[0].led_states, [(50, (30, 65, 255))]);
+        assert_eq!(frames[1].driver_leds, [(44, 49)]);
+    }
+
+    #[test]
+    fn test_firmware_race() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("../f1-hardware/src/output.bin");
+        let frames = load(&path).unwrap();
+        // Verstappen on pole, just behind the line
+        let first = &frames[0].frame[0];
+        assert_eq!((first.driver_number, first.led_num), (1, 49));
+        assert_eq!(simulator_frames(&frames[..10]).len(), 10);
+
+        // Two minutes in, Verstappen leads on his second lap and Magnussen,
+        // still in the pit lane, is a lap down
+        let mut leaderboard = Leaderboard::new(&ZANDVOORT);
+        for frame in simulator_frames(&frames[..2400]) {
+            leaderboard.update(&frame);
+        }
+        let rows = leaderboard.rows();
+        assert_eq!((&*rows[0].code, rows[0].laps), ("VER", 1));
+        assert_eq!((&*rows[1].code, &*rows[1].gap), ("NOR", "+0.300"));
+        assert_eq!((&*rows[19].code, &*rows[19].gap), ("MAG", "+1 lap"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Circuit definitions as JSON.
+//!
+//! The host side of `f1_logic::circuit`: definitions in `firmware/tracks/`
+//! are loaded and checked here, and `track_table` compiles them into the
+//! const tables the firmware builds in.
+//!
+//! ```json
+//! {
+//!   "name": "zandvoort",
+//!   "direction": "ascending",
+//!   "start_finish_led": 1,
+//!   "sector_leds": [1, 33, 65],
+//!   "pit_lane": { "entry_led": 94, "exit_led": 3 },
+//!   "transform": { "a": 108.7, "b": 0.0, "c": 0.0, "d": 108.7, "tx": -1701.0, "ty": -3603.0 },
+//!   "leds": [{ "led_number": 1, "x_mm": 74.625, "y_mm": 33.45, "rotation_deg": -15.0 }]
+//! }
+//! ```
+//!
+//! LED positions are in millimetres on the PCB, `transform` maps them to
+//! OpenF1 positions and `pit_lane` may be `null`.
+
+use f1_logic::circuit::{Circuit, Direction, PitLane, SECTOR_COUNT};
+use f1_logic::led_layout::LedPosition;
+use f1_logic::track::Point;
+use f1_logic::transform::Transform;
+use serde::Deserialize;
+use std::fmt::Write;
+use std::path::Path;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrackFile {
+    pub name: String,
+    pub direction: DirectionFile,
+    pub start_finish_led: u16,
+    pub sector_leds: [u16; SECTOR_COUNT],
+    pub pit_lane: Option<PitLaneFile>,
+    pub transform: TransformFile,
+    pub leds: Vec<LedFile>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DirectionFile {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PitLaneFile {
+    pub entry_led: u16,
+    pub exit_led: u16,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformFile {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LedFile {
+    pub led_number: u16,
+    pub x_mm: f32,
+    pub y_mm: f32,
+    pub rotation_deg: f32,
+}
+
+impl From<LedPosition> for LedFile {
+    fn from(led: LedPosition) -> Self {
+        LedFile {
+            led_number: led.led_number,
+            x_mm: led.x_mm,
+            y_mm: led.y_mm,
+            rotation_deg: led.rotation_deg,
+        }
+    }
+}
+
+impl TrackFile {
+    /// A new circuit with its LEDs. The lap starts at `U1`, the sectors
+    /// split it in thirds and the transform is to be fitted.
+    pub fn new(name: &str, leds: Vec<LedFile>) -> Self {
+        let third = leds.len() / SECTOR_COUNT;
+        TrackFile {
+            name: name.to_string(),
+            direction: DirectionFile::Ascending,
+            start_finish_led: 1,
+            sector_leds: std::array::from_fn(|i| (i * third + 1) as u16),
+            pit_lane: None,
+            transform: TransformFile {
+                a: 1.0,
+                b: 0.0,
+                c: 0.0,
+                d: 1.0,
+                tx: 0.0,
+                ty: 0.0,
+            },
+            leds,
+        }
+    }
+
+    /// Parse and check a definition
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let track: TrackFile = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let valid_name = !track.name.is_empty()
+            && track
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !valid_name {
+            return Err(format!(
+                "Invalid circuit name {:?}, use lowercase letters, digits and _",
+                track.name
+            ));
+        }
+        track
+            .circuit()
+            .check()
+            .map_err(|e| format!("Invalid circuit {}: {:?}", track.name, e))?;
+        Ok(track)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        Self::parse(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// The circuit for use on the host. Leaks the LEDs, circuits are
+    /// loaded once.
+    pub fn circuit(&self) -> Circuit {
+        let leds: Vec<LedPosition> = self
+            .leds
+            .iter()
+            .map(|led| LedPosition {
+                led_number: led.led_number,
+                x_mm: led.x_mm,
+                y_mm: led.y_mm,
+                rotation_deg: led.rotation_deg,
+            })
+            .collect();
+        Circuit {
+            name: self.name.clone().leak(),
+            leds: leds.leak(),
+            start_finish_led: self.start_finish_led,
+            sector_leds: self.sector_leds,
+            pit_lane: self.pit_lane.map(|p| PitLane {
+                entry_led: p.entry_led,
+                exit_led: p.exit_led,
+            }),
+            direction: match self.direction {
+                DirectionFile::Ascending => Direction::Ascending,
+                DirectionFile::Descending => Direction::Descending,
+            },
+            transform: self.transform(),
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        let t = &self.transform;
+        Transform {
+            a: t.a,
+            b: t.b,
+            c: t.c,
+            d: t.d,
+            tx: t.tx,
+            ty: t.ty,
+        }
+    }
+
+    /// Name of the const in the firmware table
+    pub fn const_name(&self) -> String {
+        self.name.to_ascii_uppercase()
+    }
+
+    /// The definition as JSON, one LED per line
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail
+        let _ = self.write_json(&mut out);
+        out
+    }
+
+    fn write_json(&self, out: &mut String) -> std::fmt::Result {
+        let direction = match self.direction {
+            DirectionFile::Ascending => "ascending",
+            DirectionFile::Descending => "descending",
+        };
+        let sectors: Vec<String> = self.sector_leds.iter().map(u16::to_string).collect();
+        let t = &self.transform;
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"name\": {:?},", self.name)?;
+        writeln!(out, "  \"direction\": \"{}\",", direction)?;
+        writeln!(out, "  \"start_finish_led\": {},", self.start_finish_led)?;
+        writeln!(out, "  \"sector_leds\": [{}],", sectors.join(", "))?;
+        match self.pit_lane {
+            Some(p) => writeln!(
+                out,
+                "  \"pit_lane\": {{ \"entry_led\": {}, \"exit_led\": {} }},",
+                p.entry_led, p.exit_led
+            )?,
+            None => writeln!(out, "  \"pit_lane\": null,")?,
+        }
+        writeln!(out, "  \"transform\": {{")?;
+        writeln!(out, "    \"a\": {:?},", t.a)?;
+        writeln!(out, "    \"b\": {:?},", t.b)?;
+        writeln!(out, "    \"c\": {:?},", t.c)?;
+        writeln!(out, "    \"d\": {:?},", t.d)?;
+        writeln!(out, "    \"tx\": {:?},", t.tx)?;
+        writeln!(out, "    \"ty\": {:?}", t.ty)?;
+        writeln!(out, "  }},")?;
+        writeln!(out, "  \"leds\": [")?;
+        for (i, led) in self.leds.iter().enumerate() {
+            let separator = if i + 1 < self.leds.len() { "," } else { "" };
+            writeln!(
+                out,
+                "    {{ \"led_number\": {}, \"x_mm\": {:?}, \"y_mm\": {:?}, \"rotation_deg\": {:?} }}{}",
+                led.led_number, led.x_mm, led.y_mm, led.rotation_deg, separator
+            )?;
+        }
+        writeln!(out, "  ]")?;
+        writeln!(out, "}}")
+    }
+
+    /// Rust source of `LED_DATA` for the simulator, the LEDs in OpenF1
+    /// units through the transform
+    pub fn led_data_source(&self) -> String {
+        let transform = self.transform();
+        let mut out = String::from("pub const LED_DATA: &[LedCoordinate] = &[\n");
+        for led in &self.leds {
+            let p = transform.apply(Point::new(led.x_mm, led.y_mm));
+            out += &format!(
+                "    LedCoordinate {{\n        x_led: {:.1},\n        y_led: {:.1},\n        led_number: {},\n    }},\n",
+                p.x.round(),
+                p.y.round(),
+                led.led_number
+            );
+        }
+        out + "];\n"
+    }
+
+    /// Rust source of the firmware table, formatted like rustfmt does
+    pub fn to_rust(&self, source: &str) -> String {
+        let mut out = String::new();
+        // Writing to a String can't fail
+        let _ = self.write_rust(&mut out, source);
+        out
+    }
+
+    fn write_rust(&self, out: &mut String, source: &str) -> std::fmt::Result {
+        writeln!(out, "//! Generated by `track_table` from `{}`,", source)?;
+        writeln!(out, "//! edit the definition instead.")?;
+        writeln!(out)?;
+        if self.pit_lane.is_some() {
+            writeln!(out, "use super::{{Circuit, Direction, PitLane}};")?;
+        } else {
+            writeln!(out, "use super::{{Circuit, Direction}};")?;
+        }
+        writeln!(out, "use crate::led_layout::LedPosition;")?;
+        writeln!(out, "use crate::transform::Transform;")?;
+        writeln!(out)?;
+        writeln!(out, "pub const {}: Circuit = Circuit {{", self.const_name())?;
+        writeln!(out, "    name: {:?},", self.name)?;
+        writeln!(out, "    leds: &[")?;
+        for led in &self.leds {
+            writeln!(out, "        LedPosition {{")?;
+            writeln!(out, "            led_number: {},", led.led_number)?;
+            writeln!(out, "            x_mm: {:?},", led.x_mm)?;
+            writeln!(out, "            y_mm: {:?},", led.y_mm)?;
+            writeln!(out, "            rotation_deg: {:?},", led.rotation_deg)?;
+            writeln!(out, "        }},")?;
+        }
+        writeln!(out, "    ],")?;
+        writeln!(out, "    start_finish_led: {},", self.start_finish_led)?;
+        let sectors: Vec<String> = self.sector_leds.iter().map(u16::to_string).collect();
+        writeln!(out, "    sector_leds: [{}],", sectors.join(", "))?;
+        match self.pit_lane {
+            Some(pit_lane) => {
+                writeln!(out, "    pit_lane: Some(PitLane {{")?;
+                writeln!(out, "        entry_led: {},", pit_lane.entry_led)?;
+                writeln!(out, "        exit_led: {},", pit_lane.exit_led)?;
+                writeln!(out, "    }}),")?;
+            }
+            None => writeln!(out, "    pit_lane: None,")?,
+        }
+        writeln!(out, "    direction: Direction::{:?},", self.direction)?;
+        let t = &self.transform;
+        writeln!(out, "    transform: Transform {{")?;
+        for (name, value) in [
+            ("a", t.a),
+            ("b", t.b),
+            ("c", t.c),
+            ("d", t.d),
+            ("tx", t.tx),
+            ("ty", t.ty),
+        ] {
+            writeln!(out, "        {}: {:?},", name, value)?;
+        }
+        writeln!(out, "    }},")?;
+        writeln!(out, "}};")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use f1_logic::circuit::ZANDVOORT;
+
+    const ZANDVOORT_JSON: &str = include_str!("../../tracks/zandvoort.json");
+    const ZANDVOORT_RS: &str = include_str!("../../f1-logic/src/circuit/zandvoort.rs");
+
+    #[test]
+    fn test_zandvoort_table() {
+        let track = TrackFile::parse(ZANDVOORT_JSON).unwrap();
+        // The firmware table is up to date
+        assert_eq!(
+            track.to_rust("firmware/tracks/zandvoort.json"),
+            ZANDVOORT_RS
+        );
+        assert_eq!(track.circuit(), ZANDVOORT);
+    }
+
+    #[test]
+    fn test_new() {
+        let leds: Vec<LedFile> = ZANDVOORT.leds.iter().map(|&led| led.into()).collect();
+        let track = TrackFile::new("zandvoort", leds);
+        assert_eq!(track.sector_leds, [1, 33, 65]);
+        let json = track.to_json();
+        assert_eq!(TrackFile::parse(&json).unwrap().to_json(), json);
+
+        let led_data = TrackFile::parse(ZANDVOORT_JSON).unwrap().led_data_source();
+        assert!(led_data.starts_with(
+            "pub const LED_DATA: &[LedCoordinate] = &[\n    LedCoordinate {\n        x_led: 6413.0,\n"
+        ));
+        assert_eq!(led_data.matches("LedCoordinate {").count(), 96);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let track = |f: &dyn Fn(&mut serde_json::Value)| {
+            let mut json: serde_json::Value = serde_json::from_str(ZANDVOORT_JSON).unwrap();
+            f(&mut json);
+            TrackFile::parse(&json.to_string())
+        };
+        assert!(track(&|_| {}).is_ok());
+        let err = track(&|j| j["sector_leds"][1] = 200.into()).unwrap_err();
+        assert_eq!(err, "Invalid circuit zandvoort: UnknownLed(200)");
+        let err = track(&|j| j["name"] = "Zandvoort GP".into()).unwrap_err();
+        assert!(err.starts_with("Invalid circuit name"));
+        assert!(track(&|j| j["direction"] = "clockwise".into()).is_err());
+        assert!(track(&|j| j["laps"] = 72.into()).is_err());
+        let err = track(&|j| {
+            j["pit_lane"] = serde_json::json!({ "entry_led": 94, "exit_led": 3 });
+            j["leds"].as_array_mut().unwrap().swap(3, 4);
+        })
+        .unwrap_err();
+        assert!(err.contains("LedNumbering"));
+    }
+}